@@ -0,0 +1,300 @@
+use osmio2::objects::{NodeId, ObjType, Relation, Tags, Way, WayId};
+use std::collections::{HashMap, HashSet};
+
+use crate::{NodeChain, NodeCoords, Profile, Road};
+
+/* Pedestrian areas (squares, plazas): closed ways with area=yes and multipolygons, see Profile::areas.
+Instead of a ring road around the area, there are straight edges across it: a visibility graph
+between the points where other ways enter the area and the corners that paths bend around. */
+
+pub type Rings = Vec<Vec<NodeId>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Area {
+	// like w123 or r456
+	pub id: String,
+	pub tags: Tags,
+	pub outer: Rings,
+	pub inner: Rings,
+}
+
+impl Area {
+	pub fn nodes(&self) -> impl Iterator<Item = &NodeId> {
+		self.outer.iter().chain(self.inner.iter()).flatten()
+	}
+}
+
+// collects area ways and multipolygons. Relations must come first, because member ways are usually untagged
+pub struct AreaCollector<'a> {
+	profile: &'a Profile,
+	areas: Vec<Area>,
+	// multipolygon members: way -> (area, is inner) for each area that uses it
+	members: HashMap<WayId, Vec<(usize, bool)>>,
+	// ways of multipolygon areas: (outer, inner)
+	parts: HashMap<usize, (Rings, Rings)>,
+}
+
+impl<'a> AreaCollector<'a> {
+	pub fn new(profile: &'a Profile) -> Self {
+		Self { profile, areas: vec![], members: HashMap::new(), parts: HashMap::new() }
+	}
+
+	pub fn enabled(&self) -> bool {
+		!self.profile.areas.is_empty()
+	}
+
+	pub fn relation(&mut self, rel: &Relation) {
+		if !self.profile.is_area(&rel.tags) || rel.tags.get("type").is_none_or(|t| &**t != "multipolygon") {
+			return
+		}
+		let idx = self.areas.len();
+		self.areas.push(Area { id: format!("r{}", rel.attrs.id), tags: rel.tags.clone(), outer: vec![], inner: vec![] });
+		self.parts.insert(idx, (vec![], vec![]));
+		for m in rel.members.iter().filter(|m| m.mtype == ObjType::Way) {
+			self.members.entry(WayId(m.mref)).or_default().push((idx, &*m.mrole == "inner"));
+		}
+	}
+
+	// ways that may be area members must be kept till all relations are read (single-pass build)
+	pub fn may_need(&self, way: &Way) -> bool {
+		self.enabled() && (way.tags.is_empty() || self.profile.is_area(&way.tags))
+	}
+
+	pub fn way(&mut self, way: &Way) {
+		let nodes: Vec<NodeId> = way.nodes.iter().map(|n| NodeId(*n)).collect();
+		if let Some(ms) = self.members.get(&WayId(way.attrs.id)) {
+			for (idx, inner) in ms.iter() {
+				let p = self.parts.get_mut(idx).unwrap();
+				if *inner { p.1.push(nodes.clone()) } else { p.0.push(nodes.clone()) }
+			}
+		}
+		if self.profile.is_area(&way.tags) && nodes.len() > 3 && nodes[0] == nodes[nodes.len() - 1] {
+			self.areas.push(Area { id: format!("w{}", way.attrs.id), tags: way.tags.clone(), outer: vec![nodes], inner: vec![] });
+		}
+	}
+
+	// multipolygon ways are joined into rings, areas with unclosed rings are skipped
+	pub fn finish(mut self) -> Vec<Area> {
+		let mut broken = 0;
+		for (idx, (outer, inner)) in self.parts.drain() {
+			match (join_rings(outer), join_rings(inner)) {
+				(Some(o), Some(i)) if !o.is_empty() => {
+					self.areas[idx].outer = o;
+					self.areas[idx].inner = i;
+				},
+				_ => broken += 1,
+			}
+		}
+		let enabled = !self.profile.areas.is_empty();
+		let areas: Vec<Area> = self.areas.into_iter().filter(|a| !a.outer.is_empty()).collect();
+		if enabled {
+			println!("pedestrian areas: {}, multipolygons with unclosed rings: {}", areas.len(), broken);
+		}
+		areas
+	}
+}
+
+// glues ways by their ends into closed rings, None if some ring can't be closed
+pub fn join_rings(mut ways: Rings) -> Option<Rings> {
+	let mut rings = vec![];
+	while let Some(mut ring) = ways.pop() {
+		while ring.len() < 2 || ring[0] != ring[ring.len() - 1] {
+			let end = ring[ring.len() - 1];
+			let i = ways.iter().position(|w| w[0] == end || w[w.len() - 1] == end)?;
+			let mut next = ways.swap_remove(i);
+			if next[0] != end {
+				next.reverse();
+			}
+			ring.extend_from_slice(&next[1..]);
+		}
+		if ring.len() > 3 {
+			rings.push(ring);
+		}
+	}
+	Some(rings)
+}
+
+type Pt = (f64, f64);
+
+fn cross(o: Pt, a: Pt, b: Pt) -> f64 {
+	(a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0)
+}
+
+// signed area ×2, positive for counter-clockwise rings
+fn ring_area(ring: &[Pt]) -> f64 {
+	ring.windows(2).map(|w| w[0].0 * w[1].1 - w[1].0 * w[0].1).sum()
+}
+
+// segments cross in a point inside both of them (touching doesn't count)
+fn crosses(a: Pt, b: Pt, c: Pt, d: Pt) -> bool {
+	let (d1, d2) = (cross(a, b, c), cross(a, b, d));
+	let (d3, d4) = (cross(c, d, a), cross(c, d, b));
+	d1 * d2 < 0.0 && d3 * d4 < 0.0
+}
+
+fn on_segment(p: Pt, a: Pt, b: Pt) -> bool {
+	let len2 = (b.0 - a.0).powi(2) + (b.1 - a.1).powi(2);
+	if len2 == 0.0 {
+		return (p.0 - a.0).abs() < EPS && (p.1 - a.1).abs() < EPS
+	}
+	let t = ((p.0 - a.0) * (b.0 - a.0) + (p.1 - a.1) * (b.1 - a.1)) / len2;
+	let t = t.clamp(0.0, 1.0);
+	let (x, y) = (a.0 + t * (b.0 - a.0), a.1 + t * (b.1 - a.1));
+	(p.0 - x).abs() < EPS && (p.1 - y).abs() < EPS
+}
+
+pub(crate) fn in_ring(p: Pt, ring: &[Pt]) -> bool {
+	let mut inside = false;
+	for w in ring.windows(2) {
+		let (a, b) = (w[0], w[1]);
+		if (a.1 > p.1) != (b.1 > p.1) && p.0 < a.0 + (p.1 - a.1) / (b.1 - a.1) * (b.0 - a.0) {
+			inside = !inside;
+		}
+	}
+	inside
+}
+
+// rings in coordinates, outer ones counter-clockwise and inner ones clockwise, so that the area is on the left
+struct Polygon {
+	outer: Vec<Vec<Pt>>,
+	inner: Vec<Vec<Pt>>,
+}
+
+// about 0.1 mm in degrees
+const EPS: f64 = 1e-9;
+
+impl Polygon {
+	fn rings(&self) -> impl Iterator<Item = &Vec<Pt>> {
+		self.outer.iter().chain(self.inner.iter())
+	}
+
+	// inside or on the boundary
+	fn covers(&self, p: Pt) -> bool {
+		if self.rings().any(|r| r.windows(2).any(|w| on_segment(p, w[0], w[1]))) {
+			return true
+		}
+		self.outer.iter().any(|r| in_ring(p, r)) && !self.inner.iter().any(|r| in_ring(p, r))
+	}
+
+	// the segment doesn't leave the area: it crosses no ring, and its parts between ring points on it are inside
+	fn sees(&self, a: Pt, b: Pt) -> bool {
+		if self.rings().any(|r| r.windows(2).any(|w| crosses(a, b, w[0], w[1]))) {
+			return false
+		}
+		let len2 = (b.0 - a.0).powi(2) + (b.1 - a.1).powi(2);
+		let mut ts: Vec<f64> = self.rings().flatten()
+			.filter(|p| on_segment(**p, a, b))
+			.map(|p| ((p.0 - a.0) * (b.0 - a.0) + (p.1 - a.1) * (b.1 - a.1)) / len2)
+			.filter(|t| *t > 0.0 && *t < 1.0)
+			.collect();
+		ts.push(0.0);
+		ts.push(1.0);
+		ts.sort_by(|x, y| x.partial_cmp(y).unwrap());
+		ts.windows(2).all(|w| {
+			let t = (w[0] + w[1]) / 2.0;
+			self.covers((a.0 + t * (b.0 - a.0), a.1 + t * (b.1 - a.1)))
+		})
+	}
+}
+
+// edges across the area between entry points (nodes where other ways meet the area)
+// and reflex corners, where a shortest path may bend. Nodes without coordinates make the area skipped
+pub fn area_edges(area: &Area, road: &Road, node_coords: &NodeCoords, entries: &HashSet<NodeId>) -> Vec<NodeChain> {
+	let orient = |ring: &Vec<NodeId>, ccw: bool| -> Option<(Vec<NodeId>, Vec<Pt>)> {
+		let mut ring = ring.clone();
+		let mut pts = ring.iter().map(|n| node_coords.get(n).cloned()).collect::<Option<Vec<Pt>>>()?;
+		if (ring_area(&pts) > 0.0) != ccw {
+			ring.reverse();
+			pts.reverse();
+		}
+		Some((ring, pts))
+	};
+	let outer: Option<Vec<_>> = area.outer.iter().map(|r| orient(r, true)).collect();
+	let inner: Option<Vec<_>> = area.inner.iter().map(|r| orient(r, false)).collect();
+	let (outer, inner) = match (outer, inner) {
+		(Some(o), Some(i)) => (o, i),
+		_ => return vec![],
+	};
+	let poly = Polygon {
+		outer: outer.iter().map(|r| r.1.clone()).collect(),
+		inner: inner.iter().map(|r| r.1.clone()).collect(),
+	};
+
+	// a corner is reflex when the boundary turns right, away from the area
+	let mut points: Vec<(NodeId, Pt)> = vec![];
+	for (ring, pts) in outer.iter().chain(inner.iter()) {
+		let n = ring.len() - 1;
+		for i in 0..n {
+			let prev = pts[(i + n - 1) % n];
+			let reflex = cross(prev, pts[i], pts[i + 1]) < 0.0;
+			if (reflex || entries.contains(&ring[i])) && !points.iter().any(|(id, _)| *id == ring[i]) {
+				points.push((ring[i], pts[i]));
+			}
+		}
+	}
+
+	let mut res = vec![];
+	for i in 0..points.len() {
+		for j in i + 1..points.len() {
+			let (a, b) = (points[i], points[j]);
+			if a.1 != b.1 && poly.sees(a.1, b.1) {
+				let mut nc = NodeChain::new(0, &[(a.0).0, (b.0).0], road);
+				nc.ways.clear();
+				nc.area = true;
+				res.push(nc);
+			}
+		}
+	}
+	res
+}
+
+#[cfg(test)]
+mod area_tests {
+	use super::*;
+	use crate::tags;
+
+	#[test]
+	fn test_area_edges() {
+		assert_eq!(join_rings(vec![vec![NodeId(1), NodeId(2)], vec![NodeId(3), NodeId(2)], vec![NodeId(3), NodeId(4), NodeId(1)]]).unwrap(),
+			vec![vec![NodeId(3), NodeId(4), NodeId(1), NodeId(2), NodeId(3)]]);
+		assert!(join_rings(vec![vec![NodeId(1), NodeId(2)]]).is_none());
+
+		// L-shaped square with corners 1..6
+		//  5--4
+		//  |  |
+		//  |  3-----2
+		//  |        |
+		//  6--------1
+		let mut coords = NodeCoords::new();
+		for (id, x, y) in [(1, 4.0, 0.0), (2, 4.0, 1.0), (3, 1.0, 1.0), (4, 1.0, 4.0), (5, 0.0, 4.0), (6, 0.0, 0.0)] {
+			coords.insert(NodeId(id), (x, y));
+		}
+		let profile = Profile::default();
+		let t = tags(&[("highway", "pedestrian"), ("area", "yes")]);
+		let road = profile.area_road(&t).unwrap();
+		let ring = |ids: &[i64]| ids.iter().map(|i| NodeId(*i)).collect::<Vec<_>>();
+		let area = Area { id: "w1".to_string(), tags: t.clone(), outer: vec![ring(&[1, 2, 3, 4, 5, 6, 1])], inner: vec![] };
+		let pairs = |edges: Vec<NodeChain>| {
+			let mut p: Vec<(i64, i64)> = edges.iter().map(|e| (e.nodes[0].0.min(e.nodes[1].0), e.nodes[0].0.max(e.nodes[1].0))).collect();
+			p.sort();
+			p
+		};
+		// 2 and 5 can't see each other, the path bends at reflex corner 3
+		let entries: HashSet<NodeId> = [NodeId(2), NodeId(5)].iter().cloned().collect();
+		let edges = area_edges(&area, &road, &coords, &entries);
+		assert!(edges.iter().all(|e| e.area && e.access.foot));
+		assert_eq!(pairs(edges), vec![(2, 3), (3, 5)]);
+
+		// 6 sees 2 across the square, unless there's a hole in the way; then paths go around its corners
+		let entries: HashSet<NodeId> = [NodeId(2), NodeId(5), NodeId(6)].iter().cloned().collect();
+		assert!(pairs(area_edges(&area, &road, &coords, &entries)).contains(&(2, 6)));
+		for (id, x, y) in [(11, 2.0, 0.25), (12, 3.0, 0.25), (13, 3.0, 0.75), (14, 2.0, 0.75)] {
+			coords.insert(NodeId(id), (x, y));
+		}
+		let area = Area { inner: vec![ring(&[11, 12, 13, 14, 11])], ..area };
+		let p = pairs(area_edges(&area, &road, &coords, &entries));
+		assert!(!p.contains(&(2, 6)));
+		assert!(p.contains(&(2, 12)) && p.contains(&(2, 13)) && p.contains(&(6, 11)) && p.contains(&(6, 14)) && p.contains(&(11, 12)));
+		assert!(!p.contains(&(11, 13)) && !p.contains(&(12, 14)));
+	}
+}
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+
+use crate::OneWay;
+
+// edge of one transport mode's graph
+#[derive(Clone, Copy, Debug)]
+pub struct ModeEdge {
+	pub node1: i64,
+	pub node2: i64,
+	pub oneway: OneWay,
+	// whether the mode may use the edge at all
+	pub allowed: bool,
+}
+
+// strongly connected component that is too small to route in
+#[derive(Debug, PartialEq)]
+pub struct Island {
+	pub vertice: usize,
+	// indice in the edge list
+	pub edges: Vec<usize>,
+}
+
+// strongly connected component number of each vertex (Tarjan's algorithm, without recursion,
+// because components of real road graphs are millions of vertice deep)
+pub fn strong_components(adj: &[Vec<usize>]) -> Vec<usize> {
+	let n = adj.len();
+	let mut index = vec![usize::MAX; n];
+	let mut low = vec![0; n];
+	let mut on_stack = vec![false; n];
+	let mut stack = vec![];
+	let mut comp = vec![usize::MAX; n];
+	let mut next_index = 0;
+	let mut next_comp = 0;
+
+	for root in 0..n {
+		if index[root] != usize::MAX {
+			continue
+		}
+		// (vertex, position in its adjacency list)
+		let mut work = vec![(root, 0)];
+		while let Some((v, pos)) = work.pop() {
+			if pos == 0 {
+				index[v] = next_index;
+				low[v] = next_index;
+				next_index += 1;
+				stack.push(v);
+				on_stack[v] = true;
+			}
+			if let Some(&w) = adj[v].get(pos) {
+				work.push((v, pos + 1));
+				if index[w] == usize::MAX {
+					work.push((w, 0));
+				} else if on_stack[w] {
+					low[v] = low[v].min(index[w]);
+				}
+				continue
+			}
+			if low[v] == index[v] {
+				while let Some(w) = stack.pop() {
+					on_stack[w] = false;
+					comp[w] = next_comp;
+					if w == v { break }
+				}
+				next_comp += 1;
+			}
+			if let Some(&(parent, _)) = work.last() {
+				low[parent] = low[parent].min(low[v]);
+			}
+		}
+	}
+	comp
+}
+
+// components with less than `min_size` vertice. An edge is on an island if any of its ends is,
+// because such an edge either can't be reached or can't be left. Reversible edges are not usable
+pub fn find_islands(edges: &[ModeEdge], min_size: usize) -> Vec<Island> {
+	let mut ids: HashMap<i64, usize> = HashMap::new();
+	let mut adj: Vec<Vec<usize>> = vec![];
+	let usable = |e: &ModeEdge| e.allowed && e.oneway != OneWay::Reversible;
+
+	for e in edges.iter().filter(|e| usable(e)) {
+		let mut id = |n: i64| *ids.entry(n).or_insert_with(|| { adj.push(vec![]); adj.len() - 1 });
+		let (a, b) = (id(e.node1), id(e.node2));
+		if e.oneway != OneWay::Backward { adj[a].push(b); }
+		if e.oneway != OneWay::Forward { adj[b].push(a); }
+	}
+
+	let comp = strong_components(&adj);
+	let mut sizes: HashMap<usize, usize> = HashMap::new();
+	for c in comp.iter() {
+		*sizes.entry(*c).or_default() += 1;
+	}
+
+	// islands in the order of their first edge
+	let mut islands: Vec<Island> = vec![];
+	let mut island_of: HashMap<usize, usize> = HashMap::new();
+	for (i, e) in edges.iter().enumerate().filter(|(_, e)| usable(e)) {
+		let small = [e.node1, e.node2].iter().map(|n| comp[ids[n]]).find(|c| sizes[c] < min_size);
+		if let Some(c) = small {
+			let pos = *island_of.entry(c).or_insert_with(|| {
+				islands.push(Island { vertice: sizes[&c], edges: vec![] });
+				islands.len() - 1
+			});
+			islands[pos].edges.push(i);
+		}
+	}
+	islands
+}
+
+#[cfg(test)]
+mod components_tests {
+	use super::*;
+
+	fn edge(node1: i64, node2: i64, oneway: OneWay) -> ModeEdge {
+		ModeEdge { node1, node2, oneway, allowed: true }
+	}
+
+	#[test]
+	fn test_islands() {
+		// square 1-2-3-4 and a oneway loop around it, a separate two-way segment 10-11,
+		// and a oneway dead end 4->20 that can't be left
+		let mut edges: Vec<ModeEdge> = [(1, 2), (2, 3), (3, 4), (4, 1)].iter().map(|(a, b)| edge(*a, *b, OneWay::No)).collect();
+		edges.extend([(1, 5), (5, 6), (6, 2)].iter().map(|(a, b)| edge(*a, *b, OneWay::Forward)));
+		edges.push(edge(10, 11, OneWay::No));
+		edges.push(edge(20, 4, OneWay::Backward));
+		edges.push(ModeEdge { node1: 3, node2: 30, oneway: OneWay::No, allowed: false });
+
+		let islands = find_islands(&edges, 3);
+		assert_eq!(islands, vec![
+			Island { vertice: 2, edges: vec![7] },
+			Island { vertice: 1, edges: vec![8] },
+		]);
+		assert_eq!(find_islands(&edges, 1), vec![]);
+		// everything is an island when the threshold is above the largest component
+		assert_eq!(find_islands(&edges, 100).iter().map(|i| i.edges.len()).sum::<usize>(), 9);
+
+		let comp = strong_components(&[vec![1], vec![2], vec![0, 3], vec![]]);
+		assert_eq!(comp[0], comp[2]);
+		assert_ne!(comp[0], comp[3]);
+	}
+}
@@ -0,0 +1,456 @@
+use std::{
+	collections::HashMap,
+	convert::TryInto,
+	error::Error,
+	fs::{self, File},
+	io::{BufRead, BufReader, Read, Seek, SeekFrom},
+	path::Path,
+};
+
+/* Digital elevation models in WGS84 lon/lat: SRTM .hgt, ESRI ASCII grid (.asc) and uncompressed GeoTIFF.
+Tiles are indexed by extent when opened, and their data is read on first use. */
+
+// raster of heights in metres, NaN where there's no data.
+// (x0, y0) is the centre of the top left cell, rows go from north to south
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grid {
+	pub x0: f64,
+	pub y0: f64,
+	pub dx: f64,
+	pub dy: f64,
+	pub width: usize,
+	pub height: usize,
+	pub data: Vec<f32>,
+}
+
+impl Grid {
+	// [west, south, east, north] of the cell centres, plus half a cell around
+	pub fn extent(&self) -> [f64; 4] {
+		[self.x0 - self.dx / 2.0, self.y0 - (self.height as f64 - 0.5) * self.dy,
+			self.x0 + (self.width as f64 - 0.5) * self.dx, self.y0 + self.dy / 2.0]
+	}
+
+	// bilinear interpolation between 4 cell centres. Cells without data are left out,
+	// None if there are none with data
+	pub fn sample(&self, lon: f64, lat: f64) -> Option<f32> {
+		let fx = ((lon - self.x0) / self.dx).max(0.0).min(self.width as f64 - 1.0);
+		let fy = ((self.y0 - lat) / self.dy).max(0.0).min(self.height as f64 - 1.0);
+		let i = (fx.floor() as usize).min(self.width.saturating_sub(2));
+		let j = (fy.floor() as usize).min(self.height.saturating_sub(2));
+		let (tx, ty) = (fx - i as f64, fy - j as f64);
+		let mut sum = 0.0;
+		let mut weights = 0.0;
+		for (di, dj, w) in [(0, 0, (1.0 - tx) * (1.0 - ty)), (1, 0, tx * (1.0 - ty)), (0, 1, (1.0 - tx) * ty), (1, 1, tx * ty)] {
+			let (ci, cj) = ((i + di).min(self.width - 1), (j + dj).min(self.height - 1));
+			let v = self.data[cj * self.width + ci];
+			if !v.is_nan() && w > 0.0 {
+				sum += v as f64 * w;
+				weights += w;
+			}
+		}
+		if weights > 0.0 { Some((sum / weights) as f32) } else { None }
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format { Hgt, Asc, Tiff }
+
+struct Tile {
+	path: String,
+	format: Format,
+	extent: [f64; 4],
+	grid: Option<Grid>,
+}
+
+pub struct Dem {
+	tiles: Vec<Tile>,
+	// the tile of the previous point, most points are in the same tile
+	last: Option<usize>,
+}
+
+impl Dem {
+	// files and directories (not recursive) with .hgt, .asc, .tif and .tiff files
+	pub fn open(paths: &[String]) -> Result<Self, Box<dyn Error>> {
+		let mut files = vec![];
+		for p in paths {
+			if Path::new(p).is_dir() {
+				let mut dir: Vec<String> = fs::read_dir(p)?.filter_map(|e| e.ok()).map(|e| e.path().to_string_lossy().to_string()).collect();
+				dir.sort();
+				files.extend(dir.into_iter().filter(|f| format_of(f).is_some()));
+			} else {
+				files.push(p.clone());
+			}
+		}
+		let mut tiles = vec![];
+		for path in files {
+			let format = format_of(&path).ok_or_else(|| format!("{}: unknown DEM format, expected .hgt, .asc or .tif", path))?;
+			let extent = match format {
+				Format::Hgt => hgt_extent(&path)?,
+				Format::Asc => read_asc(&path, false)?.extent(),
+				Format::Tiff => read_tiff(&path, false)?.extent(),
+			};
+			tiles.push(Tile { path, format, extent, grid: None });
+		}
+		if tiles.is_empty() {
+			return Err("no DEM files found".into())
+		}
+		Ok(Self { tiles, last: None })
+	}
+
+	pub fn from_grids(grids: Vec<Grid>) -> Self {
+		let tiles = grids.into_iter().map(|g| Tile { path: String::new(), format: Format::Asc, extent: g.extent(), grid: Some(g) }).collect();
+		Self { tiles, last: None }
+	}
+
+	pub fn elevation(&mut self, lon: f64, lat: f64) -> Result<Option<f32>, Box<dyn Error>> {
+		let inside = |t: &Tile| lon >= t.extent[0] && lon <= t.extent[2] && lat >= t.extent[1] && lat <= t.extent[3];
+		let idx = match self.last.filter(|i| inside(&self.tiles[*i])).or_else(|| self.tiles.iter().position(inside)) {
+			Some(i) => i,
+			None => return Ok(None),
+		};
+		self.last = Some(idx);
+		let tile = &mut self.tiles[idx];
+		if tile.grid.is_none() {
+			tile.grid = Some(match tile.format {
+				Format::Hgt => read_hgt(&tile.path)?,
+				Format::Asc => read_asc(&tile.path, true)?,
+				Format::Tiff => read_tiff(&tile.path, true)?,
+			});
+		}
+		Ok(tile.grid.as_ref().unwrap().sample(lon, lat))
+	}
+}
+
+fn format_of(path: &str) -> Option<Format> {
+	match path.rsplit_once('.')?.1.to_lowercase().as_str() {
+		"hgt" => Some(Format::Hgt),
+		"asc" => Some(Format::Asc),
+		"tif" | "tiff" => Some(Format::Tiff),
+		_ => None,
+	}
+}
+
+// south west corner from a name like N54E083.hgt
+fn hgt_corner(path: &str) -> Result<(f64, f64), Box<dyn Error>> {
+	let name = Path::new(path).file_name().and_then(|n| n.to_str()).unwrap_or("").to_uppercase();
+	let err = || format!("{}: .hgt file name must start with the corner, like N54E083", path);
+	if name.len() < 7 {
+		return Err(err().into())
+	}
+	let lat: f64 = name[1..3].parse().map_err(|_| err())?;
+	let lon: f64 = name[4..7].parse().map_err(|_| err())?;
+	let lat = match &name[0..1] { "N" => lat, "S" => -lat, _ => return Err(err().into()) };
+	let lon = match &name[3..4] { "E" => lon, "W" => -lon, _ => return Err(err().into()) };
+	Ok((lon, lat))
+}
+
+fn hgt_size(path: &str) -> Result<usize, Box<dyn Error>> {
+	let len = fs::metadata(path)?.len() as usize;
+	let size = ((len / 2) as f64).sqrt() as usize;
+	if size < 2 || size * size * 2 != len {
+		return Err(format!("{}: .hgt file must be a square of 16-bit values", path).into())
+	}
+	Ok(size)
+}
+
+fn hgt_extent(path: &str) -> Result<[f64; 4], Box<dyn Error>> {
+	let (lon, lat) = hgt_corner(path)?;
+	hgt_size(path)?;
+	Ok([lon, lat, lon + 1.0, lat + 1.0])
+}
+
+// 1° square, 1201×1201 (3") or 3601×3601 (1") big-endian i16, edges overlap with the next tiles
+pub fn read_hgt(path: &str) -> Result<Grid, Box<dyn Error>> {
+	let (lon, lat) = hgt_corner(path)?;
+	let size = hgt_size(path)?;
+	let bytes = fs::read(path)?;
+	let data = bytes.chunks_exact(2).map(|b| match i16::from_be_bytes([b[0], b[1]]) {
+		-32768 => f32::NAN,
+		v => v as f32,
+	}).collect();
+	let d = 1.0 / (size - 1) as f64;
+	Ok(Grid { x0: lon, y0: lat + 1.0, dx: d, dy: d, width: size, height: size, data })
+}
+
+// ESRI ASCII grid: a header of `key value` lines, then rows of numbers from north to south
+pub fn read_asc(path: &str, with_data: bool) -> Result<Grid, Box<dyn Error>> {
+	let mut rd = BufReader::new(File::open(path)?);
+	let mut header = HashMap::new();
+	let mut line = String::new();
+	let mut first_row = None;
+	while rd.read_line(&mut line)? > 0 {
+		let mut parts = line.split_whitespace();
+		match (parts.next(), parts.next()) {
+			(Some(k), Some(v)) if k.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+				header.insert(k.to_lowercase(), v.parse::<f64>()?);
+			},
+			(Some(_), _) => { first_row = Some(line.clone()); break },
+			_ => {},
+		}
+		line.clear();
+	}
+	let get = |k: &str| header.get(k).cloned().ok_or_else(|| format!("{}: {} is missing in the header", path, k));
+	let (width, height) = (get("ncols")? as usize, get("nrows")? as usize);
+	let (dx, dy) = match header.get("cellsize") {
+		Some(c) => (*c, *c),
+		None => (get("dx")?, get("dy")?),
+	};
+	let x0 = header.get("xllcenter").cloned().map_or_else(|| get("xllcorner").map(|x| x + dx / 2.0), Ok)?;
+	let yll = header.get("yllcenter").cloned().map_or_else(|| get("yllcorner").map(|y| y + dy / 2.0), Ok)?;
+	let nodata = header.get("nodata_value").cloned();
+
+	let mut data = vec![];
+	if with_data {
+		data.reserve(width * height);
+		let rest: Box<dyn Iterator<Item = std::io::Result<String>>> = Box::new(first_row.into_iter().map(Ok).chain(rd.lines()));
+		for l in rest {
+			for v in l?.split_whitespace() {
+				let v: f64 = v.parse()?;
+				data.push(if Some(v) == nodata { f32::NAN } else { v as f32 });
+			}
+		}
+		if data.len() != width * height {
+			return Err(format!("{}: expected {} values, found {}", path, width * height, data.len()).into())
+		}
+	}
+	Ok(Grid { x0, y0: yll + (height - 1) as f64 * dy, dx, dy, width, height, data })
+}
+
+// reads parts of a TIFF file with the byte order of the file
+struct TiffReader {
+	f: File,
+	len: u64,
+	le: bool,
+}
+
+impl TiffReader {
+	fn bytes(&mut self, offset: u64, len: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+		if offset + len as u64 > self.len {
+			return Err("TIFF offset is out of the file".into())
+		}
+		let mut buf = vec![0; len];
+		self.f.seek(SeekFrom::Start(offset))?;
+		self.f.read_exact(&mut buf)?;
+		Ok(buf)
+	}
+
+	fn u16(&self, b: &[u8]) -> u16 {
+		let b = [b[0], b[1]];
+		if self.le { u16::from_le_bytes(b) } else { u16::from_be_bytes(b) }
+	}
+
+	fn u32(&self, b: &[u8]) -> u32 {
+		let b = [b[0], b[1], b[2], b[3]];
+		if self.le { u32::from_le_bytes(b) } else { u32::from_be_bytes(b) }
+	}
+
+	fn f64(&self, b: &[u8]) -> f64 {
+		let b: [u8; 8] = b[..8].try_into().unwrap();
+		if self.le { f64::from_le_bytes(b) } else { f64::from_be_bytes(b) }
+	}
+
+	// converts a sample of the given format (1 uint, 2 int, 3 float) and size to f32
+	fn sample(&self, b: &[u8], format: u16, bits: u16) -> Option<f32> {
+		let u = |n: usize| -> u64 {
+			let mut v = 0u64;
+			for i in 0..n {
+				let byte = if self.le { b[n - 1 - i] } else { b[i] };
+				v = (v << 8) | byte as u64;
+			}
+			v
+		};
+		Some(match (format, bits) {
+			(1, 8) => b[0] as f32,
+			(2, 8) => b[0] as i8 as f32,
+			(1, 16) => u(2) as u16 as f32,
+			(2, 16) => u(2) as u16 as i16 as f32,
+			(1, 32) => u(4) as u32 as f32,
+			(2, 32) => u(4) as u32 as i32 as f32,
+			(3, 32) => f32::from_bits(u(4) as u32),
+			(3, 64) => f64::from_bits(u(8)) as f32,
+			_ => return None,
+		})
+	}
+}
+
+// plain GeoTIFF: one band, no compression, strips or tiles, georeferenced by a tie point and pixel scale
+pub fn read_tiff(path: &str, with_data: bool) -> Result<Grid, Box<dyn Error>> {
+	let err = |msg: &str| -> Box<dyn Error> { format!("{}: {}", path, msg).into() };
+	let f = File::open(path)?;
+	let mut rd = TiffReader { len: f.metadata()?.len(), f, le: true };
+	let head = rd.bytes(0, 8)?;
+	rd.le = match &head[..4] {
+		b"II*\0" => true,
+		b"MM\0*" => false,
+		_ => return Err(err("not a TIFF file (BigTIFF is not supported)")),
+	};
+	let ifd = rd.u32(&head[4..]) as u64;
+	let count = rd.bytes(ifd, 2)?;
+	let count = rd.u16(&count) as usize;
+	let entries = rd.bytes(ifd + 2, count * 12)?;
+
+	// tag -> values as f64 (numbers) or text
+	let mut nums: HashMap<u16, Vec<f64>> = HashMap::new();
+	let mut text: HashMap<u16, String> = HashMap::new();
+	for e in entries.chunks_exact(12) {
+		let (tag, typ, n) = (rd.u16(&e[0..]), rd.u16(&e[2..]), rd.u32(&e[4..]) as usize);
+		let size = match typ { 1 | 2 | 6 | 7 => 1, 3 | 8 => 2, 4 | 9 | 11 => 4, 5 | 10 | 12 => 8, _ => continue };
+		let raw = if n * size <= 4 {
+			e[8..8 + n * size].to_vec()
+		} else {
+			let offset = rd.u32(&e[8..]) as u64;
+			rd.bytes(offset, n * size)?
+		};
+		if typ == 2 {
+			text.insert(tag, String::from_utf8_lossy(&raw).trim_end_matches('\0').to_string());
+			continue
+		}
+		let vals = raw.chunks_exact(size).map(|b| match typ {
+			1 | 7 => b[0] as f64,
+			6 => b[0] as i8 as f64,
+			3 => rd.u16(b) as f64,
+			8 => rd.u16(b) as i16 as f64,
+			4 => rd.u32(b) as f64,
+			9 => rd.u32(b) as i32 as f64,
+			11 => f32::from_bits(rd.u32(b)) as f64,
+			12 => rd.f64(b),
+			_ => rd.u32(b) as f64 / rd.u32(&b[4..]) as f64,
+		}).collect();
+		nums.insert(tag, vals);
+	}
+
+	let one = |tag: u16| nums.get(&tag).and_then(|v| v.first()).cloned();
+	let (width, height) = (one(256).ok_or_else(|| err("no width"))? as usize, one(257).ok_or_else(|| err("no height"))? as usize);
+	if one(259).unwrap_or(1.0) != 1.0 {
+		return Err(err("compressed GeoTIFF is not supported, convert it with `gdal_translate -co COMPRESS=NONE`"))
+	}
+	if one(277).unwrap_or(1.0) != 1.0 {
+		return Err(err("only single-band GeoTIFF is supported"))
+	}
+	let scale = nums.get(&33550).filter(|v| v.len() >= 2).ok_or_else(|| err("no ModelPixelScale tag"))?;
+	let tie = nums.get(&33922).filter(|v| v.len() >= 6).ok_or_else(|| err("no ModelTiepoint tag"))?;
+	let (dx, dy) = (scale[0], scale[1]);
+	// GTRasterTypeGeoKey (1025) = 2 means the tie point is at the pixel centre, otherwise at its corner
+	let keys = nums.get(&34735).cloned().unwrap_or_default();
+	let pixel_is_point = keys.chunks_exact(4).skip(1).any(|k| k[0] == 1025.0 && k[3] == 2.0);
+	let shift = if pixel_is_point { 0.0 } else { 0.5 };
+	let x0 = tie[3] + (shift - tie[0]) * dx;
+	let y0 = tie[4] - (shift - tie[1]) * dy;
+
+	let mut data = vec![];
+	if with_data {
+		let bits = one(258).unwrap_or(8.0) as u16;
+		let format = one(339).unwrap_or(1.0) as u16;
+		let nodata: Option<f32> = text.get(&42113).and_then(|v| v.trim().parse().ok());
+		let bps = (bits / 8) as usize;
+		rd.sample(&[0; 8], format, bits).ok_or_else(|| err("unsupported sample type"))?;
+		data = vec![f32::NAN; width * height];
+		// strips are tiles as wide as the image
+		let (tw, th, offsets) = match nums.get(&324) {
+			Some(o) => (one(322).unwrap_or(0.0) as usize, one(323).unwrap_or(0.0) as usize, o.clone()),
+			None => (width, one(278).map_or(height, |r| (r as usize).min(height)), nums.get(&273).cloned().ok_or_else(|| err("no strip offsets"))?),
+		};
+		if tw == 0 || th == 0 {
+			return Err(err("bad tile size"))
+		}
+		let across = width.div_ceil(tw);
+		for (k, off) in offsets.iter().enumerate() {
+			let (tx, ty) = ((k % across) * tw, (k / across) * th);
+			let rows = th.min(height.saturating_sub(ty));
+			let block = rd.bytes(*off as u64, tw * rows * bps)?;
+			for r in 0..rows {
+				for c in 0..tw.min(width.saturating_sub(tx)) {
+					let p = (r * tw + c) * bps;
+					let v = rd.sample(&block[p..p + bps], format, bits).unwrap();
+					data[(ty + r) * width + tx + c] = if Some(v) == nodata { f32::NAN } else { v };
+				}
+			}
+		}
+	}
+	Ok(Grid { x0, y0, dx, dy, width, height, data })
+}
+
+#[cfg(test)]
+pub mod dem_tests {
+	use super::*;
+	use std::io::Write;
+	use osmio2::temp::TempFile;
+
+	// 3×3 grid with cell centres at 83.0..83.2 and 54.0..54.2, values are row*10 + col, centre has no data
+	pub fn test_grid() -> Grid {
+		let mut data: Vec<f32> = (0..9).map(|i| ((i / 3) * 10 + i % 3) as f32).collect();
+		data[4] = f32::NAN;
+		Grid { x0: 83.0, y0: 54.2, dx: 0.1, dy: 0.1, width: 3, height: 3, data }
+	}
+
+	fn write_tiff(path: &str, g: &Grid) {
+		let mut b: Vec<u8> = b"II*\0".to_vec();
+		b.extend_from_slice(&8u32.to_le_bytes());
+		let entries: Vec<(u16, u16, u32, u32)> = vec![
+			(256, 3, 1, g.width as u32), (257, 3, 1, g.height as u32), (258, 3, 1, 32), (259, 3, 1, 1),
+			(273, 4, 1, 300), (277, 3, 1, 1), (278, 3, 1, g.height as u32), (279, 4, 1, (g.data.len() * 4) as u32),
+			(339, 3, 1, 3), (33550, 12, 3, 200), (33922, 12, 6, 224),
+		];
+		b.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+		for (tag, typ, n, v) in entries {
+			b.extend_from_slice(&tag.to_le_bytes());
+			b.extend_from_slice(&typ.to_le_bytes());
+			b.extend_from_slice(&n.to_le_bytes());
+			b.extend_from_slice(&v.to_le_bytes());
+		}
+		b.resize(200, 0);
+		for v in [g.dx, g.dy, 0.0, 0.0, 0.0, 0.0, g.x0 - g.dx / 2.0, g.y0 + g.dy / 2.0, 0.0] {
+			b.extend_from_slice(&v.to_le_bytes());
+		}
+		b.resize(300, 0);
+		for v in g.data.iter() {
+			b.extend_from_slice(&v.to_le_bytes());
+		}
+		File::create(path).unwrap().write_all(&b).unwrap();
+	}
+
+	#[test]
+	fn test_sample() {
+		let g = test_grid();
+		assert_eq!(g.sample(83.0, 54.2), Some(0.0));
+		assert_eq!(g.sample(83.2, 54.0), Some(22.0));
+		assert_eq!(g.sample(83.15, 54.0), Some(21.5));
+		// half a cell outside is clamped to the edge
+		assert_eq!(g.sample(83.24, 53.96), Some(22.0));
+		// the cell without data is left out
+		assert_eq!(g.sample(83.15, 54.15), Some(5.0));
+		assert_eq!(g.extent(), [82.95, 53.95, 83.25, 54.25]);
+	}
+
+	#[test]
+	fn test_formats() {
+		let tmp = TempFile::new("dem-test");
+		std::fs::create_dir(&tmp.0).unwrap();
+		let dir = &tmp.0;
+		let g = test_grid();
+
+		let asc = dir.join("dem-test.asc").to_str().unwrap().to_string();
+		File::create(&asc).unwrap().write_all(b"ncols 3\nnrows 3\nxllcorner 82.95\nyllcorner 53.95\ncellsize 0.1\nNODATA_value -9999\n0 1 2\n10 -9999 12\n20 21 22\n").unwrap();
+		let tif = dir.join("dem-test.tif").to_str().unwrap().to_string();
+		write_tiff(&tif, &g);
+		for grid in [read_asc(&asc, true).unwrap(), read_tiff(&tif, true).unwrap()] {
+			assert!((grid.x0 - g.x0).abs() < 1e-9 && (grid.y0 - g.y0).abs() < 1e-9);
+			assert_eq!((grid.width, grid.height, grid.data[4].is_nan()), (3, 3, true));
+			assert_eq!(grid.sample(83.15, 54.0), Some(21.5));
+		}
+
+		let hgt = dir.join("N54E083.hgt").to_str().unwrap().to_string();
+		let mut data = vec![];
+		for v in [100i16, 200, -32768, 400] {
+			data.extend_from_slice(&v.to_be_bytes());
+		}
+		File::create(&hgt).unwrap().write_all(&data).unwrap();
+		let mut dem = Dem::open(&[asc, hgt]).unwrap();
+		// the first tile that covers the point
+		assert_eq!(dem.elevation(83.0, 54.0).unwrap(), Some(20.0));
+		// top row is the north edge
+		assert_eq!(dem.elevation(83.5, 55.0).unwrap(), Some(150.0));
+		assert_eq!(dem.elevation(83.9, 54.0).unwrap(), Some(400.0));
+		assert_eq!(dem.elevation(10.0, 10.0).unwrap(), None);
+	}
+}
@@ -0,0 +1,146 @@
+use geo::{HaversineDistance, Point};
+use osmio2::objects::{NodeId, Tags, Way};
+use serde::{Serialize, Serializer};
+use std::{collections::HashSet, error::Error, fmt::Write};
+
+use crate::dem::Dem;
+
+// bridges and tunnels don't follow the terrain: heights on them are interpolated between their ends
+pub fn is_structure(tags: &Tags) -> bool {
+	tags.get("bridge").is_some_and(|v| &**v != "no")
+		|| tags.get("tunnel").is_some_and(|v| &**v != "no" && &**v != "building_passage")
+}
+
+// node pairs of bridge and tunnel segments, smaller id first
+pub type Structures = HashSet<(NodeId, NodeId)>;
+
+pub fn segment(a: NodeId, b: NodeId) -> (NodeId, NodeId) {
+	if a.0 <= b.0 { (a, b) } else { (b, a) }
+}
+
+pub fn structure_segments<I, E>(ways: I) -> Result<Structures, Box<dyn Error>>
+where I: Iterator<Item = Result<Way, E>>, E: Error + 'static {
+	let mut res = HashSet::new();
+	for way in ways {
+		add_structure(&mut res, &way?);
+	}
+	Ok(res)
+}
+
+pub fn add_structure(structures: &mut Structures, way: &Way) {
+	if is_structure(&way.tags) {
+		structures.extend(way.nodes.windows(2).map(|w| segment(NodeId(w[0]), NodeId(w[1]))));
+	}
+}
+
+// 3D geometry, written as `LINESTRING Z (...)`, or empty if some heights are unknown
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineStringZ(pub Vec<(f64, f64, f64)>);
+
+impl Serialize for LineStringZ {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		if self.0.iter().any(|p| p.2.is_nan()) {
+			return serializer.serialize_str("")
+		}
+		let mut s = String::from("LINESTRING Z (");
+		for (i, (x, y, z)) in self.0.iter().enumerate() {
+			write!(s, "{}{} {} {}", if i > 0 { ", " } else { "" }, x, y, z).unwrap();
+		}
+		s.push(')');
+		serializer.serialize_str(&s)
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElevationProfile {
+	// height of each point in metres, rounded to cm
+	pub z: Vec<f64>,
+	pub ascent: f64,
+	pub descent: f64,
+	// steepest segment, in percent
+	pub max_grade: f64,
+}
+
+fn round(v: f64, digits: i32) -> f64 {
+	let m = 10f64.powi(digits);
+	(v * m).round() / m
+}
+
+// `structure[i]` tells if the segment from point i to i + 1 is on a bridge or in a tunnel.
+// None if a point is outside the DEM or has no data
+pub fn elevation_profile(dem: &mut Dem, points: &[(f64, f64)], structure: &[bool]) -> Result<Option<ElevationProfile>, Box<dyn Error>> {
+	let n = points.len();
+	let on_structure = |i: usize| i > 0 && i < n - 1 && structure[i - 1] && structure[i];
+	let mut dist = vec![0.0; n];
+	for i in 1..n {
+		let (a, b) = (points[i - 1], points[i]);
+		dist[i] = dist[i - 1] + Point::new(a.0, a.1).haversine_distance(&Point::new(b.0, b.1));
+	}
+
+	// points inside a run of structure segments are not sampled
+	let mut z = vec![0.0f64; n];
+	for i in 0..n {
+		if !on_structure(i) {
+			match dem.elevation(points[i].0, points[i].1)? {
+				Some(h) => z[i] = h as f64,
+				None => return Ok(None),
+			}
+		}
+	}
+	let mut start = 0;
+	for i in 1..n {
+		if on_structure(i) {
+			continue
+		}
+		let span = dist[i] - dist[start];
+		for k in start + 1..i {
+			z[k] = if span > 0.0 { z[start] + (z[i] - z[start]) * (dist[k] - dist[start]) / span } else { z[start] };
+		}
+		start = i;
+	}
+
+	let (mut ascent, mut descent, mut max_grade) = (0.0, 0.0, 0.0f64);
+	for i in 1..n {
+		let dz = z[i] - z[i - 1];
+		if dz > 0.0 { ascent += dz } else { descent -= dz }
+		let len = dist[i] - dist[i - 1];
+		if len > 0.0 {
+			max_grade = max_grade.max(dz.abs() / len * 100.0);
+		}
+	}
+	Ok(Some(ElevationProfile { z: z.iter().map(|v| round(*v, 2)).collect(), ascent: round(ascent, 1), descent: round(descent, 1), max_grade: round(max_grade, 1) }))
+}
+
+#[cfg(test)]
+mod elevation_tests {
+	use super::*;
+	use crate::dem::{Grid, dem_tests::test_grid};
+
+	#[test]
+	fn test_profile() {
+		let mut dem = Dem::from_grids(vec![test_grid()]);
+		// 54.0 row goes 20, 21, 22 from west to east
+		let points = [(83.0, 54.0), (83.1, 54.0), (83.2, 54.0), (83.2, 54.2)];
+		let p = elevation_profile(&mut dem, &points, &[false, false, false]).unwrap().unwrap();
+		assert_eq!(p.z, vec![20.0, 21.0, 22.0, 2.0]);
+		assert_eq!((p.ascent, p.descent), (2.0, 20.0));
+		// 20 m on 22.2 km
+		assert_eq!(p.max_grade, 0.1);
+
+		// a bridge over the valley at 83.1
+		let mut grid = test_grid();
+		grid.data[7] = -50.0;
+		let mut dem = Dem::from_grids(vec![grid]);
+		let p = elevation_profile(&mut dem, &points[..3], &[false, false]).unwrap().unwrap();
+		assert_eq!(p.z, vec![20.0, -50.0, 22.0]);
+		let p = elevation_profile(&mut dem, &points[..3], &[true, true]).unwrap().unwrap();
+		assert_eq!((p.z[1], p.ascent, p.descent), (21.0, 2.0, 0.0));
+
+		let mut dem = Dem::from_grids(vec![Grid { data: vec![f32::NAN; 9], ..test_grid() }]);
+		assert_eq!(elevation_profile(&mut dem, &points, &[false, false, false]).unwrap(), None);
+
+		let ls = LineStringZ(vec![(83.0, 54.0, 20.0), (83.1, 54.0, 21.5)]);
+		assert_eq!(serde_json::to_string(&ls).unwrap(), "\"LINESTRING Z (83 54 20, 83.1 54 21.5)\"");
+		assert_eq!(serde_json::to_string(&LineStringZ(vec![(83.0, 54.0, f64::NAN)])).unwrap(), "\"\"");
+	}
+}
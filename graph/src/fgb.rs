@@ -0,0 +1,285 @@
+use flatbuffers::FlatBufferBuilder;
+use std::{
+	error::Error,
+	fs::File,
+	io::{BufWriter, Write},
+};
+
+use crate::Edge;
+use crate::output::{EdgeWriter, ColType, Value, EDGE_COLUMNS, edge_columns, edge_values, points_z, bbox, extend_bbox};
+
+// https://github.com/flatgeobuf/flatgeobuf/tree/master/src/fbs
+const MAGIC: [u8; 8] = [0x66, 0x67, 0x62, 0x03, 0x66, 0x67, 0x62, 0x00];
+const NODE_SIZE: usize = 16;
+const LINESTRING: u8 = 2;
+const COL_BOOL: u8 = 2;
+const COL_LONG: u8 = 7;
+const COL_DOUBLE: u8 = 10;
+const COL_STRING: u8 = 11;
+
+// vtable slot of flatbuffers table field
+const fn slot(field: u16) -> u16 { 4 + 2 * field }
+
+// properties are column index (u16) and value; nulls are left out
+fn encode_properties(values: Vec<Value>) -> Vec<u8> {
+	let mut buf = vec![];
+	for (i, v) in values.into_iter().enumerate() {
+		match v {
+			Value::Null => continue,
+			Value::Int(n) => { buf.extend_from_slice(&(i as u16).to_le_bytes()); buf.extend_from_slice(&n.to_le_bytes()); },
+			Value::Float(f) => { buf.extend_from_slice(&(i as u16).to_le_bytes()); buf.extend_from_slice(&f.to_le_bytes()); },
+			Value::Bool(b) => { buf.extend_from_slice(&(i as u16).to_le_bytes()); buf.push(b as u8); },
+			Value::Text(s) => {
+				buf.extend_from_slice(&(i as u16).to_le_bytes());
+				buf.extend_from_slice(&(s.len() as u32).to_le_bytes());
+				buf.extend_from_slice(s.as_bytes());
+			},
+		}
+	}
+	buf
+}
+
+// size-prefixed Feature table. With --3d, points without heights get NaN
+fn encode_feature(edge: &Edge, extra: usize) -> Vec<u8> {
+	let mut fbb = FlatBufferBuilder::new();
+	let xy: Vec<f64> = edge.WKT.0.iter().flat_map(|c| [c.x, c.y]).collect();
+	let xy = fbb.create_vector(&xy);
+	let z = if edge.WKT_Z.is_some() {
+		let z: Vec<f64> = match points_z(edge) {
+			Some(pts) => pts.iter().map(|p| p.2).collect(),
+			None => vec![f64::NAN; edge.WKT.0.len()],
+		};
+		Some(fbb.create_vector(&z))
+	} else { None };
+	let start = fbb.start_table();
+	fbb.push_slot_always(slot(1), xy);
+	if let Some(z) = z {
+		fbb.push_slot_always(slot(2), z);
+	}
+	fbb.push_slot::<u8>(slot(6), LINESTRING, 0);
+	let geometry = fbb.end_table(start);
+
+	let props = fbb.create_vector(&encode_properties(edge_values(edge, extra)));
+	let start = fbb.start_table();
+	fbb.push_slot_always(slot(0), geometry);
+	fbb.push_slot_always(slot(1), props);
+	let feature = fbb.end_table(start);
+	fbb.finish_size_prefixed(feature, None);
+	fbb.finished_data().to_vec()
+}
+
+fn encode_header(envelope: [f64; 4], count: u64, index_node_size: u16, has_z: bool, columns: &[(String, ColType)]) -> Vec<u8> {
+	let mut fbb = FlatBufferBuilder::new();
+	let columns: Vec<_> = columns.iter().map(|(name, t)| {
+		let name = fbb.create_string(name);
+		let start = fbb.start_table();
+		fbb.push_slot_always(slot(0), name);
+		fbb.push_slot::<u8>(slot(1), match t {
+			ColType::Int => COL_LONG,
+			ColType::Float => COL_DOUBLE,
+			ColType::Bool => COL_BOOL,
+			ColType::Text => COL_STRING,
+		}, 0);
+		fbb.end_table(start)
+	}).collect();
+	let columns = fbb.create_vector(&columns);
+
+	let org = fbb.create_string("EPSG");
+	let start = fbb.start_table();
+	fbb.push_slot_always(slot(0), org);
+	fbb.push_slot::<i32>(slot(1), 4326, 0);
+	let crs = fbb.end_table(start);
+
+	let name = fbb.create_string("edges");
+	let envelope = fbb.create_vector(&envelope);
+	let start = fbb.start_table();
+	fbb.push_slot_always(slot(0), name);
+	fbb.push_slot_always(slot(1), envelope);
+	fbb.push_slot::<u8>(slot(2), LINESTRING, 0);
+	fbb.push_slot::<bool>(slot(3), has_z, false);
+	fbb.push_slot_always(slot(7), columns);
+	fbb.push_slot::<u64>(slot(8), count, 0);
+	fbb.push_slot::<u16>(slot(9), index_node_size, 16);
+	fbb.push_slot_always(slot(10), crs);
+	let header = fbb.end_table(start);
+	fbb.finish_size_prefixed(header, None);
+	fbb.finished_data().to_vec()
+}
+
+// 2D Hilbert curve index of a point on a 65535×65535 grid, like in flatbush
+pub fn hilbert(x: u32, y: u32) -> u32 {
+	let mut a = x ^ y;
+	let mut b = 0xFFFF ^ a;
+	let mut c = 0xFFFF ^ (x | y);
+	let mut d = x & (y ^ 0xFFFF);
+
+	let mut aa = a | (b >> 1);
+	let mut bb = (a >> 1) ^ a;
+	let mut cc = ((c >> 1) ^ (b & (d >> 1))) ^ c;
+	let mut dd = ((a & (c >> 1)) ^ (d >> 1)) ^ d;
+
+	a = aa; b = bb; c = cc; d = dd;
+	aa = (a & (a >> 2)) ^ (b & (b >> 2));
+	bb = (a & (b >> 2)) ^ (b & ((a ^ b) >> 2));
+	cc ^= (a & (c >> 2)) ^ (b & (d >> 2));
+	dd ^= (b & (c >> 2)) ^ ((a ^ b) & (d >> 2));
+
+	a = aa; b = bb; c = cc; d = dd;
+	aa = (a & (a >> 4)) ^ (b & (b >> 4));
+	bb = (a & (b >> 4)) ^ (b & ((a ^ b) >> 4));
+	cc ^= (a & (c >> 4)) ^ (b & (d >> 4));
+	dd ^= (b & (c >> 4)) ^ ((a ^ b) & (d >> 4));
+
+	a = aa; b = bb; c = cc; d = dd;
+	cc ^= (a & (c >> 8)) ^ (b & (d >> 8));
+	dd ^= (b & (c >> 8)) ^ ((a ^ b) & (d >> 8));
+
+	a = cc ^ (cc >> 1);
+	b = dd ^ (dd >> 1);
+
+	let mut i0 = x ^ y;
+	let mut i1 = b | (0xFFFF ^ (i0 | a));
+
+	i0 = (i0 | (i0 << 8)) & 0x00FF00FF;
+	i0 = (i0 | (i0 << 4)) & 0x0F0F0F0F;
+	i0 = (i0 | (i0 << 2)) & 0x33333333;
+	i0 = (i0 | (i0 << 1)) & 0x55555555;
+
+	i1 = (i1 | (i1 << 8)) & 0x00FF00FF;
+	i1 = (i1 | (i1 << 4)) & 0x0F0F0F0F;
+	i1 = (i1 | (i1 << 2)) & 0x33333333;
+	i1 = (i1 | (i1 << 1)) & 0x55555555;
+
+	(i1 << 1) | i0
+}
+
+// (start, end) of each tree level in the node array, leaves first. The root is node 0
+pub fn level_bounds(num_items: usize, node_size: usize) -> Vec<(usize, usize)> {
+	let mut n = num_items;
+	let mut num_nodes = n;
+	let mut level_nodes = vec![n];
+	loop {
+		n = n.div_ceil(node_size);
+		num_nodes += n;
+		level_nodes.push(n);
+		if n == 1 { break }
+	}
+	let mut end = num_nodes;
+	level_nodes.iter().map(|size| {
+		end -= size;
+		(end, end + size)
+	}).collect()
+}
+
+// packed Hilbert R-tree: nodes are [min x, min y, max x, max y] and offset, which is
+// the byte offset of a feature in leaves, or index of the first child node otherwise
+pub fn packed_rtree(leaves: &[([f64; 4], u64)], node_size: usize) -> Vec<([f64; 4], u64)> {
+	let levels = level_bounds(leaves.len(), node_size);
+	let mut nodes = vec![([f64::MAX, f64::MAX, f64::MIN, f64::MIN], 0); levels[0].1];
+	nodes[levels[0].0..levels[0].1].copy_from_slice(leaves);
+	for w in levels.windows(2) {
+		let (child, parent) = (w[0], w[1]);
+		for (i, pos) in (child.0..child.1).step_by(node_size).enumerate() {
+			let bb = nodes[pos..(pos + node_size).min(child.1)].iter().fold(nodes[parent.0 + i].0, |b, n| extend_bbox(b, n.0));
+			nodes[parent.0 + i] = (bb, pos as u64);
+		}
+	}
+	nodes
+}
+
+// FlatGeobuf needs the feature count and the index before features, so they are kept in memory till close
+pub struct FgbWriter {
+	wr: BufWriter<File>,
+	features: Vec<([f64; 4], Vec<u8>)>,
+	has_z: bool,
+	columns: Vec<(String, ColType)>,
+}
+
+impl FgbWriter {
+	pub fn new(path: &str, extra: &[(String, ColType)]) -> Result<Self, Box<dyn Error>> {
+		Ok(Self { wr: BufWriter::new(File::create(path)?), features: vec![], has_z: false, columns: edge_columns(extra) })
+	}
+}
+
+impl EdgeWriter for FgbWriter {
+	fn write(&mut self, edge: &Edge) -> Result<(), Box<dyn Error>> {
+		self.has_z |= edge.WKT_Z.is_some();
+		self.features.push((bbox(&edge.WKT), encode_feature(edge, self.columns.len() - EDGE_COLUMNS.len())));
+		Ok(())
+	}
+
+	fn close(mut self: Box<Self>) -> Result<(), Box<dyn Error>> {
+		let envelope = self.features.iter().map(|f| f.0).reduce(extend_bbox).unwrap_or([0.0; 4]);
+		let (w, h) = (envelope[2] - envelope[0], envelope[3] - envelope[1]);
+		let scale = |v: f64, min: f64, size: f64| if size > 0.0 { (65535.0 * (v - min) / size) as u32 } else { 0 };
+		self.features.sort_by_cached_key(|(b, _)| hilbert(
+			scale((b[0] + b[2]) / 2.0, envelope[0], w),
+			scale((b[1] + b[3]) / 2.0, envelope[1], h)));
+
+		let node_size = if self.features.is_empty() { 0 } else { NODE_SIZE as u16 };
+		self.wr.write_all(&MAGIC)?;
+		self.wr.write_all(&encode_header(envelope, self.features.len() as u64, node_size, self.has_z, &self.columns))?;
+		if !self.features.is_empty() {
+			let mut offset = 0;
+			let leaves: Vec<([f64; 4], u64)> = self.features.iter().map(|(b, f)| {
+				let leaf = (*b, offset);
+				offset += f.len() as u64;
+				leaf
+			}).collect();
+			for (b, o) in packed_rtree(&leaves, NODE_SIZE) {
+				for v in b {
+					self.wr.write_all(&v.to_le_bytes())?;
+				}
+				self.wr.write_all(&o.to_le_bytes())?;
+			}
+		}
+		for (_, f) in self.features.iter() {
+			self.wr.write_all(f)?;
+		}
+		Ok(self.wr.flush()?)
+	}
+}
+
+#[cfg(test)]
+mod fgb_tests {
+	use super::*;
+	use crate::output::{create_writer, output_tests::sample_edges};
+	use osmio2::temp::TempFile;
+	use std::convert::TryInto;
+
+	fn f64_at(data: &[u8], pos: usize) -> f64 {
+		f64::from_le_bytes(data[pos..pos + 8].try_into().unwrap())
+	}
+
+	#[test]
+	fn test_fgb() {
+		assert_eq!(level_bounds(40, 16), vec![(4, 44), (1, 4), (0, 1)]);
+		assert_eq!(level_bounds(1, 16), vec![(1, 2), (0, 1)]);
+
+		let tmp = TempFile::new("test.fgb");
+		let path = tmp.path();
+		let mut wr = create_writer(&path, None, &[]).unwrap();
+		for e in sample_edges().iter() {
+			wr.write(e).unwrap();
+		}
+		wr.close().unwrap();
+
+		let data = std::fs::read(&path).unwrap();
+		assert_eq!(data[..8], MAGIC);
+		let header_size = u32::from_le_bytes(data[8..12].try_into().unwrap()) as usize;
+		let index = 12 + header_size;
+		// root node covers all the features
+		let root = [f64_at(&data, index), f64_at(&data, index + 8), f64_at(&data, index + 16), f64_at(&data, index + 24)];
+		let expected = sample_edges().iter().map(|e| bbox(&e.WKT)).reduce(extend_bbox).unwrap();
+		assert_eq!(root, expected);
+
+		// the first leaf points to the first feature, which ends where the second leaf points
+		let features = index + 44 * 40;
+		let leaf = index + 4 * 40;
+		assert_eq!(u64::from_le_bytes(data[leaf + 32..leaf + 40].try_into().unwrap()), 0);
+		let first_size = u32::from_le_bytes(data[features..features + 4].try_into().unwrap()) as u64 + 4;
+		assert_eq!(u64::from_le_bytes(data[leaf + 72..leaf + 80].try_into().unwrap()), first_size);
+		let total: usize = sample_edges().iter().map(|e| encode_feature(e, 0).len()).sum();
+		assert_eq!(data.len(), features + total);
+	}
+}
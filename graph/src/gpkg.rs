@@ -0,0 +1,216 @@
+use geo::LineString;
+use rusqlite::{Connection, params_from_iter, types::Value as SqlValue};
+use std::error::Error;
+
+use crate::Edge;
+use crate::output::{EdgeWriter, ColType, Value, edge_columns, edge_values, points_z, bbox, extend_bbox};
+
+// https://www.geopackage.org/spec130/
+const TABLE: &str = "edges";
+const SRS_ID: i32 = 4326;
+
+const WGS84_WKT: &str = "GEOGCS[\"WGS 84\",DATUM[\"WGS_1984\",SPHEROID[\"WGS 84\",6378137,298.257223563,AUTHORITY[\"EPSG\",\"7030\"]],AUTHORITY[\"EPSG\",\"6326\"]],PRIMEM[\"Greenwich\",0,AUTHORITY[\"EPSG\",\"8901\"]],UNIT[\"degree\",0.0174532925199433,AUTHORITY[\"EPSG\",\"9122\"]],AUTHORITY[\"EPSG\",\"4326\"]]";
+
+const CREATE_META: &str = "
+	PRAGMA application_id = 1196444487;
+	PRAGMA user_version = 10300;
+	CREATE TABLE gpkg_spatial_ref_sys (
+		srs_name TEXT NOT NULL, srs_id INTEGER PRIMARY KEY, organization TEXT NOT NULL,
+		organization_coordsys_id INTEGER NOT NULL, definition TEXT NOT NULL, description TEXT);
+	CREATE TABLE gpkg_contents (
+		table_name TEXT NOT NULL PRIMARY KEY, data_type TEXT NOT NULL, identifier TEXT UNIQUE, description TEXT DEFAULT '',
+		last_change DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ','now')),
+		min_x DOUBLE, min_y DOUBLE, max_x DOUBLE, max_y DOUBLE,
+		srs_id INTEGER, CONSTRAINT fk_gc_r_srs_id FOREIGN KEY (srs_id) REFERENCES gpkg_spatial_ref_sys(srs_id));
+	CREATE TABLE gpkg_geometry_columns (
+		table_name TEXT NOT NULL, column_name TEXT NOT NULL, geometry_type_name TEXT NOT NULL,
+		srs_id INTEGER NOT NULL, z TINYINT NOT NULL, m TINYINT NOT NULL,
+		CONSTRAINT pk_geom_cols PRIMARY KEY (table_name, column_name),
+		CONSTRAINT fk_gc_tn FOREIGN KEY (table_name) REFERENCES gpkg_contents(table_name),
+		CONSTRAINT fk_gc_srs FOREIGN KEY (srs_id) REFERENCES gpkg_spatial_ref_sys (srs_id));
+	CREATE TABLE gpkg_extensions (
+		table_name TEXT, column_name TEXT, extension_name TEXT NOT NULL, definition TEXT NOT NULL, scope TEXT NOT NULL,
+		CONSTRAINT ge_tce UNIQUE (table_name, column_name, extension_name));
+";
+
+// triggers that keep the spatial index up to date when the file is edited (e.g. in QGIS)
+const RTREE_TRIGGERS: &str = "
+	CREATE TRIGGER rtree_edges_geom_insert AFTER INSERT ON edges WHEN (new.geom NOT NULL AND NOT ST_IsEmpty(NEW.geom))
+	BEGIN
+		INSERT OR REPLACE INTO rtree_edges_geom VALUES (NEW.fid, ST_MinX(NEW.geom), ST_MaxX(NEW.geom), ST_MinY(NEW.geom), ST_MaxY(NEW.geom));
+	END;
+	CREATE TRIGGER rtree_edges_geom_update1 AFTER UPDATE OF geom ON edges
+		WHEN OLD.fid = NEW.fid AND (NEW.geom NOTNULL AND NOT ST_IsEmpty(NEW.geom))
+	BEGIN
+		INSERT OR REPLACE INTO rtree_edges_geom VALUES (NEW.fid, ST_MinX(NEW.geom), ST_MaxX(NEW.geom), ST_MinY(NEW.geom), ST_MaxY(NEW.geom));
+	END;
+	CREATE TRIGGER rtree_edges_geom_update2 AFTER UPDATE OF geom ON edges
+		WHEN OLD.fid = NEW.fid AND (NEW.geom ISNULL OR ST_IsEmpty(NEW.geom))
+	BEGIN
+		DELETE FROM rtree_edges_geom WHERE id = OLD.fid;
+	END;
+	CREATE TRIGGER rtree_edges_geom_update3 AFTER UPDATE ON edges
+		WHEN OLD.fid != NEW.fid AND (NEW.geom NOTNULL AND NOT ST_IsEmpty(NEW.geom))
+	BEGIN
+		DELETE FROM rtree_edges_geom WHERE id = OLD.fid;
+		INSERT OR REPLACE INTO rtree_edges_geom VALUES (NEW.fid, ST_MinX(NEW.geom), ST_MaxX(NEW.geom), ST_MinY(NEW.geom), ST_MaxY(NEW.geom));
+	END;
+	CREATE TRIGGER rtree_edges_geom_update4 AFTER UPDATE ON edges
+		WHEN OLD.fid != NEW.fid AND (NEW.geom ISNULL OR ST_IsEmpty(NEW.geom))
+	BEGIN
+		DELETE FROM rtree_edges_geom WHERE id IN (OLD.fid, NEW.fid);
+	END;
+	CREATE TRIGGER rtree_edges_geom_delete AFTER DELETE ON edges WHEN old.geom NOT NULL
+	BEGIN
+		DELETE FROM rtree_edges_geom WHERE id = OLD.fid;
+	END;
+";
+
+// GeoPackage geometry: header with envelope, then WKB, with heights if `z` is given
+pub fn gpkg_geometry(ls: &LineString, z: Option<&[(f64, f64, f64)]>, bb: [f64; 4]) -> Vec<u8> {
+	let mut buf = Vec::with_capacity(8 + 32 + 9 + ls.0.len() * 24);
+	buf.extend_from_slice(b"GP");
+	buf.push(0); // version
+	buf.push(0b0000_0011); // little endian, envelope [minx, maxx, miny, maxy]
+	buf.extend_from_slice(&SRS_ID.to_le_bytes());
+	for v in [bb[0], bb[2], bb[1], bb[3]] {
+		buf.extend_from_slice(&v.to_le_bytes());
+	}
+	buf.push(1); // WKB little endian
+	match z {
+		Some(pts) => {
+			buf.extend_from_slice(&1002u32.to_le_bytes()); // LineString Z
+			buf.extend_from_slice(&(pts.len() as u32).to_le_bytes());
+			for (x, y, z) in pts.iter() {
+				for v in [x, y, z] {
+					buf.extend_from_slice(&v.to_le_bytes());
+				}
+			}
+		},
+		None => {
+			buf.extend_from_slice(&2u32.to_le_bytes()); // LineString
+			buf.extend_from_slice(&(ls.0.len() as u32).to_le_bytes());
+			for c in ls.0.iter() {
+				buf.extend_from_slice(&c.x.to_le_bytes());
+				buf.extend_from_slice(&c.y.to_le_bytes());
+			}
+		},
+	}
+	buf
+}
+
+pub struct GpkgWriter {
+	conn: Connection,
+	extra: usize,
+	insert_sql: String,
+	extent: Option<[f64; 4]>,
+	// if there were geometries with and without heights
+	has_z: (bool, bool),
+}
+
+impl GpkgWriter {
+	pub fn new(path: &str, extra: &[(String, ColType)]) -> Result<Self, Box<dyn Error>> {
+		if std::path::Path::new(path).exists() {
+			std::fs::remove_file(path)?;
+		}
+		let conn = Connection::open(path)?;
+		conn.execute_batch(CREATE_META)?;
+		conn.execute("INSERT INTO gpkg_spatial_ref_sys VALUES ('Undefined cartesian SRS', -1, 'NONE', -1, 'undefined', NULL),
+			('Undefined geographic SRS', 0, 'NONE', 0, 'undefined', NULL),
+			('WGS 84 geodetic', 4326, 'EPSG', 4326, ?1, 'longitude/latitude coordinates in decimal degrees on the WGS 84 spheroid')", [WGS84_WKT])?;
+
+		let columns = edge_columns(extra);
+		let cols: Vec<String> = columns.iter().map(|(name, t)| format!("\"{}\" {}", name, match t {
+			ColType::Int => "INTEGER",
+			ColType::Float => "REAL",
+			ColType::Bool => "BOOLEAN",
+			ColType::Text => "TEXT",
+		})).collect();
+		conn.execute_batch(&format!("
+			CREATE TABLE {t} (fid INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL, geom LINESTRING, {cols});
+			CREATE VIRTUAL TABLE rtree_{t}_geom USING rtree(id, minx, maxx, miny, maxy);
+			INSERT INTO gpkg_contents (table_name, data_type, identifier, srs_id) VALUES ('{t}', 'features', '{t}', {srs});
+			INSERT INTO gpkg_geometry_columns VALUES ('{t}', 'geom', 'LINESTRING', {srs}, 0, 0);
+			INSERT INTO gpkg_extensions VALUES ('{t}', 'geom', 'gpkg_rtree_index', 'http://www.geopackage.org/spec120/#extension_rtree', 'write-only');
+			BEGIN;",
+			t = TABLE, cols = cols.join(", "), srs = SRS_ID))?;
+
+		let names: Vec<String> = columns.iter().map(|(n, _)| format!("\"{}\"", n)).collect();
+		let marks: Vec<String> = (0..=columns.len()).map(|i| format!("?{}", i + 1)).collect();
+		let insert_sql = format!("INSERT INTO {} (geom, {}) VALUES ({})", TABLE, names.join(", "), marks.join(", "));
+		Ok(Self { conn, extra: extra.len(), insert_sql, extent: None, has_z: (false, false) })
+	}
+}
+
+impl EdgeWriter for GpkgWriter {
+	fn write(&mut self, edge: &Edge) -> Result<(), Box<dyn Error>> {
+		let bb = bbox(&edge.WKT);
+		self.extent = Some(self.extent.map_or(bb, |e| extend_bbox(e, bb)));
+
+		let z = points_z(edge);
+		if z.is_some() { self.has_z.1 = true } else { self.has_z.0 = true }
+
+		let values = std::iter::once(SqlValue::Blob(gpkg_geometry(&edge.WKT, z, bb)))
+			.chain(edge_values(edge, self.extra).into_iter().map(|v| match v {
+				Value::Int(i) => SqlValue::Integer(i),
+				Value::Float(f) => SqlValue::Real(f),
+				Value::Bool(b) => SqlValue::Integer(b as i64),
+				Value::Text(s) => SqlValue::Text(s),
+				Value::Null => SqlValue::Null,
+			}));
+		self.conn.prepare_cached(&self.insert_sql)?.execute(params_from_iter(values))?;
+		let fid = self.conn.last_insert_rowid();
+		self.conn.prepare_cached("INSERT INTO rtree_edges_geom VALUES (?1, ?2, ?3, ?4, ?5)")?
+			.execute(rusqlite::params![fid, bb[0], bb[2], bb[1], bb[3]])?;
+		Ok(())
+	}
+
+	fn close(self: Box<Self>) -> Result<(), Box<dyn Error>> {
+		if let Some(e) = self.extent {
+			self.conn.execute("UPDATE gpkg_contents SET min_x = ?1, min_y = ?2, max_x = ?3, max_y = ?4 WHERE table_name = ?5",
+				rusqlite::params![e[0], e[1], e[2], e[3], TABLE])?;
+		}
+		// z: 0 prohibited, 1 mandatory, 2 optional
+		let z = match self.has_z { (_, false) => 0, (false, true) => 1, (true, true) => 2 };
+		self.conn.execute("UPDATE gpkg_geometry_columns SET z = ?1 WHERE table_name = ?2", rusqlite::params![z, TABLE])?;
+		self.conn.execute_batch("COMMIT;")?;
+		self.conn.execute_batch(RTREE_TRIGGERS)?;
+		self.conn.close().map_err(|(_, e)| e)?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod gpkg_tests {
+	use super::*;
+	use crate::output::{create_writer, output_tests::sample_edges};
+	use osmio2::temp::TempFile;
+
+	#[test]
+	fn test_gpkg() {
+		let tmp = TempFile::new("test.gpkg");
+		let path = tmp.path();
+		// twice, to check that an existing file is replaced
+		for _ in 0..2 {
+			let mut wr = create_writer(&path, None, &[]).unwrap();
+			for e in sample_edges().iter() {
+				wr.write(e).unwrap();
+			}
+			wr.close().unwrap();
+		}
+
+		let conn = Connection::open(&path).unwrap();
+		let count: i64 = conn.query_row("SELECT count(*) FROM edges", [], |r| r.get(0)).unwrap();
+		let indexed: i64 = conn.query_row("SELECT count(*) FROM rtree_edges_geom WHERE minx >= 83.02 AND maxx <= 83.051", [], |r| r.get(0)).unwrap();
+		assert_eq!((count, indexed), (40, 12));
+
+		let (node1, maxspeed, bike, tags, geom): (i64, Option<i64>, bool, String, Vec<u8>) = conn.query_row(
+			"SELECT node1, maxspeed, bike, tags, geom FROM edges WHERE id = 6", [], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?))).unwrap();
+		assert_eq!((node1, maxspeed, bike, tags.as_str()), (50, None, true, "name=Ленина"));
+		assert_eq!(&geom[..2], b"GP");
+		assert_eq!(geom.len(), 8 + 32 + 9 + 3 * 16);
+
+		let max_x: f64 = conn.query_row("SELECT max_x FROM gpkg_contents", [], |r| r.get(0)).unwrap();
+		assert!((max_x - 83.07).abs() < 1e-9);
+	}
+}
@@ -0,0 +1,188 @@
+use osmio2::objects::{NodeId, Tags, Way};
+use std::{
+	collections::{HashMap, HashSet},
+	error::Error,
+	sync::Arc,
+};
+
+use crate::{Edge, NodeChain, Profile};
+use crate::vertex::RoutingNodes;
+
+/* Indoor levels, with the profile's `indoor` rule. Ways with `level=*` (or `layer=*` if they're indoor=*) on different levels
+are not joined at a node they share, unless a connector is there: steps, elevator or ramp ways, ways on several levels
+(`level=0;1`), or an elevator node. Such a node becomes a vertex per level: the lowest level keeps the node id,
+the other ones get ids from level_node_id. Ways without a level (outdoor footways, doors to the street) end at the node id,
+so they join the lowest level. */
+
+// vertice of a node's levels are -(node * 64 + k), k < MAX_LEVELS; sidewalk corners use the rest of the range
+pub const MAX_LEVELS: usize = 32;
+
+// `-1;0`, `0-2` (a range, its ends) -> sorted levels
+pub fn parse_levels(v: &str) -> Vec<f64> {
+	let mut res: Vec<f64> = v.split(';').flat_map(|part| {
+		let part = part.trim();
+		// a minus after the first char separates a range: `0-2`, `-2--1`
+		match part.char_indices().skip(1).find(|(_, c)| *c == '-') {
+			Some((i, _)) => vec![part[..i].trim().parse().ok(), part[i + 1..].trim().parse().ok()],
+			None => vec![part.parse().ok()],
+		}
+	}).flatten().filter(|l: &f64| l.is_finite()).collect();
+	res.sort_by(|a, b| a.partial_cmp(b).unwrap());
+	res.dedup();
+	res
+}
+
+// normalized `level`, or `layer` of indoor ways, e.g. `0;1`; empty if there's none
+pub fn level_of(tags: &Tags) -> Arc<str> {
+	let indoor = tags.get("indoor").is_some_and(|v| &**v != "no");
+	let levels = match (tags.get("level"), tags.get("layer")) {
+		(Some(l), _) => parse_levels(l),
+		(None, Some(l)) if indoor => parse_levels(l),
+		_ => vec![],
+	};
+	Arc::from(levels.iter().map(|l| l.to_string()).collect::<Vec<_>>().join(";").as_str())
+}
+
+// the level of a way that is on one level only
+pub fn single_level(level: &str) -> Option<f64> {
+	if level.contains(';') { None } else { level.parse().ok() }
+}
+
+// ways that join the levels they touch
+pub fn is_connector(tags: &Tags, level: &str) -> bool {
+	tags.get("highway").is_some_and(|v| matches!(&**v, "steps" | "elevator"))
+		|| tags.get("ramp").is_some_and(|v| &**v == "yes")
+		|| level.contains(';')
+}
+
+// vertex of a node on its k-th level, negative to stay out of the range of OSM ids;
+// levels above MAX_LEVELS share the top vertex
+pub fn level_node_id(node: NodeId, k: usize) -> NodeId {
+	NodeId(-(node.0 * 64 + k.min(MAX_LEVELS - 1) as i64))
+}
+
+#[derive(Debug, Default)]
+pub struct NodeLevels {
+	// levels of the ways that aren't connectors, at each node of theirs
+	levels: HashMap<NodeId, Vec<f64>>,
+	// nodes of connectors and elevator nodes
+	joined: HashSet<NodeId>,
+}
+
+impl NodeLevels {
+	// ways of the profile, and elevators from the routing nodes
+	pub fn read<I, E>(ways: I, profile: &Profile, routing_nodes: &RoutingNodes) -> Result<Self, Box<dyn Error>>
+	where I: Iterator<Item = Result<Way, E>>, E: Error + 'static {
+		let mut res = Self::default();
+		for way in ways {
+			let way = way?;
+			if let Some(road) = profile.road(&way.tags) {
+				res.add_way(&way, &road.level);
+			}
+		}
+		Ok(res.finish(routing_nodes))
+	}
+
+	// after all the ways are added
+	pub fn finish(mut self, routing_nodes: &RoutingNodes) -> Self {
+		self.joined.extend(routing_nodes.iter().filter(|(_, rn)| rn.highway.as_deref() == Some("elevator")).map(|(n, _)| *n));
+		println!("nodes on several levels: {}", self.levels.keys().filter(|n| self.split_levels(n).is_some()).count());
+		self
+	}
+
+	pub fn add_way(&mut self, way: &Way, level: &str) {
+		let nodes = way.nodes.iter().map(|n| NodeId(*n));
+		if is_connector(&way.tags, level) {
+			self.joined.extend(nodes);
+		} else if let Some(l) = single_level(level) {
+			for n in nodes {
+				let ls = self.levels.entry(n).or_default();
+				if !ls.contains(&l) {
+					ls.push(l);
+					ls.sort_by(|a, b| a.partial_cmp(b).unwrap());
+				}
+			}
+		}
+	}
+
+	// levels of a node that is split into a vertex per level
+	fn split_levels(&self, n: &NodeId) -> Option<&Vec<f64>> {
+		self.levels.get(n).filter(|ls| ls.len() > 1 && !self.joined.contains(n))
+	}
+
+	// vertex of a chain's end, for the chain's level
+	pub fn vertex(&self, n: NodeId, level: &str) -> NodeId {
+		match (self.split_levels(&n), single_level(level)) {
+			(Some(ls), Some(l)) => match ls.iter().position(|x| *x == l) {
+				Some(k) if k > 0 => level_node_id(n, k),
+				_ => n,
+			},
+			_ => n,
+		}
+	}
+
+	// level of a chain's end: the chain's own, or of the ways at the node if the chain is a connector or has no level
+	pub fn level_at(&self, n: &NodeId, level: &str) -> Option<f64> {
+		single_level(level).or_else(|| match self.levels.get(n) {
+			Some(ls) if ls.len() == 1 => Some(ls[0]),
+			_ => None,
+		})
+	}
+
+	// vertice and their levels for the edge built from the chain
+	pub fn apply(&self, row: &mut Edge, chain: &NodeChain) {
+		let [a, b] = chain.ends();
+		row.node1 = self.vertex(a, &chain.level);
+		row.node2 = self.vertex(b, &chain.level);
+		row.level1 = self.level_at(&a, &chain.level);
+		row.level2 = self.level_at(&b, &chain.level);
+	}
+}
+
+#[cfg(test)]
+mod levels_tests {
+	use super::*;
+	use crate::tags;
+	use osmio2::objects::OsmElementAttrs;
+
+	fn way(id: i64, nodes: &[i64], t: &[(&str, &str)]) -> Way {
+		let mut attrs = OsmElementAttrs::new();
+		attrs.id = id;
+		Way { attrs, nodes: nodes.to_vec(), tags: tags(t) }
+	}
+
+	#[test]
+	fn test_levels() {
+		assert_eq!(parse_levels("1;-1; 0"), vec![-1.0, 0.0, 1.0]);
+		assert_eq!(parse_levels("0-2"), vec![0.0, 2.0]);
+		assert_eq!(parse_levels("-2--1"), vec![-2.0, -1.0]);
+		assert_eq!(parse_levels("roof"), Vec::<f64>::new());
+		assert_eq!(&*level_of(&tags(&[("level", "0.5")])), "0.5");
+		assert_eq!(&*level_of(&tags(&[("layer", "1")])), "");
+		assert_eq!(&*level_of(&tags(&[("indoor", "corridor"), ("layer", "-1")])), "-1");
+		assert!(is_connector(&tags(&[("highway", "footway")]), "0;1"));
+		assert!(!is_connector(&tags(&[("highway", "footway")]), "1"));
+
+		// corridors on levels 0 and 1 cross at 2 and meet at 4, steps from 5 on level 0 to 4
+		let mut nl = NodeLevels::default();
+		nl.add_way(&way(1, &[1, 2, 3, 4], &[("indoor", "corridor")]), "0");
+		nl.add_way(&way(2, &[10, 2, 11, 4], &[("indoor", "corridor")]), "1");
+		nl.add_way(&way(3, &[5, 4], &[("highway", "steps")]), "");
+		nl.add_way(&way(4, &[1, 5], &[("indoor", "corridor")]), "0");
+		assert_eq!((nl.vertex(NodeId(2), "0"), nl.vertex(NodeId(2), "1")), (NodeId(2), level_node_id(NodeId(2), 1)));
+		// a way without a level joins the lowest one
+		assert_eq!(nl.vertex(NodeId(2), ""), NodeId(2));
+		assert_eq!(nl.vertex(NodeId(4), "1"), NodeId(4));
+		assert_eq!(nl.vertex(NodeId(3), "0"), NodeId(3));
+		assert_eq!((nl.level_at(&NodeId(5), ""), nl.level_at(&NodeId(4), "")), (Some(0.0), None));
+		assert_eq!(nl.level_at(&NodeId(4), "1"), Some(1.0));
+
+		// ids of levels stay below the ones of sidewalk corners
+		for l in 0..40 {
+			nl.add_way(&way(100 + l, &[20, 21], &[("indoor", "corridor")]), &l.to_string());
+		}
+		assert_eq!(nl.vertex(NodeId(20), "39"), level_node_id(NodeId(20), MAX_LEVELS - 1));
+		assert_eq!(nl.vertex(NodeId(20), "39"), nl.vertex(NodeId(20), "31"));
+		assert_eq!(level_node_id(NodeId(20), 100), NodeId(-(20 * 64 + 31)));
+	}
+}
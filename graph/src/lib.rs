@@ -4,31 +4,177 @@ use serde::{Serialize, Deserialize};
 use osmio2::serialize_wkt;
 use osmio2::{
 	reader::OsmXmlReader,
-	objects::{Way, NodeId, WayId}
+	errors::ReadError,
+	objects::{OsmObj, Way, NodeId, WayId}
 };
 
 use std::{collections::{HashSet, HashMap},
 	sync::Arc, error::Error, fmt,
 };
 
+pub use osmtags::{schema, mode, oneway, access, normalize, conditions};
+
+pub mod restrictions;
+pub mod profile;
+pub mod parallel;
+pub mod output;
+pub mod gpkg;
+pub mod fgb;
+pub mod components;
+pub mod single_pass;
+pub mod vertex;
+pub mod dem;
+pub mod elevation;
+pub mod area;
+pub mod network;
+pub mod update;
+pub mod quality;
+pub mod regions;
+pub mod levels;
+pub mod sidewalks;
+pub mod multi_pass;
+
+pub use osmtags::{Mode, OneWay, Directions, Access, MaxSpeed, PerDirection};
+pub use osmtags::schema::sidecar_path;
+pub use profile::{Profile, Road, MergeKey};
+pub use vertex::{RoutingNode, RoutingNodes};
+pub use network::{Category, Network};
+
 #[allow(dead_code, unused_imports)]
 pub type WaysInNodesCounter = HashMap<NodeId, i32>;
 
 pub type NodeCoords = HashMap<NodeId, (f64, f64)>;
 
+// tags from key-value pairs, for tests
+#[cfg(test)]
+pub(crate) fn tags(t: &[(&str, &str)]) -> osmio2::objects::Tags {
+	t.iter().map(|(k, v)| (Arc::from(*k), Arc::from(*v))).collect()
+}
+
 #[allow(non_snake_case)]
 #[derive(Serialize)]
 pub struct Edge {
+	pub id: u64,
 	pub node1: NodeId,
 	pub node2: NodeId,
 	#[serde(with = "serialize_wkt")]
 	pub WKT: LineString,
-	pub category: RoadCat,
+	pub category: Category,
 	pub lanes: u8,
 	pub oneway: OneWay,
 	pub maxspeed: MaxSpeed,
+	pub oneway_bike: OneWay,
+	pub oneway_foot: OneWay,
+	pub oneway_bus: OneWay,
+	pub car: bool,
+	pub bike: bool,
+	pub foot: bool,
+	pub bus: bool,
+	pub speed: u16,
+	pub tags: String,
+	pub maxspeed_forward: MaxSpeed,
+	pub maxspeed_backward: MaxSpeed,
+	pub lanes_forward: u8,
+	pub lanes_backward: u8,
+	pub maxspeed_conditional_forward: String,
+	pub maxspeed_conditional_backward: String,
+	pub ascent: Option<f64>,
+	pub descent: Option<f64>,
+	pub max_grade: Option<f64>,
+	// edge across a pedestrian area
+	pub area: bool,
+	pub network: Network,
+	// railway attributes
+	pub service: String,
+	pub usage: String,
+	pub electrified: String,
+	pub gauge: String,
+	// OSM ways with node ranges, see way_parts_str, and all node ids, `;`-separated
+	pub ways: String,
+	pub nodes: String,
+	// conditional restrictions, `value @ (condition)`, see conditions.rs
+	pub oneway_conditional: String,
+	pub car_conditional: String,
+	pub bike_conditional: String,
+	pub foot_conditional: String,
+	pub bus_conditional: String,
+	// level=* of the way, and levels of node1 and node2, see levels.rs
+	pub level: String,
+	pub level1: Option<f64>,
+	pub level2: Option<f64>,
+	// lanes:conditional and by direction, `value @ (condition)`
+	pub lanes_conditional: String,
+	pub lanes_conditional_forward: String,
+	pub lanes_conditional_backward: String,
+	// from layer, bridge and tunnel of the way
+	pub layer: i32,
+	// profile's attributes, written after the other columns
+	#[serde(skip)]
+	pub attrs: Vec<output::Value>,
+	// only with --3d
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub WKT_Z: Option<elevation::LineStringZ>,
+}
+
+impl Edge {
+	// row of an edge without heights, `linestring` has the coordinates of its nodes
+	pub fn new(id: u64, edge: &NodeChain, linestring: LineString) -> Self {
+		let ends = edge.ends();
+		Self {
+			id,
+			WKT: linestring,
+			node1: ends[0],
+			node2: ends[1],
+			category: edge.category.clone(),
+			lanes: edge.lanes,
+			oneway: edge.oneway.car,
+			maxspeed: edge.maxspeed,
+			oneway_bike: edge.oneway.bike,
+			oneway_foot: edge.oneway.foot,
+			oneway_bus: edge.oneway.bus,
+			car: edge.access.car,
+			bike: edge.access.bike,
+			foot: edge.access.foot,
+			bus: edge.access.bus,
+			speed: edge.speed,
+			tags: edge.tags.to_string(),
+			maxspeed_forward: edge.maxspeed_dir.forward,
+			maxspeed_backward: edge.maxspeed_dir.backward,
+			lanes_forward: edge.lanes_dir.forward,
+			lanes_backward: edge.lanes_dir.backward,
+			maxspeed_conditional_forward: edge.maxspeed_conditional.forward.to_string(),
+			maxspeed_conditional_backward: edge.maxspeed_conditional.backward.to_string(),
+			ascent: None,
+			descent: None,
+			max_grade: None,
+			area: edge.area,
+			network: edge.category.network(),
+			service: edge.rail.service.to_string(),
+			usage: edge.rail.usage.to_string(),
+			electrified: edge.rail.electrified.to_string(),
+			gauge: edge.rail.gauge.to_string(),
+			ways: way_parts_str(&edge.ways),
+			nodes: edge.nodes.iter().map(|n| n.0.to_string()).collect::<Vec<_>>().join(";"),
+			oneway_conditional: edge.conditions.oneway.to_string(),
+			car_conditional: edge.conditions.car.to_string(),
+			bike_conditional: edge.conditions.bike.to_string(),
+			foot_conditional: edge.conditions.foot.to_string(),
+			bus_conditional: edge.conditions.bus.to_string(),
+			level: edge.level.to_string(),
+			// set by NodeLevels::apply with the indoor rule
+			level1: None,
+			level2: None,
+			lanes_conditional: edge.lanes_conditional.to_string(),
+			lanes_conditional_forward: edge.lanes_dir_conditional.forward.to_string(),
+			lanes_conditional_backward: edge.lanes_dir_conditional.backward.to_string(),
+			layer: edge.layer,
+			attrs: edge.attrs.to_vec(),
+			WKT_Z: None,
+		}
+	}
 }
 
+// set by the `category` of profile's highway rules
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum RoadCat {
 	Motorway,
@@ -47,41 +193,14 @@ pub enum RoadCat {
 	Steps,
 	Path,
 	Track,
-	Bridleway
+	Bridleway,
+	Busway,
+	// made from sidewalk=* of roads, see sidewalks.rs
+	Sidewalk,
+	Crossing,
 }
 
 
-fn parse_road_cat(s: Option<&Arc<str>>) -> Option<RoadCat> {
-	if let Some(s2) = s {
-		match &**s2 {
-			"bridleway"      => Some(RoadCat::Bridleway),
-			"cycleway"       => Some(RoadCat::Cycleway),
-			"footway"        => Some(RoadCat::Footway),
-			"living_street"  => Some(RoadCat::Living),
-			"motorway"       => Some(RoadCat::Motorway),
-			"path"           => Some(RoadCat::Path),
-			"pedestrian"     => Some(RoadCat::Pedestrian),
-			"primary"        => Some(RoadCat::Primary),
-			"primary_link"   => Some(RoadCat::Primary),
-			"residential"    => Some(RoadCat::Residential),
-			"road"           => Some(RoadCat::Road),
-			"secondary"      => Some(RoadCat::Secondary),
-			"secondary_link" => Some(RoadCat::Secondary),
-			"service"        => Some(RoadCat::Service),
-			"steps"          => Some(RoadCat::Steps),
-			"tertiary"       => Some(RoadCat::Tertiary),
-			"tertiary_link"  => Some(RoadCat::Tertiary),
-			"track"          => Some(RoadCat::Track),
-			"trunk"          => Some(RoadCat::Trunk),
-			"trunk_link"     => Some(RoadCat::Trunk),
-			"unclassified"   => Some(RoadCat::Unclassified),
-			_ => None
-		}
-	} else {
-		None
-	}
-}
-
 #[derive(Debug, Clone)]
 pub struct StubError;
 impl Error for StubError {}
@@ -92,109 +211,204 @@ impl fmt::Display for StubError {
 	}
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
-pub enum OneWay { No, Forward, Backward }
 
-impl From<Option<&Arc<str>>> for OneWay {
-	fn from(data: Option<&Arc<str>>) -> Self {
-		data.map(|a| {
-			match &**a {
-				"yes" => Self::Forward,
-				"-1" => Self::Backward,
-				_ => Self::No,
-			}
-		}).unwrap_or(Self::No)
+// part of an OSM way in a chain: indices of its first and last nodes in the way,
+// `from` > `to` if the chain goes against the way
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WayPart {
+	pub way: WayId,
+	pub from: usize,
+	pub to: usize,
+}
+
+impl WayPart {
+	fn reversed(&self) -> Self {
+		Self { way: self.way, from: self.to, to: self.from }
 	}
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
-pub struct MaxSpeed(Option<u16>);
+fn reversed_parts(parts: &[WayPart]) -> impl Iterator<Item = WayPart> + '_ {
+	parts.iter().rev().map(|p| p.reversed())
+}
 
-impl From<Option<&Arc<str>>> for MaxSpeed {
-	fn from(data: Option<&Arc<str>>) -> Self {
-		Self(data.map(|v| {
-			match v.parse::<u16>() {
-				Err(_) => None,
-				Ok(w) => Some(w)
-			}
-		}).unwrap_or(None))
-	}
+// `10:0-3;12:4-2`
+pub fn way_parts_str(parts: &[WayPart]) -> String {
+	parts.iter().map(|p| format!("{}:{}-{}", p.way.0, p.from, p.to)).collect::<Vec<_>>().join(";")
+}
+
+// row of `<output>_ways.csv`, lookup from OSM ways to edges
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WayRow {
+	pub way: WayId,
+	pub edge: u64,
+	pub from: usize,
+	pub to: usize,
 }
 
+impl WayRow {
+	pub fn rows(edge: u64, parts: &[WayPart]) -> impl Iterator<Item = WayRow> + '_ {
+		parts.iter().map(move |p| WayRow { way: p.way, edge, from: p.from, to: p.to })
+	}
+}
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct NodeChain {
-	pub way_id: Option<WayId>,
+	// ways the chain is made of, in the order of its nodes; empty for edges across areas and transfers
+	pub ways: Vec<WayPart>,
 	pub nodes: Vec<NodeId>,
-	pub category: RoadCat,
+	pub category: Category,
 	pub lanes: u8,
-	pub oneway: OneWay,
+	pub oneway: Directions,
 	pub maxspeed: MaxSpeed,
+	pub access: Access,
+	pub speed: u16,
+	pub tags: Arc<str>,
+	pub maxspeed_dir: PerDirection<MaxSpeed>,
+	pub lanes_dir: PerDirection<u8>,
+	pub maxspeed_conditional: PerDirection<Arc<str>>,
+	pub lanes_conditional: Arc<str>,
+	pub lanes_dir_conditional: PerDirection<Arc<str>>,
+	// made across a pedestrian area, see area.rs
+	pub area: bool,
+	pub rail: network::RailAttrs,
+	pub conditions: conditions::Conditions,
+	pub level: Arc<str>,
+	pub layer: i32,
+	pub sidewalk: sidewalks::Sidewalk,
+	pub attrs: Arc<[output::Value]>,
 }
 
 
 impl NodeChain {
-	pub fn new(way_id: i64, nodes: &[i64], category: RoadCat, lanes: u8, oneway: OneWay, maxspeed: MaxSpeed) -> Self {
+	pub fn new(way_id: i64, nodes: &[i64], road: &Road) -> Self {
  		Self {
-			way_id: Some(WayId(way_id)),
+			ways: vec![WayPart { way: WayId(way_id), from: 0, to: nodes.len() - 1 }],
 			nodes: nodes.iter().map(|n| NodeId(*n)).collect(),
-			category, lanes, oneway, maxspeed
+			category: road.category.clone(),
+			lanes: road.lanes,
+			oneway: road.oneway,
+			maxspeed: road.maxspeed,
+			access: road.access,
+			speed: road.speed,
+			tags: road.tags.clone(),
+			maxspeed_dir: road.maxspeed_dir,
+			lanes_dir: road.lanes_dir,
+			maxspeed_conditional: road.maxspeed_conditional.clone(),
+			lanes_conditional: road.lanes_conditional.clone(),
+			lanes_dir_conditional: road.lanes_dir_conditional.clone(),
+			area: false,
+			rail: road.rail.clone(),
+			conditions: road.conditions.clone(),
+			level: road.level.clone(),
+			layer: road.layer,
+			sidewalk: road.sidewalk,
+			attrs: road.attrs.clone(),
 		}
 	}
 
+	// same chain with direction-dependent attributes for the opposite order of nodes (nodes are not reversed)
+	fn oriented(&self, reverse: bool) -> NodeChain {
+		let mut nc = self.clone();
+		if reverse {
+			nc.oneway = self.oneway.reversed();
+			nc.maxspeed_dir = self.maxspeed_dir.reversed();
+			nc.lanes_dir = self.lanes_dir.reversed();
+			nc.maxspeed_conditional = self.maxspeed_conditional.reversed();
+			nc.lanes_dir_conditional = self.lanes_dir_conditional.reversed();
+			nc.conditions = self.conditions.reversed();
+			nc.sidewalk = self.sidewalk.reversed();
+		}
+		nc
+	}
+
+	fn can_merge(&self, other: &NodeChain, merge_if_equal: &[MergeKey]) -> bool {
+		// chains must be oriented the same way, and a road is never merged with a railway or a ferry
+		self.access == other.access
+			&& self.oneway == other.oneway
+			&& self.area == other.area
+			&& self.category.network() == other.category.network()
+			&& self.rail == other.rail
+			&& self.conditions == other.conditions
+			&& self.level == other.level
+			&& self.layer == other.layer
+			&& self.sidewalk == other.sidewalk
+			&& self.attrs == other.attrs
+			&& merge_if_equal.iter().all(|k| match k {
+				MergeKey::Category => self.category == other.category,
+				MergeKey::Lanes => self.lanes == other.lanes && self.lanes_dir == other.lanes_dir
+					&& self.lanes_conditional == other.lanes_conditional && self.lanes_dir_conditional == other.lanes_dir_conditional,
+				MergeKey::Speed => self.speed == other.speed,
+				MergeKey::Maxspeed => self.maxspeed_dir == other.maxspeed_dir && self.maxspeed_conditional == other.maxspeed_conditional,
+				MergeKey::Tags => self.tags == other.tags,
+			})
+	}
+
 	pub fn ends(&self) -> [NodeId; 2] {
-		[self.nodes[0].clone(), self.nodes[self.nodes.len() - 1].clone()]
+		[self.nodes[0], self.nodes[self.nodes.len() - 1]]
 	}
 
-	pub fn couple(&self, other: &NodeChain) -> Option<NodeChain> {
-		if self.oneway != other.oneway || self.category != other.category || self.lanes != other.lanes {
-			return None
-		}
+	// the first way part by way id and node index
+	pub(crate) fn first_part(&self) -> (i64, usize) {
+		self.ways.iter().map(|p| (p.way.0, p.from.min(p.to))).min().unwrap_or((i64::MAX, 0))
+	}
 
+	// the glued chain keeps the direction and attributes of the chain with the first way part,
+	// so it's the same whatever order the parts are glued in (see parallel.rs)
+	pub fn couple(&self, other: &NodeChain, merge_if_equal: &[MergeKey]) -> Option<NodeChain> {
+		if other.first_part() < self.first_part() {
+			return other.couple(self, merge_if_equal)
+		}
 		let se = self.ends();
 		let oe = other.ends();
 		let mine = &self.nodes;
 		let their = &other.nodes;
 
-		let new_nodes:Vec<NodeId> = if se[0] == oe[0] {
-			mine[1..].iter().rev().into_iter().chain(their.iter()).cloned().collect()
-		} else if se[0] == oe[1] {
-			their.iter().chain(mine[1..].iter()).cloned().collect()
-		} else if se[1] == oe[0] {
-			mine.iter().chain(their[1..].iter()).cloned().collect()
+		// when the other chain is reversed to be glued, its direction-dependent attributes are reversed too
+		let (new_nodes, new_ways, rev_their): (Vec<NodeId>, Vec<WayPart>, bool) = if se[1] == oe[0] {
+			(mine.iter().chain(their[1..].iter()).cloned().collect(), self.ways.iter().chain(other.ways.iter()).cloned().collect(), false)
 		} else if se[1] == oe[1] {
-			mine[..mine.len() - 1].iter().chain(their.iter().rev()).cloned().collect()
+			(mine.iter().chain(their[..their.len() - 1].iter().rev()).cloned().collect(), self.ways.iter().cloned().chain(reversed_parts(&other.ways)).collect(), true)
+		} else if se[0] == oe[1] {
+			(their.iter().chain(mine[1..].iter()).cloned().collect(), other.ways.iter().chain(self.ways.iter()).cloned().collect(), false)
+		} else if se[0] == oe[0] {
+			(their[1..].iter().rev().chain(mine.iter()).cloned().collect(), reversed_parts(&other.ways).chain(self.ways.iter().cloned()).collect(), true)
 		} else {
 			return None;
 		};
 
-		Some(NodeChain {
-			way_id: None,
-			nodes: new_nodes,
-			category: self.category.clone(),
-			lanes: self.lanes,
-			oneway: self.oneway,
-			maxspeed: self.maxspeed,
-		})
+		if !self.can_merge(&other.oriented(rev_their), merge_if_equal) {
+			return None
+		}
+
+		Some(NodeChain { ways: new_ways, nodes: new_nodes, ..self.clone() })
 	}
 }
 
 
 pub type VerticeHash = HashSet<NodeId>;
 
-pub struct ChainStorage {
+// `vertice` is shared (e.g. by parallel workers), vertice found while gluing chains go to `added`
+pub struct ChainStorage<'a> {
 	pub edges: HashMap<NodeId, NodeChain>,
-	pub vertice: HashSet<NodeId>
+	pub vertice: &'a VerticeHash,
+	pub added: VerticeHash,
+	pub profile: Profile,
 }
 
-impl ChainStorage {
-	pub fn new(vertice: &VerticeHash) -> ChainStorage {
+impl<'a> ChainStorage<'a> {
+	pub fn new(vertice: &'a VerticeHash, profile: &Profile) -> ChainStorage<'a> {
 		ChainStorage {
 			edges: HashMap::new(),
-			vertice: vertice.clone()
+			vertice,
+			added: HashSet::new(),
+			profile: profile.clone(),
 		}
 	}
 
+	pub fn is_vertex(&self, n: &NodeId) -> bool {
+		self.vertice.contains(n) || self.added.contains(n)
+	}
+
 	pub fn remove(&mut self, nc: &NodeChain) {
 		for e in nc.ends() {
 			self.edges.remove(&e);
@@ -202,26 +416,7 @@ impl ChainStorage {
 	}
 
 	pub fn insert_way(&mut self, way: Way) -> Vec<NodeChain> {
-		let end = way.nodes.len() - 1;
-		let mut prev_i:usize = 0;
-		let mut res: Vec<NodeChain> = vec![];
-		let cat = match parse_road_cat(way.tags.get("highway")) {
-			Some(c) => c,
-			None => return vec![],
-		};
-
-		let ow = OneWay::from(way.tags.get("oneway"));
-		let ms = MaxSpeed::from(way.tags.get("maxspeed"));
-
-		for (cur_i, node) in way.nodes.iter().enumerate() {
-			if (cur_i > prev_i && self.vertice.contains(&NodeId(*node))) || cur_i == end {
-				let new_nc = NodeChain::new(way.attrs.id, &way.nodes[prev_i..cur_i + 1],
-					cat.clone(), 1, ow, ms);
-				res.push(new_nc);
-				prev_i = cur_i;
-			}
-		}
-		res.into_iter().map(|i| self.insert(i)).flatten().collect()
+		split_way(&way, &self.profile, |n| self.is_vertex(n)).into_iter().flat_map(|i| self.insert(i)).collect()
 	}
 
 	pub fn insert(&mut self, nc: NodeChain) -> Vec<NodeChain> {
@@ -234,18 +429,18 @@ impl ChainStorage {
 		let ends = nc.ends();
 
 		// если у пути оба конца -- вершины, он выдаётся обратно
-		if self.vertice.contains(&ends[0]) && self.vertice.contains(&ends[1]) {
+		if self.is_vertex(&ends[0]) && self.is_vertex(&ends[1]) {
 			return vec![nc];
 		}
 
 		// если путь не с вершиной, но приклеился к тому, что лежало внутри, пытаемся вставить получившееся снова. вставляем пока не окажется полным ребром или не будет вставлен.
 		for e in ends {
-			if self.vertice.contains(&e) { continue }
+			if self.is_vertex(&e) { continue }
 			match self.edges.get(&e) { // nc2 causes a borrow here
 				None => {}
 				Some(nc2) => {
 					let nc2_ = (*nc2).clone(); // nc2 causes a borrow, should be cloned
-					match nc.couple(nc2) {
+					match nc.couple(nc2, &self.profile.merge_if_equal) {
 						Some(nc3) => {
 							self.remove(&nc2_);
 							return self.insert(nc3)
@@ -254,8 +449,10 @@ impl ChainStorage {
 							/* if 2 chains could not couple, the node between them is a vertex
 							put it to vertice hashmap and try reinserting the lines
 							(some of them will be returned as full edges, some will be stored) */
-							self.vertice.insert(e);
-							return self.insert(nc).into_iter().chain(self.insert(nc2_).into_iter()).collect()
+							self.added.insert(e);
+							// otherwise nc2 would find itself at its other end
+							self.remove(&nc2_);
+							return self.insert(nc).into_iter().chain(self.insert(nc2_)).collect()
 						}
 					}
 				}
@@ -264,45 +461,117 @@ impl ChainStorage {
 
 		// did not get attached to anything in self.edges, and has dangling ends
 		for e in ends {
-			if !self.vertice.contains(&e) {
+			if !self.is_vertex(&e) {
 				self.edges.insert(e, nc.clone());
 			}
 		}
 		vec![]
 	}
 
+	// chains left with loose ends, each once
+	pub fn dangling(self) -> Vec<NodeChain> {
+		let ChainStorage { edges, vertice, added, .. } = self;
+		edges.into_iter()
+			.filter(|(n, nc)| *n == nc.ends()[0] || vertice.contains(&nc.ends()[0]) || added.contains(&nc.ends()[0]))
+			.map(|(_, nc)| nc)
+			.collect()
+	}
 }
 
 
-pub fn find_vertice(path: &str) -> Result<(VerticeHash, NodeCoords), Box<dyn Error>> {
-	let mut rd = OsmXmlReader::from_path(&path)?;
-	let mut used_nodes = WaysInNodesCounter::new();
-	println!("map ways to find vertice");
-	rd.map_ways(|way| {
-		if parse_road_cat(way.tags.get("highway")).is_some() {
-			for node_id in [&way.nodes, &way.nodes[1..way.nodes.len() - 1]].concat() {
-				*used_nodes.entry(NodeId(node_id)).or_insert(0) += 1;
-			}
+// cuts a way into chains at vertice
+pub fn split_way<V: Fn(&NodeId) -> bool>(way: &Way, profile: &Profile, is_vertex: V) -> Vec<NodeChain> {
+	let end = way.nodes.len() - 1;
+	let mut prev_i:usize = 0;
+	let mut res: Vec<NodeChain> = vec![];
+	let road = match profile.way_road(way) {
+		Some(r) => r,
+		None => return vec![],
+	};
+
+	for (cur_i, node) in way.nodes.iter().enumerate() {
+		if (cur_i > prev_i && is_vertex(&NodeId(*node))) || cur_i == end {
+			let mut new_nc = NodeChain::new(way.attrs.id, &way.nodes[prev_i..cur_i + 1], &road);
+			new_nc.ways[0] = WayPart { way: WayId(way.attrs.id), from: prev_i, to: cur_i };
+			res.push(new_nc);
+			prev_i = cur_i;
 		}
-		Ok(())
-	})?;
-	drop(rd);
-	println!("nodes: {}", used_nodes.len());
-
-	let mut node_coords = NodeCoords::new();
-	let mut rd = OsmXmlReader::from_path(&path)?;
-	println!("map nodes to find vertice");
-	rd.map_nodes(|n| {
-		let nid = NodeId(n.attrs.id);
-		if used_nodes.contains_key(&nid) {
-			node_coords.insert(nid, (n.lon as f64, n.lat as f64));
-		}
-		Ok(())
-	})?;
-	drop(rd);
-	println!("node coords: {}", node_coords.len());
+	}
+	res
+}
+
+// ways of an OSM file, nodes and relations are skipped
+pub fn read_ways(path: &str) -> Result<impl Iterator<Item = Result<Way, ReadError>> + Send, Box<dyn Error>> {
+	let mut rd = OsmXmlReader::from_path(path)?;
+	rd.skip_nodes = true;
+	rd.skip_relations = true;
+	Ok(rd.filter_map(|obj| match obj {
+		Ok(OsmObj::Way(w)) => Some(Ok(w)),
+		Ok(_) => None,
+		Err(e) => Some(Err(e)),
+	}))
+}
 
-	let vertice: VerticeHash = used_nodes.into_iter().filter(|(_, v)| *v != 2).map(|(k, _)| k).collect();
-	Ok((vertice, node_coords))
+// reads ways one by one and calls `emit` for each finished edge
+pub fn build_edges<F>(path: &str, vertice: &VerticeHash, profile: &Profile, emit: F) -> Result<(), Box<dyn Error>>
+where F: FnMut(NodeChain) -> Result<(), Box<dyn Error>> {
+	build_edges_from(read_ways(path)?, vertice, profile, emit)
 }
 
+// same, for ways from any source, e.g. the spool of single-pass build
+pub fn build_edges_from<I, E, F>(ways: I, vertice: &VerticeHash, profile: &Profile, mut emit: F) -> Result<(), Box<dyn Error>>
+where I: Iterator<Item = Result<Way, E>>, E: Error + 'static, F: FnMut(NodeChain) -> Result<(), Box<dyn Error>> {
+	let mut cs = ChainStorage::new(vertice, profile);
+	for way in ways {
+		for edge in cs.insert_way(way?).into_iter() {
+			emit(edge)?;
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod chain_tests {
+	use super::*;
+	use osmio2::objects::{OsmElementAttrs, Tags};
+
+	fn way(id: i64, nodes: &[i64]) -> Way {
+		let mut attrs = OsmElementAttrs::new();
+		attrs.id = id;
+		let mut tags = Tags::new();
+		tags.insert(Arc::from("highway"), Arc::from("residential"));
+		Way { attrs, nodes: nodes.to_vec(), tags }
+	}
+
+	#[test]
+	fn test_way_parts() {
+		let vertice: VerticeHash = [1, 5, 7].iter().map(|n| NodeId(*n)).collect();
+		let mut cs = ChainStorage::new(&vertice, &Profile::default());
+		assert!(cs.insert_way(way(10, &[1, 2, 3])).is_empty());
+		// way 11 is cut at 5, and its tail goes against way 10, so it's reversed when they're glued (way 10 is the first)
+		let chains = cs.insert_way(way(11, &[7, 6, 5, 4, 3]));
+		let parts: Vec<(Vec<i64>, String)> = chains.iter().map(|c| (c.nodes.iter().map(|n| n.0).collect(), way_parts_str(&c.ways))).collect();
+		assert_eq!(parts, vec![
+			(vec![7, 6, 5], "11:0-2".to_string()),
+			(vec![1, 2, 3, 4, 5], "10:0-2;11:4-2".to_string()),
+		]);
+		let rows: Vec<WayRow> = WayRow::rows(2, &chains[1].ways).collect();
+		assert_eq!((rows[1].way, rows[1].edge, rows[1].from, rows[1].to), (WayId(11), 2, 4, 2));
+	}
+
+	#[test]
+	fn test_attributes() {
+		let mut profile = Profile::default();
+		profile.attributes = vec![profile::Attribute { tag: "name".to_string(), kind: output::ColType::Text }];
+		let vertice: VerticeHash = [1, 5].iter().map(|n| NodeId(*n)).collect();
+		let mut cs = ChainStorage::new(&vertice, &profile);
+		let mut named = way(11, &[3, 4, 5]);
+		named.tags.insert(Arc::from("name"), Arc::from("Lenina"));
+		assert!(cs.insert_way(way(10, &[1, 2, 3])).is_empty());
+		// the name changes at 3, so it becomes a vertex
+		let chains = cs.insert_way(named);
+		assert_eq!(chains.len(), 2);
+		let edge = Edge::new(1, &chains[0], LineString::from(vec![(0.0, 0.0), (1.0, 1.0)]));
+		assert_eq!(edge.attrs, vec![output::Value::Text("Lenina".to_string())]);
+	}
+}
@@ -1,63 +1,405 @@
-use osmio2::reader::OsmXmlReader;
 use geo::LineString;
 use csv::Writer;
-use osmgraph::{ChainStorage, find_vertice, Edge};
+use osmgraph::{NodeChain, build_edges, build_edges_from, sidecar_path, Edge, NodeCoords, Profile, RoutingNodes, VerticeHash, WayRow};
+use osmgraph::dem::Dem;
+use osmgraph::area::{area_edges, Area};
+use osmgraph::network::{ferry_edges, Transfers};
+use osmgraph::levels::NodeLevels;
+use osmgraph::sidewalks::{SidewalkRule, Sidewalks};
+use osmgraph::elevation::{elevation_profile, segment, structure_segments, LineStringZ, Structures};
+use osmgraph::parallel::{build_edges_parallel, build_edges_parallel_from, BATCH_SIZE};
+use osmgraph::single_pass::{read_once, NodeStore, WaySpool};
+use osmgraph::multi_pass::{read_passes, MultiPass};
+use osmgraph::vertex::VertexRow;
+use osmgraph::update::{check_updatable, edge_hash, save_state, update_state, EdgeRecord, EdgeStore, StateVertice};
+use osmio2::{reader::OsmXmlReader, objects::{NodeId, Way, WayId}};
+use osmgraph::restrictions::{Restriction, RestrictionIndex, UnresolvedRestriction};
+use osmgraph::output::{create_writer, Format, Value};
+use osmgraph::regions::{RegionIndex, RegionLookup, RegionRings, RegionRow};
 
 use std::{
+	cell::{Cell, RefCell},
+	collections::{HashMap, HashSet},
 	error::Error,
-	path::Path,
+	path::{Path, PathBuf},
+	sync::Arc,
+	thread,
 	time::SystemTime,
 };
 
-fn process_graph(osm_file: &str, output_file: &str) -> Result<(), Box<dyn Error>> {
+// how the input is read: several times, or once with temporary node store and way spool,
+// which may be saved as a state for incremental updates
+enum Passes { Multi, Single, SaveState(PathBuf) }
+
+struct BuildOptions {
+	// by default it's taken from the output file extension
+	format: Option<Format>,
+	threads: usize,
+	passes: Passes,
+	// DEM, and if 3D geometry is written
+	elevation: Option<(Dem, bool)>,
+}
+
+// what is read from the input before building edges, in either kind of passes
+struct PrePass {
+	restrictions: Vec<Restriction>,
+	unresolved: Vec<UnresolvedRestriction>,
+	vertice: VerticeHash,
+	node_coords: NodeCoords,
+	way_nodes: HashMap<WayId, Vec<NodeId>>,
+	routing_nodes: RoutingNodes,
+	// single pass: road ways are read back from the spool instead of the file
+	spool: Option<WaySpool>,
+	areas: Vec<Area>,
+	ferries: Vec<Way>,
+	rings: Vec<RegionRings>,
+	// coordinates of area, ferry and boundary nodes that are not on roads
+	member_coords: NodeCoords,
+	// bridges and tunnels are needed only for elevation
+	structures: Structures,
+	// with the indoor rule, nodes shared by ways on different levels become a vertex per level
+	levels: Option<NodeLevels>,
+	// node store and vertice by tags, for the saved state
+	saved: Option<(NodeStore, VerticeHash)>,
+}
+
+// boundary relations of the profile's admin levels, `node_coords` must have the nodes of their rings
+fn admin_regions(rings: &[RegionRings], profile: &Profile, node_coords: &Arc<NodeCoords>) -> Option<Arc<RegionLookup>> {
+	if profile.admin_levels.is_empty() {
+		return None
+	}
+	let index = RegionIndex::build(&profile.admin_levels, rings, &[node_coords.as_ref()]);
+	Some(Arc::new(RegionLookup { index, coords: node_coords.clone() }))
+}
+
+fn process_graph(osm_file: &str, output_file: &str, profile: &Profile, opts: BuildOptions) -> Result<(), Box<dyn Error>> {
 	let t1 = SystemTime::now();
+	let BuildOptions { format, threads, passes, elevation } = opts;
+	if matches!(passes, Passes::SaveState(_)) {
+		check_updatable(profile, elevation.is_some())?;
+	}
+	let (mut dem, three_d) = match elevation {
+		Some((dem, three_d)) => (Some(dem), three_d),
+		None => (None, false),
+	};
+
+	// in single-pass mode the file is read once, and ways are read back from a spool instead of the file
+	let pre = if !matches!(passes, Passes::Multi) {
+		let mut sp = read_once(OsmXmlReader::from_path(osm_file)?, profile)?;
+		sp.restrictions.retain(|r| r.modes.iter().any(|m| profile.modes.contains(m)));
+		let keep_ways: HashSet<_> = sp.restrictions.iter().flat_map(|r| r.ways().cloned()).collect();
+		let way_nodes = sp.ways.way_nodes(&keep_ways)?;
+		let structures = if dem.is_some() { structure_segments(sp.ways.reader()?)? } else { Structures::new() };
+		let levels = match profile.indoor {
+			Some(_) => Some(NodeLevels::read(sp.ways.reader()?, profile, &sp.routing_nodes)?),
+			None => None,
+		};
+		PrePass {
+			restrictions: sp.restrictions, unresolved: sp.unresolved, vertice: sp.vertice, node_coords: sp.node_coords, way_nodes,
+			routing_nodes: sp.routing_nodes, spool: Some(sp.ways), areas: sp.areas, ferries: sp.ferries, rings: sp.regions,
+			member_coords: sp.member_coords, structures, levels, saved: Some((sp.nodes, sp.tag_vertice)),
+		}
+	} else {
+		// relations, ways and nodes are read once each, ways once more to build edges
+		let mp = read_passes(osm_file, profile, dem.is_some())?;
+		let MultiPass { vertice, node_coords, routing_nodes, restrictions, unresolved, way_nodes, areas, ferries, regions: rings, member_coords, structures, levels } = mp;
+		PrePass { restrictions, unresolved, vertice, node_coords, way_nodes, routing_nodes, spool: None, areas, ferries, rings, member_coords, structures, levels, saved: None }
+	};
+	let PrePass {
+		restrictions, mut unresolved, mut vertice, mut node_coords, way_nodes, routing_nodes, mut spool, areas, ferries, rings, member_coords,
+		structures, levels, saved,
+	} = pre;
+
+	// roads enter pedestrian areas at the road nodes on their rings, roads are split there, and at the ends of ferries
+	// (these and restriction vertice are kept in the state, they can't be found from ways)
+	let area_entries: HashSet<NodeId> = areas.iter().flat_map(|a| a.nodes()).filter(|n| node_coords.contains_key(n)).cloned().collect();
+	let mut extra_vertice: VerticeHash = area_entries.iter().cloned().collect();
+	let ferry_edges = ferry_edges(&ferries, profile);
+	extra_vertice.extend(ferry_edges.iter().flat_map(|e| e.ends()).filter(|n| node_coords.contains_key(n)));
+	node_coords.extend(member_coords);
+	let node_coords = Arc::new(node_coords);
 
-	let (vertice, node_coords) = find_vertice(&osm_file)?;
+	// ways take country defaults from the regions they start in
+	let regions = admin_regions(&rings, profile, &node_coords);
+	let profile = &Profile { regions: regions.clone(), ..profile.clone() };
+
+	// restricted turns must happen at graph vertice, so ends of via node/ways become vertice
+	let mut node_restrictions = vec![];
+	for r in restrictions.iter() {
+		match r.resolve(&way_nodes) {
+			Ok(nr) => node_restrictions.extend(nr),
+			Err(reason) => unresolved.push(UnresolvedRestriction::new(r.id, reason)),
+		}
+	}
+	extra_vertice.extend(node_restrictions.iter().flat_map(|r| r.vertice()));
+	vertice.extend(extra_vertice.iter().cloned());
+	let mut r_index = RestrictionIndex::new(&node_restrictions);
 
 	let t2 = SystemTime::now();
 	println!("{} s, building graph", t2.duration_since(t1)?.as_secs_f32());
 
-	let ppp = Path::new(&output_file);
-	let mut writer = Writer::from_path(ppp)?;
+	let mut writer = create_writer(output_file, format, &profile.extra_columns())?;
 
-	let mut cs = ChainStorage::new(&vertice);
+	let mut edge_id = 0u64;
+	// edges at barriers, signals etc., for the vertex table
+	let mut vertex_edges: HashMap<NodeId, Vec<u64>> = HashMap::new();
+	// OSM ways -> edges
+	let mut way_rows: Vec<WayRow> = vec![];
+	// edges for the state, only the ones built from ways are rebuilt in updates
+	let mut records = match passes { Passes::SaveState(_) => Some(EdgeStore::new()?), _ => None };
+	let from_ways = Cell::new(true);
+	// points of generated sidewalks, see sidewalks.rs
+	let extra_coords = RefCell::new(NodeCoords::new());
+	// regions of edge ends
+	let mut vertex_regions: HashMap<NodeId, Vec<Option<usize>>> = HashMap::new();
 
-	let mut rd = OsmXmlReader::from_path(&osm_file)?;
+	let mut emit = |edge: NodeChain| -> Result<(), Box<dyn Error>> {
+		let extra = extra_coords.borrow();
+		let ee = edge.nodes.iter().filter_map(|nid| node_coords.get(nid).or_else(|| extra.get(nid))).copied().collect::<Vec<(f64, f64)>>();
 
-	rd.map_ways(|w| {
-		for edge in cs.insert_way(w).into_iter() {
-			let ee = edge.nodes.iter().filter_map(|nid| node_coords.get(&nid)).map(|t| t.clone()).collect::<Vec<(f64, f64)>>();
+		// heights are only known if all the points have coordinates
+		let heights = match dem.as_mut().filter(|_| ee.len() == edge.nodes.len()) {
+			Some(dem) => {
+				let on_structure: Vec<bool> = edge.nodes.windows(2).map(|w| structures.contains(&segment(w[0], w[1]))).collect();
+				elevation_profile(dem, &ee, &on_structure)?
+			},
+			None => None,
+		};
+		let wkt_z = if three_d {
+			Some(LineStringZ(ee.iter().enumerate().map(|(i, p)| (p.0, p.1, heights.as_ref().map_or(f64::NAN, |h| h.z[i]))).collect()))
+		} else { None };
 
-			let linestring = LineString::from(ee);
-			let ends = edge.ends();
-			writer.serialize(Edge {
-				WKT: linestring,
-				node1: ends[0],
-				node2: ends[1],
-				category: edge.category,
-				lanes: edge.lanes,
-				oneway: edge.oneway,
-				maxspeed: edge.maxspeed,
-			})?;
+		let linestring = LineString::from(ee);
+		let ends = edge.ends();
+		edge_id += 1;
+		r_index.add_edge(edge_id, &edge.nodes);
+		for end in ends.iter().filter(|n| routing_nodes.contains_key(n)) {
+			vertex_edges.entry(*end).or_default().push(edge_id);
+		}
+		way_rows.extend(WayRow::rows(edge_id, &edge.ways));
+		let mut row = Edge::new(edge_id, &edge, linestring);
+		row.ascent = heights.as_ref().map(|h| h.ascent);
+		row.descent = heights.as_ref().map(|h| h.descent);
+		row.max_grade = heights.as_ref().map(|h| h.max_grade);
+		row.WKT_Z = wkt_z;
+		if let Some(levels) = levels.as_ref() {
+			levels.apply(&mut row, &edge);
 		}
+		if let Some(index) = regions.as_ref().map(|r| &r.index) {
+			let (middle, end_regions, border) = index.edge_regions(&row.WKT);
+			row.attrs.resize(profile.attributes.len(), Value::Null);
+			row.attrs.extend(index.values(&middle));
+			row.attrs.push(Value::Bool(border));
+			for (n, at) in ends.iter().zip(end_regions) {
+				vertex_regions.insert(*n, at);
+			}
+		}
+		if let Some(records) = records.as_mut() {
+			records.add(&EdgeRecord { id: edge_id, hash: edge_hash(&row)?, from_ways: from_ways.get(), nodes: edge.nodes.clone() })?;
+		}
+		writer.write(&row)?;
 		Ok(())
-	})?;
+	};
+	// ferry ends and stations are linked to roads after all the other edges are written
+	let mut transfers = Transfers::new(&routing_nodes);
+	let mut sidewalks = profile.sidewalks.as_ref().map(|rule| Sidewalks::new(rule, &routing_nodes));
+	let mut emit_net = |edge: NodeChain| -> Result<(), Box<dyn Error>> {
+		transfers.add(&edge, &node_coords);
+		if let Some(s) = sidewalks.as_mut() {
+			s.add(&edge, &node_coords);
+		}
+		emit(edge)
+	};
+	match spool.as_mut() {
+		Some(ways) if threads > 1 => build_edges_parallel_from(ways.reader()?, &vertice, profile, threads, BATCH_SIZE, &mut emit_net)?,
+		Some(ways) => build_edges_from(ways.reader()?, &vertice, profile, &mut emit_net)?,
+		None if threads > 1 => build_edges_parallel(osm_file, &vertice, profile, threads, BATCH_SIZE, &mut emit_net)?,
+		None => build_edges(osm_file, &vertice, profile, &mut emit_net)?,
+	}
+	from_ways.set(false);
+	let mut area_count = 0;
+	for area in areas.iter() {
+		if let Some(road) = profile.area_road(&area.tags) {
+			for edge in area_edges(area, &road, &node_coords, &area_entries) {
+				area_count += 1;
+				emit_net(edge)?;
+			}
+		}
+	}
+	println!("edges across pedestrian areas: {}", area_count);
+	for edge in ferry_edges {
+		emit_net(edge)?;
+	}
+	let transfer_edges = transfers.edges(&node_coords);
+	println!("transfers from ferries and stations to roads: {}", transfer_edges.len());
+	for edge in transfer_edges {
+		emit(edge)?;
+	}
+	if let Some(sidewalks) = sidewalks {
+		let (edges, coords) = sidewalks.edges(&node_coords);
+		println!("sidewalks, crossings and links to them: {}", edges.len());
+		extra_coords.borrow_mut().extend(coords);
+		for edge in edges {
+			emit(edge)?;
+		}
+	}
+
+	writer.close()?;
+
+	if let (Passes::SaveState(dir), Some((mut nodes, tags)), Some(ways), Some(records)) = (&passes, saved, spool.as_mut(), records.as_mut()) {
+		save_state(dir, profile, &mut nodes, ways, records, &StateVertice { tags, extra: extra_vertice }, edge_id)?;
+	}
+
+	way_rows.sort_by_key(|r| r.way.0);
+	let mut writer = Writer::from_path(sidecar_path(output_file, "ways"))?;
+	for r in way_rows.iter() {
+		writer.serialize(r)?;
+	}
+
+	let (rows, unresolved2) = r_index.rows(&node_restrictions);
+	unresolved.extend(unresolved2);
+	let mut writer = Writer::from_path(sidecar_path(output_file, "restrictions"))?;
+	for r in rows.iter() {
+		writer.serialize(r)?;
+	}
+	let mut writer = Writer::from_path(sidecar_path(output_file, "unresolved_restrictions"))?;
+	for r in unresolved.iter() {
+		writer.serialize(r)?;
+	}
+	println!("restrictions written: {}, unresolved: {}", rows.len(), unresolved.len());
+
+	let mut ids: Vec<&NodeId> = routing_nodes.keys().collect();
+	ids.sort_by_key(|n| n.0);
+	let mut writer = Writer::from_path(sidecar_path(output_file, "vertices"))?;
+	for id in ids {
+		let edges = vertex_edges.get(id).map(|e| e.as_slice()).unwrap_or(&[]);
+		writer.serialize(VertexRow::new(*id, &routing_nodes[id], node_coords[id], edges))?;
+	}
+	println!("vertices with barriers, signals, crossings and stations: {}", routing_nodes.len());
+
+	if let Some(index) = regions.as_ref().map(|r| &r.index) {
+		let mut writer = Writer::from_path(sidecar_path(output_file, "regions"))?;
+		for r in index.regions.iter() {
+			writer.serialize(RegionRow::new(r))?;
+		}
+		let mut writer = Writer::from_path(sidecar_path(output_file, "vertex_regions"))?;
+		writer.write_record(std::iter::once("node".to_string()).chain(index.levels.iter().map(|l| format!("admin{}", l))))?;
+		let mut ids: Vec<&NodeId> = vertex_regions.keys().collect();
+		ids.sort_by_key(|n| n.0);
+		for id in ids {
+			let at = vertex_regions[id].iter().map(|r| r.map_or(String::new(), |i| index.regions[i].id.to_string()));
+			writer.write_record(std::iter::once(id.0.to_string()).chain(at))?;
+		}
+		println!("admin regions written: {}, vertices: {}", index.regions.len(), vertex_regions.len());
+	}
 
 	let t3 = SystemTime::now();
 	println!("{} s, building graph", t3.duration_since(t2)?.as_secs_f32());
 
 	Ok(())
 }
-
+// applies an osmChange file to a saved state, writes added and changed edges, and the list of changes
+fn update_graph(state_dir: &str, change_file: &str, output_file: &str, format: Option<Format>, profile: &Profile) -> Result<(), Box<dyn Error>> {
+	let t1 = SystemTime::now();
+	let diff = update_state(Path::new(state_dir), change_file, profile)?;
+	let mut writer = create_writer(output_file, format, &profile.extra_columns())?;
+	for row in diff.rows.iter() {
+		writer.write(row)?;
+	}
+	writer.close()?;
+	let mut writer = Writer::from_path(sidecar_path(output_file, "changes"))?;
+	for c in diff.changes.iter() {
+		writer.serialize(c)?;
+	}
+	let mut writer = Writer::from_path(sidecar_path(output_file, "ways"))?;
+	for r in diff.ways.iter() {
+		writer.serialize(r)?;
+	}
+	println!("{} s, updating graph", SystemTime::now().duration_since(t1)?.as_secs_f32());
+	Ok(())
+}
 
 fn main() -> Result<(), Box<dyn Error>> {
-	let args: Vec<_> = std::env::args_os().collect();
-	match args.len() {
-		3 => {
-			process_graph(&args[1].to_str().unwrap(), &args[2].to_str().unwrap())?;
+	let mut args: Vec<String> = std::env::args().collect();
+	// -j N: number of threads, 1 is the sequential builder
+	let threads = match args.iter().position(|a| a == "-j") {
+		Some(i) => {
+			let n = args.get(i + 1).ok_or("-j needs a number of threads")?.parse::<usize>()?;
+			args.drain(i..i + 2);
+			n
+		},
+		None => thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+	};
+	// --single-pass: read the input once, with temporary node store and way spool
+	let single_pass = args.iter().any(|a| a == "--single-pass");
+	args.retain(|a| a != "--single-pass");
+	// --save-state DIR: single pass, and keep what's needed for --update in DIR
+	let passes = match args.iter().position(|a| a == "--save-state") {
+		Some(i) => {
+			let dir = PathBuf::from(args.get(i + 1).ok_or("--save-state needs a directory")?);
+			args.drain(i..i + 2);
+			Passes::SaveState(dir)
+		},
+		None if single_pass => Passes::Single,
+		None => Passes::Multi,
+	};
+	// --update: arguments are STATE_DIR CHANGE.OSC OUTPUT_FILE instead of input and output
+	let update = args.iter().any(|a| a == "--update");
+	args.retain(|a| a != "--update");
+	// --sidewalks: sidewalk edges along roads with sidewalk=* tags, if the profile has no `sidewalks` rule
+	let sidewalks = args.iter().any(|a| a == "--sidewalks");
+	args.retain(|a| a != "--sidewalks");
+	// -f FORMAT: output format, otherwise it's taken from the output file extension
+	let format = match args.iter().position(|a| a == "-f") {
+		Some(i) => {
+			let name = args.get(i + 1).ok_or("-f needs a format name")?;
+			let f = Format::from_name(name).ok_or_else(|| format!("unknown format {}", name))?;
+			args.drain(i..i + 2);
+			Some(f)
+		},
+		None => None,
+	};
+	// --dem PATH[,PATH...]: DEM files or directories with them, --3d: also write 3D geometry
+	let three_d = args.iter().any(|a| a == "--3d");
+	args.retain(|a| a != "--3d");
+	let elevation = match args.iter().position(|a| a == "--dem") {
+		Some(i) => {
+			let paths: Vec<String> = args.get(i + 1).ok_or("--dem needs a file or directory")?.split(',').map(|p| p.to_string()).collect();
+			args.drain(i..i + 2);
+			Some((Dem::open(&paths)?, three_d))
+		},
+		None if three_d => return Err("--3d needs --dem".into()),
+		None => None,
+	};
+	// the update mode has one more file argument
+	let files = if update { 3 } else { 2 };
+	match args.len().saturating_sub(files) {
+		1..=3 => {
+			let mut profile = match args.get(files + 1) {
+				Some(p) => Profile::load(p)?,
+				None => Profile::default(),
+			};
+			if let Some(c) = args.get(files + 2) {
+				profile.country = c.to_uppercase();
+			}
+			if sidewalks {
+				profile.sidewalks.get_or_insert_with(SidewalkRule::default);
+			}
+			println!("profile: {}, country: {}, threads: {}", profile.name, profile.country, threads);
+			if update {
+				update_graph(&args[1], &args[2], &args[3], format, &profile)?;
+			} else {
+				process_graph(&args[1], &args[2], &profile, BuildOptions { format, threads, passes, elevation })?;
+			}
 		}
-		_ => println!("usage: osmgraph INPUT.OSM.PBF OUTPUT_FILE",),
+		_ => println!("usage: osmgraph [-j THREADS] [-f FORMAT] [--single-pass] [--save-state DIR] [--dem PATH[,PATH...] [--3d]] [--sidewalks] INPUT.OSM.PBF OUTPUT_FILE [PROFILE] [COUNTRY_CODE]\n\
+			       osmgraph --update [-f FORMAT] STATE_DIR CHANGE.OSC OUTPUT_FILE [PROFILE] [COUNTRY_CODE]\n\
+			FORMAT is csv, geojson, geojsonseq, gpkg or fgb; by default it's taken from OUTPUT_FILE extension\n\
+			--dem adds ascent, descent and max_grade from .hgt, .asc or .tif files, --3d also writes 3D geometry\n\
+			--sidewalks makes sidewalk edges from sidewalk=* tags of roads, crossings and links to them\n\
+			--save-state keeps the node store, way spool and edges in DIR, --update applies a change to them\n\
+			and writes added and changed edges to OUTPUT_FILE, and all changes to OUTPUT_FILE_changes.csv\n\
+			PROFILE is all (default), car, bike, foot, or a path to a .toml profile",),
 	};
 	Ok(())
 }
-
@@ -0,0 +1,139 @@
+use osmio2::{
+	reader::OsmXmlReader,
+	objects::{OsmObj, Way, NodeId, WayId, RelId},
+};
+use std::{
+	collections::{HashMap, HashSet},
+	error::Error,
+};
+
+use crate::{NodeCoords, Profile, VerticeHash, WaysInNodesCounter, RoutingNode, RoutingNodes};
+use crate::restrictions::{Restriction, UnresolvedRestriction};
+use crate::area::{Area, AreaCollector};
+use crate::network::FerryCollector;
+use crate::regions::{RegionCollector, RegionRings};
+use crate::elevation::{add_structure, Structures};
+use crate::levels::NodeLevels;
+
+/* Multi-pass build: the file is read by object types, relations first, then ways, then nodes, and ways once more
+to build edges. Relations come first, so that member ways (areas, ferries, boundaries) are picked up in the same pass
+as road ways, and their nodes in the same pass as road nodes. */
+
+// like SinglePass without the node store and spool, plus what single-pass build reads back from the spool
+pub struct MultiPass {
+	pub vertice: VerticeHash,
+	pub node_coords: NodeCoords,
+	pub routing_nodes: RoutingNodes,
+	// only of the modes in the profile
+	pub restrictions: Vec<Restriction>,
+	pub unresolved: Vec<UnresolvedRestriction>,
+	// node lists of the ways in restrictions
+	pub way_nodes: HashMap<WayId, Vec<NodeId>>,
+	pub areas: Vec<Area>,
+	pub ferries: Vec<Way>,
+	pub regions: Vec<RegionRings>,
+	pub member_coords: NodeCoords,
+	// bridge and tunnel segments if `with_structures` (for elevation); node levels with the indoor rule
+	pub structures: Structures,
+	pub levels: Option<NodeLevels>,
+}
+
+pub fn read_passes(path: &str, profile: &Profile, with_structures: bool) -> Result<MultiPass, Box<dyn Error>> {
+	let mut restrictions = vec![];
+	let mut unresolved = vec![];
+	let mut areas = AreaCollector::new(profile);
+	let mut ferries = FerryCollector::new(profile);
+	let mut regions = RegionCollector::new(&profile.admin_levels);
+	let mut rd = OsmXmlReader::from_path(path)?;
+	rd.skip_nodes = true;
+	rd.skip_ways = true;
+	println!("map relations to find restrictions and members");
+	for obj in rd {
+		if let OsmObj::Relation(rel) = obj? {
+			areas.relation(&rel);
+			ferries.relation(&rel);
+			regions.relation(&rel);
+			match Restriction::from_relation(&rel) {
+				Ok(r) => restrictions.extend(r),
+				Err(reason) => unresolved.push(UnresolvedRestriction::new(RelId(rel.attrs.id), reason)),
+			}
+		}
+	}
+	restrictions.retain(|r| r.modes.iter().any(|m| profile.modes.contains(m)));
+	println!("restrictions: {}", restrictions.len());
+
+	let keep_ways: HashSet<WayId> = restrictions.iter().flat_map(|r| r.ways().cloned()).collect();
+	let mut way_nodes = HashMap::new();
+	let mut used_nodes = WaysInNodesCounter::new();
+	let mut structures = Structures::new();
+	let mut levels = profile.indoor.as_ref().map(|_| NodeLevels::default());
+	let mut rd = OsmXmlReader::from_path(path)?;
+	rd.skip_nodes = true;
+	rd.skip_relations = true;
+	println!("map ways to find vertice");
+	for obj in rd {
+		let way = match obj? {
+			OsmObj::Way(way) => way,
+			_ => continue,
+		};
+		areas.way(&way);
+		ferries.way(&way);
+		regions.way(&way);
+		if with_structures {
+			add_structure(&mut structures, &way);
+		}
+		if let Some(road) = profile.road(&way.tags) {
+			if keep_ways.contains(&WayId(way.attrs.id)) {
+				way_nodes.insert(WayId(way.attrs.id), way.nodes.iter().map(|n| NodeId(*n)).collect());
+			}
+			for node_id in [&way.nodes, &way.nodes[1..way.nodes.len() - 1]].concat() {
+				*used_nodes.entry(NodeId(node_id)).or_insert(0) += 1;
+			}
+			if let Some(levels) = levels.as_mut() {
+				levels.add_way(&way, &road.level);
+			}
+		}
+	}
+	println!("nodes: {}", used_nodes.len());
+	let regions = if regions.enabled() { regions.finish() } else { vec![] };
+	let (areas, ferries) = (areas.finish(), ferries.finish());
+	let member_nodes: HashSet<NodeId> = areas.iter().flat_map(|a| a.nodes()).cloned()
+		.chain(ferries.iter().flat_map(|w| w.nodes.iter().map(|n| NodeId(*n))))
+		.chain(regions.iter().flat_map(|r| r.nodes()).cloned())
+		.filter(|n| !used_nodes.contains_key(n))
+		.collect();
+
+	let mut node_coords = NodeCoords::new();
+	let mut member_coords = NodeCoords::new();
+	let mut vertex_nodes = VerticeHash::new();
+	let mut routing_nodes = RoutingNodes::new();
+	let mut rd = OsmXmlReader::from_path(path)?;
+	rd.skip_ways = true;
+	rd.skip_relations = true;
+	println!("map nodes to find vertice");
+	for obj in rd {
+		let n = match obj? {
+			OsmObj::Node(n) => n,
+			_ => continue,
+		};
+		let nid = NodeId(n.attrs.id);
+		if used_nodes.contains_key(&nid) {
+			node_coords.insert(nid, (n.lon as f64, n.lat as f64));
+			if profile.is_vertex_node(&n.tags) {
+				vertex_nodes.insert(nid);
+			}
+			if let Some(rn) = RoutingNode::from_tags(&n.tags) {
+				routing_nodes.insert(nid, rn);
+			}
+		} else if member_nodes.contains(&nid) {
+			member_coords.insert(nid, (n.lon as f64, n.lat as f64));
+		}
+	}
+	println!("node coords: {}", node_coords.len());
+
+	let levels = levels.map(|l| l.finish(&routing_nodes));
+	let mut vertice: VerticeHash = used_nodes.into_iter().filter(|(_, v)| *v != 2).map(|(k, _)| k).collect();
+	vertice.extend(vertex_nodes);
+	vertice.extend(routing_nodes.keys().cloned());
+	Ok(MultiPass { vertice, node_coords, routing_nodes, restrictions, unresolved, way_nodes, areas, ferries, regions, member_coords, structures, levels })
+}
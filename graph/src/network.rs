@@ -0,0 +1,349 @@
+use osmio2::objects::{NodeId, ObjType, Relation, Tags, Way, WayId};
+use serde::{Serialize, Serializer, Deserialize};
+use std::{collections::{HashMap, HashSet}, fmt, sync::Arc};
+
+use crate::{Access, Directions, MaxSpeed, NodeChain, NodeCoords, PerDirection, Profile, Road, RoadCat, RoutingNodes};
+use crate::conditions::Conditions;
+use crate::sidewalks::Sidewalk;
+
+/* Non-road networks: railways (profile's `railways`) and ferries (`ferry`, route=ferry ways and relations).
+They are edges of the same graph with their own categories, and `network` column tells them apart.
+Ferry ends and stations that are not on a road get transfer edges to the nearest road vertex. */
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Network { Road, Rail, Ferry, Transfer }
+
+// set by the `category` of profile's railway rules
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RailCat { Rail, LightRail, Subway, Tram }
+
+// ferries that take cars or buses, and ferries for passengers (and their bicycles) only
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum FerryCat { Vehicle, Passenger }
+
+// `category` column, written as the name of the inner category
+#[derive(Clone, Debug, PartialEq)]
+pub enum Category {
+	Road(RoadCat),
+	Rail(RailCat),
+	Ferry(FerryCat),
+	Transfer,
+}
+
+impl Category {
+	pub fn network(&self) -> Network {
+		match self {
+			Self::Road(_) => Network::Road,
+			Self::Rail(_) => Network::Rail,
+			Self::Ferry(_) => Network::Ferry,
+			Self::Transfer => Network::Transfer,
+		}
+	}
+}
+
+impl fmt::Display for Category {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Road(c) => fmt::Debug::fmt(c, f),
+			Self::Rail(c) => fmt::Debug::fmt(c, f),
+			Self::Ferry(c) => fmt::Debug::fmt(c, f),
+			Self::Transfer => write!(f, "Transfer"),
+		}
+	}
+}
+
+impl Serialize for Category {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.collect_str(self)
+	}
+}
+
+// attributes of railway edges, empty on the other networks
+#[derive(Clone, Debug, PartialEq)]
+pub struct RailAttrs {
+	pub service: Arc<str>,
+	pub usage: Arc<str>,
+	pub electrified: Arc<str>,
+	// mm, several values for multi-gauge tracks, like 1435;1520
+	pub gauge: Arc<str>,
+}
+
+impl Default for RailAttrs {
+	fn default() -> Self {
+		let e: Arc<str> = Arc::from("");
+		Self { service: e.clone(), usage: e.clone(), electrified: e.clone(), gauge: e }
+	}
+}
+
+impl RailAttrs {
+	pub fn from_tags(tags: &Tags) -> Self {
+		let get = |k: &str| tags.get(k).cloned().unwrap_or_else(|| Arc::from(""));
+		Self { service: get("service"), usage: get("usage"), electrified: get("electrified"), gauge: get("gauge") }
+	}
+}
+
+pub fn is_ferry(tags: &Tags) -> bool {
+	tags.get("route").is_some_and(|v| &**v == "ferry")
+}
+
+// railway=* values of stations and stop positions. They're routing nodes (see vertex.rs), and get transfers
+pub const STATIONS: [&str; 4] = ["station", "halt", "tram_stop", "stop"];
+
+// ways of route=ferry relations that aren't tagged as ferries themselves. Like AreaCollector,
+// relations must come first, and in the single-pass build untagged ways are spooled till the relations are read
+pub struct FerryCollector<'a> {
+	profile: &'a Profile,
+	// relation tags of member ways
+	members: HashMap<WayId, Tags>,
+	routes: Vec<Way>,
+}
+
+impl<'a> FerryCollector<'a> {
+	pub fn new(profile: &'a Profile) -> Self {
+		Self { profile, members: HashMap::new(), routes: vec![] }
+	}
+
+	pub fn enabled(&self) -> bool {
+		self.profile.ferry.is_some()
+	}
+
+	pub fn relation(&mut self, rel: &Relation) {
+		if !self.enabled() || !is_ferry(&rel.tags) {
+			return
+		}
+		for m in rel.members.iter().filter(|m| m.mtype == ObjType::Way) {
+			self.members.entry(WayId(m.mref)).or_insert_with(|| rel.tags.clone());
+		}
+	}
+
+	pub fn may_need(&self, way: &Way) -> bool {
+		self.enabled() && way.tags.is_empty()
+	}
+
+	// members that are not roads get the relation's tags (way's own tags win)
+	pub fn way(&mut self, way: &Way) {
+		let rel_tags = match self.members.get(&WayId(way.attrs.id)) {
+			Some(t) if way.nodes.len() > 1 && self.profile.road(&way.tags).is_none() => t,
+			_ => return,
+		};
+		let mut tags = rel_tags.clone();
+		tags.extend(way.tags.iter().map(|(k, v)| (k.clone(), v.clone())));
+		if self.profile.road(&tags).is_some() {
+			self.routes.push(Way { attrs: way.attrs.clone(), nodes: way.nodes.clone(), tags });
+		}
+	}
+
+	pub fn finish(self) -> Vec<Way> {
+		if self.enabled() {
+			println!("ferry ways from relations: {}", self.routes.len());
+		}
+		self.routes
+	}
+}
+
+// one edge per way, ferry routes are not split or merged
+pub fn ferry_edges(routes: &[Way], profile: &Profile) -> Vec<NodeChain> {
+	routes.iter().filter_map(|w| profile.road(&w.tags).map(|road| NodeChain::new(w.attrs.id, &w.nodes, &road))).collect()
+}
+
+// metres
+pub const TRANSFER_DISTANCE: f64 = 300.0;
+// km/h, walking or boarding
+pub const TRANSFER_SPEED: u16 = 5;
+// grid cells per degree, ~550 m by latitude
+const CELLS: f64 = 200.0;
+
+fn cell(p: (f64, f64)) -> (i64, i64) {
+	((p.0 * CELLS).floor() as i64, (p.1 * CELLS).floor() as i64)
+}
+
+fn distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+	let dx = (b.0 - a.0).to_radians() * ((a.1 + b.1) / 2.0).to_radians().cos();
+	let dy = (b.1 - a.1).to_radians();
+	(dx * dx + dy * dy).sqrt() * 6_371_000.0
+}
+
+fn intersect(a: Access, b: Access) -> Access {
+	Access::new(a.car && b.car, a.bike && b.bike, a.foot && b.foot, a.bus && b.bus)
+}
+
+fn union(a: Access, b: Access) -> Access {
+	Access::new(a.car || b.car, a.bike || b.bike, a.foot || b.foot, a.bus || b.bus)
+}
+
+// collects road vertice and terminals (ferry ends, stations on railway edges) while edges are written,
+// then links the terminals that are not on a road to the nearest road vertex
+pub struct Transfers {
+	stations: HashSet<NodeId>,
+	// who may use the road edges at a vertex
+	roads: HashMap<NodeId, Access>,
+	grid: HashMap<(i64, i64), Vec<NodeId>>,
+	// who may get in or out at a terminal
+	terminals: HashMap<NodeId, Access>,
+}
+
+impl Transfers {
+	pub fn new(routing_nodes: &RoutingNodes) -> Self {
+		let stations = routing_nodes.iter()
+			.filter(|(_, rn)| rn.railway.as_ref().is_some_and(|r| STATIONS.contains(&&**r)))
+			.map(|(n, _)| *n).collect();
+		Self { stations, roads: HashMap::new(), grid: HashMap::new(), terminals: HashMap::new() }
+	}
+
+	pub fn add(&mut self, edge: &NodeChain, node_coords: &NodeCoords) {
+		for end in edge.ends().iter() {
+			match edge.category.network() {
+				Network::Road => {
+					let a = self.roads.entry(*end).or_insert(Access::NONE);
+					if *a == Access::NONE {
+						if let Some(p) = node_coords.get(end) {
+							self.grid.entry(cell(*p)).or_default().push(*end);
+						}
+					}
+					*a = union(*a, edge.access);
+				},
+				Network::Ferry => {
+					let a = self.terminals.entry(*end).or_insert(Access::NONE);
+					*a = union(*a, edge.access);
+				},
+				Network::Rail if self.stations.contains(end) => {
+					self.terminals.insert(*end, Access::new(false, false, true, false));
+				},
+				_ => {},
+			}
+		}
+	}
+
+	// nearest road vertex within TRANSFER_DISTANCE that has any of the modes in `access`
+	fn nearest(&self, p: (f64, f64), access: Access, node_coords: &NodeCoords) -> Option<(NodeId, Access)> {
+		let (cx, cy) = cell(p);
+		// cells get narrower to the poles
+		let dx = (TRANSFER_DISTANCE / distance((0.0, p.1), (1.0 / CELLS, p.1))).ceil().min(CELLS) as i64;
+		let mut best: Option<(f64, NodeId, Access)> = None;
+		for x in cx - dx..=cx + dx {
+			for y in cy - 1..=cy + 1 {
+				for n in self.grid.get(&(x, y)).into_iter().flatten() {
+					let a = intersect(self.roads[n], access);
+					let d = distance(p, node_coords[n]);
+					if a != Access::NONE && d <= TRANSFER_DISTANCE && best.as_ref().is_none_or(|b| d < b.0) {
+						best = Some((d, *n, a));
+					}
+				}
+			}
+		}
+		best.map(|(_, n, a)| (n, a))
+	}
+
+	// transfer edges from terminals to roads, in the order of node ids
+	pub fn edges(&self, node_coords: &NodeCoords) -> Vec<NodeChain> {
+		let mut terminals: Vec<(&NodeId, &Access)> = self.terminals.iter().filter(|(n, _)| !self.roads.contains_key(n)).collect();
+		terminals.sort_by_key(|(n, _)| n.0);
+		let mut res = vec![];
+		for (n, access) in terminals {
+			if let Some((road_node, access)) = node_coords.get(n).and_then(|p| self.nearest(*p, *access, node_coords)) {
+				let mut nc = NodeChain::new(0, &[n.0, road_node.0], &transfer_road(access));
+				nc.ways.clear();
+				res.push(nc);
+			}
+		}
+		res
+	}
+}
+
+pub fn transfer_road(access: Access) -> Road {
+	let empty: Arc<str> = Arc::from("");
+	Road {
+		category: Category::Transfer,
+		lanes: 1,
+		oneway: Directions::default(),
+		maxspeed: MaxSpeed(None),
+		access,
+		speed: TRANSFER_SPEED,
+		tags: empty.clone(),
+		maxspeed_dir: PerDirection::default(),
+		lanes_dir: PerDirection { forward: 1, backward: 1 },
+		maxspeed_conditional: PerDirection { forward: empty.clone(), backward: empty.clone() },
+		lanes_conditional: empty.clone(),
+		lanes_dir_conditional: PerDirection { forward: empty.clone(), backward: empty },
+		rail: RailAttrs::default(),
+		conditions: Conditions::default(),
+		level: Arc::from(""),
+		layer: 0,
+		sidewalk: Sidewalk::default(),
+		// written as nulls
+		attrs: Arc::from(vec![]),
+	}
+}
+
+#[cfg(test)]
+mod network_tests {
+	use super::*;
+	use crate::tags;
+	use crate::build_edges;
+	use crate::multi_pass::read_passes;
+	use osmio2::temp::TempFile;
+	use osmio2::{
+		objects::{Member, Node, OsmElementAttrs, OsmObj},
+		writer::OsmXmlWriter,
+	};
+
+	fn attrs(id: i64) -> OsmElementAttrs {
+		let mut a = OsmElementAttrs::new();
+		a.id = id;
+		a
+	}
+
+	#[test]
+	fn test_networks() {
+		// road 1-2-3, railway 10-11-12 with a halt at 11 north of the road,
+		// ferry relation from 3 to 20 with an untagged way, and a service road 21-22 next to 20
+		let tmp = TempFile::new("network-test.osm");
+		let path = tmp.path();
+		let mut wr = OsmXmlWriter::from_path(&path).unwrap();
+		let nodes = [(1, 83.0, 54.0), (2, 83.001, 54.0), (3, 83.002, 54.0), (10, 82.999, 54.001), (11, 83.0008, 54.001), (12, 83.003, 54.001),
+			(20, 83.05, 54.0), (21, 83.051, 54.0), (22, 83.052, 54.0)];
+		for (id, lon, lat) in nodes {
+			let t = if id == 11 { tags(&[("railway", "halt")]) } else { Tags::new() };
+			wr.write(&OsmObj::Node(Node { attrs: attrs(id), lat, lon, tags: t })).unwrap();
+		}
+		let ways = [
+			(1, vec![1, 2, 3], tags(&[("highway", "residential")])),
+			(2, vec![10, 11, 12], tags(&[("railway", "rail"), ("usage", "main"), ("electrified", "contact_line"), ("gauge", "1520")])),
+			(3, vec![3, 20], Tags::new()),
+			(4, vec![21, 22], tags(&[("highway", "service")])),
+		];
+		for (id, nodes, t) in ways {
+			wr.write(&OsmObj::Way(Way { attrs: attrs(id), nodes, tags: t })).unwrap();
+		}
+		let members = vec![Member { mtype: ObjType::Way, mref: 3, mrole: Arc::from("") }];
+		wr.write(&OsmObj::Relation(Relation { attrs: attrs(1), tags: tags(&[("type", "route"), ("route", "ferry")]), members })).unwrap();
+		wr.close().unwrap();
+
+		let profile = Profile::default();
+		let mp = read_passes(&path, &profile, false).unwrap();
+		let (vertice, mut node_coords, routing_nodes, ferries) = (mp.vertice, mp.node_coords, mp.routing_nodes, mp.ferries);
+		assert_eq!(ferries.iter().map(|w| w.attrs.id).collect::<Vec<_>>(), vec![3]);
+		assert_eq!(mp.member_coords.keys().collect::<Vec<_>>(), vec![&NodeId(20)]);
+		node_coords.extend(mp.member_coords);
+
+		let mut transfers = Transfers::new(&routing_nodes);
+		let mut edges = vec![];
+		build_edges(&path, &vertice, &profile, |e| { transfers.add(&e, &node_coords); edges.push(e); Ok(()) }).unwrap();
+		for e in ferry_edges(&ferries, &profile) {
+			transfers.add(&e, &node_coords);
+			edges.push(e);
+		}
+		let rail: Vec<&NodeChain> = edges.iter().filter(|e| e.category == Category::Rail(RailCat::Rail)).collect();
+		assert_eq!(rail.iter().map(|e| e.nodes.len()).collect::<Vec<_>>(), vec![2, 2]);
+		assert_eq!((&*rail[0].rail.gauge, &*rail[0].rail.electrified, rail[0].access), ("1520", "contact_line", Access::NONE));
+		assert!(edges.iter().any(|e| e.category == Category::Ferry(FerryCat::Passenger) && e.ends() == [NodeId(3), NodeId(20)]));
+
+		// the halt gets a footpath to the nearest road vertex, the ferry's far end to the service road
+		let t = transfers.edges(&node_coords);
+		assert_eq!(t.iter().map(|e| (e.ends(), e.access)).collect::<Vec<_>>(), vec![
+			([NodeId(11), NodeId(1)], Access::new(false, false, true, false)),
+			([NodeId(20), NodeId(21)], Access::new(false, true, true, false)),
+		]);
+		assert_eq!(t[0].category.to_string(), "Transfer");
+	}
+}
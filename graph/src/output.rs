@@ -0,0 +1,360 @@
+use csv::{Writer as CsvWriter, WriterBuilder};
+use geo::LineString;
+use serde::Deserialize;
+use serde_json::{json, Map, Value as JsonValue};
+use std::{
+	error::Error,
+	fs::File,
+	io::{BufWriter, Write},
+};
+
+use crate::{Edge, MaxSpeed, normalize::UNLIMITED};
+use crate::{gpkg::GpkgWriter, fgb::FgbWriter};
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColType { Int, Float, Bool, Text }
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value { Int(i64), Float(f64), Bool(bool), Text(String), Null }
+
+pub type ColValue = fn(&Edge) -> Value;
+
+// attribute columns of typed formats (everything in Edge except geometry): name, type and value
+pub const EDGE_COLUMNS: [(&str, ColType, ColValue); 45] = [
+	("id", ColType::Int, |e| Value::Int(e.id as i64)),
+	("node1", ColType::Int, |e| Value::Int(e.node1.0)),
+	("node2", ColType::Int, |e| Value::Int(e.node2.0)),
+	("category", ColType::Text, |e| Value::Text(e.category.to_string())),
+	("lanes", ColType::Int, |e| Value::Int(e.lanes as i64)),
+	("oneway", ColType::Text, |e| Value::Text(format!("{:?}", e.oneway))),
+	("maxspeed", ColType::Int, |e| maxspeed_value(e.maxspeed)),
+	("oneway_bike", ColType::Text, |e| Value::Text(format!("{:?}", e.oneway_bike))),
+	("oneway_foot", ColType::Text, |e| Value::Text(format!("{:?}", e.oneway_foot))),
+	("oneway_bus", ColType::Text, |e| Value::Text(format!("{:?}", e.oneway_bus))),
+	("car", ColType::Bool, |e| Value::Bool(e.car)),
+	("bike", ColType::Bool, |e| Value::Bool(e.bike)),
+	("foot", ColType::Bool, |e| Value::Bool(e.foot)),
+	("bus", ColType::Bool, |e| Value::Bool(e.bus)),
+	("speed", ColType::Int, |e| Value::Int(e.speed as i64)),
+	("tags", ColType::Text, |e| text(&e.tags)),
+	("maxspeed_forward", ColType::Int, |e| maxspeed_value(e.maxspeed_forward)),
+	("maxspeed_backward", ColType::Int, |e| maxspeed_value(e.maxspeed_backward)),
+	("lanes_forward", ColType::Int, |e| Value::Int(e.lanes_forward as i64)),
+	("lanes_backward", ColType::Int, |e| Value::Int(e.lanes_backward as i64)),
+	("maxspeed_conditional_forward", ColType::Text, |e| text(&e.maxspeed_conditional_forward)),
+	("maxspeed_conditional_backward", ColType::Text, |e| text(&e.maxspeed_conditional_backward)),
+	("ascent", ColType::Float, |e| float(e.ascent)),
+	("descent", ColType::Float, |e| float(e.descent)),
+	("max_grade", ColType::Float, |e| float(e.max_grade)),
+	("area", ColType::Bool, |e| Value::Bool(e.area)),
+	("network", ColType::Text, |e| Value::Text(format!("{:?}", e.network))),
+	("service", ColType::Text, |e| text(&e.service)),
+	("usage", ColType::Text, |e| text(&e.usage)),
+	("electrified", ColType::Text, |e| text(&e.electrified)),
+	("gauge", ColType::Text, |e| text(&e.gauge)),
+	("ways", ColType::Text, |e| text(&e.ways)),
+	("nodes", ColType::Text, |e| text(&e.nodes)),
+	("oneway_conditional", ColType::Text, |e| text(&e.oneway_conditional)),
+	("car_conditional", ColType::Text, |e| text(&e.car_conditional)),
+	("bike_conditional", ColType::Text, |e| text(&e.bike_conditional)),
+	("foot_conditional", ColType::Text, |e| text(&e.foot_conditional)),
+	("bus_conditional", ColType::Text, |e| text(&e.bus_conditional)),
+	("level", ColType::Text, |e| text(&e.level)),
+	("level1", ColType::Float, |e| float(e.level1)),
+	("level2", ColType::Float, |e| float(e.level2)),
+	("lanes_conditional", ColType::Text, |e| text(&e.lanes_conditional)),
+	("lanes_conditional_forward", ColType::Text, |e| text(&e.lanes_conditional_forward)),
+	("lanes_conditional_backward", ColType::Text, |e| text(&e.lanes_conditional_backward)),
+	("layer", ColType::Int, |e| Value::Int(e.layer as i64)),
+];
+
+fn text(s: &str) -> Value {
+	if s.is_empty() { Value::Null } else { Value::Text(s.to_string()) }
+}
+
+fn float(v: Option<f64>) -> Value {
+	v.map_or(Value::Null, Value::Float)
+}
+
+// maxspeed=none is -1 in typed formats
+fn maxspeed_value(m: MaxSpeed) -> Value {
+	match m.0 {
+		Some(UNLIMITED) => Value::Int(-1),
+		Some(v) => Value::Int(v as i64),
+		None => Value::Null,
+	}
+}
+
+// EDGE_COLUMNS and then profile's extra columns (see Profile::extra_columns)
+pub fn edge_columns(extra: &[(String, ColType)]) -> Vec<(String, ColType)> {
+	EDGE_COLUMNS.iter().map(|(n, t, _)| (n.to_string(), *t))
+		.chain(extra.iter().cloned())
+		.collect()
+}
+
+// values in the order of edge_columns, `extra` is the number of extra columns (transfers have no values and get nulls)
+pub fn edge_values(e: &Edge, extra: usize) -> Vec<Value> {
+	EDGE_COLUMNS.iter().map(|(_, _, value)| value(e))
+		.chain((0..extra).map(|i| e.attrs.get(i).cloned().unwrap_or(Value::Null)))
+		.collect()
+}
+
+impl Value {
+	// as in CSV, where null is an empty string
+	pub fn to_text(&self) -> String {
+		match self {
+			Value::Int(i) => i.to_string(),
+			Value::Float(f) => f.to_string(),
+			Value::Bool(b) => b.to_string(),
+			Value::Text(s) => s.clone(),
+			Value::Null => String::new(),
+		}
+	}
+}
+
+// 3D points, if the edge has heights for all of them
+pub fn points_z(e: &Edge) -> Option<&[(f64, f64, f64)]> {
+	e.WKT_Z.as_ref().map(|ls| ls.0.as_slice()).filter(|pts| pts.iter().all(|p| !p.2.is_nan()))
+}
+
+// [min x, min y, max x, max y]
+pub fn bbox(ls: &LineString) -> [f64; 4] {
+	ls.0.iter().fold([f64::MAX, f64::MAX, f64::MIN, f64::MIN], |b, c| [b[0].min(c.x), b[1].min(c.y), b[2].max(c.x), b[3].max(c.y)])
+}
+
+pub fn extend_bbox(a: [f64; 4], b: [f64; 4]) -> [f64; 4] {
+	[a[0].min(b[0]), a[1].min(b[1]), a[2].max(b[2]), a[3].max(b[3])]
+}
+
+pub trait EdgeWriter {
+	fn write(&mut self, edge: &Edge) -> Result<(), Box<dyn Error>>;
+	fn close(self: Box<Self>) -> Result<(), Box<dyn Error>>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format { Csv, GeoJson, GeoJsonSeq, Gpkg, Fgb }
+
+impl Format {
+	pub fn from_name(name: &str) -> Option<Self> {
+		match name.to_lowercase().as_str() {
+			"csv" => Some(Self::Csv),
+			"geojson" | "json" => Some(Self::GeoJson),
+			"geojsonseq" | "geojsonl" | "geojsons" => Some(Self::GeoJsonSeq),
+			"gpkg" => Some(Self::Gpkg),
+			"fgb" | "flatgeobuf" => Some(Self::Fgb),
+			_ => None,
+		}
+	}
+
+	pub fn from_path(path: &str) -> Option<Self> {
+		Self::from_name(path.rsplit_once('.')?.1)
+	}
+}
+
+// format is taken from `format` if given, otherwise from the file extension
+pub fn create_writer(path: &str, format: Option<Format>, extra: &[(String, ColType)]) -> Result<Box<dyn EdgeWriter>, Box<dyn Error>> {
+	let format = format.or_else(|| Format::from_path(path))
+		.ok_or("unknown output format, use .csv, .geojson, .geojsonl, .gpkg or .fgb extension")?;
+	Ok(match format {
+		Format::Csv => Box::new(CsvEdgeWriter::new(path, extra)?),
+		Format::GeoJson => Box::new(GeoJsonWriter::new(path, false, extra)?),
+		Format::GeoJsonSeq => Box::new(GeoJsonWriter::new(path, true, extra)?),
+		Format::Gpkg => Box::new(GpkgWriter::new(path, extra)?),
+		Format::Fgb => Box::new(FgbWriter::new(path, extra)?),
+	})
+}
+
+// serde can't name the extra columns, so the header is written by hand: Edge fields, then extra columns
+pub struct CsvEdgeWriter {
+	wr: CsvWriter<File>,
+	extra: Vec<String>,
+	has_header: bool,
+}
+
+impl CsvEdgeWriter {
+	pub fn new(path: &str, extra: &[(String, ColType)]) -> Result<Self, Box<dyn Error>> {
+		let wr = WriterBuilder::new().has_headers(false).from_path(path)?;
+		Ok(Self { wr, extra: extra.iter().map(|(n, _)| n.clone()).collect(), has_header: false })
+	}
+
+	fn write_header(&mut self, edge: &Edge) -> Result<(), Box<dyn Error>> {
+		let mut hw = CsvWriter::from_writer(vec![]);
+		hw.serialize(edge)?;
+		let data = hw.into_inner().map_err(|e| e.to_string())?;
+		let mut header = csv::ReaderBuilder::new().has_headers(false).from_reader(data.as_slice())
+			.records().next().ok_or("no CSV header")??;
+		for n in self.extra.iter() {
+			header.push_field(n);
+		}
+		Ok(self.wr.write_record(&header)?)
+	}
+}
+
+impl EdgeWriter for CsvEdgeWriter {
+	fn write(&mut self, edge: &Edge) -> Result<(), Box<dyn Error>> {
+		if !self.has_header {
+			self.write_header(edge)?;
+			self.has_header = true;
+		}
+		let n = self.extra.len();
+		let attrs: Vec<String> = edge_values(edge, n)[EDGE_COLUMNS.len()..].iter().map(|v| v.to_text()).collect();
+		Ok(self.wr.serialize((edge, attrs))?)
+	}
+
+	fn close(mut self: Box<Self>) -> Result<(), Box<dyn Error>> {
+		Ok(self.wr.flush()?)
+	}
+}
+
+// FeatureCollection, or newline-delimited features (GeoJSONSeq)
+pub struct GeoJsonWriter {
+	wr: BufWriter<File>,
+	seq: bool,
+	count: usize,
+	columns: Vec<(String, ColType)>,
+}
+
+impl GeoJsonWriter {
+	pub fn new(path: &str, seq: bool, extra: &[(String, ColType)]) -> Result<Self, Box<dyn Error>> {
+		let mut wr = BufWriter::new(File::create(path)?);
+		if !seq {
+			wr.write_all(b"{\"type\":\"FeatureCollection\",\"features\":[\n")?;
+		}
+		Ok(Self { wr, seq, count: 0, columns: edge_columns(extra) })
+	}
+}
+
+pub fn geojson_feature(edge: &Edge, columns: &[(String, ColType)]) -> JsonValue {
+	let mut props = Map::new();
+	for ((name, _), v) in columns.iter().zip(edge_values(edge, columns.len() - EDGE_COLUMNS.len())) {
+		props.insert(name.clone(), match v {
+			Value::Int(i) => json!(i),
+			Value::Float(f) => json!(f),
+			Value::Bool(b) => json!(b),
+			Value::Text(s) => json!(s),
+			Value::Null => JsonValue::Null,
+		});
+	}
+	let coords: Vec<Vec<f64>> = match points_z(edge) {
+		Some(pts) => pts.iter().map(|p| vec![p.0, p.1, p.2]).collect(),
+		None => edge.WKT.0.iter().map(|c| vec![c.x, c.y]).collect(),
+	};
+	json!({
+		"type": "Feature",
+		"geometry": {"type": "LineString", "coordinates": coords},
+		"properties": props,
+	})
+}
+
+impl EdgeWriter for GeoJsonWriter {
+	fn write(&mut self, edge: &Edge) -> Result<(), Box<dyn Error>> {
+		if !self.seq && self.count > 0 {
+			self.wr.write_all(b",\n")?;
+		}
+		serde_json::to_writer(&mut self.wr, &geojson_feature(edge, &self.columns))?;
+		if self.seq {
+			self.wr.write_all(b"\n")?;
+		}
+		self.count += 1;
+		Ok(())
+	}
+
+	fn close(mut self: Box<Self>) -> Result<(), Box<dyn Error>> {
+		if !self.seq {
+			self.wr.write_all(b"\n]}\n")?;
+		}
+		Ok(self.wr.flush()?)
+	}
+}
+
+#[cfg(test)]
+pub mod output_tests {
+	use super::*;
+	use crate::{Category, Network, RoadCat, OneWay, profile::Profile};
+	use osmio2::temp::TempFile;
+	use osmio2::objects::NodeId;
+
+	pub fn sample_edges() -> Vec<Edge> {
+		(0..40).map(|i| {
+			let x = 83.0 + (i % 7) as f64 * 0.01;
+			let y = 54.0 + (i / 7) as f64 * 0.01;
+			Edge {
+				id: i + 1, node1: NodeId(i as i64 * 10), node2: NodeId(i as i64 * 10 + 1),
+				WKT: LineString::from(vec![(x, y), (x + 0.005, y + 0.002), (x + 0.01, y)]),
+				category: Category::Road(RoadCat::Residential), lanes: 2, oneway: if i % 3 == 0 { OneWay::Forward } else { OneWay::No },
+				maxspeed: MaxSpeed(if i % 2 == 0 { Some(60) } else { None }),
+				oneway_bike: OneWay::No, oneway_foot: OneWay::No, oneway_bus: OneWay::No,
+				car: true, bike: i % 4 != 0, foot: true, bus: true, speed: 60,
+				tags: if i == 5 { "name=Ленина".to_string() } else { String::new() },
+				maxspeed_forward: MaxSpeed(Some(UNLIMITED)), maxspeed_backward: MaxSpeed(None),
+				lanes_forward: 1, lanes_backward: 1,
+				maxspeed_conditional_forward: String::new(), maxspeed_conditional_backward: String::new(),
+				ascent: Some(i as f64 * 0.5), descent: None, max_grade: Some(1.5), area: false, network: Network::Road,
+				service: String::new(), usage: String::new(), electrified: String::new(), gauge: String::new(),
+				ways: format!("{}:0-2", i + 100), nodes: format!("{};{};{}", i * 10, i * 10 + 5, i * 10 + 1),
+				oneway_conditional: String::new(),
+				car_conditional: if i == 5 { "no @ (Mo-Fr 07:00-19:00)".to_string() } else { String::new() },
+				bike_conditional: String::new(), foot_conditional: String::new(), bus_conditional: String::new(),
+				level: String::new(), level1: None, level2: None,
+				lanes_conditional: String::new(), lanes_conditional_forward: String::new(), lanes_conditional_backward: String::new(),
+				layer: 0,
+				attrs: if i == 5 { vec![Value::Text("Ленина".to_string()), Value::Bool(true)] } else { vec![] },
+				WKT_Z: None,
+			}
+		}).collect()
+	}
+
+	#[test]
+	fn test_geojson() {
+		for (name, seq) in [("test.geojson", false), ("test.geojsonl", true)] {
+			let tmp = TempFile::new(name);
+			let path = tmp.path();
+			let mut wr = create_writer(&path, None, &[]).unwrap();
+			for e in sample_edges().iter() {
+				wr.write(e).unwrap();
+			}
+			wr.close().unwrap();
+			let text = std::fs::read_to_string(&path).unwrap();
+			let features: Vec<JsonValue> = if seq {
+				text.lines().map(|l| serde_json::from_str(l).unwrap()).collect()
+			} else {
+				serde_json::from_str::<JsonValue>(&text).unwrap()["features"].as_array().unwrap().clone()
+			};
+			assert_eq!(features.len(), 40);
+			let p = &features[5]["properties"];
+			assert_eq!((p["node1"].as_i64(), p["maxspeed"].is_null(), p["maxspeed_forward"].as_i64()), (Some(50), true, Some(-1)));
+			assert_eq!((p["oneway"].as_str(), p["bike"].as_bool(), p["tags"].as_str()), (Some("No"), Some(true), Some("name=Ленина")));
+			assert_eq!((p["car_conditional"].as_str(), p["bus_conditional"].is_null()), (Some("no @ (Mo-Fr 07:00-19:00)"), true));
+			assert_eq!(features[0]["geometry"]["coordinates"][1][0].as_f64(), Some(83.005));
+		}
+		assert_eq!(Format::from_path("a/b.c/graph.FGB"), Some(Format::Fgb));
+		assert!(create_writer("graph.shp", None, &[]).is_err());
+	}
+
+	#[test]
+	fn test_attributes() {
+		let p = Profile::parse(r#"
+			name = "test"
+			modes = ["Foot"]
+			highways = {}
+			attributes = [{ tag = "name" }, { tag = "lit", type = "bool" }]
+		"#).unwrap();
+		let tmp = TempFile::new("test-attrs.csv");
+		let path = tmp.path();
+		let mut wr = create_writer(&path, None, &p.extra_columns()).unwrap();
+		for e in sample_edges().iter() {
+			wr.write(e).unwrap();
+		}
+		wr.close().unwrap();
+		let mut rd = csv::Reader::from_path(&path).unwrap();
+		let header = rd.headers().unwrap().clone();
+		assert_eq!(header.iter().rev().take(3).collect::<Vec<_>>(), vec!["lit", "name", "layer"]);
+		let rows: Vec<csv::StringRecord> = rd.records().map(|r| r.unwrap()).collect();
+		assert_eq!((&rows[5][header.len() - 2], &rows[5][header.len() - 1]), ("Ленина", "true"));
+		assert_eq!((&rows[4][header.len() - 2], &rows[4][header.len() - 1]), ("", ""));
+		assert_eq!(&rows[4][0], "5");
+
+		assert!(Profile::parse("name = \"x\"\nmodes = []\nhighways = {}\nattributes = [{ tag = \"lanes\" }]").is_err());
+	}
+}
@@ -0,0 +1,211 @@
+use crossbeam_channel::{bounded, Sender, Receiver};
+use osmio2::objects::Way;
+use std::{
+	collections::BTreeMap,
+	error::Error,
+	fmt::Display,
+	thread,
+};
+
+use crate::{ChainStorage, NodeChain, Profile, VerticeHash, read_ways};
+
+pub const BATCH_SIZE: usize = 1024;
+
+type Batch<T> = (usize, Vec<T>);
+
+/* Batches of ways go round-robin to `threads` workers, each splits and glues them in a ChainStorage of its own.
+Chains left with loose ends in the workers are glued at the end in one more ChainStorage.
+
+A node that isn't a vertex has exactly 2 chain ends, so whether they can be glued doesn't depend on the other ways,
+and a glued chain keeps the direction and attributes of its first way part (see NodeChain::couple). So the edges
+are the same as from `build_edges`, only their order differs. Completed edges are emitted in the order of batches,
+and batches always go to the same workers, so the order is the same for the same number of threads. */
+pub fn build_edges_parallel<F>(path: &str, vertice: &VerticeHash, profile: &Profile, threads: usize, batch_size: usize, emit: F) -> Result<(), Box<dyn Error>>
+where F: FnMut(NodeChain) -> Result<(), Box<dyn Error>> {
+	build_edges_parallel_from(read_ways(path)?, vertice, profile, threads, batch_size, emit)
+}
+
+// same, for ways from any source, e.g. the spool of single-pass build
+pub fn build_edges_parallel_from<I, E, F>(ways: I, vertice: &VerticeHash, profile: &Profile, threads: usize, batch_size: usize, mut emit: F) -> Result<(), Box<dyn Error>>
+where I: Iterator<Item = Result<Way, E>> + Send + 'static, E: Display, F: FnMut(NodeChain) -> Result<(), Box<dyn Error>> {
+	let threads = threads.max(1);
+	let (way_snds, way_rcvs): (Vec<Sender<Batch<Way>>>, Vec<_>) = (0..threads).map(|_| bounded(2)).unzip();
+	let (chain_snd, chain_rcv): (Sender<Batch<NodeChain>>, Receiver<Batch<NodeChain>>) = bounded(threads * 2);
+
+	let reader = thread::spawn(move || -> Result<(), String> {
+		let mut batch = vec![];
+		let mut seq = 0;
+		for w in ways {
+			batch.push(w.map_err(|e| e.to_string())?);
+			if batch.len() >= batch_size {
+				way_snds[seq % threads].send((seq, std::mem::take(&mut batch))).map_err(|e| e.to_string())?;
+				seq += 1;
+			}
+		}
+		if !batch.is_empty() {
+			way_snds[seq % threads].send((seq, batch)).map_err(|e| e.to_string())?;
+		}
+		Ok(())
+	});
+
+	// workers share `vertice` and the emitted chains are collected on this thread, hence the scope
+	let (storages, res) = thread::scope(|s| -> Result<_, Box<dyn Error>> {
+		let workers: Vec<_> = way_rcvs.into_iter().map(|rcv| {
+			let snd = chain_snd.clone();
+			let mut cs = ChainStorage::new(vertice, profile);
+			s.spawn(move || {
+				for (seq, ways) in rcv {
+					let chains = ways.into_iter().flat_map(|w| cs.insert_way(w)).collect();
+					// receiver is gone only if emit failed, the error is returned there
+					if snd.send((seq, chains)).is_err() { break }
+				}
+				cs
+			})
+		}).collect();
+		drop(chain_snd);
+
+		// batches come out of order, they're kept here until the previous ones are emitted
+		let mut pending: BTreeMap<usize, Vec<NodeChain>> = BTreeMap::new();
+		let mut next = 0;
+		let mut res = Ok(());
+		'outer: for (seq, chains) in chain_rcv.iter() {
+			pending.insert(seq, chains);
+			while let Some(chains) = pending.remove(&next) {
+				for edge in chains {
+					if let Err(e) = emit(edge) {
+						res = Err(e);
+						break 'outer;
+					}
+				}
+				next += 1;
+			}
+		}
+		drop(chain_rcv);
+
+		let mut storages = vec![];
+		for w in workers {
+			storages.push(w.join().map_err(|_| "graph worker thread panicked")?);
+		}
+		Ok((storages, res))
+	})?;
+	let read = reader.join().map_err(|_| "reader thread panicked")?;
+	// if emit failed, the workers and the reader stopped on closed channels, and emit's error is the one to return
+	res?;
+	read?;
+
+	// the first worker's storage already has its loose chains, the other ones are glued to them.
+	// Vertice added in a worker are between chains of that worker, the other ones never end there
+	let mut storages = storages.into_iter();
+	let mut last = match storages.next() {
+		Some(cs) => cs,
+		None => return Ok(()),
+	};
+	let mut dangling = vec![];
+	for cs in storages {
+		last.added.extend(&cs.added);
+		dangling.extend(cs.dangling());
+	}
+	// sorted, because storages are hash maps
+	dangling.sort_by_key(|nc| nc.first_part());
+	for nc in dangling {
+		for edge in last.insert(nc) {
+			emit(edge)?;
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod parallel_tests {
+	use super::*;
+	use crate::build_edges;
+	use crate::multi_pass::read_passes;
+	use osmio2::temp::TempFile;
+	use osmio2::{
+		objects::{OsmElementAttrs, OsmObj, Node, Tags},
+		writer::OsmXmlWriter,
+	};
+	use std::sync::Arc;
+
+	// a grid of streets, each cut into short ways, some of them reversed and with other maxspeed,
+	// so that chains are glued in different directions and some of them can't be glued
+	fn write_grid(path: &str, size: i64) {
+		let mut wr = OsmXmlWriter::from_path(path).unwrap();
+		let nid = |x: i64, y: i64| y * size + x + 1;
+		for y in 0..size {
+			for x in 0..size {
+				let mut attrs = OsmElementAttrs::new();
+				attrs.id = nid(x, y);
+				wr.write(&OsmObj::Node(Node { attrs, lat: 54.0 + y as f32 / 1000.0, lon: 83.0 + x as f32 / 1000.0, tags: Tags::new() })).unwrap();
+			}
+		}
+		let mut way_id = 0;
+		for y in (0..size).step_by(4) {
+			for x0 in (0..size - 1).step_by(3) {
+				way_id += 1;
+				let mut nodes: Vec<i64> = (x0..(x0 + 4).min(size)).map(|x| nid(x, y)).collect();
+				let mut tags = Tags::new();
+				tags.insert(Arc::from("highway"), Arc::from(if y % 8 == 0 { "residential" } else { "tertiary" }));
+				if way_id % 3 == 0 { nodes.reverse(); }
+				if way_id % 5 == 0 { tags.insert(Arc::from("maxspeed"), Arc::from("40")); }
+				if way_id % 7 == 0 { tags.insert(Arc::from("oneway"), Arc::from("yes")); }
+				let mut attrs = OsmElementAttrs::new();
+				attrs.id = way_id;
+				wr.write(&OsmObj::Way(Way { attrs, nodes, tags })).unwrap();
+			}
+		}
+		for x in (0..size).step_by(5) {
+			way_id += 1;
+			let mut tags = Tags::new();
+			tags.insert(Arc::from("highway"), Arc::from("residential"));
+			let mut attrs = OsmElementAttrs::new();
+			attrs.id = way_id;
+			wr.write(&OsmObj::Way(Way { attrs, nodes: (0..size).map(|y| nid(x, y)).collect(), tags })).unwrap();
+		}
+		wr.close().unwrap();
+	}
+
+	fn build(path: &str, profile: &Profile, threads: usize, batch_size: usize) -> Vec<NodeChain> {
+		let vertice = read_passes(path, profile, false).unwrap().vertice;
+		let mut edges = vec![];
+		if threads == 0 {
+			build_edges(path, &vertice, profile, |e| { edges.push(e); Ok(()) }).unwrap();
+		} else {
+			build_edges_parallel(path, &vertice, profile, threads, batch_size, |e| { edges.push(e); Ok(()) }).unwrap();
+		}
+		edges
+	}
+
+	// edges in the order of their first way parts
+	fn sorted(mut edges: Vec<NodeChain>) -> Vec<NodeChain> {
+		edges.sort_by_key(|e| e.first_part());
+		edges
+	}
+
+	#[test]
+	fn test_same_as_sequential() {
+		let tmp = TempFile::new("parallel-test.osm");
+		let grid = tmp.path();
+		write_grid(&grid, 40);
+		// without maxspeed in merge_if_equal, ways with other maxspeed are glued, and the edge gets the first way's one
+		let car = Profile::load("car").unwrap();
+		let mut car_maxspeed = car.clone();
+		car_maxspeed.merge_if_equal.push(crate::MergeKey::Maxspeed);
+
+		for (path, profile) in [(grid.as_str(), car), (grid.as_str(), car_maxspeed), ("test_data/ilyicha-1.osm.gz", Profile::default())] {
+			let expected = sorted(build(path, &profile, 0, 0));
+			assert!(expected.len() > 10);
+			for (threads, batch_size) in [(1, 1), (3, 2), (4, 7), (8, BATCH_SIZE)] {
+				let edges = build(path, &profile, threads, batch_size);
+				// the order only depends on the number of threads
+				assert_eq!(build(path, &profile, threads, batch_size), edges);
+				assert_eq!(sorted(edges), expected, "{} threads, batches of {}", threads, batch_size);
+			}
+		}
+
+		// emit's error is returned, not the one of the stopped reader
+		let vertice = read_passes(&grid, &Profile::default(), false).unwrap().vertice;
+		let res = build_edges_parallel(&grid, &vertice, &Profile::default(), 3, 1, |_| Err("emit failed".into()));
+		assert_eq!(res.unwrap_err().to_string(), "emit failed");
+	}
+}
@@ -0,0 +1,488 @@
+use osmio2::objects::{NodeId, Tags, Way};
+use serde::Deserialize;
+use std::{
+	collections::HashMap,
+	error::Error,
+	sync::Arc,
+};
+
+use crate::{RoadCat, MaxSpeed, Access, Directions, Mode};
+use crate::network::{Category, FerryCat, RailAttrs, RailCat, is_ferry};
+use crate::normalize::{PerDirection, parse_lanes, lanes_per_direction, lanes_conditional, maxspeed_conditional, UNLIMITED};
+use crate::output::{ColType, Value, EDGE_COLUMNS};
+use crate::conditions::Conditions;
+use crate::regions::{RegionLookup, admin_columns};
+use crate::levels::level_of;
+use crate::quality::way_layer;
+use crate::sidewalks::{Sidewalk, SidewalkRule};
+
+// profiles that are compiled in, so that they can be used by name
+const BUILTIN: [(&str, &str); 4] = [
+	("all", include_str!("../profiles/all.toml")),
+	("car", include_str!("../profiles/car.toml")),
+	("bike", include_str!("../profiles/bike.toml")),
+	("foot", include_str!("../profiles/foot.toml")),
+];
+
+// tag condition written as "key" (any value) or "key=value"
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(from = "String")]
+pub struct TagCond {
+	pub key: String,
+	pub value: Option<String>,
+}
+
+impl From<String> for TagCond {
+	fn from(s: String) -> Self {
+		match s.split_once('=') {
+			Some((k, v)) => Self { key: k.to_string(), value: Some(v.to_string()) },
+			None => Self { key: s, value: None },
+		}
+	}
+}
+
+impl TagCond {
+	pub fn matches(&self, tags: &Tags) -> bool {
+		match (tags.get(self.key.as_str()), &self.value) {
+			(Some(_), None) => true,
+			(Some(v), Some(v2)) => **v == **v2,
+			_ => false,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HighwayRule {
+	pub category: RoadCat,
+	// km/h, when maxspeed is not used or not set
+	pub speed: u16,
+	// when there's no lanes=* tag
+	#[serde(default = "one_lane")]
+	pub lanes: u8,
+}
+
+fn one_lane() -> u8 { 1 }
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RailRule {
+	pub category: RailCat,
+	// km/h, when maxspeed is not used or not set
+	pub speed: u16,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FerryRule {
+	// km/h
+	pub speed: u16,
+}
+
+// speed is multiplied by `factor` on ways that match `tag`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Penalty {
+	pub tag: TagCond,
+	pub factor: f32,
+}
+
+// attributes that must be equal for 2 chains to be merged into one edge.
+// Direction and access are always compared, otherwise the edge would be wrong
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeKey { Category, Lanes, Speed, Maxspeed, Tags }
+
+// tag written into its own edge column, `{ tag = "surface", type = "text" }`.
+// Chains with different values are never merged
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Attribute {
+	pub tag: String,
+	#[serde(rename = "type", default = "text_type")]
+	pub kind: ColType,
+}
+
+fn text_type() -> ColType { ColType::Text }
+
+impl Attribute {
+	// Null if the tag is missing or its value doesn't parse as the type
+	pub fn value(&self, tags: &Tags) -> Value {
+		let v = match tags.get(self.tag.as_str()) {
+			Some(v) => v.trim(),
+			None => return Value::Null,
+		};
+		match self.kind {
+			ColType::Text => Value::Text(v.to_string()),
+			ColType::Int => v.parse().map_or(Value::Null, Value::Int),
+			// width=2.5 m
+			ColType::Float => v.split_whitespace().next().and_then(|n| n.parse().ok()).filter(|f: &f64| f.is_finite()).map_or(Value::Null, Value::Float),
+			ColType::Bool => match v {
+				"yes" | "true" | "1" => Value::Bool(true),
+				"no" | "false" | "0" => Value::Bool(false),
+				_ => Value::Null,
+			},
+		}
+	}
+}
+
+fn default_merge() -> Vec<MergeKey> { vec![MergeKey::Category, MergeKey::Lanes] }
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+	pub name: String,
+	// a way is included if any of these modes may use it
+	pub modes: Vec<Mode>,
+	// country code for default access, see Access::default_for
+	#[serde(default)]
+	pub country: String,
+	// routable highway=* values
+	pub highways: HashMap<String, HighwayRule>,
+	// railway=* values of the rail network, see network.rs
+	#[serde(default)]
+	pub railways: HashMap<String, RailRule>,
+	// route=ferry ways and relations are included if set
+	#[serde(default)]
+	pub ferry: Option<FerryRule>,
+	// ways that match any of these are left out
+	#[serde(default)]
+	pub exclude: Vec<TagCond>,
+	// nodes that match any of these become graph vertice, besides barriers, signals and crossings (see vertex.rs)
+	#[serde(default)]
+	pub vertex_nodes: Vec<TagCond>,
+	// tags written into `tags` column
+	#[serde(default)]
+	pub keep_tags: Vec<String>,
+	#[serde(default)]
+	pub penalties: Vec<Penalty>,
+	// speed is maxspeed=* if it is set
+	#[serde(default)]
+	pub use_maxspeed: bool,
+	#[serde(default = "default_merge")]
+	pub merge_if_equal: Vec<MergeKey>,
+	// highway=* values that are walked across, not around, when they're areas (area=yes or multipolygons), see area.rs
+	#[serde(default)]
+	pub areas: Vec<String>,
+	// tags written as typed columns after the standard ones
+	#[serde(default)]
+	pub attributes: Vec<Attribute>,
+	// admin_level=* values of boundary relations that edges are tagged with, see regions.rs
+	#[serde(default)]
+	pub admin_levels: Vec<u8>,
+	// set when admin_levels are read, gives the country of ways if `country` is empty
+	#[serde(skip)]
+	pub regions: Option<Arc<RegionLookup>>,
+	// indoor=corridor and railway=platform ways; if set, ways on different levels are kept apart, see levels.rs
+	#[serde(default)]
+	pub indoor: Option<HighwayRule>,
+	// sidewalk edges along roads with sidewalk=* tags, see sidewalks.rs
+	#[serde(default)]
+	pub sidewalks: Option<SidewalkRule>,
+}
+
+// attributes of a routable way
+#[derive(Debug, Clone, PartialEq)]
+pub struct Road {
+	pub category: Category,
+	pub lanes: u8,
+	pub oneway: Directions,
+	pub maxspeed: MaxSpeed,
+	pub access: Access,
+	pub speed: u16,
+	pub tags: Arc<str>,
+	pub maxspeed_dir: PerDirection<MaxSpeed>,
+	pub lanes_dir: PerDirection<u8>,
+	pub maxspeed_conditional: PerDirection<Arc<str>>,
+	pub lanes_conditional: Arc<str>,
+	pub lanes_dir_conditional: PerDirection<Arc<str>>,
+	pub rail: RailAttrs,
+	pub conditions: Conditions,
+	// normalized level=*, only with the `indoor` rule
+	pub level: Arc<str>,
+	// from layer, bridge and tunnel
+	pub layer: i32,
+	// only with the `sidewalks` rule
+	pub sidewalk: Sidewalk,
+	// values of profile's attributes
+	pub attrs: Arc<[Value]>,
+}
+
+impl Default for Profile {
+	fn default() -> Self {
+		Self::builtin("all").unwrap().unwrap()
+	}
+}
+
+impl Profile {
+	pub fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+		let p: Self = toml::from_str(s)?;
+		let admin = admin_columns(&p.admin_levels);
+		for a in p.attributes.iter() {
+			if EDGE_COLUMNS.iter().any(|(n, _, _)| *n == a.tag) || a.tag.starts_with("WKT") || p.attributes.iter().filter(|b| b.tag == a.tag).count() > 1
+				|| admin.iter().any(|(n, _)| *n == a.tag) {
+				return Err(format!("attribute {} would duplicate a column", a.tag).into())
+			}
+		}
+		Ok(p)
+	}
+
+	pub fn builtin(name: &str) -> Option<Result<Self, Box<dyn Error>>> {
+		BUILTIN.iter().find(|(n, _)| *n == name).map(|(_, s)| Self::parse(s))
+	}
+
+	// name of a builtin profile or path to a .toml file
+	pub fn load(name_or_path: &str) -> Result<Self, Box<dyn Error>> {
+		match Self::builtin(name_or_path) {
+			Some(p) => p,
+			None => Self::parse(&std::fs::read_to_string(name_or_path)?),
+		}
+	}
+
+	// area ways are not roads, they're replaced by edges across the area
+	pub fn road(&self, tags: &Tags) -> Option<Road> {
+		if self.is_area(tags) {
+			return None
+		}
+		self.road_of(tags, &self.country)
+	}
+
+	// like road(), with defaults of another country
+	pub fn road_in(&self, tags: &Tags, country: &str) -> Option<Road> {
+		if self.is_area(tags) {
+			return None
+		}
+		self.road_of(tags, country)
+	}
+
+	// road() with defaults of the country the way starts in, if regions are read and no country is set.
+	// The way is kept even if the country's defaults forbid it, with no access, so that the graph doesn't depend on them.
+	pub fn way_road(&self, way: &Way) -> Option<Road> {
+		let road = self.road(&way.tags)?;
+		let country = match (&self.regions, way.nodes.first()) {
+			(Some(r), Some(n)) if self.country.is_empty() => r.country_of(NodeId(*n)),
+			_ => None,
+		};
+		match country {
+			Some(c) => Some(self.road_in(&way.tags, c).unwrap_or(Road { access: Access::NONE, ..road })),
+			None => Some(road),
+		}
+	}
+
+	// attributes of the edges across an area
+	pub fn area_road(&self, tags: &Tags) -> Option<Road> {
+		if !self.is_area(tags) {
+			return None
+		}
+		self.road_of(tags, &self.country)
+	}
+
+	pub fn is_area(&self, tags: &Tags) -> bool {
+		let area = tags.get("area").is_some_and(|v| &**v == "yes") || tags.get("type").is_some_and(|v| &**v == "multipolygon");
+		area && tags.get("highway").is_some_and(|h| self.areas.iter().any(|a| a == &**h))
+	}
+
+	fn road_of(&self, tags: &Tags, country: &str) -> Option<Road> {
+		if self.exclude.iter().any(|c| c.matches(tags)) {
+			return None
+		}
+		self.highway_road(tags, country).or_else(|| self.indoor_road(tags, country)).or_else(|| self.rail_road(tags, country)).or_else(|| self.ferry_road(tags))
+	}
+
+	fn highway_road(&self, tags: &Tags, country: &str) -> Option<Road> {
+		let rule = self.highways.get(&**tags.get("highway")?)?;
+		self.rule_road(rule, tags, country, Access::from_tags(tags, country))
+	}
+
+	// indoor=corridor and railway=platform ways with no highway=*, by the `indoor` rule
+	fn indoor_road(&self, tags: &Tags, country: &str) -> Option<Road> {
+		let rule = self.indoor.as_ref().filter(|_| !tags.contains_key("highway"))?;
+		let indoor = tags.get("indoor").is_some_and(|v| &**v == "corridor") || tags.get("railway").is_some_and(|v| &**v == "platform");
+		if !indoor {
+			return None
+		}
+		self.rule_road(rule, tags, country, Access::for_indoor(tags, country))
+	}
+
+	fn rule_road(&self, rule: &HighwayRule, tags: &Tags, country: &str, mut access: Access) -> Option<Road> {
+		if !self.modes.iter().any(|m| access.get(*m)) {
+			return None
+		}
+
+		// pedestrians are moved from the road to its sidewalks
+		let sidewalk = if self.sidewalks.is_some() { Sidewalk::from_tags(tags) } else { Sidewalk::default() };
+		if sidewalk.any() {
+			access.foot = false;
+		}
+
+		let maxspeed = MaxSpeed::from_tags(tags, country);
+		let base = match maxspeed.0 {
+			Some(v) if self.use_maxspeed && v != UNLIMITED => v,
+			_ => rule.speed,
+		} as f32;
+		let factor: f32 = self.penalties.iter().filter(|p| p.tag.matches(tags)).map(|p| p.factor).product();
+
+		let oneway = Directions::from_tags(tags);
+		let (lanes_conditional, lanes_dir_conditional) = lanes_conditional(tags);
+
+		Some(Road {
+			category: Category::Road(rule.category.clone()),
+			lanes: tags.get("lanes").and_then(|v| parse_lanes(v)).unwrap_or(rule.lanes),
+			oneway,
+			maxspeed,
+			access,
+			speed: (base * factor).round() as u16,
+			tags: self.kept_tags(tags),
+			maxspeed_dir: MaxSpeed::per_direction(tags, country),
+			lanes_dir: lanes_per_direction(tags, oneway.car, rule.lanes),
+			maxspeed_conditional: maxspeed_conditional(tags, country),
+			lanes_conditional,
+			lanes_dir_conditional,
+			rail: RailAttrs::default(),
+			conditions: Conditions::from_tags(tags),
+			level: self.level_of(tags),
+			layer: way_layer(tags),
+			sidewalk,
+			attrs: self.attr_values(tags),
+		})
+	}
+
+	// railways are in the graph for any modes, but no road mode may use them
+	fn rail_road(&self, tags: &Tags, country: &str) -> Option<Road> {
+		let rule = self.railways.get(&**tags.get("railway")?)?;
+		let maxspeed = MaxSpeed::from_tags(tags, country);
+		let speed = match maxspeed.0 {
+			Some(v) if self.use_maxspeed && v != UNLIMITED => v,
+			_ => rule.speed,
+		};
+		Some(Road {
+			category: Category::Rail(rule.category),
+			lanes: 1,
+			oneway: Directions::from_tags(tags),
+			maxspeed,
+			access: Access::NONE,
+			speed,
+			tags: self.kept_tags(tags),
+			maxspeed_dir: MaxSpeed::per_direction(tags, country),
+			lanes_dir: PerDirection { forward: 1, backward: 1 },
+			maxspeed_conditional: maxspeed_conditional(tags, country),
+			lanes_conditional: Arc::from(""),
+			lanes_dir_conditional: PerDirection { forward: Arc::from(""), backward: Arc::from("") },
+			rail: RailAttrs::from_tags(tags),
+			conditions: Conditions::default(),
+			level: Arc::from(""),
+			layer: way_layer(tags),
+			sidewalk: Sidewalk::default(),
+			attrs: self.attr_values(tags),
+		})
+	}
+
+	fn ferry_road(&self, tags: &Tags) -> Option<Road> {
+		let rule = self.ferry.as_ref().filter(|_| is_ferry(tags))?;
+		let access = Access::for_ferry(tags);
+		if !self.modes.iter().any(|m| access.get(*m)) {
+			return None
+		}
+		let empty: Arc<str> = Arc::from("");
+		Some(Road {
+			category: Category::Ferry(if access.car || access.bus { FerryCat::Vehicle } else { FerryCat::Passenger }),
+			lanes: 1,
+			oneway: Directions::from_tags(tags),
+			maxspeed: MaxSpeed(None),
+			access,
+			speed: rule.speed,
+			tags: self.kept_tags(tags),
+			maxspeed_dir: PerDirection::default(),
+			lanes_dir: PerDirection { forward: 1, backward: 1 },
+			maxspeed_conditional: PerDirection { forward: empty.clone(), backward: empty.clone() },
+			lanes_conditional: empty.clone(),
+			lanes_dir_conditional: PerDirection { forward: empty.clone(), backward: empty },
+			rail: RailAttrs::default(),
+			conditions: Conditions::from_tags(tags),
+			level: Arc::from(""),
+			layer: way_layer(tags),
+			sidewalk: Sidewalk::default(),
+			attrs: self.attr_values(tags),
+		})
+	}
+
+	fn kept_tags(&self, tags: &Tags) -> Arc<str> {
+		let kept: Vec<String> = self.keep_tags.iter()
+			.filter_map(|k| tags.get(k.as_str()).map(|v| format!("{}={}", k, v)))
+			.collect();
+		Arc::from(kept.join(";").as_str())
+	}
+
+	fn level_of(&self, tags: &Tags) -> Arc<str> {
+		if self.indoor.is_some() { level_of(tags) } else { Arc::from("") }
+	}
+
+	fn attr_values(&self, tags: &Tags) -> Arc<[Value]> {
+		self.attributes.iter().map(|a| a.value(tags)).collect()
+	}
+
+	// columns written after the standard ones: attributes, then admin regions
+	pub fn extra_columns(&self) -> Vec<(String, ColType)> {
+		self.attributes.iter().map(|a| (a.tag.clone(), a.kind))
+			.chain(admin_columns(&self.admin_levels))
+			.collect()
+	}
+
+	pub fn is_vertex_node(&self, tags: &Tags) -> bool {
+		self.vertex_nodes.iter().any(|c| c.matches(tags))
+	}
+}
+
+#[cfg(test)]
+mod profile_tests {
+	use super::*;
+	use crate::tags;
+
+	#[test]
+	fn test_builtin_profiles() {
+		for (name, _) in BUILTIN.iter() {
+			Profile::load(name).unwrap();
+		}
+		let car = Profile::load("car").unwrap();
+		let foot = Profile::load("foot").unwrap();
+		let fw = tags(&[("highway", "footway")]);
+		assert!(car.road(&fw).is_none());
+		assert!(foot.road(&fw).is_some());
+
+		let r = car.road(&tags(&[("highway", "primary"), ("maxspeed", "90"), ("lanes", "3"), ("surface", "gravel")])).unwrap();
+		assert_eq!((r.category, r.lanes, r.maxspeed, r.speed), (Category::Road(RoadCat::Primary), 3, MaxSpeed(Some(90)), 63));
+		assert!(car.road(&tags(&[("highway", "service"), ("area", "yes")])).is_none());
+		let square = tags(&[("highway", "pedestrian"), ("area", "yes")]);
+		assert!(foot.road(&square).is_none() && foot.area_road(&square).is_some());
+		assert!(foot.area_road(&tags(&[("highway", "pedestrian")])).is_none());
+
+		let all = Profile::default();
+		let tram = all.road(&tags(&[("railway", "tram"), ("gauge", "1524")])).unwrap();
+		assert_eq!((tram.category, tram.access, &*tram.rail.gauge), (Category::Rail(RailCat::Tram), Access::NONE, "1524"));
+		assert!(car.road(&tags(&[("railway", "rail")])).is_none());
+		assert!(car.road(&tags(&[("route", "ferry")])).is_none());
+		let ferry = car.road(&tags(&[("route", "ferry"), ("motor_vehicle", "yes")])).unwrap();
+		assert_eq!((ferry.category, ferry.speed), (Category::Ferry(FerryCat::Vehicle), 15));
+
+		let bike = Profile::load("bike").unwrap();
+		let r = bike.road(&tags(&[("highway", "track"), ("surface", "gravel")])).unwrap();
+		assert!(r.speed < bike.highways["track"].speed);
+	}
+
+	#[test]
+	fn test_profile_file() {
+		let p = Profile::parse(r#"
+			name = "test"
+			modes = ["Bus"]
+			keep_tags = ["name", "ref"]
+			merge_if_equal = ["category", "tags"]
+			[highways.busway]
+			category = "Busway"
+			speed = 40
+		"#).unwrap();
+		let r = p.road(&tags(&[("highway", "busway"), ("name", "Main")])).unwrap();
+		assert_eq!((&*r.tags, r.lanes, r.speed), ("name=Main", 1, 40));
+		assert!(p.road(&tags(&[("highway", "residential")])).is_none());
+		assert!(Profile::parse("name = \"x\"\nmodes = []\nhighways = {}\nunknown = 1").is_err());
+	}
+}
@@ -0,0 +1,164 @@
+use csv::{Reader, Writer, StringRecord};
+use serde::{Serialize, Deserialize};
+use osmgraph::{Mode, OneWay, sidecar_path};
+use osmgraph::components::{ModeEdge, find_islands};
+use osmgraph::mode::modes_str;
+use osmgraph::schema::schema_version;
+
+use std::{collections::HashMap, error::Error};
+
+const MIN_SIZE: usize = 50;
+
+// columns that prune needs, others are copied as is
+#[allow(non_snake_case)]
+#[derive(Deserialize)]
+struct PruneEdge {
+	#[serde(default)]
+	id: Option<u64>,
+	node1: i64,
+	node2: i64,
+	WKT: String,
+	#[serde(default)]
+	oneway: OneWay,
+	#[serde(default)]
+	oneway_bike: OneWay,
+	#[serde(default)]
+	oneway_foot: OneWay,
+	#[serde(default)]
+	oneway_bus: OneWay,
+	#[serde(default = "allowed")]
+	car: bool,
+	#[serde(default = "allowed")]
+	bike: bool,
+	#[serde(default = "allowed")]
+	foot: bool,
+	#[serde(default = "allowed")]
+	bus: bool,
+}
+
+fn allowed() -> bool { true }
+
+impl PruneEdge {
+	fn mode_edge(&self, mode: Mode) -> ModeEdge {
+		let (oneway, allowed) = match mode {
+			Mode::Car => (self.oneway, self.car),
+			Mode::Bike => (self.oneway_bike, self.bike),
+			Mode::Foot => (self.oneway_foot, self.foot),
+			Mode::Bus => (self.oneway_bus, self.bus),
+		};
+		ModeEdge { node1: self.node1, node2: self.node2, oneway, allowed }
+	}
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize)]
+struct IslandRow {
+	mode: Mode,
+	island: usize,
+	vertice: usize,
+	edges: usize,
+	edge_ids: String,
+	WKT: String,
+}
+
+// LINESTRING(...) x N -> MULTILINESTRING((...), ...)
+fn multilinestring<'a>(lines: impl Iterator<Item = &'a str>) -> String {
+	let parts: Vec<&str> = lines.map(|l| l.trim().trim_start_matches("LINESTRING").trim()).collect();
+	format!("MULTILINESTRING({})", parts.join(","))
+}
+
+fn prune(input: &str, output: &str, min_size: usize, flag: bool) -> Result<(), Box<dyn Error>> {
+	let mut rd = Reader::from_path(input)?;
+	let headers = rd.headers()?.clone();
+	let version = schema_version(&headers).ok_or("not a graph file: node1, node2, WKT, category or lanes column is missing")?;
+
+	let mut records = vec![];
+	let mut edges = vec![];
+	for rec in rd.records() {
+		let rec = rec?;
+		edges.push(rec.deserialize::<PruneEdge>(Some(&headers))?);
+		records.push(rec);
+	}
+
+	// before schema v5 there are no access columns, and the graph is routed by car only
+	let modes: &[Mode] = if version >= 5 { &Mode::ALL } else { &[Mode::Car] };
+	let mut islands_of: HashMap<usize, Vec<Mode>> = HashMap::new();
+	let mut report = Writer::from_path(sidecar_path(output, "islands"))?;
+	for mode in modes.iter() {
+		let mode_edges: Vec<ModeEdge> = edges.iter().map(|e| e.mode_edge(*mode)).collect();
+		let islands = find_islands(&mode_edges, min_size);
+		println!("{}: {} islands, {} edges", mode, islands.len(), islands.iter().map(|i| i.edges.len()).sum::<usize>());
+		for (n, island) in islands.iter().enumerate() {
+			for i in island.edges.iter() {
+				islands_of.entry(*i).or_default().push(*mode);
+			}
+			report.serialize(IslandRow {
+				mode: *mode,
+				island: n + 1,
+				vertice: island.vertice,
+				edges: island.edges.len(),
+				edge_ids: island.edges.iter().filter_map(|i| edges[*i].id).map(|id| id.to_string()).collect::<Vec<_>>().join(";"),
+				WKT: multilinestring(island.edges.iter().map(|i| edges[*i].WKT.as_str())),
+			})?;
+		}
+	}
+
+	// --flag adds `island` column with the modes, otherwise the modes are disallowed on the edge,
+	// and edges that no mode may use are dropped
+	let column = |name: &str| headers.iter().position(|h| h == name);
+	let mut wr = Writer::from_path(output)?;
+	let mut out_headers = headers.clone();
+	if flag {
+		out_headers.push_field("island");
+	}
+	wr.write_record(&out_headers)?;
+	let mut dropped = 0;
+	for (i, rec) in records.iter().enumerate() {
+		let island = islands_of.get(&i).map(|m| m.as_slice()).unwrap_or(&[]);
+		if flag {
+			let mut rec = rec.clone();
+			rec.push_field(&modes_str(island));
+			wr.write_record(&rec)?;
+			continue
+		}
+		// railways are open to no mode from the start, they're kept
+		let remaining: Vec<Mode> = modes.iter().cloned().filter(|m| edges[i].mode_edge(*m).allowed && !island.contains(m)).collect();
+		if remaining.is_empty() && !island.is_empty() {
+			dropped += 1;
+			continue
+		}
+		let mut fields: Vec<&str> = rec.iter().collect();
+		for m in island.iter() {
+			if let Some(c) = column(&m.to_string().to_lowercase()) {
+				fields[c] = "false";
+			}
+		}
+		wr.write_record(&StringRecord::from(fields))?;
+	}
+	println!("edges: {}, dropped: {}", records.len(), dropped);
+	Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+	let mut args: Vec<String> = std::env::args().collect();
+	let flag = args.iter().any(|a| a == "--flag");
+	args.retain(|a| a != "--flag");
+	// --min-size N: components with less vertice are islands
+	let min_size = match args.iter().position(|a| a == "--min-size") {
+		Some(i) => {
+			let n = args.get(i + 1).ok_or("--min-size needs a number of vertice")?.parse::<usize>()?;
+			args.drain(i..i + 2);
+			n
+		},
+		None => MIN_SIZE,
+	};
+	match args.len() {
+		3 => prune(&args[1], &args[2], min_size, flag)?,
+		_ => println!("usage: prune [--min-size N] [--flag] INPUT.CSV OUTPUT.CSV\n\
+			Finds strongly connected components of each mode with less than N vertice (default {}),\n\
+			disallows the modes on their edges and drops the edges that no mode can use.\n\
+			With --flag, edges are kept and get `island` column with the modes instead.\n\
+			Islands are listed in OUTPUT_islands.csv", MIN_SIZE),
+	};
+	Ok(())
+}
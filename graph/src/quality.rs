@@ -0,0 +1,336 @@
+use osmio2::objects::Tags;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/* Checks of a built graph, used by `validate`: broken geometries, self-loops, duplicates,
+crossings without a shared node on the same layer, dead ends close to other edges, and odd maxspeeds. */
+
+// grid cells per degree, ~110 m by latitude
+const CELLS: f64 = 1000.0;
+// shorter edges are zero-length, metres
+const ZERO_LENGTH: f64 = 0.01;
+pub const MIN_MAXSPEED: u16 = 5;
+pub const MAX_MAXSPEED: u16 = 150;
+
+type Pt = (f64, f64);
+
+// edge as the checks need it
+#[derive(Debug, Clone, Default)]
+pub struct QualityEdge {
+	pub id: u64,
+	pub node1: i64,
+	pub node2: i64,
+	pub points: Vec<Pt>,
+	// all node ids, if the file has them
+	pub nodes: Vec<i64>,
+	// `layer` column, or from layer, bridge and tunnel in tags for older files
+	pub layer: i32,
+	// edges across areas, ferries and transfers are straight lines that may cross anything
+	pub straight: bool,
+	// maxspeed columns with values, e.g. ("maxspeed_forward", "300")
+	pub maxspeed: Vec<(&'static str, String)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity { Error, Warning }
+
+// row of the issues file
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Issue {
+	pub kind: &'static str,
+	pub severity: Severity,
+	// `;`-separated
+	pub edges: String,
+	pub node: Option<i64>,
+	pub description: String,
+	#[serde(skip)]
+	pub point: Option<Pt>,
+	pub WKT: String,
+}
+
+impl Issue {
+	fn new(kind: &'static str, severity: Severity, edges: &[u64], node: Option<i64>, description: String, point: Option<Pt>) -> Self {
+		Self {
+			kind, severity, node, description, point,
+			edges: edges.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(";"),
+			WKT: point.map_or(String::new(), |p| format!("POINT({} {})", p.0, p.1)),
+		}
+	}
+}
+
+// layer of a way, bridges and tunnels without layer are 1 and -1
+pub fn way_layer(tags: &Tags) -> i32 {
+	layer(|k| tags.get(k).map(|v| &**v))
+}
+
+// layer of tags column ("layer=1;name=...")
+pub fn layer_of(tags: &str) -> i32 {
+	layer(|k| tags.split(';').find_map(|kv| kv.split_once('=').filter(|(k2, _)| *k2 == k).map(|(_, v)| v)))
+}
+
+fn layer<'a>(tag: impl Fn(&str) -> Option<&'a str>) -> i32 {
+	if let Some(l) = tag("layer").and_then(|v| v.trim().parse().ok()) {
+		return l
+	}
+	match (tag("bridge"), tag("tunnel")) {
+		(Some(b), _) if b != "no" => 1,
+		(_, Some(t)) if t != "no" => -1,
+		_ => 0,
+	}
+}
+
+// LINESTRING(83 54,83.1 54.1) -> points
+pub fn parse_linestring(wkt: &str) -> Vec<Pt> {
+	let inner = match (wkt.find('('), wkt.rfind(')')) {
+		(Some(a), Some(b)) if a < b => &wkt[a + 1..b],
+		_ => return vec![],
+	};
+	inner.split(',').filter_map(|p| {
+		let mut xy = p.split_whitespace().map(|v| v.parse::<f64>());
+		match (xy.next(), xy.next()) {
+			(Some(Ok(x)), Some(Ok(y))) => Some((x, y)),
+			_ => None,
+		}
+	}).collect()
+}
+
+fn cell(p: Pt) -> (i64, i64) {
+	((p.0 * CELLS).floor() as i64, (p.1 * CELLS).floor() as i64)
+}
+
+// metres, in a local projection around `o`
+fn local(o: Pt, p: Pt) -> Pt {
+	let k = 6_371_000.0f64.to_radians();
+	((p.0 - o.0) * k * o.1.to_radians().cos(), (p.1 - o.1) * k)
+}
+
+fn length(points: &[Pt]) -> f64 {
+	points.windows(2).map(|w| { let d = local(w[0], w[1]); d.0.hypot(d.1) }).sum()
+}
+
+fn cross(o: Pt, a: Pt, b: Pt) -> f64 {
+	(a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0)
+}
+
+// intersection point of segments ab and cd, if they cross inside both of them (touching doesn't count)
+fn crossing(a: Pt, b: Pt, c: Pt, d: Pt) -> Option<Pt> {
+	let (d1, d2) = (cross(a, b, c), cross(a, b, d));
+	let (d3, d4) = (cross(c, d, a), cross(c, d, b));
+	if d1 * d2 < 0.0 && d3 * d4 < 0.0 {
+		let t = d3 / (d3 - d4);
+		Some((a.0 + t * (b.0 - a.0), a.1 + t * (b.1 - a.1)))
+	} else { None }
+}
+
+// distance from p to segment ab in metres
+fn to_segment(p: Pt, a: Pt, b: Pt) -> f64 {
+	let (a, b) = (local(p, a), local(p, b));
+	let len2 = (b.0 - a.0).powi(2) + (b.1 - a.1).powi(2);
+	let t = if len2 == 0.0 { 0.0 } else { (-(a.0 * (b.0 - a.0) + a.1 * (b.1 - a.1)) / len2).clamp(0.0, 1.0) };
+	(a.0 + t * (b.0 - a.0)).hypot(a.1 + t * (b.1 - a.1))
+}
+
+// segments of the valid edges by grid cells they touch: (edge index, segment index)
+fn segment_grid(edges: &[QualityEdge], valid: &[usize]) -> HashMap<(i64, i64), Vec<(usize, usize)>> {
+	let mut grid: HashMap<(i64, i64), Vec<(usize, usize)>> = HashMap::new();
+	for (i, e) in valid.iter().map(|i| (*i, &edges[*i])).filter(|(_, e)| !e.straight) {
+		for (j, w) in e.points.windows(2).enumerate() {
+			let (c1, c2) = (cell(w[0]), cell(w[1]));
+			for x in c1.0.min(c2.0)..=c1.0.max(c2.0) {
+				for y in c1.1.min(c2.1)..=c1.1.max(c2.1) {
+					grid.entry((x, y)).or_default().push((i, j));
+				}
+			}
+		}
+	}
+	grid
+}
+
+fn middle(points: &[Pt]) -> Option<Pt> {
+	points.get(points.len() / 2).cloned()
+}
+
+// all the checks, issues are in the order of the checks and then of the edges
+pub fn find_issues(edges: &[QualityEdge], snap: f64) -> Vec<Issue> {
+	let mut issues = vec![];
+	let mut valid = vec![];
+	for (i, e) in edges.iter().enumerate() {
+		if e.points.len() < 2 {
+			issues.push(Issue::new("few_points", Severity::Error, &[e.id], None,
+				format!("{} points, some nodes have no coordinates", e.points.len()), e.points.first().cloned()));
+		} else if length(&e.points) < ZERO_LENGTH {
+			issues.push(Issue::new("zero_length", Severity::Error, &[e.id], Some(e.node1), "edge has zero length".to_string(), Some(e.points[0])));
+		} else {
+			valid.push(i);
+		}
+	}
+	for e in valid.iter().map(|i| &edges[*i]).filter(|e| e.node1 == e.node2) {
+		issues.push(Issue::new("self_loop", Severity::Warning, &[e.id], Some(e.node1), "edge starts and ends at the same node".to_string(), middle(&e.points)));
+	}
+
+	// same ends and the same nodes (or geometry, in older files), in either direction
+	let mut same: HashMap<(i64, i64, String), Vec<usize>> = HashMap::new();
+	for i in valid.iter() {
+		let e = &edges[*i];
+		let rev = (e.node1, e.nodes.first()) > (e.node2, e.nodes.last());
+		let shape = if e.nodes.is_empty() {
+			let pts: Vec<String> = e.points.iter().map(|p| format!("{} {}", p.0, p.1)).collect();
+			if rev { pts.into_iter().rev().collect::<Vec<_>>().join(",") } else { pts.join(",") }
+		} else {
+			let ids: Vec<String> = e.nodes.iter().map(|n| n.to_string()).collect();
+			if rev { ids.into_iter().rev().collect::<Vec<_>>().join(";") } else { ids.join(";") }
+		};
+		same.entry((e.node1.min(e.node2), e.node1.max(e.node2), shape)).or_default().push(*i);
+	}
+	let mut groups: Vec<&Vec<usize>> = same.values().filter(|g| g.len() > 1).collect();
+	groups.sort();
+	for g in groups {
+		let ids: Vec<u64> = g.iter().map(|i| edges[*i].id).collect();
+		issues.push(Issue::new("duplicate", Severity::Warning, &ids, None, format!("{} edges with the same nodes", g.len()), middle(&edges[g[0]].points)));
+	}
+
+	for e in valid.iter().map(|i| &edges[*i]) {
+		let odd: Vec<String> = e.maxspeed.iter().filter(|(_, v)| match v.parse::<u16>() {
+			Ok(s) => !(MIN_MAXSPEED..=MAX_MAXSPEED).contains(&s),
+			Err(_) => !v.is_empty() && v != "none",
+		}).map(|(k, v)| format!("{}={}", k, v)).collect();
+		if !odd.is_empty() {
+			issues.push(Issue::new("maxspeed", Severity::Warning, &[e.id], None, format!("unreasonable {}", odd.join(", ")), middle(&e.points)));
+		}
+	}
+
+	// crossings: segments of different edges on the same layer that cross inside, not at a node
+	let grid = segment_grid(edges, &valid);
+	let mut cells: Vec<&(i64, i64)> = grid.keys().collect();
+	cells.sort();
+	let mut seen = HashSet::new();
+	let mut crossings = vec![];
+	for c in cells {
+		let segs = &grid[c];
+		for (k, (i, si)) in segs.iter().enumerate() {
+			for (j, sj) in segs[k + 1..].iter() {
+				let (a, b) = (&edges[*i], &edges[*j]);
+				if i == j || a.layer != b.layer || !seen.insert((*i, *si, *j, *sj)) {
+					continue
+				}
+				if let Some(p) = crossing(a.points[*si], a.points[si + 1], b.points[*sj], b.points[sj + 1]) {
+					crossings.push(((*i.min(j), *i.max(j)), p));
+				}
+			}
+		}
+	}
+	crossings.sort_by_key(|x| x.0);
+	crossings.dedup_by(|x, y| x.0 == y.0 && (x.1.0 - y.1.0).abs() < 1e-9 && (x.1.1 - y.1.1).abs() < 1e-9);
+	for ((i, j), p) in crossings {
+		issues.push(Issue::new("crossing", Severity::Warning, &[edges[i].id, edges[j].id], None,
+			format!("edges cross without a shared node on layer {}", edges[i].layer), Some(p)));
+	}
+
+	// dead ends closer than `snap` metres to other edges, likely missing connections
+	let mut degree: HashMap<i64, usize> = HashMap::new();
+	for e in valid.iter().map(|i| &edges[*i]) {
+		*degree.entry(e.node1).or_default() += 1;
+		*degree.entry(e.node2).or_default() += 1;
+	}
+	let reach = (snap / 111_000.0 * CELLS).ceil() as i64 + 1;
+	for i in valid.iter() {
+		let e = &edges[*i];
+		for (node, p) in [(e.node1, e.points[0]), (e.node2, e.points[e.points.len() - 1])] {
+			if degree[&node] != 1 {
+				continue
+			}
+			let c = cell(p);
+			let mut best: Option<(f64, usize)> = None;
+			for x in c.0 - reach..=c.0 + reach {
+				for y in c.1 - reach..=c.1 + reach {
+					for (j, s) in grid.get(&(x, y)).map_or(&[][..], |v| v.as_slice()) {
+						if j == i || edges[*j].layer != e.layer {
+							continue
+						}
+						let d = to_segment(p, edges[*j].points[*s], edges[*j].points[s + 1]);
+						if d < snap && best.is_none_or(|b| d < b.0) {
+							best = Some((d, *j));
+						}
+					}
+				}
+			}
+			if let Some((d, j)) = best {
+				issues.push(Issue::new("dangling", Severity::Warning, &[e.id, edges[j].id], Some(node),
+					format!("dead end {:.1} m from edge {}", d, edges[j].id), Some(p)));
+			}
+		}
+	}
+	issues
+}
+
+#[cfg(test)]
+mod quality_tests {
+	use super::*;
+	use crate::{build_edges_from, single_pass::read_once, Edge, Profile};
+	use geo::LineString;
+	use osmio2::reader::OsmXmlReader;
+	use std::io::{BufReader, Cursor, Read};
+
+	fn edge(id: u64, node1: i64, node2: i64, points: &[Pt]) -> QualityEdge {
+		QualityEdge { id, node1, node2, points: points.to_vec(), ..Default::default() }
+	}
+
+	#[test]
+	fn test_issues() {
+		assert_eq!(parse_linestring("LINESTRING(83 54,83.5 54.25)"), vec![(83.0, 54.0), (83.5, 54.25)]);
+		assert_eq!(parse_linestring("LINESTRING EMPTY"), vec![]);
+		assert_eq!((layer_of("name=A;layer=-2"), layer_of("bridge=yes"), layer_of("tunnel=no;name=B")), (-2, 1, 0));
+
+		let mut edges = vec![
+			// a cross of 1-2 and 3-4 without a shared node
+			edge(1, 1, 2, &[(83.0, 54.0), (83.002, 54.0)]),
+			edge(2, 3, 4, &[(83.001, 53.999), (83.001, 54.001)]),
+			// a bridge over it, and a dead end 2 m short of edge 1
+			edge(3, 5, 6, &[(83.0015, 53.999), (83.0015, 54.001)]),
+			edge(4, 7, 8, &[(83.0005, 54.0005), (83.0005, 54.000018)]),
+			edge(5, 9, 9, &[(83.01, 54.0), (83.011, 54.0), (83.01, 54.001), (83.01, 54.0)]),
+			edge(6, 10, 11, &[(83.02, 54.0)]),
+			edge(7, 12, 13, &[(83.03, 54.0), (83.03, 54.0)]),
+			edge(8, 14, 15, &[(83.04, 54.0), (83.041, 54.0)]),
+			edge(9, 15, 14, &[(83.041, 54.0), (83.04, 54.0)]),
+		];
+		edges[2].layer = 1;
+		edges[7].maxspeed = vec![("maxspeed", "300".to_string()), ("maxspeed_forward", "none".to_string())];
+		let issues = find_issues(&edges, 5.0);
+		let short: Vec<(&str, &str, Option<i64>)> = issues.iter().map(|i| (i.kind, i.edges.as_str(), i.node)).collect();
+		assert_eq!(short, vec![
+			("few_points", "6", None), ("zero_length", "7", Some(12)), ("self_loop", "5", Some(9)), ("duplicate", "8;9", None),
+			("maxspeed", "8", None), ("crossing", "1;2", None),
+			("dangling", "4;1", Some(8)),
+		]);
+		assert_eq!((issues[5].severity, issues[5].WKT.as_str()), (Severity::Warning, "POINT(83.001 54)"));
+		assert_eq!(issues[6].description, "dead end 2.0 m from edge 1");
+	}
+
+	#[test]
+	fn test_built_layers() {
+		// a bridge and a tunnel over a road, with tags that the default profile doesn't keep
+		let text = r#"<osm><node id="1" lat="54.0" lon="83.0"/><node id="2" lat="54.0" lon="83.002"/>
+<node id="3" lat="53.999" lon="83.001"/><node id="4" lat="54.001" lon="83.001"/>
+<node id="5" lat="53.999" lon="83.0015"/><node id="6" lat="54.001" lon="83.0015"/>
+<way id="10"><nd ref="1"/><nd ref="2"/><tag k="highway" v="residential"/></way>
+<way id="11"><nd ref="3"/><nd ref="4"/><tag k="highway" v="primary"/><tag k="bridge" v="yes"/></way>
+<way id="12"><nd ref="5"/><nd ref="6"/><tag k="highway" v="primary"/><tag k="tunnel" v="yes"/><tag k="layer" v="-2"/></way></osm>"#;
+		let profile = Profile::default();
+		let rd: Box<dyn Read + Send> = Box::new(Cursor::new(text.as_bytes().to_vec()));
+		let mut sp = read_once(OsmXmlReader::new(BufReader::new(rd)), &profile).unwrap();
+		let mut edges = vec![];
+		build_edges_from(sp.ways.reader().unwrap(), &sp.vertice, &profile, |e| {
+			let points: Vec<Pt> = e.nodes.iter().map(|n| sp.node_coords[n]).collect();
+			let row = Edge::new(edges.len() as u64 + 1, &e, LineString::from(points.clone()));
+			edges.push(QualityEdge { id: row.id, node1: row.node1.0, node2: row.node2.0, points, layer: row.layer, ..Default::default() });
+			Ok(())
+		}).unwrap();
+		let mut layers: Vec<i32> = edges.iter().map(|e| e.layer).collect();
+		layers.sort();
+		assert_eq!(layers, vec![-2, 0, 1]);
+		assert!(find_issues(&edges, 5.0).is_empty());
+	}
+}
@@ -0,0 +1,333 @@
+use geo::LineString;
+use osmio2::objects::{NodeId, ObjType, Relation, Tags, Way, WayId};
+use serde::Serialize;
+use std::{
+	collections::HashMap,
+	sync::Arc,
+};
+
+use crate::NodeCoords;
+use crate::area::{join_rings, in_ring, Rings};
+use crate::output::{ColType, Value};
+
+/* Administrative regions: boundary=administrative relations at the profile's admin_levels, joined into polygons.
+Edges get the id and name of the region of each level their middle point is in, and `admin_border` if some of their
+points are in other regions. The country code of the region a way starts in is used for the profile's defaults. */
+
+type Pt = (f64, f64);
+// index of the region of each level, or none
+type AtLevels = Vec<Option<usize>>;
+
+// horizontal bands of a region's segments, so that a point is tested against a part of the boundary
+const BANDS: usize = 256;
+// grid of region bounding boxes, degrees
+const CELL: f64 = 1.0;
+
+// `admin4`, `admin4_name` for every level and `admin_border`, written after the profile's attributes
+pub fn admin_columns(levels: &[u8]) -> Vec<(String, ColType)> {
+	if levels.is_empty() {
+		return vec![]
+	}
+	levels.iter()
+		.flat_map(|l| [(format!("admin{}", l), ColType::Int), (format!("admin{}_name", l), ColType::Text)])
+		.chain([("admin_border".to_string(), ColType::Bool)])
+		.collect()
+}
+
+// relation with member ways joined into rings
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegionRings {
+	pub id: i64,
+	pub level: u8,
+	pub tags: Tags,
+	pub outer: Rings,
+	pub inner: Rings,
+}
+
+impl RegionRings {
+	pub fn nodes(&self) -> impl Iterator<Item = &NodeId> {
+		self.outer.iter().chain(self.inner.iter()).flatten()
+	}
+}
+
+fn admin_level(tags: &Tags) -> Option<u8> {
+	if tags.get("boundary").is_none_or(|b| &**b != "administrative") {
+		return None
+	}
+	tags.get("admin_level")?.parse().ok()
+}
+
+// like AreaCollector, relations must come first
+pub struct RegionCollector<'a> {
+	levels: &'a [u8],
+	regions: Vec<RegionRings>,
+	// way -> (region, is inner)
+	members: HashMap<WayId, Vec<(usize, bool)>>,
+}
+
+impl<'a> RegionCollector<'a> {
+	pub fn new(levels: &'a [u8]) -> Self {
+		Self { levels, regions: vec![], members: HashMap::new() }
+	}
+
+	pub fn relation(&mut self, rel: &Relation) {
+		let level = match admin_level(&rel.tags) {
+			Some(l) if self.levels.contains(&l) => l,
+			_ => return,
+		};
+		let idx = self.regions.len();
+		self.regions.push(RegionRings { id: rel.attrs.id, level, tags: rel.tags.clone(), outer: vec![], inner: vec![] });
+		for m in rel.members.iter().filter(|m| m.mtype == ObjType::Way) {
+			self.members.entry(WayId(m.mref)).or_default().push((idx, &*m.mrole == "inner"));
+		}
+	}
+
+	pub fn enabled(&self) -> bool {
+		!self.levels.is_empty()
+	}
+
+	// boundaries run along rivers, coastlines and anything else, so any way that isn't a road
+	// must be kept till all relations are read (single-pass build; roads are read back from the spool)
+	pub fn may_need(&self, _way: &Way) -> bool {
+		self.enabled()
+	}
+
+	pub fn way(&mut self, way: &Way) {
+		if let Some(ms) = self.members.get(&WayId(way.attrs.id)) {
+			let nodes: Vec<NodeId> = way.nodes.iter().map(|n| NodeId(*n)).collect();
+			for (idx, inner) in ms.iter() {
+				let r = &mut self.regions[*idx];
+				if *inner { r.inner.push(nodes.clone()) } else { r.outer.push(nodes.clone()) }
+			}
+		}
+	}
+
+	// regions with unclosed rings (e.g. cut by the extract's border) are skipped
+	pub fn finish(self) -> Vec<RegionRings> {
+		let total = self.regions.len();
+		let regions: Vec<RegionRings> = self.regions.into_iter().filter_map(|r| match (join_rings(r.outer), join_rings(r.inner)) {
+			(Some(outer), Some(inner)) if !outer.is_empty() => Some(RegionRings { outer, inner, ..r }),
+			_ => None,
+		}).collect();
+		println!("admin regions: {}, with unclosed rings: {}", regions.len(), total - regions.len());
+		regions
+	}
+}
+
+#[derive(Debug)]
+pub struct Region {
+	pub id: i64,
+	pub level: u8,
+	pub name: String,
+	// ISO3166-1 or ISO3166-2 tag, e.g. RU or RU-NVS
+	pub code: String,
+	bbox: [f64; 4],
+	// ring segments by bands of latitude
+	bands: Vec<Vec<(Pt, Pt)>>,
+}
+
+impl Region {
+	// None if some ring node has no coordinates
+	pub fn new(r: &RegionRings, coords: &[&NodeCoords]) -> Option<Self> {
+		let coord = |n: &NodeId| coords.iter().find_map(|c| c.get(n)).cloned();
+		let rings: Vec<Vec<Pt>> = r.outer.iter().chain(r.inner.iter())
+			.map(|ring| ring.iter().map(coord).collect::<Option<_>>())
+			.collect::<Option<_>>()?;
+		let bbox = rings.iter().flatten().fold([f64::MAX, f64::MAX, f64::MIN, f64::MIN], |b, p| [b[0].min(p.0), b[1].min(p.1), b[2].max(p.0), b[3].max(p.1)]);
+		let mut bands = vec![vec![]; BANDS];
+		for w in rings.iter().flat_map(|ring| ring.windows(2)) {
+			let (a, b) = (band_of(&bbox, w[0].1), band_of(&bbox, w[1].1));
+			for bs in bands[a.min(b)..=a.max(b)].iter_mut() {
+				bs.push((w[0], w[1]));
+			}
+		}
+		let get = |k: &str| r.tags.get(k).map(|v| v.to_string());
+		Some(Self {
+			id: r.id,
+			level: r.level,
+			name: get("name").unwrap_or_default(),
+			code: get("ISO3166-1:alpha2").or_else(|| get("ISO3166-1")).or_else(|| get("ISO3166-2")).unwrap_or_default(),
+			bbox,
+			bands,
+		})
+	}
+
+	// even-odd rule over all rings, so holes are outside
+	pub fn contains(&self, p: Pt) -> bool {
+		if p.0 < self.bbox[0] || p.0 > self.bbox[2] || p.1 < self.bbox[1] || p.1 > self.bbox[3] {
+			return false
+		}
+		let segments = &self.bands[band_of(&self.bbox, p.1)];
+		segments.iter().filter(|(a, b)| in_ring(p, &[*a, *b])).count() % 2 == 1
+	}
+
+	// RU-NVS -> RU
+	pub fn country(&self) -> Option<&str> {
+		self.code.split('-').next().filter(|c| !c.is_empty())
+	}
+}
+
+fn band_of(bbox: &[f64; 4], y: f64) -> usize {
+	let h = (bbox[3] - bbox[1]) / BANDS as f64;
+	if h > 0.0 { (((y - bbox[1]) / h).max(0.0) as usize).min(BANDS - 1) } else { 0 }
+}
+
+fn cell(p: Pt) -> (i32, i32) {
+	((p.0 / CELL).floor() as i32, (p.1 / CELL).floor() as i32)
+}
+
+#[derive(Debug)]
+pub struct RegionIndex {
+	pub levels: Vec<u8>,
+	pub regions: Vec<Region>,
+	grid: HashMap<(i32, i32), Vec<usize>>,
+}
+
+impl RegionIndex {
+	pub fn new(levels: &[u8], regions: Vec<Region>) -> Self {
+		let mut grid: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+		for (i, r) in regions.iter().enumerate() {
+			let (c0, c1) = (cell((r.bbox[0], r.bbox[1])), cell((r.bbox[2], r.bbox[3])));
+			for x in c0.0..=c1.0 {
+				for y in c0.1..=c1.1 {
+					grid.entry((x, y)).or_default().push(i);
+				}
+			}
+		}
+		Self { levels: levels.to_vec(), regions, grid }
+	}
+
+	// joins rings of the relations with node coordinates, regions with missing nodes are skipped
+	pub fn build(levels: &[u8], rings: &[RegionRings], coords: &[&NodeCoords]) -> Self {
+		Self::new(levels, rings.iter().filter_map(|r| Region::new(r, coords)).collect())
+	}
+
+	// region of each level the point is in
+	pub fn at(&self, p: Pt) -> AtLevels {
+		let found: Vec<usize> = self.grid.get(&cell(p)).map_or(vec![], |rs| rs.iter().cloned().filter(|i| self.regions[*i].contains(p)).collect());
+		self.levels.iter().map(|l| found.iter().cloned().find(|i| self.regions[*i].level == *l)).collect()
+	}
+
+	// country code of the region the point is in, from the lowest level that has it
+	pub fn country_at(&self, p: Pt) -> Option<&str> {
+		let mut regions: Vec<&Region> = self.at(p).into_iter().flatten().map(|i| &self.regions[i]).collect();
+		regions.sort_by_key(|r| r.level);
+		regions.into_iter().find_map(|r| r.country())
+	}
+
+	// ids and names by level, as in admin_columns
+	pub fn values(&self, at: &[Option<usize>]) -> Vec<Value> {
+		at.iter().flat_map(|r| match r {
+			Some(i) => [Value::Int(self.regions[*i].id), Value::Text(self.regions[*i].name.clone())],
+			None => [Value::Null, Value::Null],
+		}).collect()
+	}
+
+	// regions at the middle point of the line, the regions at its ends, and if some point is in other regions
+	pub fn edge_regions(&self, line: &LineString) -> (AtLevels, [AtLevels; 2], bool) {
+		let pts: Vec<Pt> = line.0.iter().map(|c| (c.x, c.y)).collect();
+		if pts.is_empty() {
+			let none = vec![None; self.levels.len()];
+			return (none.clone(), [none.clone(), none], false)
+		}
+		let middle = self.at(middle_point(&pts));
+		let at: Vec<AtLevels> = pts.iter().map(|p| self.at(*p)).collect();
+		let border = at.iter().any(|a| *a != middle);
+		(middle, [at[0].clone(), at[at.len() - 1].clone()], border)
+	}
+}
+
+// point at the half of the line's length
+fn middle_point(pts: &[Pt]) -> Pt {
+	let len = |a: Pt, b: Pt| ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt();
+	let mut rest = pts.windows(2).map(|w| len(w[0], w[1])).sum::<f64>() / 2.0;
+	for w in pts.windows(2) {
+		let l = len(w[0], w[1]);
+		if l >= rest && l > 0.0 {
+			let t = rest / l;
+			return (w[0].0 + t * (w[1].0 - w[0].0), w[0].1 + t * (w[1].1 - w[0].1))
+		}
+		rest -= l;
+	}
+	pts[0]
+}
+
+// regions and node coordinates for the profile, to find the country of a way (see Profile::way_road)
+#[derive(Debug)]
+pub struct RegionLookup {
+	pub index: RegionIndex,
+	pub coords: Arc<NodeCoords>,
+}
+
+impl RegionLookup {
+	pub fn country_of(&self, node: NodeId) -> Option<&str> {
+		self.index.country_at(*self.coords.get(&node)?)
+	}
+}
+
+// row of `<output>_regions.csv`
+#[derive(Debug, Clone, Serialize)]
+pub struct RegionRow {
+	pub id: i64,
+	pub level: u8,
+	pub name: String,
+	pub code: String,
+}
+
+impl RegionRow {
+	pub fn new(r: &Region) -> Self {
+		Self { id: r.id, level: r.level, name: r.name.clone(), code: r.code.clone() }
+	}
+}
+
+#[cfg(test)]
+mod regions_tests {
+	use super::*;
+
+	fn square(first: i64, x0: f64, y0: f64, x1: f64, y1: f64, coords: &mut NodeCoords) -> Vec<NodeId> {
+		let pts = [(x0, y0), (x1, y0), (x1, y1), (x0, y1)];
+		let mut ring: Vec<NodeId> = (0..4).map(|i| NodeId(first + i)).collect();
+		for (n, p) in ring.iter().zip(pts.iter()) {
+			coords.insert(*n, *p);
+		}
+		ring.push(ring[0]);
+		ring
+	}
+
+	fn rings(id: i64, level: u8, tags: &[(&str, &str)], outer: Rings, inner: Rings) -> RegionRings {
+		let tags = crate::tags(tags);
+		RegionRings { id, level, tags, outer, inner }
+	}
+
+	#[test]
+	fn test_regions() {
+		assert!(admin_columns(&[]).is_empty());
+		let names: Vec<String> = admin_columns(&[4, 6]).into_iter().map(|(n, _)| n).collect();
+		assert_eq!(names, vec!["admin4", "admin4_name", "admin6", "admin6_name", "admin_border"]);
+
+		let mut coords = NodeCoords::new();
+		let country = rings(1, 2, &[("name", "Country"), ("ISO3166-1", "RU")], vec![square(1, 0.0, 0.0, 20.0, 10.0, &mut coords)], vec![]);
+		let west = rings(2, 4, &[("name", "West"), ("ISO3166-2", "RU-NVS")], vec![square(11, 0.0, 0.0, 10.0, 10.0, &mut coords)], vec![square(21, 4.0, 4.0, 6.0, 6.0, &mut coords)]);
+		let east = rings(3, 4, &[("name", "East")], vec![square(31, 10.0, 0.0, 20.0, 10.0, &mut coords)], vec![]);
+		let missing = rings(4, 4, &[], vec![vec![NodeId(100), NodeId(101), NodeId(102), NodeId(100)]], vec![]);
+		let index = RegionIndex::build(&[2, 4], &[country, west, east, missing], &[&coords]);
+		assert_eq!(index.regions.len(), 3);
+
+		assert_eq!(index.at((2.0, 2.0)), vec![Some(0), Some(1)]);
+		// the hole is in the country only
+		assert_eq!(index.at((5.0, 5.0)), vec![Some(0), None]);
+		assert_eq!(index.at((15.0, 2.0)), vec![Some(0), Some(2)]);
+		assert_eq!(index.at((25.0, 2.0)), vec![None, None]);
+		assert_eq!(index.country_at((2.0, 2.0)), Some("RU"));
+		assert_eq!(index.country_at((25.0, 2.0)), None);
+		assert_eq!(index.values(&index.at((15.0, 2.0))), vec![Value::Int(1), Value::Text("Country".to_string()), Value::Int(3), Value::Text("East".to_string())]);
+
+		let line = |pts: &[(f64, f64)]| LineString::from(pts.to_vec());
+		let (middle, ends, border) = index.edge_regions(&line(&[(1.0, 1.0), (3.0, 1.0)]));
+		assert_eq!((middle, border), (vec![Some(0), Some(1)], false));
+		assert_eq!(ends[0], ends[1]);
+		let (middle, ends, border) = index.edge_regions(&line(&[(2.0, 1.0), (14.0, 1.0)]));
+		assert_eq!((middle, border), (vec![Some(0), Some(1)], true));
+		assert_eq!(ends[1], vec![Some(0), Some(2)]);
+	}
+}
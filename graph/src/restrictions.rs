@@ -0,0 +1,323 @@
+use osmio2::objects::{Relation, ObjType, NodeId, WayId, RelId};
+use serde::Serialize;
+use std::{
+	collections::HashMap,
+	sync::Arc,
+};
+
+use crate::mode::{Mode, osm_modes, modes_str};
+
+pub type WayNodes = HashMap<WayId, Vec<NodeId>>;
+
+// turn restriction as it is in OSM, with one restriction value for all its modes
+#[derive(Debug, Clone)]
+pub struct Restriction {
+	pub id: RelId,
+	pub kind: Arc<str>,  // e.g. no_left_turn, only_straight_on
+	pub modes: Vec<Mode>,
+	pub from: Vec<WayId>,
+	pub via_node: Option<NodeId>,
+	pub via_ways: Vec<WayId>,
+	pub to: Vec<WayId>,
+}
+
+// restriction resolved to a sequence of nodes: [node before via, via node(s)..., node after via].
+// Ends of the via part must be graph vertice, so that from/to segments are the ends of graph edges
+#[derive(Debug, Clone)]
+pub struct NodeRestriction {
+	pub id: RelId,
+	pub kind: Arc<str>,
+	pub modes: Vec<Mode>,
+	pub via_node: Option<NodeId>,
+	pub nodes: Vec<NodeId>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UnresolvedRestriction {
+	pub id: i64,
+	pub reason: String,
+}
+
+impl UnresolvedRestriction {
+	pub fn new(id: RelId, reason: String) -> Self { Self { id: id.0, reason } }
+}
+
+impl Restriction {
+	// empty vector if it's not a restriction or it is only for modes not in the graph
+	pub fn from_relation(rel: &Relation) -> Result<Vec<Self>, String> {
+		match rel.tags.get("type").map(|v| &**v) {
+			Some(t) if t == "restriction" || t.starts_with("restriction:") => {},
+			_ => return Ok(vec![])
+		}
+
+		// restriction=* is for all vehicles except listed in except=*, restriction:<mode>=* overrides it for the mode
+		let mut kinds: Vec<(Mode, Arc<str>)> = vec![];
+		let mut has_value = false;
+		let mut conditional = false;
+		if let Some(k) = rel.tags.get("restriction") {
+			has_value = true;
+			let except: Vec<Mode> = rel.tags.get("except")
+				.map(|v| v.split(';').flat_map(|m| osm_modes(m.trim()).iter().cloned()).collect())
+				.unwrap_or_default();
+			kinds.extend(Mode::VEHICLES.iter().filter(|m| !except.contains(m)).map(|m| (*m, k.clone())));
+		}
+		for (key, val) in rel.tags.iter() {
+			if let Some(mode_key) = key.strip_prefix("restriction:") {
+				// restriction:conditional, restriction:bus:conditional
+				if mode_key == "conditional" || mode_key.ends_with(":conditional") {
+					conditional = true;
+					continue
+				}
+				has_value = true;
+				for m in osm_modes(mode_key) {
+					kinds.retain(|(m2, _)| m2 != m);
+					kinds.push((*m, val.clone()));
+				}
+			}
+		}
+		if !has_value && conditional {
+			return Err("conditional restriction without restriction=* tag, not supported".to_string())
+		}
+		if !has_value {
+			return Err("no restriction=* tag".to_string())
+		}
+
+		let mut from = vec![];
+		let mut to = vec![];
+		let mut via_nodes = vec![];
+		let mut via_ways = vec![];
+		for m in rel.members.iter() {
+			match (&*m.mrole, m.mtype) {
+				("from", ObjType::Way) => from.push(WayId(m.mref)),
+				("to", ObjType::Way) => to.push(WayId(m.mref)),
+				("via", ObjType::Node) => via_nodes.push(NodeId(m.mref)),
+				("via", ObjType::Way) => via_ways.push(WayId(m.mref)),
+				_ => {}
+			}
+		}
+		if from.is_empty() || to.is_empty() {
+			return Err("no from or to way".to_string())
+		}
+		let via_node = match (via_nodes.len(), via_ways.len()) {
+			(1, 0) => Some(via_nodes[0]),
+			(0, n) if n > 0 => None,
+			_ => return Err("via must be one node or one or more ways".to_string())
+		};
+
+		// group modes with the same restriction value, keeping the order of modes stable
+		let mut res: Vec<Self> = vec![];
+		for m in Mode::ALL {
+			let kind = match kinds.iter().find(|(m2, _)| *m2 == m) {
+				Some((_, k)) => k.clone(),
+				None => continue,
+			};
+			match res.iter_mut().find(|r| r.kind == kind) {
+				Some(r) => r.modes.push(m),
+				None => res.push(Self {
+					id: RelId(rel.attrs.id), kind, modes: vec![m],
+					from: from.clone(), via_node, via_ways: via_ways.clone(), to: to.clone()
+				}),
+			}
+		}
+		Ok(res)
+	}
+
+	pub fn ways(&self) -> impl Iterator<Item = &WayId> {
+		self.from.iter().chain(self.via_ways.iter()).chain(self.to.iter())
+	}
+
+	fn way_nodes<'a>(way_nodes: &'a WayNodes, w: &WayId) -> Result<&'a Vec<NodeId>, String> {
+		match way_nodes.get(w) {
+			Some(n) if n.len() >= 2 => Ok(n),
+			_ => Err(format!("way {} is missing or is not a road", w.0)),
+		}
+	}
+
+	// nodes of via ways, from the end of `from` way to the start of `to` way
+	fn via_path(&self, way_nodes: &WayNodes, from_nodes: &[NodeId]) -> Result<Vec<NodeId>, String> {
+		let mut path: Vec<NodeId> = vec![];
+		for w in self.via_ways.iter() {
+			let wn = Self::way_nodes(way_nodes, w)?;
+			let (first, last) = (wn[0], wn[wn.len() - 1]);
+			let connects = |n: &NodeId| *n == first || *n == last;
+			let start = match path.last() {
+				None => [from_nodes[0], from_nodes[from_nodes.len() - 1]].iter().cloned().find(connects),
+				Some(end) => Some(*end).filter(connects),
+			}.ok_or_else(|| format!("via way {} is not connected", w.0))?;
+
+			let oriented: Vec<NodeId> = if first == start { wn.clone() } else { wn.iter().rev().cloned().collect() };
+			let skip = if path.is_empty() { 0 } else { 1 };
+			path.extend(oriented.into_iter().skip(skip));
+		}
+		Ok(path)
+	}
+
+	pub fn resolve(&self, way_nodes: &WayNodes) -> Result<Vec<NodeRestriction>, String> {
+		let mut res = vec![];
+		for f in self.from.iter() {
+			for t in self.to.iter() {
+				let from_nodes = Self::way_nodes(way_nodes, f)?;
+				let to_nodes = Self::way_nodes(way_nodes, t)?;
+				let via = match self.via_node {
+					Some(v) => vec![v],
+					None => self.via_path(way_nodes, from_nodes)?,
+				};
+				let (a, b) = (via[0], via[via.len() - 1]);
+				let before = neighbour(from_nodes, a).ok_or_else(|| format!("from way {} does not start or end at via", f.0))?;
+				let after = neighbour(to_nodes, b).ok_or_else(|| format!("to way {} does not start or end at via", t.0))?;
+
+				let nodes = [vec![before], via, vec![after]].concat();
+				res.push(NodeRestriction { id: self.id, kind: self.kind.clone(), modes: self.modes.clone(), via_node: self.via_node, nodes });
+			}
+		}
+		Ok(res)
+	}
+}
+
+// the node next to `end`, if the way starts or ends at it
+fn neighbour(nodes: &[NodeId], end: NodeId) -> Option<NodeId> {
+	if nodes[0] == end {
+		Some(nodes[1])
+	} else if nodes[nodes.len() - 1] == end {
+		Some(nodes[nodes.len() - 2])
+	} else {
+		None
+	}
+}
+
+impl NodeRestriction {
+	// nodes that must be graph vertice: both ends of the via part
+	pub fn vertice(&self) -> [NodeId; 2] {
+		[self.nodes[1], self.nodes[self.nodes.len() - 2]]
+	}
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RestrictionRow {
+	pub id: i64,
+	pub kind: String,
+	pub modes: String,
+	pub from_edge: u64,
+	pub via_node: Option<i64>,
+	pub via_edges: String,
+	pub to_edge: u64,
+}
+
+// collects ids of the graph edges that contain restrictions' segments, while the edges are being written
+pub struct RestrictionIndex {
+	pairs: HashMap<(NodeId, NodeId), Option<u64>>,
+}
+
+impl RestrictionIndex {
+	pub fn new(restrictions: &[NodeRestriction]) -> Self {
+		let mut pairs = HashMap::new();
+		for r in restrictions {
+			for w in r.nodes.windows(2) {
+				pairs.insert((w[0], w[1]), None);
+			}
+		}
+		Self { pairs }
+	}
+
+	pub fn add_edge(&mut self, edge_id: u64, nodes: &[NodeId]) {
+		if self.pairs.is_empty() { return }
+		for w in nodes.windows(2) {
+			for key in [(w[0], w[1]), (w[1], w[0])].iter() {
+				if let Some(v) = self.pairs.get_mut(key) {
+					*v = Some(edge_id);
+				}
+			}
+		}
+	}
+
+	fn edge(&self, a: NodeId, b: NodeId) -> Option<u64> {
+		self.pairs.get(&(a, b)).cloned().flatten()
+	}
+
+	pub fn rows(&self, restrictions: &[NodeRestriction]) -> (Vec<RestrictionRow>, Vec<UnresolvedRestriction>) {
+		let mut rows = vec![];
+		let mut unresolved = vec![];
+		for r in restrictions {
+			let n = &r.nodes;
+			let mut edges = vec![];
+			for w in n.windows(2) {
+				match self.edge(w[0], w[1]) {
+					Some(e) => if edges.last() != Some(&e) { edges.push(e) },
+					None => { edges.clear(); break; }
+				}
+			}
+			if edges.is_empty() {
+				unresolved.push(UnresolvedRestriction::new(r.id, "segment is not in the graph".to_string()));
+				continue;
+			}
+			// u-turn restrictions have the same edge as from and to
+			let from_edge = self.edge(n[0], n[1]).unwrap();
+			let to_edge = self.edge(n[n.len() - 2], n[n.len() - 1]).unwrap();
+			let via_edges: Vec<String> = n[1..n.len() - 1].windows(2)
+				.filter_map(|w| self.edge(w[0], w[1]))
+				.fold(vec![], |mut v: Vec<u64>, e| { if v.last() != Some(&e) { v.push(e) }; v })
+				.into_iter().map(|e| e.to_string()).collect();
+			rows.push(RestrictionRow {
+				id: r.id.0, kind: r.kind.to_string(), modes: modes_str(&r.modes),
+				from_edge, via_node: r.via_node.map(|v| v.0), via_edges: via_edges.join(";"), to_edge
+			});
+		}
+		(rows, unresolved)
+	}
+}
+
+#[cfg(test)]
+mod restrictions_tests {
+	use super::*;
+	use osmio2::objects::{Member, OsmElementAttrs};
+
+	fn rel(tags: &[(&str, &str)], members: &[(ObjType, i64, &str)]) -> Relation {
+		let mut attrs = OsmElementAttrs::new();
+		attrs.id = 100;
+		let tags = crate::tags(tags);
+		let members = members.iter().map(|(t, r, role)| Member { mtype: *t, mref: *r, mrole: Arc::from(*role) }).collect();
+		Relation { attrs, tags, members }
+	}
+
+	fn nodes(ids: &[i64]) -> Vec<NodeId> { ids.iter().map(|i| NodeId(*i)).collect() }
+
+	#[test]
+	fn test_modes_and_resolve() {
+		let r = rel(&[("type", "restriction"), ("restriction", "no_left_turn"), ("except", "bicycle"), ("restriction:bus", "only_straight_on")],
+			&[(ObjType::Way, 1, "from"), (ObjType::Node, 3, "via"), (ObjType::Way, 2, "to")]);
+		let rs = Restriction::from_relation(&r).unwrap();
+		assert_eq!(rs.len(), 2);
+		assert_eq!((&*rs[0].kind, rs[0].modes.clone()), ("no_left_turn", vec![Mode::Car]));
+		assert_eq!((&*rs[1].kind, rs[1].modes.clone()), ("only_straight_on", vec![Mode::Bus]));
+
+		let way_nodes: WayNodes = vec![(WayId(1), nodes(&[1, 2, 3])), (WayId(2), nodes(&[5, 4, 3]))].into_iter().collect();
+		let nr = rs[0].resolve(&way_nodes).unwrap();
+		assert_eq!(nr[0].nodes, nodes(&[2, 3, 4]));
+
+		// only a conditional restriction is reported instead of being dropped
+		let r = rel(&[("type", "restriction"), ("restriction:conditional", "no_left_turn @ (Mo-Fr 07:00-09:00)")],
+			&[(ObjType::Way, 1, "from"), (ObjType::Node, 3, "via"), (ObjType::Way, 2, "to")]);
+		assert!(Restriction::from_relation(&r).is_err());
+
+		// via way, given in the opposite direction
+		let r = rel(&[("type", "restriction"), ("restriction", "no_u_turn")],
+			&[(ObjType::Way, 1, "from"), (ObjType::Way, 3, "via"), (ObjType::Way, 2, "to")]);
+		let way_nodes: WayNodes = vec![(WayId(1), nodes(&[1, 2])), (WayId(3), nodes(&[7, 6, 2])), (WayId(2), nodes(&[7, 8]))].into_iter().collect();
+		let nr = Restriction::from_relation(&r).unwrap()[0].resolve(&way_nodes).unwrap();
+		assert_eq!(nr[0].nodes, nodes(&[1, 2, 6, 7, 8]));
+		assert_eq!(nr[0].vertice(), [NodeId(2), NodeId(7)]);
+
+		// edges after chains were merged: 1-2, 2-6-7 and 7-8
+		let mut idx = RestrictionIndex::new(&nr);
+		idx.add_edge(10, &nodes(&[1, 2]));
+		idx.add_edge(11, &nodes(&[7, 6, 2]));
+		idx.add_edge(12, &nodes(&[7, 8]));
+		let (rows, unresolved) = idx.rows(&nr);
+		assert!(unresolved.is_empty());
+		assert_eq!((rows[0].from_edge, rows[0].via_edges.as_str(), rows[0].to_edge), (10, "11", 12));
+
+		// from way passes through via node
+		let way_nodes: WayNodes = vec![(WayId(1), nodes(&[1, 2, 3])), (WayId(3), nodes(&[2, 9])), (WayId(2), nodes(&[9, 8]))].into_iter().collect();
+		assert!(Restriction::from_relation(&r).unwrap()[0].resolve(&way_nodes).is_err());
+	}
+}
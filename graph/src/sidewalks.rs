@@ -0,0 +1,310 @@
+use osmio2::objects::{NodeId, Tags};
+use serde::Deserialize;
+use std::{
+	collections::{HashMap, HashSet},
+	f64::consts::PI,
+};
+
+use crate::{Access, NodeChain, NodeCoords, Road, RoadCat, RoutingNodes, WayPart};
+use crate::network::{Category, Network, transfer_road};
+use crate::levels::MAX_LEVELS;
+
+/* Sidewalks, with the profile's `sidewalks` rule. Roads with a sidewalk on some side (sidewalk=both/left/right,
+sidewalk:left=yes etc.) are closed for pedestrians, and sidewalk edges are made `offset` metres to the side instead.
+Around a node, sidewalks meet at corners between neighbouring roads. Corners are linked by crossings across every road
+at intersections (3 or more roads) and at highway=crossing nodes; nodes where walkable ways end (footways, mapped
+footway=sidewalk and crossing ways, roads with no sidewalk tags) are linked to the corners, which joins them into the same network. */
+
+// corners are vertice -(node * 64 + 32 + k), k < MAX_ARMS (see level_node_id for the rest of the range),
+// points along sidewalks get ids from POINTS down
+const CORNER_IDS: i64 = MAX_LEVELS as i64;
+const MAX_ARMS: usize = 32;
+const POINTS: i64 = -(1 << 62);
+// metres in a degree of latitude
+const METRES: f64 = 111_320.0;
+
+fn default_offset() -> f64 { 5.0 }
+fn default_speed() -> u16 { 5 }
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SidewalkRule {
+	// from the road centreline, metres
+	#[serde(default = "default_offset")]
+	pub offset: f64,
+	// km/h
+	#[serde(default = "default_speed")]
+	pub speed: u16,
+}
+
+impl Default for SidewalkRule {
+	fn default() -> Self { Self { offset: default_offset(), speed: default_speed() } }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Side { #[default] Unknown, No, Yes, Separate }
+
+impl Side {
+	fn parse(v: &str) -> Option<Self> {
+		match v {
+			"yes" => Some(Self::Yes),
+			"no" | "none" => Some(Self::No),
+			"separate" => Some(Self::Separate),
+			_ => None,
+		}
+	}
+}
+
+// sidewalks by side, relative to the order of nodes
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Sidewalk {
+	pub left: Side,
+	pub right: Side,
+}
+
+impl Sidewalk {
+	pub fn from_tags(tags: &Tags) -> Self {
+		use Side::*;
+		let (mut left, mut right) = match tags.get("sidewalk").map(|v| &**v) {
+			Some("both") | Some("yes") => (Yes, Yes),
+			Some("left") => (Yes, No),
+			Some("right") => (No, Yes),
+			Some("no") | Some("none") => (No, No),
+			Some("separate") => (Separate, Separate),
+			_ => (Unknown, Unknown),
+		};
+		if let Some(s) = tags.get("sidewalk:both").and_then(|v| Side::parse(v)) {
+			left = s;
+			right = s;
+		}
+		left = tags.get("sidewalk:left").and_then(|v| Side::parse(v)).unwrap_or(left);
+		right = tags.get("sidewalk:right").and_then(|v| Side::parse(v)).unwrap_or(right);
+		Self { left, right }
+	}
+
+	pub fn reversed(&self) -> Self {
+		Self { left: self.right, right: self.left }
+	}
+
+	// pedestrians walk on the sidewalks, not on the road
+	pub fn any(&self) -> bool {
+		[self.left, self.right].iter().any(|s| matches!(s, Side::Yes | Side::Separate))
+	}
+}
+
+fn corner_id(node: NodeId, k: usize) -> NodeId {
+	NodeId(-(node.0 * 64 + CORNER_IDS + k as i64))
+}
+
+// local metres around `o`, and back
+fn to_local(o: (f64, f64), p: (f64, f64)) -> (f64, f64) {
+	((p.0 - o.0) * METRES * o.1.to_radians().cos(), (p.1 - o.1) * METRES)
+}
+
+fn from_local(o: (f64, f64), p: (f64, f64)) -> (f64, f64) {
+	(o.0 + p.0 / (METRES * o.1.to_radians().cos()), o.1 + p.1 / METRES)
+}
+
+fn unit(v: (f64, f64)) -> (f64, f64) {
+	let l = (v.0 * v.0 + v.1 * v.1).sqrt();
+	if l > 0.0 { (v.0 / l, v.1 / l) } else { (0.0, 0.0) }
+}
+
+// a road leaving a node
+struct Arm {
+	angle: f64,
+	// index in `roads`, if it has sidewalks, and if it leaves the node in the order of its nodes
+	road: Option<(usize, bool)>,
+}
+
+// road with sidewalks
+struct SidewalkRoad {
+	nodes: Vec<NodeId>,
+	sidewalk: Sidewalk,
+	ways: Vec<WayPart>,
+}
+
+// collects roads and walkable ways while edges are written, then makes sidewalks, crossings and links, like Transfers
+pub struct Sidewalks {
+	rule: SidewalkRule,
+	// highway=crossing nodes, except crossing=no
+	crossings: HashSet<NodeId>,
+	arms: HashMap<NodeId, Vec<Arm>>,
+	roads: Vec<SidewalkRoad>,
+	// ends of edges that pedestrians may use
+	walkable: HashSet<NodeId>,
+}
+
+impl Sidewalks {
+	pub fn new(rule: &SidewalkRule, routing_nodes: &RoutingNodes) -> Self {
+		let crossings = routing_nodes.iter()
+			.filter(|(_, rn)| rn.highway.as_deref() == Some("crossing") || rn.crossing.is_some())
+			.filter(|(_, rn)| rn.crossing.as_deref() != Some("no"))
+			.map(|(n, _)| *n).collect();
+		Self { rule: rule.clone(), crossings, arms: HashMap::new(), roads: vec![], walkable: HashSet::new() }
+	}
+
+	pub fn add(&mut self, edge: &NodeChain, node_coords: &NodeCoords) {
+		if edge.category.network() != Network::Road {
+			return
+		}
+		if edge.access.foot {
+			self.walkable.extend(edge.ends().iter());
+		}
+		// roads for vehicles, footways are not walked around
+		if edge.area || !(edge.access.car || edge.access.bus) || edge.nodes.len() < 2 {
+			return
+		}
+		let pts: Option<Vec<(f64, f64)>> = edge.nodes.iter().map(|n| node_coords.get(n).cloned()).collect();
+		let pts = match pts { Some(p) => p, None => return };
+		let road = if edge.sidewalk.left == Side::Yes || edge.sidewalk.right == Side::Yes {
+			self.roads.push(SidewalkRoad { nodes: edge.nodes.clone(), sidewalk: edge.sidewalk, ways: edge.ways.clone() });
+			Some(self.roads.len() - 1)
+		} else { None };
+		let last = pts.len() - 1;
+		for (end, next, forward) in [(0, 1, true), (last, last - 1, false)] {
+			let d = to_local(pts[end], pts[next]);
+			self.arms.entry(edge.nodes[end]).or_default().push(Arm { angle: d.1.atan2(d.0), road: road.map(|r| (r, forward)) });
+		}
+	}
+
+	// sidewalks, crossings and links from walkable ways to the corners
+	pub fn edges(mut self, node_coords: &NodeCoords) -> (Vec<NodeChain>, NodeCoords) {
+		let offset = self.rule.offset;
+		let mut coords = NodeCoords::new();
+		// corners at the ends of roads: (road, is the last node) -> (corner on the left, on the right), relative to the road
+		let mut road_corners: HashMap<(usize, bool), (NodeId, NodeId)> = HashMap::new();
+		// corners of each node, in order around it
+		let mut node_corners: HashMap<NodeId, Vec<NodeId>> = HashMap::new();
+		for (node, arms) in self.arms.iter_mut() {
+			let o = match node_coords.get(node) { Some(o) if arms.len() <= MAX_ARMS => *o, _ => continue };
+			arms.sort_by(|a, b| a.angle.partial_cmp(&b.angle).unwrap());
+			let n = arms.len();
+			// corner k is between arm k and the next one counter-clockwise, on the bisector, `offset` from both
+			for k in 0..n {
+				let a = arms[k].angle;
+				let gap = if n == 1 { 2.0 * PI } else { (arms[(k + 1) % n].angle - a).rem_euclid(2.0 * PI) };
+				let s = (gap / 2.0).sin();
+				let d = if s > 0.3 { offset / s } else { offset };
+				let b = a + gap / 2.0;
+				coords.insert(corner_id(*node, k), from_local(o, (d * b.cos(), d * b.sin())));
+			}
+			for (k, arm) in arms.iter().enumerate() {
+				let (arm_left, arm_right) = (corner_id(*node, k), corner_id(*node, (k + n - 1) % n));
+				if let Some((r, forward)) = arm.road {
+					road_corners.insert((r, !forward), if forward { (arm_left, arm_right) } else { (arm_right, arm_left) });
+				}
+			}
+			node_corners.insert(*node, (0..n).map(|k| corner_id(*node, k)).collect());
+		}
+
+		let sidewalk = sidewalk_road(RoadCat::Sidewalk, self.rule.speed);
+		let crossing = sidewalk_road(RoadCat::Crossing, self.rule.speed);
+		let mut res = vec![];
+		let mut used: HashSet<NodeId> = HashSet::new();
+		let mut next_point = POINTS;
+		for (r, road) in self.roads.iter().enumerate() {
+			let (start, end) = match (road_corners.get(&(r, false)), road_corners.get(&(r, true))) {
+				(Some(s), Some(e)) => (*s, *e),
+				_ => continue,
+			};
+			let pts: Vec<(f64, f64)> = road.nodes.iter().map(|n| node_coords[n]).collect();
+			for (side, left) in [(road.sidewalk.left, true), (road.sidewalk.right, false)] {
+				if side != Side::Yes {
+					continue
+				}
+				let (first, last) = if left { (start.0, end.0) } else { (start.1, end.1) };
+				let mut nodes = vec![first.0];
+				// points along the road are moved along the mean normal of their segments
+				for w in pts.windows(3) {
+					let (d1, d2) = (unit(to_local(w[0], w[1])), unit(to_local(w[1], w[2])));
+					let n = unit((-d1.1 - d2.1, d1.0 + d2.0));
+					let s = if left { offset } else { -offset };
+					next_point -= 1;
+					coords.insert(NodeId(next_point), from_local(w[1], (n.0 * s, n.1 * s)));
+					nodes.push(next_point);
+				}
+				nodes.push(last.0);
+				used.extend([first, last]);
+				let mut nc = NodeChain::new(0, &nodes, &sidewalk);
+				nc.ways = road.ways.clone();
+				res.push(nc);
+			}
+		}
+
+		let mut ids: Vec<&NodeId> = node_corners.keys().collect();
+		ids.sort_by_key(|n| n.0);
+		for node in ids {
+			let corners = &node_corners[node];
+			let n = corners.len();
+			if n >= 3 || self.crossings.contains(node) {
+				// across arm k, from the corner on its right to the one on its left; with 2 arms it's one crossing
+				for k in 0..(if n == 2 { 1 } else { n }) {
+					let (a, b) = (corners[(k + n - 1) % n], corners[k]);
+					if a != b && used.contains(&a) && used.contains(&b) {
+						let mut nc = NodeChain::new(0, &[a.0, b.0], &crossing);
+						nc.ways.clear();
+						res.push(nc);
+					}
+				}
+			}
+			if self.walkable.contains(node) {
+				for c in corners.iter().filter(|c| used.contains(c)) {
+					let mut nc = NodeChain::new(0, &[node.0, c.0], &sidewalk);
+					nc.ways.clear();
+					res.push(nc);
+				}
+			}
+		}
+		coords.retain(|n, _| used.contains(n) || n.0 < POINTS);
+		(res, coords)
+	}
+}
+
+fn sidewalk_road(category: RoadCat, speed: u16) -> Road {
+	Road { category: Category::Road(category), speed, ..transfer_road(Access::new(false, false, true, false)) }
+}
+
+#[cfg(test)]
+mod sidewalks_tests {
+	use super::*;
+	use crate::tags;
+	use crate::Profile;
+
+	#[test]
+	fn test_sidewalks() {
+		use Side::*;
+		assert_eq!(Sidewalk::from_tags(&tags(&[("sidewalk", "left")])), Sidewalk { left: Yes, right: No });
+		assert_eq!(Sidewalk::from_tags(&tags(&[("sidewalk:both", "separate"), ("sidewalk:right", "no")])), Sidewalk { left: Separate, right: No });
+		assert_eq!(Sidewalk::from_tags(&tags(&[("sidewalk", "right")])).reversed(), Sidewalk { left: Yes, right: No });
+		assert!(!Sidewalk::from_tags(&tags(&[("sidewalk", "no")])).any());
+
+		let mut profile = Profile::builtin("foot").unwrap().unwrap();
+		assert!(profile.road(&tags(&[("highway", "residential"), ("sidewalk", "both")])).unwrap().access.foot);
+		profile.sidewalks = Some(SidewalkRule::default());
+		let main = profile.road(&tags(&[("highway", "residential"), ("sidewalk", "both")])).unwrap();
+		let side = profile.road(&tags(&[("highway", "residential")])).unwrap();
+		assert!(!main.access.foot && side.access.foot);
+
+		// main road 10-11-12-13 from west to east, bent at 11, side street 12-14 to the north
+		let m = 1.0 / METRES;
+		let node_coords: NodeCoords = [(10, (-0.8, 0.0)), (11, (-0.4, 0.1)), (12, (0.0, 0.0)), (13, (0.8, 0.0)), (14, (0.0, 0.8))]
+			.iter().map(|(n, (x, y))| (NodeId(*n), (100.0 * x * m, 100.0 * y * m))).collect();
+		let mut sw = Sidewalks::new(&SidewalkRule::default(), &RoutingNodes::new());
+		sw.add(&NodeChain::new(1, &[10, 11, 12], &main), &node_coords);
+		sw.add(&NodeChain::new(1, &[12, 13], &main), &node_coords);
+		sw.add(&NodeChain::new(2, &[12, 14], &side), &node_coords);
+		let (edges, coords) = sw.edges(&node_coords);
+		let count = |c: RoadCat| edges.iter().filter(|e| e.category == Category::Road(c.clone())).count();
+		// 2 sides of 2 edges, links from the side street to 3 corners, and crossings across 3 roads
+		assert_eq!((count(RoadCat::Sidewalk), count(RoadCat::Crossing)), (4 + 3, 3));
+		let first = &edges[0];
+		assert_eq!(first.nodes.len(), 3);
+		assert_eq!(first.ways, vec![WayPart { way: osmio2::objects::WayId(1), from: 0, to: 2 }]);
+		// the point along the sidewalk on the left is about 5 m north of node 11
+		let p = coords[&first.nodes[1]];
+		let d = to_local(node_coords[&NodeId(11)], p);
+		assert!(d.0.abs() < 1.0 && (d.1 - 5.0).abs() < 0.5);
+		assert!(edges.iter().all(|e| e.nodes.iter().all(|n| coords.contains_key(n) || node_coords.contains_key(n))));
+	}
+}
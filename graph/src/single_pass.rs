@@ -0,0 +1,422 @@
+use osmio2::objects::{OsmObj, OsmElementAttrs, Way, Tags, NodeId, WayId, RelId};
+use std::{
+	collections::{HashMap, HashSet},
+	error::Error,
+	fs::{self, File},
+	io::{self, BufReader, BufWriter, Read, Write},
+	path::Path,
+	sync::Arc,
+};
+
+use crate::{NodeCoords, Profile, VerticeHash, WaysInNodesCounter, RoutingNode, RoutingNodes};
+use crate::restrictions::{Restriction, UnresolvedRestriction};
+use crate::area::{Area, AreaCollector};
+use crate::network::FerryCollector;
+use crate::regions::{RegionCollector, RegionRings};
+
+/* Single-pass build: the input is read once, nodes go to a node store and road ways to a spool,
+both are temporary files in a compact format (ids are delta-coded varints), and then
+node coordinates and ways are read back from them instead of decompressing the input again. */
+
+use osmio2::temp::TempFile;
+
+pub(crate) fn write_varint<W: Write>(wr: &mut W, mut v: u64) -> io::Result<()> {
+	let mut buf = [0u8; 10];
+	let mut i = 0;
+	while v >= 0x80 {
+		buf[i] = (v as u8) | 0x80;
+		v >>= 7;
+		i += 1;
+	}
+	buf[i] = v as u8;
+	wr.write_all(&buf[..=i])
+}
+
+// None at the end of file
+pub(crate) fn read_varint<R: Read>(rd: &mut R) -> io::Result<Option<u64>> {
+	let mut v = 0u64;
+	let mut byte = [0u8];
+	for shift in (0..64).step_by(7) {
+		if rd.read(&mut byte)? == 0 {
+			return if shift == 0 { Ok(None) } else { Err(io::ErrorKind::UnexpectedEof.into()) }
+		}
+		v |= ((byte[0] & 0x7f) as u64) << shift;
+		if byte[0] < 0x80 {
+			return Ok(Some(v))
+		}
+	}
+	Err(io::Error::new(io::ErrorKind::InvalidData, "varint is too long"))
+}
+
+pub(crate) fn read_u64<R: Read>(rd: &mut R) -> io::Result<u64> {
+	read_varint(rd)?.ok_or_else(|| io::ErrorKind::UnexpectedEof.into())
+}
+
+// ids are written as differences to the previous one, zigzag-coded, so that unsorted ids work too
+pub(crate) fn write_delta<W: Write>(wr: &mut W, prev: &mut i64, v: i64) -> io::Result<()> {
+	let d = v.wrapping_sub(*prev);
+	*prev = v;
+	write_varint(wr, ((d << 1) ^ (d >> 63)) as u64)
+}
+
+pub(crate) fn read_delta<R: Read>(rd: &mut R, prev: &mut i64) -> io::Result<i64> {
+	let z = read_u64(rd)?;
+	*prev = prev.wrapping_add(((z >> 1) as i64) ^ -((z & 1) as i64));
+	Ok(*prev)
+}
+
+fn write_str<W: Write>(wr: &mut W, s: &str) -> io::Result<()> {
+	write_varint(wr, s.len() as u64)?;
+	wr.write_all(s.as_bytes())
+}
+
+fn read_str<R: Read>(rd: &mut R) -> io::Result<Arc<str>> {
+	let mut buf = vec![0; read_u64(rd)? as usize];
+	rd.read_exact(&mut buf)?;
+	let s = String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+	Ok(Arc::from(s.as_str()))
+}
+
+// coordinates of all nodes of the file: id delta, then lon and lat as in the reader (f32)
+pub struct NodeStore {
+	file: TempFile,
+	wr: BufWriter<File>,
+	prev: i64,
+	pub len: usize,
+}
+
+impl NodeStore {
+	pub fn new() -> Result<Self, Box<dyn Error>> {
+		let file = TempFile::new("nodes.tmp");
+		let wr = BufWriter::new(File::create(&file.0)?);
+		Ok(Self { file, wr, prev: 0, len: 0 })
+	}
+
+	pub fn add(&mut self, id: i64, lon: f32, lat: f32) -> io::Result<()> {
+		write_delta(&mut self.wr, &mut self.prev, id)?;
+		self.wr.write_all(&lon.to_le_bytes())?;
+		self.wr.write_all(&lat.to_le_bytes())?;
+		self.len += 1;
+		Ok(())
+	}
+
+	// coordinates of the nodes in `used`, read in one sequential pass
+	pub fn coords<V>(&mut self, used: &HashMap<NodeId, V>) -> Result<NodeCoords, Box<dyn Error>> {
+		let mut coords = NodeCoords::new();
+		for n in self.reader()? {
+			let (id, lon, lat) = n?;
+			if used.contains_key(&NodeId(id)) {
+				coords.insert(NodeId(id), (lon as f64, lat as f64));
+			}
+		}
+		Ok(coords)
+	}
+
+	pub fn reader(&mut self) -> Result<NodeReader, Box<dyn Error>> {
+		self.wr.flush()?;
+		NodeReader::open(&self.file.0)
+	}
+
+	// copy that outlives the store, see update.rs
+	pub fn save(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+		self.wr.flush()?;
+		fs::copy(&self.file.0, path)?;
+		Ok(())
+	}
+}
+
+// (id, lon, lat) of a node store or its saved copy
+pub struct NodeReader {
+	rd: BufReader<File>,
+	prev: i64,
+}
+
+impl NodeReader {
+	pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+		Ok(Self { rd: BufReader::new(File::open(path)?), prev: 0 })
+	}
+
+	pub fn _next(&mut self) -> io::Result<Option<(i64, f32, f32)>> {
+		let z = match read_varint(&mut self.rd)? {
+			Some(z) => z,
+			None => return Ok(None),
+		};
+		self.prev = self.prev.wrapping_add(((z >> 1) as i64) ^ -((z & 1) as i64));
+		let mut buf = [0u8; 8];
+		self.rd.read_exact(&mut buf)?;
+		let lon = f32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+		let lat = f32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]);
+		Ok(Some((self.prev, lon, lat)))
+	}
+}
+
+impl Iterator for NodeReader {
+	type Item = io::Result<(i64, f32, f32)>;
+	fn next(&mut self) -> Option<Self::Item> {
+		self._next().transpose()
+	}
+}
+
+// road ways in the order of the input file: id delta, tags, node id deltas
+pub struct WaySpool {
+	file: TempFile,
+	wr: BufWriter<File>,
+	prev: i64,
+	pub len: usize,
+}
+
+impl WaySpool {
+	pub fn new() -> Result<Self, Box<dyn Error>> {
+		let file = TempFile::new("ways.tmp");
+		let wr = BufWriter::new(File::create(&file.0)?);
+		Ok(Self { file, wr, prev: 0, len: 0 })
+	}
+
+	pub fn add(&mut self, way: &Way) -> io::Result<()> {
+		let wr = &mut self.wr;
+		write_delta(wr, &mut self.prev, way.attrs.id)?;
+		write_varint(wr, way.tags.len() as u64)?;
+		for (k, v) in way.tags.iter() {
+			write_str(wr, k)?;
+			write_str(wr, v)?;
+		}
+		write_varint(wr, way.nodes.len() as u64)?;
+		let mut prev = 0;
+		for n in way.nodes.iter() {
+			write_delta(wr, &mut prev, *n)?;
+		}
+		self.len += 1;
+		Ok(())
+	}
+
+	// can be called several times, each reader starts from the first way
+	pub fn reader(&mut self) -> Result<SpoolReader, Box<dyn Error>> {
+		self.wr.flush()?;
+		SpoolReader::open(&self.file.0)
+	}
+
+	pub fn save(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+		self.wr.flush()?;
+		fs::copy(&self.file.0, path)?;
+		Ok(())
+	}
+
+	// node lists of the ways in `keep_ways`
+	pub fn way_nodes(&mut self, keep_ways: &HashSet<WayId>) -> Result<HashMap<WayId, Vec<NodeId>>, Box<dyn Error>> {
+		let mut res = HashMap::new();
+		for way in self.reader()? {
+			let way = way?;
+			if keep_ways.contains(&WayId(way.attrs.id)) {
+				res.insert(WayId(way.attrs.id), way.nodes.iter().map(|n| NodeId(*n)).collect());
+			}
+		}
+		Ok(res)
+	}
+}
+
+pub struct SpoolReader {
+	rd: BufReader<File>,
+	prev: i64,
+}
+
+impl SpoolReader {
+	pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+		Ok(Self { rd: BufReader::new(File::open(path)?), prev: 0 })
+	}
+
+	pub fn _next(&mut self) -> io::Result<Option<Way>> {
+		let z = match read_varint(&mut self.rd)? {
+			Some(z) => z,
+			None => return Ok(None),
+		};
+		self.prev = self.prev.wrapping_add(((z >> 1) as i64) ^ -((z & 1) as i64));
+		let mut attrs = OsmElementAttrs::new();
+		attrs.id = self.prev;
+		let mut tags = Tags::new();
+		for _ in 0..read_u64(&mut self.rd)? {
+			let k = read_str(&mut self.rd)?;
+			tags.insert(k, read_str(&mut self.rd)?);
+		}
+		let mut prev = 0;
+		let nodes = (0..read_u64(&mut self.rd)?).map(|_| read_delta(&mut self.rd, &mut prev)).collect::<io::Result<_>>()?;
+		Ok(Some(Way { attrs, nodes, tags }))
+	}
+}
+
+impl Iterator for SpoolReader {
+	type Item = io::Result<Way>;
+	fn next(&mut self) -> Option<Self::Item> {
+		self._next().transpose()
+	}
+}
+
+// everything that the multi-pass build gets from `read_passes`, except structures and levels that are read from the spool
+pub struct SinglePass {
+	pub vertice: VerticeHash,
+	pub node_coords: NodeCoords,
+	pub routing_nodes: RoutingNodes,
+	pub restrictions: Vec<Restriction>,
+	pub unresolved: Vec<UnresolvedRestriction>,
+	pub ways: WaySpool,
+	// all nodes of the file, and the ones that are vertice by their tags, for the saved state of updates
+	pub nodes: NodeStore,
+	pub tag_vertice: VerticeHash,
+	// pedestrian areas, ferry ways of relations, boundaries of admin regions, and coordinates of their nodes that are not on roads
+	pub areas: Vec<Area>,
+	pub ferries: Vec<Way>,
+	pub regions: Vec<RegionRings>,
+	pub member_coords: NodeCoords,
+}
+
+// reads the objects once. Works with any order of objects, but sorted files (nodes, ways, relations)
+// are the usual case. The node store keeps all nodes, because it's not yet known which of them are used
+pub fn read_once<I, E>(objs: I, profile: &Profile) -> Result<SinglePass, Box<dyn Error>>
+where I: Iterator<Item = Result<OsmObj, E>>, E: Error + 'static {
+	let mut nodes = NodeStore::new()?;
+	let mut ways = WaySpool::new()?;
+	let mut used_nodes = WaysInNodesCounter::new();
+	let mut vertex_nodes = VerticeHash::new();
+	// all of them, because it's not yet known which are on roads
+	let mut routing_nodes = RoutingNodes::new();
+	let mut restrictions = vec![];
+	let mut unresolved = vec![];
+	// relation members usually come before their relations, so possible members are kept in another spool
+	let mut areas = AreaCollector::new(profile);
+	let mut ferries = FerryCollector::new(profile);
+	let mut regions = RegionCollector::new(&profile.admin_levels);
+	let mut member_ways = WaySpool::new()?;
+	println!("reading the file once");
+	for obj in objs {
+		match obj? {
+			OsmObj::Node(n) => {
+				nodes.add(n.attrs.id, n.lon, n.lat)?;
+				if profile.is_vertex_node(&n.tags) {
+					vertex_nodes.insert(NodeId(n.attrs.id));
+				}
+				if let Some(rn) = RoutingNode::from_tags(&n.tags) {
+					routing_nodes.insert(NodeId(n.attrs.id), rn);
+				}
+			},
+			OsmObj::Way(way) => {
+				if profile.road(&way.tags).is_some() {
+					for node_id in [&way.nodes, &way.nodes[1..way.nodes.len() - 1]].concat() {
+						*used_nodes.entry(NodeId(node_id)).or_insert(0) += 1;
+					}
+					ways.add(&way)?;
+				} else if areas.may_need(&way) || ferries.may_need(&way) || regions.may_need(&way) {
+					member_ways.add(&way)?;
+				}
+			},
+			OsmObj::Relation(rel) => {
+				areas.relation(&rel);
+				ferries.relation(&rel);
+				regions.relation(&rel);
+				match Restriction::from_relation(&rel) {
+					Ok(r) => restrictions.extend(r),
+					Err(reason) => unresolved.push(UnresolvedRestriction::new(RelId(rel.attrs.id), reason)),
+				}
+			},
+		}
+	}
+	println!("nodes: {}, road ways: {}, restrictions: {}", nodes.len, ways.len, restrictions.len());
+
+	let node_coords = nodes.coords(&used_nodes)?;
+	println!("node coords: {}", node_coords.len());
+	for way in member_ways.reader()? {
+		let way = way?;
+		areas.way(&way);
+		ferries.way(&way);
+		regions.way(&way);
+	}
+	let regions = if regions.enabled() {
+		for way in ways.reader()? {
+			regions.way(&way?);
+		}
+		regions.finish()
+	} else { vec![] };
+	let (areas, ferries) = (areas.finish(), ferries.finish());
+	let member_nodes: HashMap<NodeId, ()> = areas.iter().flat_map(|a| a.nodes()).cloned()
+		.chain(ferries.iter().flat_map(|w| w.nodes.iter().map(|n| NodeId(*n))))
+		.chain(regions.iter().flat_map(|r| r.nodes()).cloned())
+		.filter(|n| !node_coords.contains_key(n)).map(|n| (n, ())).collect();
+	let member_coords = nodes.coords(&member_nodes)?;
+	let tag_vertice: VerticeHash = vertex_nodes.iter().chain(routing_nodes.keys()).cloned().collect();
+	vertex_nodes.retain(|n| used_nodes.contains_key(n));
+	routing_nodes.retain(|n, _| used_nodes.contains_key(n));
+	let mut vertice: VerticeHash = used_nodes.into_iter().filter(|(_, v)| *v != 2).map(|(k, _)| k).collect();
+	vertice.extend(vertex_nodes);
+	vertice.extend(routing_nodes.keys().cloned());
+	Ok(SinglePass { vertice, node_coords, routing_nodes, restrictions, unresolved, ways, nodes, tag_vertice, areas, ferries, regions, member_coords })
+}
+
+#[cfg(test)]
+mod single_pass_tests {
+	use super::*;
+	use crate::{build_edges, build_edges_from};
+	use crate::multi_pass::{read_passes, MultiPass};
+	use crate::parallel::build_edges_parallel_from;
+	use osmio2::reader::OsmXmlReader;
+
+	#[test]
+	fn test_varints() {
+		let mut buf = vec![];
+		let mut prev = 0;
+		let ids = [5, 3_000_000_000, 3_000_000_001, -7, i64::MAX, i64::MIN, 0];
+		for v in ids {
+			write_delta(&mut buf, &mut prev, v).unwrap();
+		}
+		let mut rd = buf.as_slice();
+		let mut prev = 0;
+		let back: Vec<i64> = ids.iter().map(|_| read_delta(&mut rd, &mut prev).unwrap()).collect();
+		assert_eq!(back, ids);
+		assert!(read_varint(&mut rd).unwrap().is_none());
+	}
+
+	#[test]
+	fn test_same_as_multi_pass() {
+		let path = "test_data/ilyicha-1.osm.gz";
+		let profile = Profile::default();
+		let mut sp = read_once(OsmXmlReader::from_path(path).unwrap(), &profile).unwrap();
+		let MultiPass { vertice, node_coords, routing_nodes, .. } = read_passes(path, &profile, false).unwrap();
+		assert_eq!((&sp.vertice, &sp.node_coords, &sp.routing_nodes), (&vertice, &node_coords, &routing_nodes));
+
+		let mut expected = vec![];
+		build_edges(path, &vertice, &profile, |e| { expected.push(e); Ok(()) }).unwrap();
+		let mut edges = vec![];
+		build_edges_from(sp.ways.reader().unwrap(), &sp.vertice, &profile, |e| { edges.push(e); Ok(()) }).unwrap();
+		assert_eq!(edges, expected);
+		let mut edges = vec![];
+		build_edges_parallel_from(sp.ways.reader().unwrap(), &sp.vertice, &profile, 3, 2, |e| { edges.push(e); Ok(()) }).unwrap();
+		// the same edges in another order
+		edges.sort_by_key(|e| e.first_part());
+		expected.sort_by_key(|e| e.first_part());
+		assert_eq!(edges, expected);
+
+		let first = sp.ways.reader().unwrap().next().unwrap().unwrap();
+		let nodes = sp.ways.way_nodes(&[WayId(first.attrs.id)].iter().cloned().collect()).unwrap();
+		assert_eq!(nodes[&WayId(first.attrs.id)].len(), first.nodes.len());
+	}
+
+	#[test]
+	fn test_regions_read_once() {
+		// a boundary made of a road and an untagged way, the relation comes after them
+		let tmp = TempFile::new("regions.osm");
+		fs::write(&tmp.0, r#"<osm><node id="1" lat="54.0" lon="83.0"/><node id="2" lat="54.0" lon="83.1"/>
+<node id="3" lat="54.1" lon="83.1"/><node id="4" lat="54.1" lon="83.0"/>
+<way id="10"><nd ref="1"/><nd ref="2"/><nd ref="3"/><tag k="highway" v="residential"/></way>
+<way id="11"><nd ref="3"/><nd ref="4"/><nd ref="1"/></way>
+<relation id="20"><member type="way" ref="10" role="outer"/><member type="way" ref="11" role="outer"/>
+<tag k="boundary" v="administrative"/><tag k="admin_level" v="4"/><tag k="name" v="Region"/></relation></osm>"#).unwrap();
+		let profile = Profile { admin_levels: vec![4], ..Profile::default() };
+		let sp = read_once(OsmXmlReader::from_path(&tmp.path()).unwrap(), &profile).unwrap();
+		let mp = read_passes(&tmp.path(), &profile, false).unwrap();
+		let expected = mp.regions;
+		let node_set = |r: &RegionRings| r.nodes().map(|n| n.0).collect::<HashSet<i64>>();
+		assert_eq!(sp.regions.len(), 1);
+		assert_eq!((sp.regions[0].id, node_set(&sp.regions[0])), (expected[0].id, node_set(&expected[0])));
+		// the node off the road gets coordinates too
+		assert!(sp.regions[0].nodes().all(|n| sp.node_coords.contains_key(n) || sp.member_coords.contains_key(n)));
+		assert!(sp.member_coords.contains_key(&NodeId(4)));
+		assert_eq!(sp.member_coords, mp.member_coords);
+	}
+}
@@ -0,0 +1,462 @@
+use geo::LineString;
+use osmio2::{
+	change::{ChangeAction, OsmChangeReader},
+	objects::{Node, NodeId, OsmObj, Way, WayId},
+};
+use serde::{Serialize, Deserialize};
+use std::{
+	collections::{HashMap, HashSet},
+	error::Error,
+	fs::File,
+	io::{self, BufReader, BufWriter, Read, Write},
+	path::Path,
+};
+
+use crate::{split_way, ChainStorage, Edge, NodeCoords, Profile, RoutingNode, VerticeHash, WayRow, WaysInNodesCounter};
+use crate::elevation::segment;
+use crate::single_pass::{read_delta, read_u64, read_varint, write_delta, write_varint, NodeReader, NodeStore, SpoolReader, WaySpool};
+use osmio2::temp::TempFile;
+
+/* Incremental update of a graph from an osmChange file.
+
+A build with --save-state writes the state into a directory: all nodes (node store), road ways (way spool),
+edges with their node lists and hashes of their rows, and the vertice that don't come from counting ways at nodes.
+An update finds the edges that pass through changed nodes or nodes of changed ways, and rebuilds them
+from the ways they were made of, with the ends of all the other edges kept as vertice.
+Rebuilt edges with the same nodes as before keep their ids. The state is then replaced with the updated one.
+Relations (restrictions, area multipolygons, ferry routes) are not updated, neither is vertex table.
+Rebuilt rows are not finished as in a full build, so states can't be kept for builds with heights, levels or admin regions. */
+
+const STATE: &str = "state.json";
+const NODES: &str = "nodes.bin";
+const WAYS: &str = "ways.bin";
+const EDGES: &str = "edges.bin";
+const TAG_VERTICE: &str = "tag_vertice.bin";
+const EXTRA_VERTICE: &str = "extra_vertice.bin";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StateInfo {
+	// updates must use the same profile
+	profile: String,
+	last_edge_id: u64,
+}
+
+// edge as it's kept in the state
+#[derive(Debug, Clone, PartialEq)]
+pub struct EdgeRecord {
+	pub id: u64,
+	// see edge_hash
+	pub hash: u64,
+	// built from the ways of the spool, other edges (areas, ferry relations, transfers) are never rebuilt
+	pub from_ways: bool,
+	pub nodes: Vec<NodeId>,
+}
+
+// FNV-1a of the row without id, so that unchanged rebuilt edges are not written into the diff
+pub fn edge_hash(row: &Edge) -> Result<u64, Box<dyn Error>> {
+	let mut v = serde_json::to_value(row)?;
+	v["id"] = 0.into();
+	// attributes are not serialized with the row
+	v["attrs"] = format!("{:?}", row.attrs).into();
+	Ok(v.to_string().bytes().fold(0xcbf29ce484222325, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3)))
+}
+
+// edge records in a temporary file: id, hash, from_ways, node id deltas
+pub struct EdgeStore {
+	file: TempFile,
+	wr: BufWriter<File>,
+	pub len: usize,
+}
+
+impl EdgeStore {
+	pub fn new() -> Result<Self, Box<dyn Error>> {
+		let file = TempFile::new("edges.tmp");
+		let wr = BufWriter::new(File::create(&file.0)?);
+		Ok(Self { file, wr, len: 0 })
+	}
+
+	pub fn add(&mut self, e: &EdgeRecord) -> io::Result<()> {
+		let wr = &mut self.wr;
+		write_varint(wr, e.id)?;
+		wr.write_all(&e.hash.to_le_bytes())?;
+		wr.write_all(&[e.from_ways as u8])?;
+		write_varint(wr, e.nodes.len() as u64)?;
+		let mut prev = 0;
+		for n in e.nodes.iter() {
+			write_delta(wr, &mut prev, n.0)?;
+		}
+		self.len += 1;
+		Ok(())
+	}
+
+	pub fn save(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+		self.wr.flush()?;
+		std::fs::copy(&self.file.0, path)?;
+		Ok(())
+	}
+}
+
+pub struct EdgeReader {
+	rd: BufReader<File>,
+}
+
+impl EdgeReader {
+	pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+		Ok(Self { rd: BufReader::new(File::open(path)?) })
+	}
+
+	pub fn _next(&mut self) -> io::Result<Option<EdgeRecord>> {
+		let id = match read_varint(&mut self.rd)? {
+			Some(id) => id,
+			None => return Ok(None),
+		};
+		let mut buf = [0u8; 9];
+		self.rd.read_exact(&mut buf)?;
+		let hash = u64::from_le_bytes([buf[0], buf[1], buf[2], buf[3], buf[4], buf[5], buf[6], buf[7]]);
+		let mut prev = 0;
+		let nodes = (0..read_u64(&mut self.rd)?).map(|_| read_delta(&mut self.rd, &mut prev).map(NodeId)).collect::<io::Result<_>>()?;
+		Ok(Some(EdgeRecord { id, hash, from_ways: buf[8] != 0, nodes }))
+	}
+}
+
+impl Iterator for EdgeReader {
+	type Item = io::Result<EdgeRecord>;
+	fn next(&mut self) -> Option<Self::Item> {
+		self._next().transpose()
+	}
+}
+
+fn write_ids(path: &Path, ids: &VerticeHash) -> Result<(), Box<dyn Error>> {
+	let mut ids: Vec<i64> = ids.iter().map(|n| n.0).collect();
+	ids.sort_unstable();
+	let mut wr = BufWriter::new(File::create(path)?);
+	write_varint(&mut wr, ids.len() as u64)?;
+	let mut prev = 0;
+	for id in ids {
+		write_delta(&mut wr, &mut prev, id)?;
+	}
+	Ok(wr.flush()?)
+}
+
+fn read_ids(path: &Path) -> Result<VerticeHash, Box<dyn Error>> {
+	let mut rd = BufReader::new(File::open(path)?);
+	let mut prev = 0;
+	let n = read_u64(&mut rd)?;
+	Ok((0..n).map(|_| read_delta(&mut rd, &mut prev).map(NodeId)).collect::<io::Result<_>>()?)
+}
+
+// vertice that don't come from counting ways at nodes
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StateVertice {
+	// barriers, crossings etc. and profile's vertex_nodes, of all nodes (a changed way may use any of them)
+	pub tags: VerticeHash,
+	// area entries, ferry ends and ends of restriction vias
+	pub extra: VerticeHash,
+}
+
+pub fn save_state(dir: &Path, profile: &Profile, nodes: &mut NodeStore, ways: &mut WaySpool, edges: &mut EdgeStore, vertice: &StateVertice, last_edge_id: u64) -> Result<(), Box<dyn Error>> {
+	std::fs::create_dir_all(dir)?;
+	nodes.save(&dir.join(NODES))?;
+	ways.save(&dir.join(WAYS))?;
+	edges.save(&dir.join(EDGES))?;
+	write_ids(&dir.join(TAG_VERTICE), &vertice.tags)?;
+	write_ids(&dir.join(EXTRA_VERTICE), &vertice.extra)?;
+	let info = StateInfo { profile: profile.name.clone(), last_edge_id };
+	serde_json::to_writer(File::create(dir.join(STATE))?, &info)?;
+	println!("state saved: {} nodes, {} ways, {} edges", nodes.len, ways.len, edges.len);
+	Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind { Added, Changed, Removed }
+
+// row of `<output>_changes.csv`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EdgeChange {
+	pub id: u64,
+	pub action: ChangeKind,
+	pub node1: NodeId,
+	pub node2: NodeId,
+}
+
+pub struct GraphDiff {
+	// added and changed edges
+	pub rows: Vec<Edge>,
+	pub changes: Vec<EdgeChange>,
+	// ways of the added and changed edges
+	pub ways: Vec<WayRow>,
+}
+
+// the same edge may be rebuilt in the other direction
+fn edge_key(nodes: &[NodeId]) -> Vec<NodeId> {
+	if nodes[0].0 <= nodes[nodes.len() - 1].0 { nodes.to_vec() } else { nodes.iter().rev().cloned().collect() }
+}
+
+// rebuilt edges would lose heights, per-level vertex ids and region attributes, and be reported as changed
+pub fn check_updatable(profile: &Profile, heights: bool) -> Result<(), Box<dyn Error>> {
+	let reason = if heights {
+		"--dem"
+	} else if profile.indoor.is_some() {
+		"the indoor rule"
+	} else if !profile.admin_levels.is_empty() {
+		"admin_levels"
+	} else {
+		return Ok(())
+	};
+	Err(format!("states for --update can't be used with {}", reason).into())
+}
+
+// applies the change to the state in `dir` and returns the changes of the graph
+pub fn update_state(dir: &Path, change: &str, profile: &Profile) -> Result<GraphDiff, Box<dyn Error>> {
+	check_updatable(profile, false)?;
+	let info: StateInfo = serde_json::from_reader(File::open(dir.join(STATE))?)?;
+	if info.profile != profile.name {
+		return Err(format!("the state was saved with profile {}, not {}", info.profile, profile.name).into())
+	}
+
+	// new versions of objects, None for the deleted ones
+	let mut nodes: HashMap<NodeId, Option<Node>> = HashMap::new();
+	let mut ways: HashMap<WayId, Option<Way>> = HashMap::new();
+	let mut relations = 0;
+	for item in OsmChangeReader::from_path(change)? {
+		match item? {
+			(ChangeAction::Delete, OsmObj::Node(n)) => { nodes.insert(NodeId(n.attrs.id), None); },
+			(_, OsmObj::Node(n)) => { nodes.insert(NodeId(n.attrs.id), Some(n)); },
+			(ChangeAction::Delete, OsmObj::Way(w)) => { ways.insert(WayId(w.attrs.id), None); },
+			(_, OsmObj::Way(w)) => { ways.insert(WayId(w.attrs.id), Some(w)); },
+			(_, OsmObj::Relation(_)) => relations += 1,
+		}
+	}
+	println!("changed nodes: {}, ways: {}, relations (not applied): {}", nodes.len(), ways.len(), relations);
+
+	let mut vertice = StateVertice { tags: read_ids(&dir.join(TAG_VERTICE))?, extra: read_ids(&dir.join(EXTRA_VERTICE))? };
+	for (id, n) in nodes.iter() {
+		vertice.tags.remove(id);
+		if n.as_ref().is_some_and(|n| profile.is_vertex_node(&n.tags) || RoutingNode::from_tags(&n.tags).is_some()) {
+			vertice.tags.insert(*id);
+		}
+	}
+
+	// changed nodes, and nodes of the old and new versions of changed ways
+	let mut affected: HashSet<NodeId> = nodes.keys().cloned().collect();
+	affected.extend(ways.values().flatten().flat_map(|w| w.nodes.iter().map(|n| NodeId(*n))));
+	for w in SpoolReader::open(&dir.join(WAYS))? {
+		let w = w?;
+		if ways.contains_key(&WayId(w.attrs.id)) {
+			affected.extend(w.nodes.iter().map(|n| NodeId(*n)));
+		}
+	}
+
+	// edges through them are rebuilt, and their segments tell which parts of unchanged ways are rebuilt too
+	let is_affected = |e: &EdgeRecord| e.from_ways && e.nodes.iter().any(|n| affected.contains(n));
+	let mut old_edges: HashMap<Vec<NodeId>, Vec<EdgeRecord>> = HashMap::new();
+	let mut segments = HashSet::new();
+	for e in EdgeReader::open(&dir.join(EDGES))? {
+		let e = e?;
+		if is_affected(&e) {
+			segments.extend(e.nodes.windows(2).map(|w| segment(w[0], w[1])));
+			old_edges.entry(edge_key(&e.nodes)).or_default().push(e);
+		}
+	}
+
+	// new spool: unchanged ways, new versions of modified ones, then created ones (and modified ones that became roads)
+	let mut spool = WaySpool::new()?;
+	let mut rebuilt: Vec<(Way, bool)> = vec![];
+	let mut seen = HashSet::new();
+	for w in SpoolReader::open(&dir.join(WAYS))? {
+		let w = w?;
+		let id = WayId(w.attrs.id);
+		seen.insert(id);
+		let (way, changed) = match ways.get(&id) {
+			Some(Some(new)) => (new.clone(), true),
+			Some(None) => continue,
+			None => (w, false),
+		};
+		if profile.road(&way.tags).is_some() {
+			spool.add(&way)?;
+			if changed || way.nodes.windows(2).any(|p| segments.contains(&segment(NodeId(p[0]), NodeId(p[1])))) {
+				rebuilt.push((way, changed));
+			}
+		}
+	}
+	let mut created: Vec<&Way> = ways.iter().filter(|(id, _)| !seen.contains(id)).filter_map(|(_, w)| w.as_ref()).collect();
+	created.sort_by_key(|w| w.attrs.id);
+	for way in created {
+		if profile.road(&way.tags).is_some() {
+			spool.add(way)?;
+			rebuilt.push((way.clone(), true));
+		}
+	}
+
+	// vertice among the nodes of rebuilt ways: as in read_passes, plus the ends of the edges that stay
+	let rebuilt_nodes: HashSet<NodeId> = rebuilt.iter().flat_map(|(w, _)| w.nodes.iter().map(|n| NodeId(*n))).collect();
+	let mut used = WaysInNodesCounter::new();
+	for w in spool.reader()? {
+		let w = w?;
+		for node_id in [&w.nodes, &w.nodes[1..w.nodes.len() - 1]].concat() {
+			if rebuilt_nodes.contains(&NodeId(node_id)) {
+				*used.entry(NodeId(node_id)).or_insert(0) += 1;
+			}
+		}
+	}
+	let mut kept_ends = VerticeHash::new();
+	let mut edges = EdgeStore::new()?;
+	for e in EdgeReader::open(&dir.join(EDGES))? {
+		let e = e?;
+		if !is_affected(&e) {
+			kept_ends.extend([e.nodes[0], e.nodes[e.nodes.len() - 1]].iter().filter(|n| rebuilt_nodes.contains(n)));
+			edges.add(&e)?;
+		}
+	}
+	let vertex_set: VerticeHash = rebuilt_nodes.iter()
+		.filter(|n| used.get(n).is_none_or(|c| *c != 2) || vertice.tags.contains(n) || vertice.extra.contains(n) || kept_ends.contains(n))
+		.cloned().collect();
+
+	// only the chains that were in the rebuilt edges, or are on changed ways
+	let mut cs = ChainStorage::new(&vertex_set, profile);
+	let mut chains = vec![];
+	for (way, changed) in rebuilt.iter() {
+		for nc in split_way(way, profile, |n| vertex_set.contains(n)) {
+			if *changed || nc.nodes.windows(2).any(|p| segments.contains(&segment(p[0], p[1]))) {
+				chains.extend(cs.insert(nc));
+			}
+		}
+	}
+	if !cs.edges.is_empty() {
+		println!("chains left unfinished: {}", cs.edges.len());
+	}
+
+	// new node store, and coordinates of the rebuilt nodes
+	let mut store = NodeStore::new()?;
+	let mut coords = NodeCoords::new();
+	let mut seen = HashSet::new();
+	let mut add_node = |id: i64, lon: f32, lat: f32, store: &mut NodeStore| -> io::Result<()> {
+		if rebuilt_nodes.contains(&NodeId(id)) {
+			coords.insert(NodeId(id), (lon as f64, lat as f64));
+		}
+		store.add(id, lon, lat)
+	};
+	for n in NodeReader::open(&dir.join(NODES))? {
+		let (id, lon, lat) = n?;
+		seen.insert(NodeId(id));
+		match nodes.get(&NodeId(id)) {
+			Some(Some(n)) => add_node(id, n.lon, n.lat, &mut store)?,
+			Some(None) => {},
+			None => add_node(id, lon, lat, &mut store)?,
+		}
+	}
+	let mut created: Vec<&Node> = nodes.iter().filter(|(id, _)| !seen.contains(id)).filter_map(|(_, n)| n.as_ref()).collect();
+	created.sort_by_key(|n| n.attrs.id);
+	for n in created {
+		add_node(n.attrs.id, n.lon, n.lat, &mut store)?;
+	}
+
+	let mut diff = GraphDiff { rows: vec![], changes: vec![], ways: vec![] };
+	let mut last_edge_id = info.last_edge_id;
+	for nc in chains {
+		let points: Vec<(f64, f64)> = nc.nodes.iter().filter_map(|n| coords.get(n)).cloned().collect();
+		let mut row = Edge::new(0, &nc, LineString::from(points));
+		let hash = edge_hash(&row)?;
+		let (id, action) = match old_edges.get_mut(&edge_key(&nc.nodes)).and_then(|v| v.pop()) {
+			Some(old) if old.hash == hash => (old.id, None),
+			Some(old) => (old.id, Some(ChangeKind::Changed)),
+			None => {
+				last_edge_id += 1;
+				(last_edge_id, Some(ChangeKind::Added))
+			},
+		};
+		row.id = id;
+		edges.add(&EdgeRecord { id, hash, from_ways: true, nodes: nc.nodes.clone() })?;
+		if let Some(action) = action {
+			diff.changes.push(EdgeChange { id, action, node1: row.node1, node2: row.node2 });
+			diff.ways.extend(WayRow::rows(id, &nc.ways));
+			diff.rows.push(row);
+		}
+	}
+	let mut removed: Vec<EdgeRecord> = old_edges.into_values().flatten().collect();
+	removed.sort_by_key(|e| e.id);
+	diff.changes.extend(removed.iter().map(|e| EdgeChange { id: e.id, action: ChangeKind::Removed, node1: e.nodes[0], node2: e.nodes[e.nodes.len() - 1] }));
+	println!("edges rebuilt: {}, added: {}, changed: {}, removed: {}", edges.len, diff.changes.iter().filter(|c| c.action == ChangeKind::Added).count(),
+		diff.changes.iter().filter(|c| c.action == ChangeKind::Changed).count(), removed.len());
+
+	save_state(dir, profile, &mut store, &mut spool, &mut edges, &vertice, last_edge_id)?;
+	Ok(diff)
+}
+
+#[cfg(test)]
+mod update_tests {
+	use super::*;
+	use crate::{build_edges_from, single_pass::read_once};
+	use osmio2::reader::OsmXmlReader;
+	use std::io::Cursor;
+
+	const NODES_XML: &str = r#"<node id="1" lat="54.000" lon="83.000"/><node id="2" lat="54.000" lon="83.001"/>
+<node id="3" lat="54.000" lon="83.002"/><node id="4" lat="54.000" lon="83.003"/>
+<node id="7" lat="54.002" lon="83.003"/><node id="8" lat="54.002" lon="83.004"/>"#;
+
+	// builds the graph like main does, saves the state and returns (nodes, hash) of the edges
+	fn build(text: String, dir: &Path, profile: &Profile) -> Vec<(Vec<i64>, u64)> {
+		let rd: Box<dyn Read + Send> = Box::new(Cursor::new(text.into_bytes()));
+		let mut sp = read_once(OsmXmlReader::new(BufReader::new(rd)), profile).unwrap();
+		let mut records = EdgeStore::new().unwrap();
+		let mut id = 0;
+		build_edges_from(sp.ways.reader().unwrap(), &sp.vertice, profile, |e| {
+			id += 1;
+			let points: Vec<(f64, f64)> = e.nodes.iter().map(|n| sp.node_coords[n]).collect();
+			let hash = edge_hash(&Edge::new(id, &e, LineString::from(points)))?;
+			records.add(&EdgeRecord { id, hash, from_ways: true, nodes: e.nodes })?;
+			Ok(())
+		}).unwrap();
+		let vertice = StateVertice { tags: sp.tag_vertice.clone(), extra: VerticeHash::new() };
+		save_state(dir, profile, &mut sp.nodes, &mut sp.ways, &mut records, &vertice, id).unwrap();
+		edges_of(dir)
+	}
+
+	fn edges_of(dir: &Path) -> Vec<(Vec<i64>, u64)> {
+		let mut edges: Vec<_> = EdgeReader::open(&dir.join(EDGES)).unwrap().map(|e| e.unwrap())
+			.map(|e| (edge_key(&e.nodes).iter().map(|n| n.0).collect(), e.hash)).collect();
+		edges.sort();
+		edges
+	}
+
+	#[test]
+	fn test_update() {
+		let profile = Profile::default();
+		let (tmp, expected_tmp) = (TempFile::new("update-test"), TempFile::new("update-test-2"));
+		let (dir, expected_dir) = (tmp.0.clone(), expected_tmp.0.clone());
+		build(format!(r#"<osm>{}<node id="5" lat="54.001" lon="83.001"/><node id="6" lat="54.001" lon="83.003"/>
+<way id="10"><nd ref="1"/><nd ref="2"/><nd ref="3"/><nd ref="4"/><tag k="highway" v="residential"/></way>
+<way id="11"><nd ref="2"/><nd ref="5"/><tag k="highway" v="service"/></way>
+<way id="12"><nd ref="4"/><nd ref="6"/><nd ref="7"/><tag k="highway" v="tertiary"/></way>
+<way id="13"><nd ref="7"/><nd ref="8"/><tag k="highway" v="tertiary"/></way></osm>"#, NODES_XML), &dir, &profile);
+
+		let change = tmp.0.join("change.osc");
+		std::fs::write(&change, r#"<osmChange version="0.6">
+<create><node id="9" lat="54.001" lon="83.002"/><way id="14"><nd ref="3"/><nd ref="9"/><tag k="highway" v="footway"/></way></create>
+<modify><node id="6" lat="54.0015" lon="83.0031"/></modify>
+<delete><way id="11"/><node id="5"/></delete>
+</osmChange>"#).unwrap();
+		let diff = update_state(&dir, change.to_str().unwrap(), &profile).unwrap();
+		let short: Vec<(u64, ChangeKind, i64, i64)> = diff.changes.iter().map(|c| (c.id, c.action, c.node1.0, c.node2.0)).collect();
+		assert_eq!(short, vec![
+			(5, ChangeKind::Added, 1, 3), (6, ChangeKind::Added, 3, 4), (4, ChangeKind::Changed, 4, 8), (7, ChangeKind::Added, 3, 9),
+			(1, ChangeKind::Removed, 1, 2), (2, ChangeKind::Removed, 2, 5), (3, ChangeKind::Removed, 2, 4),
+		]);
+		assert_eq!(diff.rows.len(), 4);
+
+		// the state is the same as from a build of the changed data
+		let expected = build(format!(r#"<osm>{}<node id="6" lat="54.0015" lon="83.0031"/><node id="9" lat="54.001" lon="83.002"/>
+<way id="10"><nd ref="1"/><nd ref="2"/><nd ref="3"/><nd ref="4"/><tag k="highway" v="residential"/></way>
+<way id="12"><nd ref="4"/><nd ref="6"/><nd ref="7"/><tag k="highway" v="tertiary"/></way>
+<way id="13"><nd ref="7"/><nd ref="8"/><tag k="highway" v="tertiary"/></way>
+<way id="14"><nd ref="3"/><nd ref="9"/><tag k="highway" v="footway"/></way></osm>"#, NODES_XML), &expected_dir, &profile);
+		assert_eq!(edges_of(&dir), expected);
+
+		// a state saved with another profile is refused
+		assert!(update_state(&dir, change.to_str().unwrap(), &Profile::load("car").unwrap()).is_err());
+		// and so are options whose columns rebuilt rows wouldn't have
+		assert!(check_updatable(&profile, true).is_err());
+		assert!(check_updatable(&Profile { admin_levels: vec![2], ..profile.clone() }, false).is_err());
+	}
+}
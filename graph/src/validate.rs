@@ -0,0 +1,125 @@
+use csv::{Reader, Writer};
+use serde::Deserialize;
+use osmgraph::{Network, sidecar_path};
+use osmgraph::quality::{find_issues, layer_of, parse_linestring, QualityEdge, MAX_MAXSPEED, MIN_MAXSPEED};
+use osmgraph::schema::schema_version;
+use osmio2::notes::{Note, NotesWriter};
+
+use std::{collections::BTreeMap, error::Error};
+
+// metres, dead ends closer to other edges are reported
+const SNAP: f64 = 5.0;
+
+// columns that validate needs, all but the first ones may be missing in older files
+#[allow(non_snake_case)]
+#[derive(Deserialize)]
+struct CheckEdge {
+	#[serde(default)]
+	id: Option<u64>,
+	node1: i64,
+	node2: i64,
+	WKT: String,
+	#[serde(default)]
+	maxspeed: String,
+	#[serde(default)]
+	maxspeed_forward: String,
+	#[serde(default)]
+	maxspeed_backward: String,
+	#[serde(default)]
+	tags: String,
+	#[serde(default)]
+	area: bool,
+	#[serde(default)]
+	network: Option<Network>,
+	#[serde(default)]
+	nodes: String,
+	#[serde(default)]
+	layer: Option<i32>,
+}
+
+impl CheckEdge {
+	// edges without id are numbered by rows
+	fn quality_edge(self, row: usize) -> QualityEdge {
+		QualityEdge {
+			id: self.id.unwrap_or(row as u64 + 1),
+			node1: self.node1,
+			node2: self.node2,
+			points: parse_linestring(&self.WKT),
+			nodes: self.nodes.split(';').filter_map(|n| n.parse().ok()).collect(),
+			layer: self.layer.unwrap_or_else(|| layer_of(&self.tags)),
+			straight: self.area || matches!(self.network, Some(Network::Ferry) | Some(Network::Transfer)),
+			maxspeed: vec![("maxspeed", self.maxspeed), ("maxspeed_forward", self.maxspeed_forward), ("maxspeed_backward", self.maxspeed_backward)],
+		}
+	}
+}
+
+fn validate(input: &str, output: &str, snap: f64, notes: Option<&str>) -> Result<(), Box<dyn Error>> {
+	let mut rd = Reader::from_path(input)?;
+	let headers = rd.headers()?.clone();
+	schema_version(&headers).ok_or("not a graph file: node1, node2, WKT, category or lanes column is missing")?;
+	let mut edges = vec![];
+	for (i, rec) in rd.records().enumerate() {
+		edges.push(rec?.deserialize::<CheckEdge>(Some(&headers))?.quality_edge(i));
+	}
+
+	let issues = find_issues(&edges, snap);
+	let mut wr = Writer::from_path(output)?;
+	for issue in issues.iter() {
+		wr.serialize(issue)?;
+	}
+	wr.flush()?;
+	if let Some(path) = notes {
+		let mut wr = NotesWriter::from_path(path)?;
+		for (i, issue) in issues.iter().enumerate() {
+			if let Some(p) = issue.point {
+				let text = format!("{}: {} (edges {}): {}", format!("{:?}", issue.severity).to_lowercase(), issue.kind, issue.edges, issue.description);
+				wr.write(&Note { id: -(i as i64 + 1), lon: p.0, lat: p.1, text })?;
+			}
+		}
+		wr.close()?;
+	}
+
+	let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+	for issue in issues.iter() {
+		*counts.entry(issue.kind).or_default() += 1;
+	}
+	println!("edges: {}, issues: {}", edges.len(), issues.len());
+	for (kind, n) in counts {
+		println!("{}: {}", kind, n);
+	}
+	Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+	let mut args: Vec<String> = std::env::args().collect();
+	// --snap METRES: dead ends closer than this to other edges are reported
+	let snap = match args.iter().position(|a| a == "--snap") {
+		Some(i) => {
+			let v = args.get(i + 1).ok_or("--snap needs a distance in metres")?.parse::<f64>()?;
+			args.drain(i..i + 2);
+			v
+		},
+		None => SNAP,
+	};
+	// --notes PATH.osn: also write the issues as OSM notes
+	let notes = match args.iter().position(|a| a == "--notes") {
+		Some(i) => {
+			let path = args.get(i + 1).ok_or("--notes needs a file path")?.clone();
+			args.drain(i..i + 2);
+			Some(path)
+		},
+		None => None,
+	};
+	match args.len() {
+		2 | 3 => {
+			let output = args.get(2).cloned().unwrap_or_else(|| sidecar_path(&args[1], "issues"));
+			validate(&args[1], &output, snap, notes.as_deref())?
+		},
+		_ => println!("usage: validate [--snap METRES] [--notes NOTES.osn] INPUT.CSV [ISSUES.CSV]\n\
+			Checks a graph for edges with less than 2 points, zero-length edges, self-loops, duplicate edges,\n\
+			maxspeed outside of {}..{} km/h, edges crossing without a shared node on the same layer,\n\
+			and dead ends closer than METRES (default {}) to another edge. Issues are written with\n\
+			severity and a point to ISSUES.CSV (by default INPUT_issues.csv), and as OSM notes to NOTES.osn", MIN_MAXSPEED, MAX_MAXSPEED, SNAP),
+	};
+	Ok(())
+}
@@ -0,0 +1,121 @@
+use osmio2::objects::{NodeId, Tags};
+use serde::Serialize;
+use std::{collections::HashMap, sync::Arc};
+
+use crate::Access;
+use crate::network::STATIONS;
+
+const HIGHWAY_NODES: [&str; 6] = ["traffic_signals", "stop", "give_way", "crossing", "elevator", "mini_roundabout"];
+const RAILWAY_NODES: [&str; 2] = ["level_crossing", "crossing"];
+
+// nodes that affect travel time or access: they always become graph vertice,
+// so that an edge never passes through them. Stations are there to get transfers to roads, see network.rs
+pub fn is_routing_node(tags: &Tags) -> bool {
+	let has = |k: &str, values: &[&str]| tags.get(k).is_some_and(|v| values.contains(&&**v));
+	tags.get("barrier").is_some_and(|v| &**v != "no")
+		|| has("highway", &HIGHWAY_NODES)
+		|| has("railway", &RAILWAY_NODES)
+		|| has("railway", &STATIONS)
+		|| tags.get("crossing").is_some_and(|v| &**v != "no")
+}
+
+// tags of a routing node that are written into the vertex table
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoutingNode {
+	pub barrier: Option<Arc<str>>,
+	pub highway: Option<Arc<str>>,
+	pub railway: Option<Arc<str>>,
+	pub crossing: Option<Arc<str>>,
+	// modes that may pass the node
+	pub access: Access,
+}
+
+pub type RoutingNodes = HashMap<NodeId, RoutingNode>;
+
+impl RoutingNode {
+	pub fn from_tags(tags: &Tags) -> Option<Self> {
+		if !is_routing_node(tags) {
+			return None
+		}
+		let get = |k: &str| tags.get(k).cloned();
+		Some(Self { barrier: get("barrier"), highway: get("highway"), railway: get("railway"), crossing: get("crossing"), access: Access::for_node(tags) })
+	}
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VertexRow {
+	pub node: i64,
+	pub lon: f64,
+	pub lat: f64,
+	pub barrier: Option<String>,
+	pub highway: Option<String>,
+	pub railway: Option<String>,
+	pub crossing: Option<String>,
+	pub car: bool,
+	pub bike: bool,
+	pub foot: bool,
+	pub bus: bool,
+	// ids of the edges that start or end at the node
+	pub edges: String,
+}
+
+impl VertexRow {
+	pub fn new(id: NodeId, node: &RoutingNode, coords: (f64, f64), edges: &[u64]) -> Self {
+		let s = |v: &Option<Arc<str>>| v.as_ref().map(|v| v.to_string());
+		Self {
+			node: id.0, lon: coords.0, lat: coords.1,
+			barrier: s(&node.barrier), highway: s(&node.highway), railway: s(&node.railway), crossing: s(&node.crossing),
+			car: node.access.car, bike: node.access.bike, foot: node.access.foot, bus: node.access.bus,
+			edges: edges.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(";"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod vertex_tests {
+	use super::*;
+	use crate::tags;
+	use crate::{build_edges, Profile};
+	use crate::multi_pass::{read_passes, MultiPass};
+	use osmio2::temp::TempFile;
+	use osmio2::{
+		objects::{OsmElementAttrs, OsmObj, Node, Way},
+		writer::OsmXmlWriter,
+	};
+
+	#[test]
+	fn test_routing_nodes() {
+		assert!(is_routing_node(&tags(&[("highway", "traffic_signals")])));
+		assert!(is_routing_node(&tags(&[("railway", "level_crossing")])));
+		assert!(is_routing_node(&tags(&[("railway", "halt")])));
+		assert!(is_routing_node(&tags(&[("barrier", "kissing_gate")])));
+		assert!(!is_routing_node(&tags(&[("barrier", "no"), ("highway", "street_lamp")])));
+
+		// one way 1-2-3-4-5 with a bollard at 2 and signals at 4: 3 edges instead of 1
+		let tmp = TempFile::new("vertex-test.osm");
+		let path = tmp.path();
+		let mut wr = OsmXmlWriter::from_path(&path).unwrap();
+		for i in 1..=5 {
+			let mut attrs = OsmElementAttrs::new();
+			attrs.id = i;
+			let t = match i {
+				2 => tags(&[("barrier", "bollard")]),
+				4 => tags(&[("highway", "traffic_signals")]),
+				_ => Tags::new(),
+			};
+			wr.write(&OsmObj::Node(Node { attrs, lat: 54.0, lon: 83.0 + i as f32 / 1000.0, tags: t })).unwrap();
+		}
+		let mut attrs = OsmElementAttrs::new();
+		attrs.id = 1;
+		wr.write(&OsmObj::Way(Way { attrs, nodes: vec![1, 2, 3, 4, 5], tags: tags(&[("highway", "residential")]) })).unwrap();
+		wr.close().unwrap();
+
+		let profile = Profile::default();
+		let MultiPass { vertice, routing_nodes, .. } = read_passes(&path, &profile, false).unwrap();
+		assert_eq!(routing_nodes.len(), 2);
+		assert_eq!(routing_nodes[&NodeId(2)].access, Access::new(false, true, true, false));
+		let mut edges = vec![];
+		build_edges(&path, &vertice, &profile, |e| { edges.push(e.nodes.len()); Ok(()) }).unwrap();
+		assert_eq!(edges, vec![2, 3, 2]);
+	}
+}
@@ -0,0 +1,51 @@
+use osmio2::{
+	reader::OsmXmlReader,
+	diff::{OsmDiff, DiffSummary},
+	stats::FileStats,
+};
+use std::error::Error;
+
+const TOP_TAGS: usize = 20;
+
+fn info(path: &str, json: bool) -> Result<(), Box<dyn Error>> {
+	let stats = FileStats::from_path(path, TOP_TAGS)?;
+	if json {
+		println!("{}", serde_json::to_string_pretty(&stats)?);
+	} else {
+		print!("{}", stats);
+	}
+	Ok(())
+}
+
+fn diff(old_path: &str, new_path: &str, json: bool) -> Result<(), Box<dyn Error>> {
+	let old = OsmXmlReader::from_path(old_path)?;
+	let new = OsmXmlReader::from_path(new_path)?;
+	let mut summary = DiffSummary::default();
+	for ch in OsmDiff::new(old, new) {
+		let ch = ch?;
+		summary.add(&ch);
+		if json {
+			// one object per line, to keep the output streamable
+			println!("{}", serde_json::to_string(&ch)?);
+		} else {
+			println!("{}", ch);
+		}
+	}
+	eprint!("{}", summary);
+	Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+	let args: Vec<String> = std::env::args().collect();
+	let json = args.iter().any(|a| a == "--json");
+	let args: Vec<&str> = args.iter().filter(|a| *a != "--json").map(|a| a.as_str()).collect();
+	match args[1..] {
+		["info", path] => info(path, json)?,
+		["diff", old_path, new_path] => diff(old_path, new_path, json)?,
+		_ => {
+			eprintln!("usage: osmfile info FILE.OSM [--json]\n       osmfile diff OLD.OSM NEW.OSM [--json]");
+			std::process::exit(2);
+		},
+	};
+	Ok(())
+}
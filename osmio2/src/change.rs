@@ -0,0 +1,93 @@
+use crate::{errors::ReadError, objects::OsmObj, reader::OsmXmlReader};
+use std::error::Error;
+
+/// Block of an [osmChange](https://wiki.openstreetmap.org/wiki/OsmChange) file an object is in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeAction { Create, Modify, Delete }
+
+impl ChangeAction {
+	pub fn from_name(name: &[u8]) -> Option<Self> {
+		match name {
+			b"create" => Some(Self::Create),
+			b"modify" => Some(Self::Modify),
+			b"delete" => Some(Self::Delete),
+			_ => None,
+		}
+	}
+}
+
+/// Reads `.osc[.gz|.bz2]` files: objects with the action of their block, in the order of the file.
+/// Objects in `<delete>` usually have no tags, way nodes or member lists, and nodes may have no coordinates (they get 0, 0).
+pub struct OsmChangeReader {
+	rd: OsmXmlReader,
+}
+
+impl OsmChangeReader {
+	pub fn new(rd: OsmXmlReader) -> Self {
+		Self { rd }
+	}
+
+	pub fn from_path(path: &str) -> Result<Self, Box<dyn Error>> {
+		Ok(Self::new(OsmXmlReader::from_path(path)?))
+	}
+
+	pub fn _next(&mut self) -> Result<Option<(ChangeAction, OsmObj)>, ReadError> {
+		let obj = match self.rd._next()? {
+			Some(obj) => obj,
+			None => return Ok(None),
+		};
+		match self.rd.action {
+			Some(a) => Ok(Some((a, obj))),
+			None => {
+				let otype: &str = obj.obj_type().into();
+				Err(ReadError { msg: format!("{} {} is not in create, modify or delete block", otype, obj.id()) })
+			},
+		}
+	}
+}
+
+impl Iterator for OsmChangeReader {
+	type Item = Result<(ChangeAction, OsmObj), ReadError>;
+	fn next(&mut self) -> Option<Self::Item> {
+		self._next().transpose()
+	}
+}
+
+#[cfg(test)]
+mod change_tests {
+	use super::*;
+	use std::io::{BufReader, Cursor, Read};
+
+	fn reader(text: &str) -> OsmChangeReader {
+		let rd: Box<dyn Read + Send> = Box::new(Cursor::new(text.as_bytes().to_vec()));
+		OsmChangeReader::new(OsmXmlReader::new(BufReader::new(rd)))
+	}
+
+	#[test]
+	fn test_change() {
+		let text = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osmChange version="0.6">
+<create>
+	<node id="5" version="1" lat="54.0" lon="83.0"><tag k="barrier" v="gate"/></node>
+</create>
+<modify>
+	<way id="10" version="2"><nd ref="1"/><nd ref="5"/><tag k="highway" v="service"/></way>
+</modify>
+<delete>
+	<node id="3" version="4"/>
+	<way id="11" version="3"/>
+</delete>
+</osmChange>"#;
+		let objs: Vec<(ChangeAction, OsmObj)> = reader(text).collect::<Result<_, _>>().unwrap();
+		let short: Vec<(ChangeAction, i64)> = objs.iter().map(|(a, o)| (*a, o.id())).collect();
+		assert_eq!(short, vec![(ChangeAction::Create, 5), (ChangeAction::Modify, 10), (ChangeAction::Delete, 3), (ChangeAction::Delete, 11)]);
+		match &objs[1].1 {
+			OsmObj::Way(w) => assert_eq!((w.nodes.clone(), w.tags.len()), (vec![1, 5], 1)),
+			o => panic!("not a way: {:?}", o),
+		}
+
+		// objects outside of the blocks are an error, and only deleted nodes may have no coordinates
+		assert!(reader("<osmChange><node id=\"1\" lat=\"1\" lon=\"1\"/></osmChange>").next().unwrap().is_err());
+		assert!(reader("<osmChange><modify><node id=\"1\"/></modify></osmChange>").next().unwrap().is_err());
+	}
+}
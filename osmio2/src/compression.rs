@@ -0,0 +1,327 @@
+use bzip2::{Decompress, Status, bufread::BzDecoder};
+use crossbeam_channel::{Receiver, Sender, bounded};
+use flate2::{bufread::GzDecoder as BufGzDecoder, read::MultiGzDecoder};
+use std::{
+	collections::BTreeMap,
+	error::Error,
+	io::{self, BufRead, BufReader, Cursor, ErrorKind, Read},
+	thread::{available_parallelism, spawn},
+};
+
+// how much of a compressed file is read to look for a second stream/member
+const PROBE_SIZE: u64 = 4 << 20;
+// streams are glued into jobs of at least this size, to keep thread overhead low
+const MIN_SEGMENT: usize = 1 << 20;
+const READ_CHUNK: usize = 1 << 20;
+
+// bzip2 0.3 decoder stops after the first stream, but files written by parallel compressors
+// (and framed files written by index module) consist of many streams concatenated.
+pub struct MultiBzDecoder<R: BufRead> {
+	inner: Option<BzDecoder<R>>,
+}
+
+impl<R: BufRead> MultiBzDecoder<R> {
+	pub fn new(rd: R) -> Self {
+		Self { inner: Some(BzDecoder::new(rd)) }
+	}
+}
+
+impl<R: BufRead> Read for MultiBzDecoder<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		loop {
+			let dec = match self.inner.as_mut() {
+				Some(d) => d,
+				None => return Ok(0),
+			};
+			let n = dec.read(buf)?;
+			if n > 0 || buf.is_empty() {
+				return Ok(n)
+			}
+			// current stream has ended. if there are more bytes after it, they are the next stream
+			let mut rd = self.inner.take().unwrap().into_inner();
+			if rd.fill_buf()?.is_empty() {
+				return Ok(0)
+			}
+			self.inner = Some(BzDecoder::new(rd));
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format { Gzip, Bzip2 }
+
+impl Format {
+	pub fn from_path(path: &str) -> Option<Self> {
+		if path.ends_with(".gz") { Some(Self::Gzip) }
+		else if path.ends_with(".bz2") { Some(Self::Bzip2) }
+		else { None }
+	}
+
+	const HEADER_LEN: usize = 10;
+
+	// checks if a stream (bzip2) or member (gzip) may start here. False positives are possible, they are handled by ParallelDecoder
+	fn is_header(&self, d: &[u8]) -> bool {
+		if d.len() < Self::HEADER_LEN { return false }
+		match self {
+			// "BZh", block size 1-9, then either block magic (pi) or end of stream magic (sqrt pi) for empty streams
+			Self::Bzip2 => &d[..3] == b"BZh" && (b'1'..=b'9').contains(&d[3])
+				&& (d[4..10] == [0x31, 0x41, 0x59, 0x26, 0x53, 0x59] || d[4..10] == [0x17, 0x72, 0x45, 0x38, 0x50, 0x90]),
+			// magic, deflate method, reserved flag bits are zero
+			Self::Gzip => d[..3] == [0x1f, 0x8b, 0x08] && d[3] & 0xe0 == 0,
+		}
+	}
+
+	// offsets of possible stream starts in data[from..], except 0
+	fn find_headers(&self, data: &[u8], from: usize) -> Vec<usize> {
+		let first = match self { Self::Bzip2 => b'B', Self::Gzip => 0x1f };
+		(from.max(1)..data.len())
+			.filter(|i| data[*i] == first && self.is_header(&data[*i..]))
+			.collect()
+	}
+}
+
+fn decode_bzip2(data: &[u8], out: &mut Vec<u8>) -> io::Result<usize> {
+	// low-level decompressor, because bzip2::bufread::BzDecoder doesn't report truncated streams
+	let mut d = Decompress::new(false);
+	loop {
+		out.reserve(READ_CHUNK);
+		let (before_in, before_out) = (d.total_in(), d.total_out());
+		let status = d.decompress_vec(&data[d.total_in() as usize..], out).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+		if status == Status::StreamEnd {
+			return Ok(d.total_in() as usize)
+		}
+		if d.total_in() == before_in && d.total_out() == before_out {
+			return Err(io::Error::new(ErrorKind::UnexpectedEof, "bzip2 stream is truncated"))
+		}
+	}
+}
+
+fn decode_gzip(data: &[u8], out: &mut Vec<u8>) -> io::Result<usize> {
+	let mut d = BufGzDecoder::new(data);
+	d.read_to_end(out)?;
+	Ok(data.len() - d.into_inner().len())
+}
+
+// decodes all streams/members in the segment. Fails if the segment is cut in the middle of a stream
+pub fn decode_segment(format: Format, data: &[u8]) -> io::Result<Vec<u8>> {
+	let mut out = Vec::with_capacity(data.len() * 5);
+	let mut pos = 0;
+	while pos < data.len() {
+		pos += match format {
+			Format::Bzip2 => decode_bzip2(&data[pos..], &mut out)?,
+			Format::Gzip => decode_gzip(&data[pos..], &mut out)?,
+		};
+	}
+	Ok(out)
+}
+
+type Job = (usize, io::Result<Vec<u8>>);
+type Decoded = (usize, Vec<u8>, io::Result<Vec<u8>>);
+
+// reads the compressed input and cuts it at (possible) stream starts
+fn split_segments<R: Read>(mut rd: R, format: Format, min_segment: usize, jobs: Sender<Job>) {
+	let mut seq = 0;
+	let mut seg: Vec<u8> = vec![];
+	let mut scan_from = 0;
+	let mut chunk = vec![0u8; READ_CHUNK];
+	loop {
+		let n = match rd.read(&mut chunk) {
+			Ok(0) => break,
+			Ok(n) => n,
+			Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+			Err(e) => { let _ = jobs.send((seq, Err(e))); return }
+		};
+		seg.extend_from_slice(&chunk[..n]);
+		// the header might have been split by the previous read
+		let found = format.find_headers(&seg, scan_from.max(min_segment));
+		scan_from = seg.len().saturating_sub(Format::HEADER_LEN - 1);
+		if let Some(cut) = found.last() {
+			let rest = seg.split_off(*cut);
+			if jobs.send((seq, Ok(seg))).is_err() { return }
+			seq += 1;
+			scan_from -= cut;
+			seg = rest;
+		}
+	}
+	if !seg.is_empty() {
+		let _ = jobs.send((seq, Ok(seg)));
+	}
+}
+
+/// Decompresses multi-stream bzip2 or multi-member gzip in several threads, and returns the data in the original order.
+/// Input is cut at stream headers, and if a cut turns out to be wrong (header-like bytes inside a stream),
+/// the pieces are glued back and decoded again.
+pub struct ParallelDecoder {
+	format: Format,
+	results: Receiver<Decoded>,
+	pending: BTreeMap<usize, (Vec<u8>, io::Result<Vec<u8>>)>,
+	next_seq: usize,
+	current: Cursor<Vec<u8>>,
+}
+
+impl ParallelDecoder {
+	pub fn new<R: Read + Send + 'static>(rd: R, format: Format, threads: usize, min_segment: usize) -> Self {
+		let threads = threads.max(1);
+		let (job_snd, job_rec): (Sender<Job>, Receiver<Job>) = bounded(threads * 2);
+		let (res_snd, res_rec): (Sender<Decoded>, Receiver<Decoded>) = bounded(threads * 2);
+
+		spawn(move || split_segments(rd, format, min_segment, job_snd));
+		for _ in 0..threads {
+			let job_rec = job_rec.clone();
+			let res_snd = res_snd.clone();
+			spawn(move || {
+				while let Ok((seq, data)) = job_rec.recv() {
+					let res = match data {
+						Ok(data) => { let r = decode_segment(format, &data); (seq, data, r) },
+						Err(e) => (seq, vec![], Err(e)),
+					};
+					if res_snd.send(res).is_err() { break }
+				}
+			});
+		}
+
+		Self { format, results: res_rec, pending: BTreeMap::new(), next_seq: 0, current: Cursor::new(vec![]) }
+	}
+
+	fn take_seq(&mut self, seq: usize) -> Option<(Vec<u8>, io::Result<Vec<u8>>)> {
+		loop {
+			if let Some(r) = self.pending.remove(&seq) {
+				return Some(r)
+			}
+			match self.results.recv() {
+				Ok((s, data, res)) => { self.pending.insert(s, (data, res)); },
+				Err(_) => return None,  // all workers are done
+			}
+		}
+	}
+
+	fn next_segment(&mut self) -> io::Result<Option<Vec<u8>>> {
+		let (mut data, mut res) = match self.take_seq(self.next_seq) {
+			Some(r) => r,
+			None => return Ok(None),
+		};
+		self.next_seq += 1;
+		while let Err(e) = res {
+			// the segment was cut at a false header, glue it with the next one and try again
+			if data.is_empty() { return Err(e) }
+			match self.take_seq(self.next_seq) {
+				Some((next_data, _)) => {
+					self.next_seq += 1;
+					data.extend(next_data);
+					res = decode_segment(self.format, &data);
+				},
+				None => return Err(e),
+			}
+		}
+		res.map(Some)
+	}
+}
+
+impl Read for ParallelDecoder {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		loop {
+			let n = self.current.read(buf)?;
+			if n > 0 || buf.is_empty() {
+				return Ok(n)
+			}
+			match self.next_segment()? {
+				Some(data) => self.current = Cursor::new(data),
+				None => return Ok(0),
+			}
+		}
+	}
+}
+
+// checks the beginning of the file for a second stream/member. The probed bytes are put back in front of the reader
+fn probe_multistream<R: Read + Send + 'static>(mut rd: R, format: Format) -> io::Result<(bool, Box<dyn Read + Send>)> {
+	let mut head = vec![];
+	(&mut rd).take(PROBE_SIZE).read_to_end(&mut head)?;
+	let multi = !format.find_headers(&head, 1).is_empty();
+	Ok((multi, Box::new(Cursor::new(head).chain(rd))))
+}
+
+// picks decompressor by file extension
+pub fn decompress<R: Read + Send + 'static>(path: &str, rd: R) -> Result<Box<dyn Read + Send>, Box<dyn Error>> {
+	// osmChange files are read the same way
+	let name = path.trim_end_matches(".gz").trim_end_matches(".bz2");
+	if !name.ends_with(".osm") && !name.ends_with(".osc") {
+		return Err("file is not .osm or .osc format".into());
+	}
+	let format = match Format::from_path(path) {
+		Some(f) => f,
+		None => return Ok(Box::new(rd)),
+	};
+
+	let (multi, rd) = probe_multistream(rd, format)?;
+	if multi {
+		let threads = available_parallelism().map(|n| n.get()).unwrap_or(4);
+		return Ok(Box::new(ParallelDecoder::new(rd, format, threads, MIN_SEGMENT)))
+	}
+	Ok(match format {
+		Format::Gzip => Box::new(MultiGzDecoder::new(rd)) as Box<dyn Read + Send>,
+		Format::Bzip2 => Box::new(MultiBzDecoder::new(BufReader::new(rd))) as Box<dyn Read + Send>,
+	})
+}
+
+#[cfg(test)]
+mod compression_tests {
+	use super::*;
+	use bzip2::{Compression as bzCompression, write::BzEncoder};
+	use flate2::{Compression as gzCompression, write::GzEncoder};
+	use std::io::Write;
+
+	fn sample(i: usize) -> Vec<u8> {
+		(0..2000).map(|j| format!("<node id=\"{}\"/>\n", i * 10000 + j)).collect::<String>().into_bytes()
+	}
+
+	fn compress(format: Format, data: &[u8], level: u32) -> Vec<u8> {
+		match format {
+			Format::Gzip => {
+				let mut enc = GzEncoder::new(vec![], gzCompression::new(level));
+				enc.write_all(data).unwrap();
+				enc.finish().unwrap()
+			},
+			Format::Bzip2 => {
+				let mut enc = BzEncoder::new(vec![], bzCompression::Fastest);
+				enc.write_all(data).unwrap();
+				enc.finish().unwrap()
+			}
+		}
+	}
+
+	#[test]
+	fn test_parallel_decoder() {
+		for format in [Format::Gzip, Format::Bzip2] {
+			let mut expected = vec![];
+			let mut compressed = vec![];
+			for i in 0..30 {
+				let s = sample(i);
+				compressed.extend(compress(format, &s, 6));
+				expected.extend(s);
+			}
+			let mut out = vec![];
+			ParallelDecoder::new(Cursor::new(compressed.clone()), format, 3, 1000).read_to_end(&mut out).unwrap();
+			assert!(out == expected);
+
+			// truncated input must be an error, not a silently shorter output
+			let mut out = vec![];
+			let truncated = compressed[..compressed.len() - 20].to_vec();
+			assert!(ParallelDecoder::new(Cursor::new(truncated), format, 3, 1000).read_to_end(&mut out).is_err());
+		}
+	}
+
+	#[test]
+	fn test_false_header() {
+		// gzip without compression stores the data as is, so a header-like sequence inside it must not break decoding
+		let mut data = sample(1);
+		data.extend([0x1f, 0x8b, 0x08, 0, 0, 0, 0, 0, 0, 3]);
+		data.extend(sample(2));
+		let mut compressed = compress(Format::Gzip, &data, 0);
+		compressed.extend(compress(Format::Gzip, &sample(3), 6));
+		data.extend(sample(3));
+
+		let mut out = vec![];
+		ParallelDecoder::new(Cursor::new(compressed), Format::Gzip, 2, 100).read_to_end(&mut out).unwrap();
+		assert!(out == data);
+	}
+}
@@ -0,0 +1,307 @@
+use serde::Serialize;
+use std::{
+	collections::BTreeSet,
+	fmt,
+	iter::Peekable,
+};
+
+use crate::{
+	errors::ReadError,
+	objects::{Member, ObjType, OsmObj, Tags},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind { Added, Removed, Modified }
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TagChange {
+	pub key: String,
+	pub old: Option<String>,
+	pub new: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GeomChange {
+	/// node coordinates changed, (lon, lat)
+	Moved { old: (f32, f32), new: (f32, f32) },
+	/// way node list changed
+	Nodes { old: Vec<i64>, new: Vec<i64> },
+	/// relation members changed: how many were dropped from the old list and added to the new one
+	Members { removed: usize, added: usize },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ObjChange {
+	pub otype: ObjType,
+	pub id: i64,
+	pub kind: ChangeKind,
+	pub old_version: Option<u32>,
+	pub new_version: Option<u32>,
+	pub tags: Vec<TagChange>,
+	pub geometry: Option<GeomChange>,
+}
+
+fn version(obj: &OsmObj) -> Option<u32> {
+	match obj {
+		OsmObj::Node(n) => n.attrs.version,
+		OsmObj::Way(w) => w.attrs.version,
+		OsmObj::Relation(r) => r.attrs.version,
+	}
+}
+
+fn tags(obj: &OsmObj) -> &Tags {
+	match obj {
+		OsmObj::Node(n) => &n.tags,
+		OsmObj::Way(w) => &w.tags,
+		OsmObj::Relation(r) => &r.tags,
+	}
+}
+
+pub fn diff_tags(old: &Tags, new: &Tags) -> Vec<TagChange> {
+	// sorted keys, so that output is stable
+	let keys: BTreeSet<&str> = old.keys().chain(new.keys()).map(|k| &**k).collect();
+	keys.into_iter().filter_map(|k| {
+		let (o, n) = (old.get(k), new.get(k));
+		(o != n).then(|| TagChange {
+			key: k.to_string(),
+			old: o.map(|v| v.to_string()),
+			new: n.map(|v| v.to_string()),
+		})
+	}).collect()
+}
+
+fn diff_members(old: &[Member], new: &[Member]) -> GeomChange {
+	// members are compared as multisets, order changes are reported as 0/0
+	let mut rest = new.to_vec();
+	let mut removed = 0;
+	for m in old {
+		match rest.iter().position(|r| r == m) {
+			Some(i) => { rest.remove(i); },
+			None => removed += 1,
+		}
+	}
+	GeomChange::Members { removed, added: rest.len() }
+}
+
+pub fn diff_geometry(old: &OsmObj, new: &OsmObj) -> Option<GeomChange> {
+	match (old, new) {
+		(OsmObj::Node(o), OsmObj::Node(n)) => {
+			(o.lon != n.lon || o.lat != n.lat).then_some(GeomChange::Moved { old: (o.lon, o.lat), new: (n.lon, n.lat) })
+		},
+		(OsmObj::Way(o), OsmObj::Way(n)) => {
+			(o.nodes != n.nodes).then(|| GeomChange::Nodes { old: o.nodes.clone(), new: n.nodes.clone() })
+		},
+		(OsmObj::Relation(o), OsmObj::Relation(n)) => {
+			(o.members != n.members).then(|| diff_members(&o.members, &n.members))
+		},
+		_ => None
+	}
+}
+
+impl ObjChange {
+	fn added(obj: &OsmObj) -> Self {
+		Self { otype: obj.obj_type(), id: obj.id(), kind: ChangeKind::Added,
+			old_version: None, new_version: version(obj), tags: diff_tags(&Tags::new(), tags(obj)), geometry: None }
+	}
+
+	fn removed(obj: &OsmObj) -> Self {
+		Self { otype: obj.obj_type(), id: obj.id(), kind: ChangeKind::Removed,
+			old_version: version(obj), new_version: None, tags: diff_tags(tags(obj), &Tags::new()), geometry: None }
+	}
+
+	// returns None if the objects have the same tags and geometry
+	fn modified(old: &OsmObj, new: &OsmObj) -> Option<Self> {
+		let tag_changes = diff_tags(tags(old), tags(new));
+		let geometry = diff_geometry(old, new);
+		if tag_changes.is_empty() && geometry.is_none() {
+			return None
+		}
+		Some(Self { otype: old.obj_type(), id: old.id(), kind: ChangeKind::Modified,
+			old_version: version(old), new_version: version(new), tags: tag_changes, geometry })
+	}
+}
+
+impl fmt::Display for ObjChange {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let sign = match self.kind { ChangeKind::Added => "+", ChangeKind::Removed => "-", ChangeKind::Modified => "~" };
+		let otype: &str = self.otype.into();
+		write!(f, "{} {} {}", sign, otype, self.id)?;
+		if self.kind == ChangeKind::Modified {
+			let v = |v: Option<u32>| v.map(|v| v.to_string()).unwrap_or_else(|| "?".to_string());
+			write!(f, " v{}->v{}", v(self.old_version), v(self.new_version))?;
+			for t in self.tags.iter() {
+				match (&t.old, &t.new) {
+					(None, Some(n)) => write!(f, " +{}={}", t.key, n)?,
+					(Some(_), None) => write!(f, " -{}", t.key)?,
+					(Some(o), Some(n)) => write!(f, " {}: {} -> {}", t.key, o, n)?,
+					(None, None) => {}
+				}
+			}
+			match &self.geometry {
+				Some(GeomChange::Moved { old, new }) => write!(f, " moved ({} {}) -> ({} {})", old.0, old.1, new.0, new.1)?,
+				Some(GeomChange::Nodes { old, new }) => write!(f, " nodes {} -> {}", old.len(), new.len())?,
+				Some(GeomChange::Members { removed, added }) => write!(f, " members -{} +{}", removed, added)?,
+				None => {}
+			}
+		}
+		Ok(())
+	}
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct DiffSummary {
+	pub added: [u64; 3],
+	pub removed: [u64; 3],
+	pub modified: [u64; 3],
+}
+
+impl DiffSummary {
+	pub fn add(&mut self, ch: &ObjChange) {
+		let i = ch.otype as usize;
+		match ch.kind {
+			ChangeKind::Added => self.added[i] += 1,
+			ChangeKind::Removed => self.removed[i] += 1,
+			ChangeKind::Modified => self.modified[i] += 1,
+		}
+	}
+}
+
+impl fmt::Display for DiffSummary {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		for (i, name) in ["nodes", "ways", "relations"].iter().enumerate() {
+			writeln!(f, "{}: +{} -{} ~{}", name, self.added[i], self.removed[i], self.modified[i])?;
+		}
+		Ok(())
+	}
+}
+
+/// Streaming diff of two files sorted in the usual way (nodes, ways, relations, each by id).
+/// Walks both inputs at once, keeping only one object of each in memory.
+pub struct OsmDiff<I: Iterator<Item = Result<OsmObj, ReadError>>> {
+	old: Peekable<I>,
+	new: Peekable<I>,
+	last_old: Option<(ObjType, i64)>,
+	last_new: Option<(ObjType, i64)>,
+}
+
+impl<I: Iterator<Item = Result<OsmObj, ReadError>>> OsmDiff<I> {
+	pub fn new(old: I, new: I) -> Self {
+		Self { old: old.peekable(), new: new.peekable(), last_old: None, last_new: None }
+	}
+
+	fn check_sorted(last: &mut Option<(ObjType, i64)>, obj: &OsmObj) -> Result<(), ReadError> {
+		let key = (obj.obj_type(), obj.id());
+		if let Some(prev) = last {
+			if *prev >= key {
+				let otype: &str = key.0.into();
+				return Err(ReadError { msg: format!("input is not sorted at {} {}", otype, key.1) })
+			}
+		}
+		*last = Some(key);
+		Ok(())
+	}
+
+	pub fn _next(&mut self) -> Result<Option<ObjChange>, ReadError> {
+		loop {
+			// errors are taken out of the iterators right away, so that peeked values are always Ok
+			if let Some(Err(_)) = self.old.peek() { return Err(self.old.next().unwrap().unwrap_err()) }
+			if let Some(Err(_)) = self.new.peek() { return Err(self.new.next().unwrap().unwrap_err()) }
+
+			let ko = self.old.peek().and_then(|o| o.as_ref().map(|o| (o.obj_type(), o.id())).ok());
+			let kn = self.new.peek().and_then(|o| o.as_ref().map(|o| (o.obj_type(), o.id())).ok());
+
+			match (ko, kn) {
+				(None, None) => return Ok(None),
+				(Some(_), None) => {
+					let o = self.old.next().unwrap()?;
+					Self::check_sorted(&mut self.last_old, &o)?;
+					return Ok(Some(ObjChange::removed(&o)))
+				},
+				(None, Some(_)) => {
+					let n = self.new.next().unwrap()?;
+					Self::check_sorted(&mut self.last_new, &n)?;
+					return Ok(Some(ObjChange::added(&n)))
+				},
+				(Some(ko), Some(kn)) if ko < kn => {
+					let o = self.old.next().unwrap()?;
+					Self::check_sorted(&mut self.last_old, &o)?;
+					return Ok(Some(ObjChange::removed(&o)))
+				},
+				(Some(ko), Some(kn)) if ko > kn => {
+					let n = self.new.next().unwrap()?;
+					Self::check_sorted(&mut self.last_new, &n)?;
+					return Ok(Some(ObjChange::added(&n)))
+				},
+				_ => {
+					let o = self.old.next().unwrap()?;
+					let n = self.new.next().unwrap()?;
+					Self::check_sorted(&mut self.last_old, &o)?;
+					Self::check_sorted(&mut self.last_new, &n)?;
+					if let Some(ch) = ObjChange::modified(&o, &n) {
+						return Ok(Some(ch))
+					}
+				}
+			}
+		}
+	}
+}
+
+impl<I: Iterator<Item = Result<OsmObj, ReadError>>> Iterator for OsmDiff<I> {
+	type Item = Result<ObjChange, ReadError>;
+	fn next(&mut self) -> Option<Self::Item> {
+		self._next().transpose()
+	}
+}
+
+#[cfg(test)]
+mod diff_tests {
+	use super::*;
+	use crate::objects::{Node, Way, OsmElementAttrs};
+	use std::sync::Arc;
+
+	fn node(id: i64, lon: f32) -> OsmObj {
+		let mut attrs = OsmElementAttrs::new();
+		attrs.id = id;
+		OsmObj::Node(Node { attrs, lon, lat: 10.0, tags: Tags::new() })
+	}
+
+	fn way(id: i64, nodes: Vec<i64>, hw: &str) -> OsmObj {
+		let mut attrs = OsmElementAttrs::new();
+		attrs.id = id;
+		let mut tags = Tags::new();
+		tags.insert(Arc::from("highway"), Arc::from(hw));
+		OsmObj::Way(Way { attrs, nodes, tags })
+	}
+
+	#[test]
+	fn test_diff() {
+		let old = vec![node(1, 1.0), node(2, 2.0), node(3, 3.0), way(10, vec![1, 2], "primary"), way(11, vec![2, 3], "service")];
+		let new = vec![node(1, 1.0), node(2, 2.5), node(4, 4.0), way(10, vec![1, 2], "secondary"), way(11, vec![2, 4], "service")];
+		let changes: Vec<ObjChange> = OsmDiff::new(old.into_iter().map(Ok), new.into_iter().map(Ok))
+			.collect::<Result<_, _>>().unwrap();
+
+		let short: Vec<(ObjType, i64, ChangeKind)> = changes.iter().map(|c| (c.otype, c.id, c.kind)).collect();
+		assert_eq!(short, vec![
+			(ObjType::Node, 2, ChangeKind::Modified),
+			(ObjType::Node, 3, ChangeKind::Removed),
+			(ObjType::Node, 4, ChangeKind::Added),
+			(ObjType::Way, 10, ChangeKind::Modified),
+			(ObjType::Way, 11, ChangeKind::Modified),
+		]);
+		assert_eq!(changes[0].geometry, Some(GeomChange::Moved { old: (2.0, 10.0), new: (2.5, 10.0) }));
+		assert_eq!(changes[3].tags, vec![TagChange { key: "highway".into(), old: Some("primary".into()), new: Some("secondary".into()) }]);
+		assert!(changes[3].geometry.is_none());
+		assert!(changes[4].tags.is_empty());
+		assert_eq!(changes[4].geometry, Some(GeomChange::Nodes { old: vec![2, 3], new: vec![2, 4] }));
+	}
+
+	#[test]
+	fn test_unsorted() {
+		let old = vec![node(2, 1.0), node(1, 1.0)];
+		let new = vec![node(1, 1.0), node(2, 1.0)];
+		let res: Result<Vec<ObjChange>, ReadError> = OsmDiff::new(old.into_iter().map(Ok), new.into_iter().map(Ok)).collect();
+		assert!(res.is_err());
+	}
+}
@@ -84,3 +84,9 @@ impl From<QError> for WriteError {
 		Self { msg: format!("Parsing error {:?}", e)}
 	}
 }
+
+impl From<std::io::Error> for WriteError {
+	fn from(e: std::io::Error) -> Self {
+		Self { msg: format!("IO error {:?}", e)}
+	}
+}
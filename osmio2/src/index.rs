@@ -0,0 +1,289 @@
+use bzip2::{Compression as bzCompression, write::BzEncoder};
+use csv::{Reader as CsvReader, Writer as CsvWriter};
+use flate2::{Compression as gzCompression, write::GzEncoder};
+use quick_xml::Writer;
+use serde::{Serialize, Deserialize};
+use std::{
+	error::Error,
+	fs::File,
+	io::{BufReader, Read, Seek, SeekFrom, Write},
+};
+
+use crate::{
+	compression::decompress,
+	objects::{ObjType, OsmObj, Node, Way, Relation},
+	reader::OsmXmlReader,
+	writer::{write_header, write_footer, write_obj},
+};
+
+pub const DEFAULT_BLOCK_SIZE: usize = 4096;
+
+/// A block of objects of one type, that can be read without reading anything before it.
+/// For plain .osm files, offset/length are positions of the XML text,
+/// for compressed files, they are positions of an independent gzip member/bzip2 stream.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexEntry {
+	pub otype: ObjType,
+	pub first_id: i64,
+	pub last_id: i64,
+	pub offset: u64,
+	pub length: u64,
+}
+
+impl IndexEntry {
+	fn new(otype: ObjType, id: i64, offset: u64) -> Self {
+		Self { otype, first_id: id, last_id: id, offset, length: 0 }
+	}
+
+	fn extend(&mut self, id: i64) {
+		// files are usually sorted by id, but this is not guaranteed, hence min/max
+		self.first_id = self.first_id.min(id);
+		self.last_id = self.last_id.max(id);
+	}
+
+	pub fn contains(&self, otype: ObjType, id: i64) -> bool {
+		self.otype == otype && self.first_id <= id && id <= self.last_id
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct OsmIndex {
+	pub entries: Vec<IndexEntry>,
+}
+
+impl OsmIndex {
+	pub fn sidecar_path(path: &str) -> String {
+		format!("{}.idx", path)
+	}
+
+	pub fn load(idx_path: &str) -> Result<Self, Box<dyn Error>> {
+		let mut rd = CsvReader::from_path(idx_path)?;
+		let entries = rd.deserialize().collect::<Result<Vec<IndexEntry>, _>>()?;
+		Ok(Self { entries })
+	}
+
+	pub fn save(&self, idx_path: &str) -> Result<(), Box<dyn Error>> {
+		let mut wr = CsvWriter::from_path(idx_path)?;
+		for e in self.entries.iter() {
+			wr.serialize(e)?;
+		}
+		wr.flush()?;
+		Ok(())
+	}
+
+	pub fn find(&self, otype: ObjType, id: i64) -> impl Iterator<Item = &IndexEntry> {
+		self.entries.iter().filter(move |e| e.contains(otype, id))
+	}
+
+	/// Indexes a plain (uncompressed) .osm file in place and saves the sidecar index next to it.
+	pub fn build(path: &str, block_size: usize) -> Result<Self, Box<dyn Error>> {
+		if !path.ends_with(".osm") {
+			return Err("only plain .osm files can be indexed in place, use OsmIndex::build_framed for compressed ones".into());
+		}
+
+		let mut rd = OsmXmlReader::from_path(path)?;
+		let mut entries: Vec<IndexEntry> = vec![];
+		let mut block: Option<IndexEntry> = None;
+		let mut count = 0;
+
+		while let Some(obj) = rd._next()? {
+			let (otype, id) = (obj.obj_type(), obj.id());
+			let end = rd.rd.buffer_position() as u64;
+			match block {
+				Some(ref mut b) if b.otype == otype && count < block_size => {
+					b.extend(id);
+					count += 1;
+				},
+				_ => {
+					entries.extend(block.take());
+					block = Some(IndexEntry::new(otype, id, rd.obj_start as u64));
+					count = 1;
+				}
+			}
+			if let Some(ref mut b) = block {
+				b.length = end - b.offset;
+			}
+		}
+		entries.extend(block);
+
+		let idx = Self { entries };
+		idx.save(&Self::sidecar_path(path))?;
+		Ok(idx)
+	}
+
+	/// Copies `src` into `dst`, compressing every block of objects as a separate gzip member/bzip2 stream
+	/// (depending on `dst` extension), so that each block can be decompressed on its own.
+	/// `dst` remains a normal .osm.gz/.osm.bz2 file for other readers. The index is saved next to `dst`.
+	pub fn build_framed(src: &str, dst: &str, block_size: usize) -> Result<Self, Box<dyn Error>> {
+		let rd = OsmXmlReader::from_path(src)?;
+		let mut fp = File::create(dst)?;
+		let mut wr = Writer::new_with_indent(Vec::new(), 9, 1);
+		let mut entries: Vec<IndexEntry> = vec![];
+		let mut block: Option<IndexEntry> = None;
+		let mut count = 0;
+		let mut offset = 0u64;
+
+		// the XML header goes into the first frame together with the first block
+		write_header(&mut wr)?;
+
+		for obj in rd {
+			let obj = obj?;
+			let (otype, id) = (obj.obj_type(), obj.id());
+			match block {
+				Some(ref mut b) if b.otype == otype && count < block_size => {
+					b.extend(id);
+					count += 1;
+				},
+				_ => {
+					if let Some(mut b) = block.take() {
+						b.length = write_frame(dst, &mut fp, wr.inner())?;
+						offset += b.length;
+						entries.push(b);
+					}
+					block = Some(IndexEntry::new(otype, id, offset));
+					count = 1;
+				}
+			}
+			write_obj(&mut wr, &obj)?;
+		}
+
+		if let Some(mut b) = block.take() {
+			b.length = write_frame(dst, &mut fp, wr.inner())?;
+			entries.push(b);
+		}
+		// closing tag is a frame of its own, so that the last block can be read separately
+		write_footer(&mut wr)?;
+		write_frame(dst, &mut fp, wr.inner())?;
+
+		let idx = Self { entries };
+		idx.save(&Self::sidecar_path(dst))?;
+		Ok(idx)
+	}
+}
+
+// compresses the buffer as an independent member/stream, appends it to the file and returns its size in bytes
+fn write_frame(path: &str, fp: &mut File, buf: &mut Vec<u8>) -> Result<u64, Box<dyn Error>> {
+	let data = std::mem::take(buf);
+	let compressed = if path.ends_with(".osm.gz") {
+		let mut enc = GzEncoder::new(Vec::new(), gzCompression::new(5));
+		enc.write_all(&data)?;
+		enc.finish()?
+	} else if path.ends_with(".osm.bz2") {
+		let mut enc = BzEncoder::new(Vec::new(), bzCompression::Default);
+		enc.write_all(&data)?;
+		enc.finish()?
+	} else if path.ends_with(".osm") {
+		data
+	} else {
+		return Err("file is not .osm format".into());
+	};
+	fp.write_all(&compressed)?;
+	Ok(compressed.len() as u64)
+}
+
+/// Reads single objects from a file with a sidecar index, decompressing only the block that contains them.
+pub struct OsmIndexedReader {
+	pub path: String,
+	pub index: OsmIndex,
+	fp: File,
+}
+
+impl OsmIndexedReader {
+	pub fn open(path: &str) -> Result<Self, Box<dyn Error>> {
+		let index = OsmIndex::load(&OsmIndex::sidecar_path(path))?;
+		Ok(Self { path: path.to_string(), index, fp: File::open(path)? })
+	}
+
+	pub fn get(&mut self, otype: ObjType, id: i64) -> Result<Option<OsmObj>, Box<dyn Error>> {
+		let entries: Vec<IndexEntry> = self.index.find(otype, id).cloned().collect();
+		for e in entries {
+			self.fp.seek(SeekFrom::Start(e.offset))?;
+			let frame = self.fp.try_clone()?.take(e.length);
+			let mut rd = OsmXmlReader::new(BufReader::new(decompress(&self.path, frame)?));
+			rd.skip_nodes = otype != ObjType::Node;
+			rd.skip_ways = otype != ObjType::Way;
+			rd.skip_relations = otype != ObjType::Relation;
+			for obj in rd {
+				let obj = obj?;
+				if obj.id() == id {
+					return Ok(Some(obj))
+				}
+			}
+		}
+		Ok(None)
+	}
+
+	pub fn get_node(&mut self, id: i64) -> Result<Option<Node>, Box<dyn Error>> {
+		Ok(match self.get(ObjType::Node, id)? { Some(OsmObj::Node(n)) => Some(n), _ => None })
+	}
+
+	pub fn get_way(&mut self, id: i64) -> Result<Option<Way>, Box<dyn Error>> {
+		Ok(match self.get(ObjType::Way, id)? { Some(OsmObj::Way(w)) => Some(w), _ => None })
+	}
+
+	pub fn get_relation(&mut self, id: i64) -> Result<Option<Relation>, Box<dyn Error>> {
+		Ok(match self.get(ObjType::Relation, id)? { Some(OsmObj::Relation(r)) => Some(r), _ => None })
+	}
+}
+
+#[cfg(test)]
+mod index_tests {
+	use super::*;
+	use crate::{
+		objects::{OsmElementAttrs, Tags},
+		writer::OsmXmlWriter,
+	};
+	use std::sync::Arc;
+	use crate::temp::TempFile;
+
+	fn write_sample(path: &str) {
+		let mut wr = OsmXmlWriter::from_path(path).unwrap();
+		for i in 1..=25 {
+			let mut attrs = OsmElementAttrs::new();
+			attrs.id = i;
+			wr.write(&OsmObj::Node(Node { attrs, lat: 54.0 + i as f32 / 100.0, lon: 83.0, tags: Tags::new() })).unwrap();
+		}
+		for i in 1..=7 {
+			let mut attrs = OsmElementAttrs::new();
+			attrs.id = i * 10;
+			let mut tags = Tags::new();
+			tags.insert(Arc::from("highway"), Arc::from("residential"));
+			tags.insert(Arc::from("name"), Arc::from(format!("street {}", i).as_str()));
+			wr.write(&OsmObj::Way(Way { attrs, nodes: vec![i, i + 1, i + 2], tags })).unwrap();
+		}
+		wr.close().unwrap();
+	}
+
+	#[test]
+	fn test_plain_and_framed_index() {
+		let tmp = TempFile::new("index-test");
+		std::fs::create_dir(&tmp.0).unwrap();
+		let dir = &tmp.0;
+		let src = dir.join("index-test.osm").to_str().unwrap().to_string();
+		write_sample(&src);
+
+		let idx = OsmIndex::build(&src, 10).unwrap();
+		// 25 nodes in blocks of 10 -> 3 blocks, 7 ways -> 1 block
+		assert_eq!(idx.entries.len(), 4);
+
+		for dst in [dir.join("index-test.osm.gz"), dir.join("index-test.osm.bz2")] {
+			let dst = dst.to_str().unwrap();
+			let idx = OsmIndex::build_framed(&src, dst, 10).unwrap();
+			assert_eq!(idx.entries.len(), 4);
+
+			// framed file must be readable as a whole
+			assert_eq!(OsmXmlReader::from_path(dst).unwrap().count(), 32);
+		}
+
+		for path in [src.clone(), format!("{}.gz", src), format!("{}.bz2", src)] {
+			let mut rd = OsmIndexedReader::open(&path).unwrap();
+			let w = rd.get_way(50).unwrap().unwrap();
+			assert_eq!(w.nodes, vec![5, 6, 7]);
+			assert_eq!(&*w.tags["name"], "street 5");
+			let n = rd.get_node(17).unwrap().unwrap();
+			assert_eq!(n.attrs.id, 17);
+			assert!(rd.get_node(26).unwrap().is_none());
+			assert!(rd.get_way(55).unwrap().is_none());
+		}
+	}
+}
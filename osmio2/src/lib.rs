@@ -1,5 +1,12 @@
 pub mod reader;
+pub mod compression;
+pub mod index;
+pub mod diff;
+pub mod change;
+pub mod stats;
 pub mod writer;
+pub mod notes;
+pub mod temp;
 pub mod errors;
 pub mod objects;
 pub mod serialize_wkt;
@@ -0,0 +1,104 @@
+use quick_xml::{
+	events::{Event, BytesDecl, BytesStart, BytesEnd, BytesText},
+	Writer,
+};
+
+use std::{
+	error::Error,
+	fs::File,
+	io::Write,
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::errors::WriteError;
+
+/// Note at a point, as in [notes dumps](https://planet.openstreetmap.org/notes/) (`.osn`), which JOSM opens as a notes layer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Note {
+	/// notes that are not on the server yet have negative ids
+	pub id: i64,
+	pub lon: f64,
+	pub lat: f64,
+	pub text: String,
+}
+
+/// Writes open notes with one comment each, created now.
+pub struct NotesWriter {
+	wr: Writer<Box<dyn Write + Send>>,
+	created_at: String,
+}
+
+// 2024-05-01T12:00:00Z
+fn iso_time(secs: u64) -> String {
+	// days to civil date, http://howardhinnant.github.io/date_algorithms.html
+	let z = (secs / 86400) as i64 + 719468;
+	let era = z.div_euclid(146097);
+	let doe = z - era * 146097;
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + (month <= 2) as i64;
+	let s = secs % 86400;
+	format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, s / 3600, s % 3600 / 60, s % 60)
+}
+
+impl NotesWriter {
+	pub fn new(wr: Box<dyn Write + Send>) -> Result<Self, Box<dyn Error>> {
+		let mut wr = Writer::new_with_indent(wr, 9, 1);
+		wr.write_event(Event::Decl(BytesDecl::from_start(BytesStart::borrowed(b"xml version='1.0' encoding='UTF-8'", 3))))?;
+		wr.write_event(Event::Start(BytesStart::owned_name("osm-notes")))?;
+		let created_at = iso_time(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs());
+		Ok(Self { wr, created_at })
+	}
+
+	pub fn from_path(path: &str) -> Result<Self, Box<dyn Error>> {
+		Self::new(Box::new(File::create(path)?))
+	}
+
+	pub fn write(&mut self, note: &Note) -> Result<(), WriteError> {
+		let mut elt = BytesStart::owned_name("note");
+		elt.push_attribute(("id", note.id.to_string().as_str()));
+		elt.push_attribute(("lat", note.lat.to_string().as_str()));
+		elt.push_attribute(("lon", note.lon.to_string().as_str()));
+		elt.push_attribute(("created_at", self.created_at.as_str()));
+		self.wr.write_event(Event::Start(elt))?;
+		let mut comment = BytesStart::owned_name("comment");
+		comment.push_attribute(("action", "opened"));
+		comment.push_attribute(("timestamp", self.created_at.as_str()));
+		self.wr.write_event(Event::Start(comment))?;
+		self.wr.write_event(Event::Text(BytesText::from_plain_str(&note.text)))?;
+		self.wr.write_event(Event::End(BytesEnd::owned(b"comment".to_vec())))?;
+		self.wr.write_event(Event::End(BytesEnd::owned(b"note".to_vec())))?;
+		Ok(())
+	}
+
+	pub fn close(&mut self) -> Result<(), WriteError> {
+		self.wr.write_event(Event::End(BytesEnd::owned(b"osm-notes".to_vec())))?;
+		Ok(self.wr.inner().flush()?)
+	}
+}
+
+#[cfg(test)]
+mod notes_tests {
+	use super::*;
+	use crate::temp::TempFile;
+
+	#[test]
+	fn test_notes() {
+		assert_eq!(iso_time(0), "1970-01-01T00:00:00Z");
+		assert_eq!(iso_time(1709210096), "2024-02-29T12:34:56Z");
+
+		let tmp = TempFile::new("notes-test.osn");
+		let path = tmp.path();
+		let path = path.as_str();
+		let mut wr = NotesWriter::from_path(path).unwrap();
+		wr.write(&Note { id: -1, lon: 83.1, lat: 54.2, text: "crossing <without> node & layer".to_string() }).unwrap();
+		wr.close().unwrap();
+		let text = std::fs::read_to_string(path).unwrap();
+		assert!(text.contains(r#"<note id="-1" lat="54.2" lon="83.1" created_at=""#));
+		assert!(text.contains("crossing &lt;without&gt; node &amp; layer</comment>"));
+		assert!(text.trim_end().ends_with("</osm-notes>"));
+	}
+}
@@ -28,6 +28,22 @@ pub enum OsmObj {
 }
 
 impl OsmObj {
+	pub fn id(&self) -> i64 {
+		match self {
+			OsmObj::Node(n) => n.attrs.id,
+			OsmObj::Way(w) => w.attrs.id,
+			OsmObj::Relation(r) => r.attrs.id,
+		}
+	}
+
+	pub fn obj_type(&self) -> ObjType {
+		match self {
+			OsmObj::Node(_) => ObjType::Node,
+			OsmObj::Way(_) => ObjType::Way,
+			OsmObj::Relation(_) => ObjType::Relation,
+		}
+	}
+
 	pub fn tags_insert(&mut self, k: Arc<str>, v: Arc<str>) {
 		match self {
 			OsmObj::Node(n) => { n.tags.insert(k, v); }
@@ -126,14 +142,16 @@ pub struct Relation {
 	pub members: Vec<Member>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Member {
 	pub mtype: ObjType,
 	pub mref: i64,
 	pub mrole: Arc<str>
 }
 
-#[derive(Debug, Clone)]
+// order of variants is the order of objects in sorted files
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum ObjType {
 	Node, Way, Relation
 }
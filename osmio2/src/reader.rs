@@ -1,13 +1,13 @@
 use crossbeam_channel::{Receiver, Sender, bounded, SendError, IntoIter as CbIntoIter};
-use bzip2::read::BzDecoder;
 use crate::{
+	change::ChangeAction,
+	compression::decompress,
 	errors::ReadError,
 	objects::{
 		Member, OsmObj, ObjType, Tags,
 		Node, Way, Relation,
 		OsmElementAttrs, ParsedAttrs}
 };
-use flate2::read::GzDecoder;
 use indicatif::{ProgressBar, ProgressState, ProgressStyle, ProgressFinish};
 use quick_xml::{
 	Reader,
@@ -30,13 +30,17 @@ pub struct OsmXmlReader {
 	pub skip_ways: bool,
 	pub skip_relations: bool,
 	pub curr_elt: Option<ObjType>,
+	// position in the decompressed stream where the last returned object starts (used by index)
+	pub obj_start: usize,
+	// <create>, <modify> or <delete> block of an osmChange file the last object is in
+	pub action: Option<ChangeAction>,
 }
 
 type OkOrBox = Result<(), Box<dyn Error>>;
 pub type OsmXmlItem = Result<OsmObj, ReadError>;
 impl OsmXmlReader {
 	pub fn new(rd: BufReader<Box<dyn Read + Send>>) -> OsmXmlReader {
-		Self {rd: Reader::from_reader(rd), elt: None, skip_nodes: false, skip_ways: false, skip_relations: false, curr_elt: None }
+		Self {rd: Reader::from_reader(rd), elt: None, skip_nodes: false, skip_ways: false, skip_relations: false, curr_elt: None, obj_start: 0, action: None }
 	}
 
 	pub fn from_path(path: &str) -> Result<OsmXmlReader, Box<dyn Error>> {
@@ -51,16 +55,7 @@ impl OsmXmlReader {
 			.with_finish(ProgressFinish::Abandon)
 			.wrap_read(fp);
 
-		let rd = if path.ends_with(".osm.gz") {
-			Box::new(GzDecoder::new(pbr)) as Box<dyn Read + Send>
-		} else if path.ends_with(".osm.bz2") {
-			Box::new(BzDecoder::new(pbr)) as Box<dyn Read + Send>
-		} else if path.ends_with(".osm") {
-			Box::new(pbr) as Box<dyn Read + Send>
-		} else {
-			return Err("file is not .osm format".into());
-		};
-
+		let rd = decompress(path, pbr)?;
 		Ok(Self::new(BufReader::new(rd)))
 	}
 
@@ -101,8 +96,17 @@ impl OsmXmlReader {
 		let osm_attrs = OsmElementAttrs::try_from(&attrs_hashmap)?;
 		let mut res = match elt.name() {
 			b"node" => {
-				let lon:f32 = attrs_hashmap.get("lon").ok_or_else(|| ReadError { msg: "node has no longitude".to_string()})?.parse()?;
-				let lat:f32 = attrs_hashmap.get("lat").ok_or_else(|| ReadError{ msg: "node has no latitude".to_string()})?.parse()?;
+				// deleted nodes in osmChange files may come without coordinates
+				let deleted = self.action == Some(ChangeAction::Delete);
+				let coord = |k: &str| -> Result<f32, ReadError> {
+					match attrs_hashmap.get(k) {
+						Some(v) => Ok(v.parse()?),
+						None if deleted => Ok(0.0),
+						None => Err(ReadError { msg: format!("node has no {}", if k == "lon" { "longitude" } else { "latitude" }) }),
+					}
+				};
+				let lon:f32 = coord("lon")?;
+				let lat:f32 = coord("lat")?;
 				OsmObj::Node(Node { attrs: osm_attrs, lon: lon, lat: lat, tags: tags })
 			},
 			b"way" => {
@@ -154,6 +158,7 @@ impl OsmXmlReader {
 		let mut do_skip: bool = false;
 
 		loop {
+			let pos = self.rd.buffer_position();
 			let e1 = self.rd.read_event(&mut buf);
 			match (obj_started, &e1) {
 				(_, Err(e)) => { panic!("Error at position {}: {:?}", self.rd.buffer_position(), e) },
@@ -169,9 +174,13 @@ impl OsmXmlReader {
 						b"relation" => self.skip_relations,
 						_ => false
 					};
+					if let Some(a) = ChangeAction::from_name(nm) {
+						self.action = Some(a);
+					}
 
 					if matches!(nm, b"node" | b"way" | b"relation") {
 						obj_started = true;
+						self.obj_start = pos;
 						if !do_skip {
 							elements.push(e2.to_owned());
 						}
@@ -200,6 +209,9 @@ impl OsmXmlReader {
 						return self._process_elements(elements);
 					}
 				},
+				(false, Ok(Event::End(ref e2))) if ChangeAction::from_name(e2.name()).is_some() => {
+					self.action = None;
+				},
 				(_, Ok(Event::Eof)) => {
 					return Ok(None)
 				},
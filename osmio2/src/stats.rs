@@ -0,0 +1,206 @@
+use geo::{LineString, HaversineLength};
+use serde::Serialize;
+use std::{
+	collections::{BTreeMap, HashMap, HashSet},
+	error::Error,
+	fmt,
+	sync::Arc,
+};
+
+use crate::{
+	objects::{OsmElementAttrs, OsmObj, Tags},
+	reader::OsmXmlReader,
+};
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct TypeStats {
+	pub count: u64,
+	pub min_id: Option<i64>,
+	pub max_id: Option<i64>,
+}
+
+impl TypeStats {
+	fn add(&mut self, id: i64) {
+		self.count += 1;
+		self.min_id = Some(self.min_id.map_or(id, |v| v.min(id)));
+		self.max_id = Some(self.max_id.map_or(id, |v| v.max(id)));
+	}
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TagCount {
+	pub key: String,
+	pub value: Option<String>,
+	pub count: u64,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct FileStats {
+	pub nodes: TypeStats,
+	pub ways: TypeStats,
+	pub relations: TypeStats,
+	/// min lon, min lat, max lon, max lat
+	pub bbox: Option<[f32; 4]>,
+	pub first_timestamp: Option<String>,
+	pub last_timestamp: Option<String>,
+	pub top_keys: Vec<TagCount>,
+	pub top_tags: Vec<TagCount>,
+	/// total length of ways in km by `highway` value. Ways with nodes missing from the file are counted partially
+	pub highway_km: BTreeMap<String, f64>,
+}
+
+/// Collects file statistics in one pass, without keeping node coordinates. Highway lengths are not measured here:
+/// the collector only remembers which nodes highways use, see `HighwayLengths`.
+pub struct StatsCollector {
+	stats: FileStats,
+	top_n: usize,
+	key_counts: HashMap<Arc<str>, u64>,
+	tag_counts: HashMap<(Arc<str>, Arc<str>), u64>,
+	highway_nodes: HashSet<i64>,
+}
+
+impl StatsCollector {
+	pub fn new(top_n: usize) -> Self {
+		Self { stats: FileStats::default(), top_n, key_counts: HashMap::new(), tag_counts: HashMap::new(), highway_nodes: HashSet::new() }
+	}
+
+	fn add_attrs(&mut self, attrs: &OsmElementAttrs) {
+		if let Some(ts) = &attrs.timestamp {
+			// ISO 8601 timestamps of the same format compare correctly as strings
+			if self.stats.first_timestamp.as_deref().is_none_or(|t| t > &**ts) {
+				self.stats.first_timestamp = Some(ts.to_string());
+			}
+			if self.stats.last_timestamp.as_deref().is_none_or(|t| t < &**ts) {
+				self.stats.last_timestamp = Some(ts.to_string());
+			}
+		}
+	}
+
+	fn add_tags(&mut self, tags: &Tags) {
+		for (k, v) in tags.iter() {
+			*self.key_counts.entry(k.clone()).or_insert(0) += 1;
+			*self.tag_counts.entry((k.clone(), v.clone())).or_insert(0) += 1;
+		}
+	}
+
+	pub fn add(&mut self, obj: &OsmObj) {
+		match obj {
+			OsmObj::Node(n) => {
+				self.stats.nodes.add(n.attrs.id);
+				self.add_attrs(&n.attrs);
+				self.add_tags(&n.tags);
+				self.stats.bbox = Some(match self.stats.bbox {
+					None => [n.lon, n.lat, n.lon, n.lat],
+					Some(b) => [b[0].min(n.lon), b[1].min(n.lat), b[2].max(n.lon), b[3].max(n.lat)],
+				});
+			},
+			OsmObj::Way(w) => {
+				self.stats.ways.add(w.attrs.id);
+				self.add_attrs(&w.attrs);
+				self.add_tags(&w.tags);
+				if w.tags.contains_key("highway") {
+					self.highway_nodes.extend(w.nodes.iter().copied());
+				}
+			},
+			OsmObj::Relation(r) => {
+				self.stats.relations.add(r.attrs.id);
+				self.add_attrs(&r.attrs);
+				self.add_tags(&r.tags);
+			},
+		}
+	}
+
+	/// returns the statistics and the ids of nodes used by highways
+	pub fn finish(mut self) -> (FileStats, HashSet<i64>) {
+		let mut keys: Vec<TagCount> = self.key_counts.into_iter()
+			.map(|(k, c)| TagCount { key: k.to_string(), value: None, count: c }).collect();
+		keys.sort_by(|a, b| b.count.cmp(&a.count).then(a.key.cmp(&b.key)));
+		keys.truncate(self.top_n);
+
+		let mut tags: Vec<TagCount> = self.tag_counts.into_iter()
+			.map(|((k, v), c)| TagCount { key: k.to_string(), value: Some(v.to_string()), count: c }).collect();
+		tags.sort_by(|a, b| b.count.cmp(&a.count).then(a.key.cmp(&b.key)).then(a.value.cmp(&b.value)));
+		tags.truncate(self.top_n);
+
+		self.stats.top_keys = keys;
+		self.stats.top_tags = tags;
+		(self.stats, self.highway_nodes)
+	}
+}
+
+/// Measures highway lengths in a second pass, keeping coordinates of highway nodes only.
+/// This expects the usual order of objects (nodes before ways).
+pub struct HighwayLengths<'a> {
+	highway_nodes: &'a HashSet<i64>,
+	node_coords: HashMap<i64, (f64, f64)>,
+	km: BTreeMap<String, f64>,
+}
+
+impl<'a> HighwayLengths<'a> {
+	pub fn new(highway_nodes: &'a HashSet<i64>) -> Self {
+		Self { highway_nodes, node_coords: HashMap::new(), km: BTreeMap::new() }
+	}
+
+	pub fn add(&mut self, obj: &OsmObj) {
+		match obj {
+			OsmObj::Node(n) if self.highway_nodes.contains(&n.attrs.id) => {
+				self.node_coords.insert(n.attrs.id, (n.lon as f64, n.lat as f64));
+			},
+			OsmObj::Way(w) => if let Some(hw) = w.tags.get("highway") {
+				let ls: LineString = w.nodes.iter().filter_map(|n| self.node_coords.get(n)).cloned().collect::<Vec<_>>().into();
+				*self.km.entry(hw.to_string()).or_insert(0.0) += ls.haversine_length() / 1000.0;
+			},
+			_ => {},
+		}
+	}
+
+	pub fn finish(self) -> BTreeMap<String, f64> {
+		self.km
+	}
+}
+
+impl FileStats {
+	/// reads the file twice: for counts, tags and the set of highway nodes, then for highway lengths
+	pub fn from_path(path: &str, top_n: usize) -> Result<Self, Box<dyn Error>> {
+		let mut sc = StatsCollector::new(top_n);
+		for obj in OsmXmlReader::from_path(path)? {
+			sc.add(&obj?);
+		}
+		let (mut stats, highway_nodes) = sc.finish();
+		let mut hl = HighwayLengths::new(&highway_nodes);
+		for obj in OsmXmlReader::from_path(path)? {
+			hl.add(&obj?);
+		}
+		stats.highway_km = hl.finish();
+		Ok(stats)
+	}
+}
+
+impl fmt::Display for FileStats {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let opt = |v: Option<i64>| v.map(|v| v.to_string()).unwrap_or_else(|| "-".to_string());
+		for (name, ts) in [("nodes", &self.nodes), ("ways", &self.ways), ("relations", &self.relations)] {
+			writeln!(f, "{:<10} {:>12}  ids {} .. {}", name, ts.count, opt(ts.min_id), opt(ts.max_id))?;
+		}
+		match self.bbox {
+			Some(b) => writeln!(f, "bbox: {} {} {} {}", b[0], b[1], b[2], b[3])?,
+			None => writeln!(f, "bbox: -")?,
+		}
+		writeln!(f, "timestamps: {} .. {}",
+			self.first_timestamp.as_deref().unwrap_or("-"), self.last_timestamp.as_deref().unwrap_or("-"))?;
+
+		writeln!(f, "\ntop keys:")?;
+		for t in self.top_keys.iter() {
+			writeln!(f, "{:>12}  {}", t.count, t.key)?;
+		}
+		writeln!(f, "\ntop tags:")?;
+		for t in self.top_tags.iter() {
+			writeln!(f, "{:>12}  {}={}", t.count, t.key, t.value.as_deref().unwrap_or(""))?;
+		}
+		writeln!(f, "\nhighway length, km:")?;
+		for (k, v) in self.highway_km.iter() {
+			writeln!(f, "{:>12.3}  {}", v, k)?;
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,33 @@
+use std::{
+	fs,
+	path::PathBuf,
+	sync::atomic::{AtomicUsize, Ordering},
+};
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// file or directory in the temp directory, removed on drop. The name gets the pid and a counter,
+// so that parallel tests and concurrent runs don't collide; `name` may have an extension, e.g. `test.osm`
+pub struct TempFile(pub PathBuf);
+
+impl TempFile {
+	pub fn new(name: &str) -> Self {
+		let n = TEMP_COUNTER.fetch_add(1, Ordering::SeqCst);
+		Self(std::env::temp_dir().join(format!("osm-{}-{}-{}", std::process::id(), n, name)))
+	}
+
+	// the path as a string, for APIs that take &str (temp paths are UTF-8)
+	pub fn path(&self) -> String {
+		self.0.to_string_lossy().to_string()
+	}
+}
+
+impl Drop for TempFile {
+	fn drop(&mut self) {
+		if self.0.is_dir() {
+			let _ = fs::remove_dir_all(&self.0);
+		} else {
+			let _ = fs::remove_file(&self.0);
+		}
+	}
+}
@@ -26,14 +26,7 @@ pub struct OsmXmlWriter {
 impl OsmXmlWriter {
 	pub fn new(wr: Box<dyn Write + Send>) -> Result<OsmXmlWriter, Box<dyn Error>> {
 		let mut wr1 = Writer::new_with_indent(wr, 9, 1);  // char 9 = \t (ASCII tab)
-		wr1.write_event(Event::Decl(
-			BytesDecl::from_start(
-				BytesStart::borrowed(b"xml version='1.0' encoding='UTF-8'", 3))))?;
-		let mut open_tag = BytesStart::owned("osm", "osm".len());
-		open_tag.push_attribute(("version", "0.6"));
-		open_tag.push_attribute(("generator", "cosmos"));
-		wr1.write_event(Event::Start(open_tag))?;
-
+		write_header(&mut wr1)?;
 		Ok(OsmXmlWriter { wr: wr1 })
 	}
 
@@ -53,64 +46,83 @@ impl OsmXmlWriter {
 	}
 
 	pub fn write(&mut self, osmobj: &OsmObj) -> Result<(), WriteError> {
-		let (tagname, tagnamelen, tags, nodes, members) = match osmobj {
-			OsmObj::Node(ref n) => { ("node", 4, n.tags.clone(), vec![], vec![]) },
-			OsmObj::Way(ref w) => { ("way", 3, w.tags.clone(), w.nodes.clone(), vec![]) },
-			OsmObj::Relation(ref r) => { ("relation", 8, r.tags.clone(), vec![], r.members.clone()) },
-		};
+		write_obj(&mut self.wr, osmobj)
+	}
 
-		let mut start_elt = BytesStart::owned(tagname, tagnamelen);
-		let has_inner = tags.len() + nodes.len() + members.len() > 0;
-		match osmobj {
-			OsmObj::Node(node) => {
-				node.attrs.push_to(&mut start_elt);
-				start_elt.push_attribute(("lon", &node.lon.to_string() as &str));
-				start_elt.push_attribute(("lat", &node.lat.to_string() as &str));
-			},
-			OsmObj::Way(way) => {
-				way.attrs.push_to(&mut start_elt);
-			},
-			OsmObj::Relation(rel) => {
-				rel.attrs.push_to(&mut start_elt);
+	pub fn close(&mut self) -> Result<(), WriteError> {
+		write_footer(&mut self.wr)
+	}
+}
 
-			}
-		};
+// the functions below are generic over the output, so that other modules (e.g. index) can write XML into memory buffers
+pub fn write_header<W: Write>(wr: &mut Writer<W>) -> Result<(), WriteError> {
+	wr.write_event(Event::Decl(
+		BytesDecl::from_start(
+			BytesStart::borrowed(b"xml version='1.0' encoding='UTF-8'", 3))))?;
+	let mut open_tag = BytesStart::owned("osm", "osm".len());
+	open_tag.push_attribute(("version", "0.6"));
+	open_tag.push_attribute(("generator", "cosmos"));
+	wr.write_event(Event::Start(open_tag))?;
+	Ok(())
+}
 
-		if !has_inner {
-			self.wr.write_event(Event::Empty(start_elt))?;
-			return Ok(())
-		}
+pub fn write_footer<W: Write>(wr: &mut Writer<W>) -> Result<(), WriteError> {
+	wr.write_event(Event::End(BytesEnd::owned(b"osm".to_vec())))?;
+	Ok(())
+}
+
+pub fn write_obj<W: Write>(wr: &mut Writer<W>, osmobj: &OsmObj) -> Result<(), WriteError> {
+	let (tagname, tagnamelen, tags, nodes, members) = match osmobj {
+		OsmObj::Node(ref n) => { ("node", 4, n.tags.clone(), vec![], vec![]) },
+		OsmObj::Way(ref w) => { ("way", 3, w.tags.clone(), w.nodes.clone(), vec![]) },
+		OsmObj::Relation(ref r) => { ("relation", 8, r.tags.clone(), vec![], r.members.clone()) },
+	};
+
+	let mut start_elt = BytesStart::owned(tagname, tagnamelen);
+	let has_inner = tags.len() + nodes.len() + members.len() > 0;
+	match osmobj {
+		OsmObj::Node(node) => {
+			node.attrs.push_to(&mut start_elt);
+			start_elt.push_attribute(("lon", &node.lon.to_string() as &str));
+			start_elt.push_attribute(("lat", &node.lat.to_string() as &str));
+		},
+		OsmObj::Way(way) => {
+			way.attrs.push_to(&mut start_elt);
+		},
+		OsmObj::Relation(rel) => {
+			rel.attrs.push_to(&mut start_elt);
 
-		self.wr.write_event(Event::Start(start_elt))?;
-		for n in members {
-			let mut nelt = BytesStart::owned("member", 6);
-			nelt.push_attribute(("type", n.mtype.into()));
-			nelt.push_attribute(("ref", &n.mref.to_string() as &str));
-			nelt.push_attribute(("role", &*n.mrole));
-			self.wr.write_event(Event::Empty(nelt))?;
-		}
-		for (k, v) in tags {
-			let mut nelt = BytesStart::owned("tag", 3);
-			nelt.push_attribute(("k", &*k));
-			nelt.push_attribute(("v", &*v));
-			self.wr.write_event(Event::Empty(nelt))?;
-		}
-		for n in nodes {
-			let mut nelt = BytesStart::owned("nd", 2);
-			nelt.push_attribute(("ref", &n.to_string() as &str));
-			self.wr.write_event(Event::Empty(nelt))?;
 		}
+	};
 
-		self.wr.write_event(Event::End(BytesEnd::owned(tagname.as_bytes().to_vec())))?;
-		Ok(())
+	if !has_inner {
+		wr.write_event(Event::Empty(start_elt))?;
+		return Ok(())
 	}
 
-	pub fn close(&mut self) -> Result<(), WriteError> {
-		self.wr.write_event(Event::End(BytesEnd::owned(b"osm".to_vec())))?;
-		Ok(())
+	wr.write_event(Event::Start(start_elt))?;
+	for n in members {
+		let mut nelt = BytesStart::owned("member", 6);
+		nelt.push_attribute(("type", n.mtype.into()));
+		nelt.push_attribute(("ref", &n.mref.to_string() as &str));
+		nelt.push_attribute(("role", &*n.mrole));
+		wr.write_event(Event::Empty(nelt))?;
+	}
+	for (k, v) in tags {
+		let mut nelt = BytesStart::owned("tag", 3);
+		nelt.push_attribute(("k", &*k));
+		nelt.push_attribute(("v", &*v));
+		wr.write_event(Event::Empty(nelt))?;
+	}
+	for n in nodes {
+		let mut nelt = BytesStart::owned("nd", 2);
+		nelt.push_attribute(("ref", &n.to_string() as &str));
+		wr.write_event(Event::Empty(nelt))?;
 	}
-}
 
+	wr.write_event(Event::End(BytesEnd::owned(tagname.as_bytes().to_vec())))?;
+	Ok(())
+}
 
 pub struct BgWriter {
 	handle: Option<JoinHandle<Result<(), WriteError>>>,
@@ -0,0 +1,189 @@
+use osmio2::objects::Tags;
+use serde::{Serialize, Deserialize};
+
+use crate::mode::Mode;
+
+// which transport modes may use a way
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Access {
+	pub car: bool,
+	pub bike: bool,
+	pub foot: bool,
+	pub bus: bool,
+}
+
+// keys from the most generic to the most specific, the last one set wins.
+// https://wiki.openstreetmap.org/wiki/Key:access#Transport_mode_restrictions
+pub(crate) const CAR_KEYS: [&str; 4] = ["access", "vehicle", "motor_vehicle", "motorcar"];
+pub(crate) const BIKE_KEYS: [&str; 3] = ["access", "vehicle", "bicycle"];
+pub(crate) const FOOT_KEYS: [&str; 2] = ["access", "foot"];
+pub(crate) const BUS_KEYS: [&str; 5] = ["access", "vehicle", "motor_vehicle", "psv", "bus"];
+
+// None if the value is not about legal access (or unknown), then the less specific key applies
+pub fn parse_access(v: &str) -> Option<bool> {
+	match v {
+		"yes" | "designated" | "permissive" | "destination" | "customers" | "delivery" | "official" | "permit"
+			| "discouraged" | "dismount" => Some(true),
+		"no" | "private" | "use_sidepath" | "agricultural" | "forestry" | "emergency" | "military" => Some(false),
+		_ => None,
+	}
+}
+
+fn resolve(tags: &Tags, keys: &[&str], default: bool) -> bool {
+	keys.iter().rev()
+		.find_map(|k| tags.get(*k).and_then(|v| parse_access(v.split(';').next().unwrap_or("").trim())))
+		.unwrap_or(default)
+}
+
+impl Access {
+	pub const NONE: Access = Access { car: false, bike: false, foot: false, bus: false };
+	pub const ALL: Access = Access { car: true, bike: true, foot: true, bus: true };
+
+	pub fn new(car: bool, bike: bool, foot: bool, bus: bool) -> Self {
+		Self { car, bike, foot, bus }
+	}
+
+	// legal access by highway type, when there are no access tags.
+	// Worldwide table with country exceptions from https://wiki.openstreetmap.org/wiki/OSM_tags_for_routing/Access_restrictions
+	// `country` is ISO 3166-1 alpha-2 code, empty string means worldwide defaults
+	pub fn default_for(highway: &str, country: &str) -> Self {
+		match (country, highway) {
+			// bridleways are open to pedestrians and cyclists by law in England and Wales
+			("GB", "bridleway") => Self::new(false, true, true, false),
+			("RU", "cycleway") => Self::new(false, true, true, false),
+			("RU", "footway" | "pedestrian") => Self::new(false, true, true, false),
+			("DE", "path") => Self::new(false, false, true, false),
+
+			(_, "motorway" | "motorway_link") => Self::new(true, false, false, true),
+			(_, "pedestrian" | "footway" | "steps" | "corridor" | "elevator") => Self::new(false, false, true, false),
+			(_, "cycleway") => Self::new(false, true, false, false),
+			(_, "path") => Self::new(false, true, true, false),
+			(_, "busway") => Self::new(false, false, false, true),
+			(_, "bridleway") => Self::NONE,
+			_ => Self::ALL,
+		}
+	}
+
+	pub fn from_tags(tags: &Tags, country: &str) -> Self {
+		let mut def = match tags.get("highway") {
+			Some(hw) => Self::default_for(hw, country),
+			None => return Self::NONE,
+		};
+		// motorroad=yes (expressway) closes the road for everyone slower than cars
+		if tags.get("motorroad").is_some_and(|v| &**v == "yes") {
+			def.bike = false;
+			def.foot = false;
+		}
+		Self {
+			car: resolve(tags, &CAR_KEYS, def.car),
+			bike: resolve(tags, &BIKE_KEYS, def.bike),
+			foot: resolve(tags, &FOOT_KEYS, def.foot),
+			bus: resolve(tags, &BUS_KEYS, def.bus),
+		}
+	}
+
+	// who may pass a node with barrier=* (or elevator), when there are no access tags.
+	// Barriers not listed here are assumed to let only pedestrians through
+	pub fn default_for_barrier(barrier: &str) -> Self {
+		match barrier {
+			"gate" | "lift_gate" | "swing_gate" | "sliding_gate" | "entrance" | "toll_booth" | "border_control"
+				| "cattle_grid" | "sally_port" | "height_restrictor" | "arch" => Self::ALL,
+			"bus_trap" | "rising_bollard" => Self::new(false, true, true, true),
+			"bollard" | "block" | "chain" | "cycle_barrier" | "motorcycle_barrier" | "kerb" | "elevator" => Self::new(false, true, true, false),
+			_ => Self::new(false, false, true, false),
+		}
+	}
+
+	// who may pass a graph vertex. Only barriers and elevators restrict access, access tags apply to them as on ways
+	pub fn for_node(tags: &Tags) -> Self {
+		let def = match (tags.get("barrier").map(|v| &**v), tags.get("highway").map(|v| &**v)) {
+			(Some(b), _) if b != "no" => Self::default_for_barrier(b),
+			(_, Some("elevator")) => Self::default_for_barrier("elevator"),
+			_ => return Self::ALL,
+		};
+		Self {
+			car: resolve(tags, &CAR_KEYS, def.car),
+			bike: resolve(tags, &BIKE_KEYS, def.bike),
+			foot: resolve(tags, &FOOT_KEYS, def.foot),
+			bus: resolve(tags, &BUS_KEYS, def.bus),
+		}
+	}
+
+	// indoor ways with no highway=* are walked like highway=corridor
+	pub fn for_indoor(tags: &Tags, country: &str) -> Self {
+		let def = Self::default_for("corridor", country);
+		Self {
+			car: resolve(tags, &CAR_KEYS, def.car),
+			bike: resolve(tags, &BIKE_KEYS, def.bike),
+			foot: resolve(tags, &FOOT_KEYS, def.foot),
+			bus: resolve(tags, &BUS_KEYS, def.bus),
+		}
+	}
+
+	// route=ferry takes pedestrians and cyclists, vehicles only when the ferry has motor_vehicle=yes and the like,
+	// or ferry=<highway class> (the road it continues)
+	pub fn for_ferry(tags: &Tags) -> Self {
+		let vehicles = tags.contains_key("ferry");
+		Self {
+			car: resolve(tags, &CAR_KEYS, vehicles),
+			bike: resolve(tags, &BIKE_KEYS, true),
+			foot: resolve(tags, &FOOT_KEYS, true),
+			bus: resolve(tags, &BUS_KEYS, vehicles),
+		}
+	}
+
+	pub fn any(&self) -> bool {
+		self.car || self.bike || self.foot || self.bus
+	}
+
+	pub fn get(&self, mode: Mode) -> bool {
+		match mode {
+			Mode::Car => self.car,
+			Mode::Bike => self.bike,
+			Mode::Foot => self.foot,
+			Mode::Bus => self.bus,
+		}
+	}
+}
+
+#[cfg(test)]
+mod access_tests {
+	use super::*;
+
+	fn access(tags: &[(&str, &str)], country: &str) -> Access {
+		Access::from_tags(&crate::tags(tags), country)
+	}
+
+	#[test]
+	fn test_access() {
+		assert_eq!(access(&[("highway", "residential")], ""), Access::ALL);
+		assert_eq!(access(&[("highway", "footway")], ""), Access::new(false, false, true, false));
+		assert_eq!(access(&[("highway", "footway"), ("bicycle", "designated")], ""), Access::new(false, true, true, false));
+		assert_eq!(access(&[("highway", "footway")], "RU"), Access::new(false, true, true, false));
+
+		// more specific key wins regardless of tag order
+		assert_eq!(access(&[("highway", "service"), ("access", "private"), ("foot", "yes")], ""), Access::new(false, false, true, false));
+		assert_eq!(access(&[("highway", "tertiary"), ("motor_vehicle", "no"), ("psv", "yes")], ""), Access::new(false, true, true, true));
+		assert_eq!(access(&[("highway", "tertiary"), ("vehicle", "no"), ("bicycle", "unknown_value")], ""), Access::new(false, false, true, false));
+		assert_eq!(access(&[("highway", "primary"), ("motorroad", "yes")], ""), Access::new(true, false, false, true));
+		assert_eq!(access(&[("highway", "cycleway"), ("foot", "use_sidepath")], ""), Access::new(false, true, false, false));
+		assert!(!access(&[("building", "yes")], "").any());
+
+		let ferry = |t: &[(&str, &str)]| Access::for_ferry(&crate::tags(t));
+		assert_eq!(ferry(&[("route", "ferry")]), Access::new(false, true, true, false));
+		assert_eq!(ferry(&[("route", "ferry"), ("motor_vehicle", "yes")]), Access::ALL);
+		assert_eq!(ferry(&[("route", "ferry"), ("ferry", "primary"), ("bus", "no")]), Access::new(true, true, true, false));
+	}
+
+	#[test]
+	fn test_node_access() {
+		let node = |t: &[(&str, &str)]| Access::for_node(&crate::tags(t));
+		assert_eq!(node(&[("highway", "traffic_signals"), ("access", "no")]), Access::ALL);
+		assert_eq!(node(&[("barrier", "gate")]), Access::ALL);
+		assert_eq!(node(&[("barrier", "gate"), ("access", "private"), ("foot", "yes")]), Access::new(false, false, true, false));
+		assert_eq!(node(&[("barrier", "bollard")]), Access::new(false, true, true, false));
+		assert_eq!(node(&[("barrier", "bollard"), ("bus", "yes")]), Access::new(false, true, true, true));
+		assert_eq!(node(&[("barrier", "stile")]), Access::new(false, false, true, false));
+		assert_eq!(node(&[("highway", "elevator")]), Access::new(false, true, true, false));
+	}
+}
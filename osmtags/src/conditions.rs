@@ -0,0 +1,334 @@
+use osmio2::objects::Tags;
+use std::sync::Arc;
+
+use crate::OneWay;
+use crate::access::{CAR_KEYS, BIKE_KEYS, FOOT_KEYS, BUS_KEYS, parse_access};
+use crate::normalize::{normalize_conditional, split_conditional};
+
+// https://wiki.openstreetmap.org/wiki/Conditional_restrictions
+// Only time conditions are evaluated, in opening_hours syntax: weekdays, months and times of day, e.g.
+// `Mo-Fr 07:00-19:00`, `Nov-Mar; Sa,Su 22:00-06:00`. Other conditions (wet, weight>7, holidays)
+// are never considered true, so such restrictions are not applied
+
+const WEEKDAYS: [&str; 7] = ["Mo", "Tu", "We", "Th", "Fr", "Sa", "Su"];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+const DAY: u16 = 1440;
+
+// local time of departure: days since 1970-01-01 and minutes since midnight
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Moment {
+	pub days: i64,
+	pub minute: u16,
+}
+
+// http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+	let y = if m <= 2 { y - 1 } else { y };
+	let era = y.div_euclid(400);
+	let yoe = y - era * 400;
+	let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d - 1;
+	let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+	era * 146097 + doe - 719468
+}
+
+// month 1..12 of a day
+fn month_of(days: i64) -> usize {
+	let doe = (days + 719468).rem_euclid(146097);
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let mp = (5 * (doe - (365 * yoe + yoe / 4 - yoe / 100)) + 2) / 153;
+	(if mp < 10 { mp + 3 } else { mp - 9 }) as usize
+}
+
+impl Moment {
+	// `2024-05-06T08:30`, `2024-05-06 08:30` (seconds are ignored)
+	pub fn parse(s: &str) -> Option<Self> {
+		let (date, time) = s.trim().split_once(['T', ' '])?;
+		let date: Vec<i64> = date.split('-').map(|p| p.parse().ok()).collect::<Option<_>>()?;
+		let time: Vec<u16> = time.split(':').map(|p| p.parse().ok()).collect::<Option<_>>()?;
+		match (date.as_slice(), time.as_slice()) {
+			(&[y, m, d], &[h, min, ..]) if (1..=12).contains(&m) && (1..=31).contains(&d) && h < 24 && min < 60 =>
+				Some(Self { days: days_from_civil(y, m, d), minute: h * 60 + min }),
+			_ => None,
+		}
+	}
+
+	// 0 is Monday
+	pub fn weekday(&self) -> usize {
+		(self.days + 3).rem_euclid(7) as usize
+	}
+}
+
+// weekday or month selector, `Mo-Fr,Su`, `Nov-Mar`; ranges may wrap around
+fn parse_selector(s: &str, names: &[&str]) -> Option<u16> {
+	let index = |n: &str| names.iter().position(|x| *x == n);
+	let mut mask = 0u16;
+	for part in s.split(',') {
+		let (a, b) = match part.split_once('-') {
+			Some((a, b)) => (index(a)?, index(b)?),
+			None => { let i = index(part)?; (i, i) },
+		};
+		let mut i = a;
+		loop {
+			mask |= 1 << i;
+			if i == b { break }
+			i = (i + 1) % names.len();
+		}
+	}
+	Some(mask)
+}
+
+// `07:00-19:00`, `22:00-06:00` (till 06:00 next day); the end is above DAY if the range goes past midnight
+fn parse_time_range(s: &str) -> Option<(u16, u16)> {
+	let minute = |t: &str| -> Option<u16> {
+		let (h, m) = t.split_once(':')?;
+		let (h, m): (u16, u16) = (h.parse().ok()?, m.parse().ok()?);
+		(h <= 48 && m < 60).then(|| h * 60 + m)
+	};
+	let (a, b) = s.split_once('-')?;
+	let (a, b) = (minute(a)?, minute(b)?);
+	if a >= DAY { return None }
+	Some((a, if b <= a { b + DAY } else { b }))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Rule {
+	// bit masks, None is any day
+	months: Option<u16>,
+	weekdays: Option<u16>,
+	// empty is the whole day
+	times: Vec<(u16, u16)>,
+	off: bool,
+}
+
+impl Rule {
+	fn parse(s: &str) -> Option<Self> {
+		let mut rule = Rule { months: None, weekdays: None, times: vec![], off: false };
+		let s = s.trim();
+		if s == "24/7" {
+			return Some(rule)
+		}
+		for token in s.split_whitespace() {
+			match token {
+				"off" | "closed" => rule.off = true,
+				"open" => {},
+				t if t.starts_with(|c: char| c.is_ascii_digit()) => rule.times = t.split(',').map(parse_time_range).collect::<Option<_>>()?,
+				t => match parse_selector(t, &MONTHS) {
+					Some(m) => rule.months = Some(m),
+					None => {
+						// public and school holidays are not known, they match no day
+						let known: Vec<&str> = t.split(',').filter(|d| *d != "PH" && *d != "SH").collect();
+						rule.weekdays = Some(if known.is_empty() { 0 } else { parse_selector(&known.join(","), &WEEKDAYS)? });
+					},
+				},
+			}
+		}
+		Some(rule)
+	}
+
+	fn on_day(&self, days: i64) -> bool {
+		let weekday = (days + 3).rem_euclid(7);
+		self.months.is_none_or(|m| m & (1 << (month_of(days) - 1)) != 0)
+			&& self.weekdays.is_none_or(|w| w & (1 << weekday) != 0)
+	}
+}
+
+// opening_hours value: rules separated by `;`, the later ones override the earlier ones for their days.
+// `off` rules only close the times they have
+#[derive(Clone, Debug, PartialEq)]
+pub struct OpeningHours(Vec<Rule>);
+
+impl OpeningHours {
+	pub fn parse(s: &str) -> Option<Self> {
+		let mut rules = vec![];
+		for rule in s.split(';').flat_map(|r| r.split("||")) {
+			// `Mo-Fr 07:00-19:00, Sa 08:00-14:00`: a comma after a time starts a new rule, other commas are lists
+			let mut start = 0;
+			let mut parts = vec![];
+			for (i, _) in rule.match_indices(',') {
+				let before = rule[..i].trim_end();
+				let after = rule[i + 1..].trim_start();
+				if before.ends_with(|c: char| c.is_ascii_digit()) && after.starts_with(|c: char| c.is_ascii_alphabetic()) {
+					parts.push(&rule[start..i]);
+					start = i + 1;
+				}
+			}
+			parts.push(&rule[start..]);
+			for p in parts {
+				// `Sa, Su` and `07:00-09:00, 16:00-18:00` are lists
+				let p = p.split(',').map(|x| x.trim()).collect::<Vec<_>>().join(",");
+				if !p.is_empty() {
+					rules.push(Rule::parse(&p)?);
+				}
+			}
+		}
+		(!rules.is_empty()).then_some(Self(rules))
+	}
+
+	pub fn is_open(&self, at: &Moment) -> bool {
+		let mut open = false;
+		for r in self.0.iter() {
+			let today = r.on_day(at.days);
+			let in_today = r.times.is_empty() || r.times.iter().any(|(a, b)| *a <= at.minute && at.minute < *b);
+			// the part of yesterday's range after midnight
+			let from_yesterday = r.on_day(at.days - 1) && r.times.iter().any(|(_, b)| *b > DAY && at.minute + DAY < *b);
+			let covers = (today && in_today) || from_yesterday;
+			if r.off {
+				if covers { open = false }
+			} else if today {
+				open = covers
+			} else if covers {
+				open = true
+			}
+		}
+		open
+	}
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Condition {
+	Time(OpeningHours),
+	// not evaluated: wet, snow, weight>7.5, delivery etc.
+	Other(String),
+}
+
+impl Condition {
+	pub fn parse(s: &str) -> Self {
+		match OpeningHours::parse(s) {
+			Some(oh) => Self::Time(oh),
+			None => Self::Other(s.trim().to_string()),
+		}
+	}
+
+	pub fn holds(&self, at: &Moment) -> bool {
+		match self {
+			Self::Time(oh) => oh.is_open(at),
+			Self::Other(_) => false,
+		}
+	}
+}
+
+// `value @ (condition AND condition)`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Conditional {
+	pub value: String,
+	pub conditions: Vec<Condition>,
+}
+
+impl Conditional {
+	pub fn parse_list(v: &str) -> Vec<Self> {
+		split_conditional(v).into_iter().map(|(value, cond)| Self {
+			value: value.to_string(),
+			conditions: cond.split(" AND ").map(Condition::parse).collect(),
+		}).collect()
+	}
+
+	pub fn applies(&self, at: &Moment) -> bool {
+		self.conditions.iter().all(|c| c.holds(at))
+	}
+}
+
+// value of a conditional column at the moment, None if no condition holds.
+// The last one that holds wins, as in OSM
+pub fn value_at(v: &str, at: &Moment) -> Option<String> {
+	Conditional::parse_list(v).into_iter().rev().find(|c| c.applies(at)).map(|c| c.value)
+}
+
+// conditional restrictions of a way, normalized to `value @ (condition)` lists, empty if there are none
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Conditions {
+	// OneWay names, relative to the order of nodes, for cars as `oneway`
+	pub oneway: Arc<str>,
+	// yes or no, from the most specific access key of the mode
+	pub car: Arc<str>,
+	pub bike: Arc<str>,
+	pub foot: Arc<str>,
+	pub bus: Arc<str>,
+}
+
+fn access_conditional(tags: &Tags, keys: &[&str]) -> Arc<str> {
+	keys.iter().rev()
+		.find_map(|k| tags.get(format!("{}:conditional", k).as_str()))
+		.map(|v| normalize_conditional(v, |val| parse_access(val).map(|a| if a { "yes" } else { "no" }.to_string())))
+		.unwrap_or_default()
+		.into()
+}
+
+impl Conditions {
+	pub fn from_tags(tags: &Tags) -> Self {
+		let oneway = ["oneway:conditional", "oneway:motor_vehicle:conditional"].iter().rev()
+			.find_map(|k| tags.get(*k))
+			.map(|v| normalize_conditional(v, |val| OneWay::parse(val).map(|o| format!("{:?}", o))))
+			.unwrap_or_default();
+		Self {
+			oneway: oneway.into(),
+			car: access_conditional(tags, &CAR_KEYS),
+			bike: access_conditional(tags, &BIKE_KEYS),
+			foot: access_conditional(tags, &FOOT_KEYS),
+			bus: access_conditional(tags, &BUS_KEYS),
+		}
+	}
+
+	// for the opposite order of nodes
+	pub fn reversed(&self) -> Self {
+		let oneway = split_conditional(&self.oneway).into_iter().map(|(val, cond)| {
+			let val = match val { "Forward" => "Backward", "Backward" => "Forward", v => v };
+			format!("{} @ ({})", val, cond)
+		}).collect::<Vec<_>>().join("; ");
+		Self { oneway: oneway.into(), ..self.clone() }
+	}
+}
+
+#[cfg(test)]
+mod conditions_tests {
+	use super::*;
+	use crate::tags;
+
+	#[test]
+	fn test_opening_hours() {
+		// Monday
+		let m = Moment::parse("2024-05-06T08:30").unwrap();
+		assert_eq!((m.weekday(), month_of(m.days)), (0, 5));
+		let at = |d: &str| Moment::parse(d).unwrap();
+		for (oh, time, open) in [
+			("Mo-Fr 07:00-19:00", "2024-05-06 08:30", true),
+			("Mo-Fr 07:00-19:00", "2024-05-06 19:00", false),
+			("Mo-Fr 07:00-19:00", "2024-05-05 12:00", false),
+			("Sa,Su", "2024-05-05 12:00", true),
+			("22:00-06:00", "2024-05-07 05:59", true),
+			("Fr 22:00-06:00", "2024-05-11 01:00", true),
+			("Fr 22:00-06:00", "2024-05-10 01:00", false),
+			("Nov-Mar", "2024-01-15 10:00", true),
+			("Nov-Mar", "2024-05-15 10:00", false),
+			("Mo-Fr 07:00-09:00, 16:00-18:00", "2024-05-06 17:00", true),
+			("Mo-Fr 07:00-09:00,Sa 10:00-12:00", "2024-05-11 11:00", true),
+			("Mo-Su 08:00-20:00; We 12:00-14:00 off", "2024-05-08 13:00", false),
+			("Mo-Su 08:00-20:00; We 12:00-14:00 off", "2024-05-08 15:00", true),
+			("Mo-Fr 08:00-20:00; Fr 10:00-12:00", "2024-05-10 09:00", false),
+			("24/7", "2024-05-10 09:00", true),
+			("PH off", "2024-05-10 09:00", false),
+		] {
+			assert_eq!(OpeningHours::parse(oh).unwrap().is_open(&at(time)), open, "{} {}", oh, time);
+		}
+		assert!(OpeningHours::parse("sunrise-sunset").is_none());
+		assert!(Moment::parse("2024-13-01 10:00").is_none());
+
+		assert_eq!(value_at("no @ (Mo-Fr 07:00-19:00); yes @ (Mo 08:00-09:00)", &m), Some("yes".to_string()));
+		assert_eq!(value_at("no @ (Mo-Fr 07:00-19:00 AND weight>7.5)", &m), None);
+		assert_eq!(value_at("no @ wet", &m), None);
+	}
+
+	#[test]
+	fn test_conditions() {
+		let c = Conditions::from_tags(&tags(&[
+			("oneway:conditional", "yes @ (Mo-Fr 07:00-10:00); -1 @ (Mo-Fr 16:00-19:00); maybe @ (Su)"),
+			("access:conditional", "no @ (22:00-06:00)"),
+			("motor_vehicle:conditional", "delivery @ (Mo-Sa 06:00-11:00)"),
+		]));
+		assert_eq!(&*c.oneway, "Forward @ (Mo-Fr 07:00-10:00); Backward @ (Mo-Fr 16:00-19:00)");
+		assert_eq!((&*c.car, &*c.bike, &*c.bus), ("yes @ (Mo-Sa 06:00-11:00)", "no @ (22:00-06:00)", "yes @ (Mo-Sa 06:00-11:00)"));
+		let r = c.reversed();
+		assert_eq!(&*r.oneway, "Backward @ (Mo-Fr 07:00-10:00); Forward @ (Mo-Fr 16:00-19:00)");
+		assert_eq!(r.reversed(), c);
+		assert_eq!(Conditions::from_tags(&tags(&[])), Conditions::default());
+	}
+}
@@ -0,0 +1,17 @@
+pub mod schema;
+pub mod mode;
+pub mod oneway;
+pub mod access;
+pub mod normalize;
+pub mod conditions;
+
+pub use mode::Mode;
+pub use oneway::{OneWay, Directions};
+pub use access::Access;
+pub use normalize::{MaxSpeed, PerDirection};
+
+// tags from key-value pairs, for tests
+#[cfg(test)]
+pub(crate) fn tags(t: &[(&str, &str)]) -> osmio2::objects::Tags {
+	t.iter().map(|(k, v)| (std::sync::Arc::from(*k), std::sync::Arc::from(*v))).collect()
+}
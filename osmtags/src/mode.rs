@@ -0,0 +1,36 @@
+use serde::{Serialize, Deserialize};
+use std::fmt;
+
+// transport modes the graph is built for
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Mode { Car, Bike, Foot, Bus }
+
+impl Mode {
+	pub const ALL: [Mode; 4] = [Mode::Car, Mode::Bike, Mode::Foot, Mode::Bus];
+	pub const VEHICLES: [Mode; 3] = [Mode::Car, Mode::Bike, Mode::Bus];
+}
+
+impl fmt::Display for Mode {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		fmt::Debug::fmt(self, f)
+	}
+}
+
+// modes that an OSM transport key (as in `access:<key>`, `restriction:<key>`, `except=<key>`) applies to.
+// Keys for modes not in the graph (hgv, moped, ...) give nothing
+pub fn osm_modes(key: &str) -> &'static [Mode] {
+	match key {
+		"vehicle" => &Mode::VEHICLES,
+		"motor_vehicle" => &[Mode::Car, Mode::Bus],
+		"motorcar" => &[Mode::Car],
+		"bicycle" => &[Mode::Bike],
+		"psv" | "bus" => &[Mode::Bus],
+		"foot" => &[Mode::Foot],
+		_ => &[],
+	}
+}
+
+// joins modes for a CSV cell, e.g. "Car;Bus"
+pub fn modes_str(modes: &[Mode]) -> String {
+	modes.iter().map(|m| m.to_string()).collect::<Vec<_>>().join(";")
+}
@@ -0,0 +1,234 @@
+use osmio2::objects::Tags;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use std::sync::Arc;
+
+use crate::OneWay;
+
+// km/h
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MaxSpeed(pub Option<u16>);
+
+// maxspeed=none, e.g. on German autobahns. Routing should use the profile's speed there
+pub const UNLIMITED: u16 = u16::MAX;
+
+// km/h, https://wiki.openstreetmap.org/wiki/Default_speed_limits
+const ZONES: [(&str, u16); 37] = [
+	("RU:urban", 60), ("RU:rural", 90), ("RU:motorway", 110), ("RU:living_street", 20),
+	("UA:urban", 50), ("UA:rural", 90), ("UA:motorway", 130), ("UA:living_street", 20),
+	("BY:urban", 60), ("BY:rural", 90), ("BY:motorway", 110), ("BY:living_street", 20),
+	("KZ:urban", 60), ("KZ:rural", 90), ("KZ:motorway", 110), ("KZ:living_street", 20),
+	("DE:urban", 50), ("DE:rural", 100), ("DE:motorway", UNLIMITED), ("DE:living_street", 7), ("DE:bicycle_road", 30),
+	("AT:urban", 50), ("AT:rural", 100), ("AT:motorway", 130),
+	("FR:urban", 50), ("FR:rural", 80), ("FR:motorway", 130),
+	("IT:urban", 50), ("IT:rural", 90), ("IT:motorway", 130),
+	("PL:urban", 50), ("PL:rural", 90), ("PL:motorway", 140),
+	("GB:nsl_single", 97), ("GB:nsl_dual", 113), ("GB:motorway", 113),
+	("NL:urban", 50),
+];
+
+// "walk" by country, when it's not 5 km/h
+const WALK: [(&str, u16); 2] = [("DE", 7), ("AT", 5)];
+
+fn with_unit(num: f32, unit: &str) -> Option<u16> {
+	let kmh = match unit.trim() {
+		"" | "km/h" | "kmh" | "kph" => num,
+		"mph" => num * 1.609_344,
+		"knots" => num * 1.852,
+		_ => return None,
+	};
+	Some(kmh.round() as u16)
+}
+
+// one value, without `;`
+fn parse_one_maxspeed(v: &str, country: &str) -> Option<u16> {
+	let v = v.trim();
+	match v {
+		"none" => return Some(UNLIMITED),
+		"walk" => return Some(WALK.iter().find(|(c, _)| *c == country).map_or(5, |(_, s)| *s)),
+		_ => {}
+	}
+	if let Some((_, s)) = ZONES.iter().find(|(z, _)| *z == v) {
+		return Some(*s)
+	}
+	// zone:30, DE:zone30, DE:zone:30
+	if let Some(pos) = v.find("zone") {
+		return v[pos + 4..].trim_start_matches(':').parse().ok()
+	}
+	let split = v.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(v.len());
+	let num: f32 = v[..split].parse().ok()?;
+	with_unit(num, &v[split..])
+}
+
+// km/h. Several values (`50;70`) give the lowest one
+pub fn parse_maxspeed(v: &str, country: &str) -> Option<u16> {
+	v.split(';').filter_map(|p| parse_one_maxspeed(p, country)).min()
+}
+
+pub fn parse_lanes(v: &str) -> Option<u8> {
+	v.split(';').filter_map(|p| p.trim().parse::<u8>().ok()).min()
+}
+
+// value of a tag for both directions of a way
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PerDirection<T> {
+	pub forward: T,
+	pub backward: T,
+}
+
+impl<T: Clone> PerDirection<T> {
+	pub fn reversed(&self) -> Self {
+		Self { forward: self.backward.clone(), backward: self.forward.clone() }
+	}
+}
+
+fn fmt_maxspeed(v: u16) -> String {
+	if v == UNLIMITED { "none".to_string() } else { v.to_string() }
+}
+
+// in CSV maxspeed is km/h, "none" for no limit, or empty
+impl Serialize for MaxSpeed {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		self.0.map(fmt_maxspeed).serialize(serializer)
+	}
+}
+
+impl<'de> Deserialize<'de> for MaxSpeed {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		Ok(Self(Option::<String>::deserialize(deserializer)?.and_then(|v| parse_maxspeed(&v, ""))))
+	}
+}
+
+impl MaxSpeed {
+	pub fn parse(tags: &Tags, key: &str, country: &str) -> Self {
+		Self(tags.get(key).and_then(|v| parse_maxspeed(v, country)))
+	}
+
+	// maxspeed=*, or zone code from maxspeed:type=* / source:maxspeed=*
+	pub fn from_tags(tags: &Tags, country: &str) -> Self {
+		["maxspeed", "maxspeed:type", "source:maxspeed", "zone:maxspeed"].iter()
+			.map(|k| Self::parse(tags, k, country))
+			.find(|m| m.0.is_some())
+			.unwrap_or_default()
+	}
+
+	pub fn per_direction(tags: &Tags, country: &str) -> PerDirection<Self> {
+		let both = Self::from_tags(tags, country);
+		let fw = Self::parse(tags, "maxspeed:forward", country);
+		let bw = Self::parse(tags, "maxspeed:backward", country);
+		PerDirection { forward: if fw.0.is_some() { fw } else { both }, backward: if bw.0.is_some() { bw } else { both } }
+	}
+}
+
+// lanes in each direction. `lanes` is total; without lanes:forward/backward it's split evenly,
+// or goes to the forward direction on oneways. `default_total` is used when there's no lanes=*
+// lanes:forward/backward are relative to the way's nodes whatever the oneway is,
+// only the implied split of `lanes` depends on it
+pub fn lanes_per_direction(tags: &Tags, oneway: OneWay, default_total: u8) -> PerDirection<u8> {
+	let get = |k: &str| tags.get(k).and_then(|v| parse_lanes(v));
+	let total = get("lanes").unwrap_or(default_total);
+	match (get("lanes:forward"), get("lanes:backward")) {
+		(Some(f), Some(b)) => PerDirection { forward: f, backward: b },
+		(Some(f), None) => PerDirection { forward: f, backward: if oneway == OneWay::Forward { 0 } else { total.saturating_sub(f).max(1) } },
+		(None, Some(b)) => PerDirection { forward: if oneway == OneWay::Backward { 0 } else { total.saturating_sub(b).max(1) }, backward: b },
+		(None, None) => match oneway {
+			OneWay::Forward => PerDirection { forward: total, backward: 0 },
+			OneWay::Backward => PerDirection { forward: 0, backward: total },
+			_ => PerDirection { forward: total.div_ceil(2), backward: (total / 2).max(1) },
+		},
+	}
+}
+
+// `value @ (condition); value @ condition` as `value @ (condition); value @ (condition)` with values
+// converted by `parse`. Values that can't be parsed are dropped
+pub fn normalize_conditional(v: &str, parse: impl Fn(&str) -> Option<String>) -> String {
+	split_conditional(v).into_iter()
+		.filter_map(|(val, cond)| parse(val).map(|val| format!("{} @ ({})", val, cond)))
+		.collect::<Vec<_>>().join("; ")
+}
+
+// maxspeeds converted to km/h, e.g. "30 @ (22:00-06:00)"
+pub fn normalize_maxspeed_conditional(v: &str, country: &str) -> String {
+	normalize_conditional(v, |val| parse_maxspeed(val, country).map(fmt_maxspeed))
+}
+
+// splits `a @ (x; y); b @ z` at the `;` that are outside parentheses
+pub fn split_conditional(v: &str) -> Vec<(&str, &str)> {
+	let mut res = vec![];
+	let mut depth = 0;
+	let mut start = 0;
+	for (i, c) in v.char_indices().chain([(v.len(), ';')].iter().cloned()) {
+		match c {
+			'(' => depth += 1,
+			')' => depth -= 1,
+			';' if depth == 0 => {
+				if let Some((val, cond)) = v[start..i].split_once('@') {
+					let cond = cond.trim();
+					let cond = cond.strip_prefix('(').and_then(|c| c.strip_suffix(')')).unwrap_or(cond);
+					res.push((val.trim(), cond.trim()));
+				}
+				start = (i + 1).min(v.len());
+			},
+			_ => {}
+		}
+	}
+	res
+}
+
+pub fn maxspeed_conditional(tags: &Tags, country: &str) -> PerDirection<Arc<str>> {
+	let get = |k: &str| tags.get(k).map(|v| normalize_maxspeed_conditional(v, country));
+	let both = get("maxspeed:conditional").unwrap_or_default();
+	PerDirection {
+		forward: Arc::from(get("maxspeed:forward:conditional").unwrap_or_else(|| both.clone()).as_str()),
+		backward: Arc::from(get("maxspeed:backward:conditional").unwrap_or(both).as_str()),
+	}
+}
+
+// lane counts, e.g. "3 @ (Mo-Fr 07:00-10:00)"
+pub fn normalize_lanes_conditional(v: &str) -> String {
+	normalize_conditional(v, |val| parse_lanes(val).map(|l| l.to_string()))
+}
+
+// lanes:conditional (all lanes, like `lanes`) and lanes:forward/backward:conditional
+pub fn lanes_conditional(tags: &Tags) -> (Arc<str>, PerDirection<Arc<str>>) {
+	let get = |k: &str| Arc::from(tags.get(k).map(|v| normalize_lanes_conditional(v)).unwrap_or_default().as_str());
+	(get("lanes:conditional"), PerDirection { forward: get("lanes:forward:conditional"), backward: get("lanes:backward:conditional") })
+}
+
+#[cfg(test)]
+mod normalize_tests {
+	use super::*;
+	use crate::tags;
+
+	#[test]
+	fn test_maxspeed() {
+		for (v, c, res) in [
+			("60", "", Some(60)), ("60 mph", "", Some(97)), ("30mph", "GB", Some(48)), ("10 knots", "", Some(19)),
+			("RU:urban", "", Some(60)), ("DE:zone30", "", Some(30)), ("DE:zone:20", "", Some(20)),
+			("walk", "DE", Some(7)), ("walk", "", Some(5)), ("none", "", Some(UNLIMITED)),
+			("50;70", "", Some(50)), ("signals", "", None), ("fast", "", None),
+		] {
+			assert_eq!(parse_maxspeed(v, c), res, "{}", v);
+		}
+		let t = tags(&[("maxspeed:type", "RU:rural"), ("maxspeed:forward", "70")]);
+		let pd = MaxSpeed::per_direction(&t, "RU");
+		assert_eq!((pd.forward, pd.backward), (MaxSpeed(Some(70)), MaxSpeed(Some(90))));
+		assert_eq!(pd.reversed().forward, MaxSpeed(Some(90)));
+	}
+
+	#[test]
+	fn test_lanes_and_conditional() {
+		assert_eq!(lanes_per_direction(&tags(&[("lanes", "3")]), OneWay::No, 1), PerDirection { forward: 2, backward: 1 });
+		assert_eq!(lanes_per_direction(&tags(&[("lanes", "3")]), OneWay::Forward, 1), PerDirection { forward: 3, backward: 0 });
+		assert_eq!(lanes_per_direction(&tags(&[("lanes", "3")]), OneWay::Backward, 1), PerDirection { forward: 0, backward: 3 });
+		assert_eq!(lanes_per_direction(&tags(&[("lanes", "2"), ("lanes:backward", "2")]), OneWay::Backward, 1), PerDirection { forward: 0, backward: 2 });
+		assert_eq!(lanes_per_direction(&tags(&[("lanes", "4"), ("lanes:backward", "1")]), OneWay::No, 1), PerDirection { forward: 3, backward: 1 });
+		assert_eq!(lanes_per_direction(&tags(&[]), OneWay::No, 2), PerDirection { forward: 1, backward: 1 });
+		let (all, dir) = lanes_conditional(&tags(&[("lanes:conditional", "3 @ (Mo-Fr 07:00-10:00); x @ wet"), ("lanes:forward:conditional", "2 @ (summer)")]));
+		assert_eq!((&*all, &*dir.forward, &*dir.backward), ("3 @ (Mo-Fr 07:00-10:00)", "2 @ (summer)", ""));
+
+		assert_eq!(normalize_maxspeed_conditional("30 @ (22:00-06:00; Sa,Su); 20 mph @ wet; x @ y", ""),
+			"30 @ (22:00-06:00; Sa,Su); 32 @ (wet)");
+		let c = maxspeed_conditional(&tags(&[("maxspeed:conditional", "30 @ (Mo-Fr 07:00-17:00)"), ("maxspeed:backward:conditional", "none @ (wet)")]), "");
+		assert_eq!((&*c.forward, &*c.backward), ("30 @ (Mo-Fr 07:00-17:00)", "none @ (wet)"));
+	}
+}
@@ -0,0 +1,153 @@
+use osmio2::objects::Tags;
+use serde::{Serialize, Deserialize};
+use std::sync::Arc;
+
+use crate::mode::Mode;
+
+// direction of traffic relative to the order of nodes.
+// Reversible means the direction changes with time (e.g. by lane signals), such ways can't be used in a static graph
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum OneWay { #[default] No, Forward, Backward, Reversible }
+
+impl OneWay {
+	// direction of the same way, when its nodes are read in reverse order
+	pub fn reversed(&self) -> Self {
+		match self {
+			Self::Forward => Self::Backward,
+			Self::Backward => Self::Forward,
+			other => *other,
+		}
+	}
+
+	// name as it's written in graph columns, e.g. `Forward`
+	pub fn from_name(v: &str) -> Option<Self> {
+		match v {
+			"No" => Some(Self::No),
+			"Forward" => Some(Self::Forward),
+			"Backward" => Some(Self::Backward),
+			"Reversible" => Some(Self::Reversible),
+			_ => None,
+		}
+	}
+
+	// None if the value is not recognised, so that the caller can fall back to the implied value
+	pub fn parse(v: &str) -> Option<Self> {
+		match v {
+			"yes" | "true" | "1" => Some(Self::Forward),
+			"-1" | "reverse" => Some(Self::Backward),
+			"no" | "false" | "0" => Some(Self::No),
+			"reversible" => Some(Self::Reversible),
+			// alternating: one direction at a time, but both are possible (e.g. narrow bridge with traffic lights)
+			"alternating" => Some(Self::No),
+			_ => None,
+		}
+	}
+}
+
+impl From<Option<&Arc<str>>> for OneWay {
+	fn from(data: Option<&Arc<str>>) -> Self {
+		data.and_then(|a| Self::parse(a)).unwrap_or(Self::No)
+	}
+}
+
+// direction of traffic for each transport mode
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Directions {
+	pub car: OneWay,
+	pub bike: OneWay,
+	pub foot: OneWay,
+	pub bus: OneWay,
+}
+
+fn tag_oneway(tags: &Tags, key: &str) -> Option<OneWay> {
+	tags.get(key).and_then(|v| OneWay::parse(v))
+}
+
+// cycleway=opposite, cycleway:left=opposite_lane etc. mean that bicycles may go against oneway
+fn has_opposite(tags: &Tags, prefix: &str) -> bool {
+	[prefix.to_string(), format!("{}:left", prefix), format!("{}:right", prefix), format!("{}:both", prefix)].iter()
+		.any(|k| tags.get(k.as_str()).is_some_and(|v| v.starts_with("opposite")))
+}
+
+impl Directions {
+	pub fn from_tags(tags: &Tags) -> Self {
+		let highway = tags.get("highway").map(|v| &**v);
+		let implied = if matches!(tags.get("junction").map(|v| &**v), Some("roundabout" | "circular"))
+			|| matches!(highway, Some("motorway" | "motorway_link")) {
+			OneWay::Forward
+		} else {
+			OneWay::No
+		};
+
+		// oneway=* is for vehicles, pedestrians are bound only by oneway:foot
+		let vehicle = tag_oneway(tags, "oneway").unwrap_or(implied);
+		let car = tag_oneway(tags, "oneway:motor_vehicle").or_else(|| tag_oneway(tags, "oneway:vehicle")).unwrap_or(vehicle);
+
+		let bike = match tag_oneway(tags, "oneway:bicycle").or_else(|| tag_oneway(tags, "oneway:vehicle")) {
+			Some(ow) => ow,
+			None if vehicle != OneWay::No && has_opposite(tags, "cycleway") => OneWay::No,
+			None => vehicle,
+		};
+
+		let bus = match tag_oneway(tags, "oneway:bus").or_else(|| tag_oneway(tags, "oneway:psv")) {
+			Some(ow) => ow,
+			None if car != OneWay::No && has_opposite(tags, "busway") => OneWay::No,
+			None => car,
+		};
+
+		let foot = tag_oneway(tags, "oneway:foot").unwrap_or(OneWay::No);
+
+		Self { car, bike, foot, bus }
+	}
+
+	pub fn reversed(&self) -> Self {
+		Self { car: self.car.reversed(), bike: self.bike.reversed(), foot: self.foot.reversed(), bus: self.bus.reversed() }
+	}
+
+	pub fn get(&self, mode: Mode) -> OneWay {
+		match mode {
+			Mode::Car => self.car,
+			Mode::Bike => self.bike,
+			Mode::Foot => self.foot,
+			Mode::Bus => self.bus,
+		}
+	}
+}
+
+#[cfg(test)]
+mod oneway_tests {
+	use super::*;
+
+	fn dirs(tags: &[(&str, &str)]) -> Directions {
+		Directions::from_tags(&crate::tags(tags))
+	}
+
+	#[test]
+	fn test_directions() {
+		use OneWay::*;
+		let d = dirs(&[("highway", "residential")]);
+		assert_eq!((d.car, d.bike, d.foot, d.bus), (No, No, No, No));
+
+		let d = dirs(&[("highway", "primary"), ("junction", "roundabout")]);
+		assert_eq!((d.car, d.bike, d.foot, d.bus), (Forward, Forward, No, Forward));
+
+		// explicit tag wins over implied oneway
+		let d = dirs(&[("highway", "motorway_link"), ("oneway", "no")]);
+		assert_eq!(d.car, No);
+		assert_eq!(dirs(&[("highway", "motorway")]).car, Forward);
+
+		let d = dirs(&[("highway", "residential"), ("oneway", "-1"), ("oneway:bicycle", "no")]);
+		assert_eq!((d.car, d.bike, d.foot), (Backward, No, No));
+
+		let d = dirs(&[("highway", "residential"), ("oneway", "1"), ("cycleway:left", "opposite_lane"), ("oneway:psv", "no")]);
+		assert_eq!((d.car, d.bike, d.bus), (Forward, No, No));
+
+		let d = dirs(&[("highway", "tertiary"), ("oneway", "reversible")]);
+		assert_eq!((d.car, d.foot), (Reversible, No));
+		assert_eq!(dirs(&[("highway", "tertiary"), ("oneway", "alternating")]).car, No);
+
+		let d = dirs(&[("highway", "footway"), ("oneway:foot", "yes")]);
+		assert_eq!((d.car, d.foot), (No, Forward));
+		assert_eq!(d.reversed().foot, Backward);
+	}
+}
@@ -0,0 +1,65 @@
+use csv::StringRecord;
+
+// Edge CSV written by osmgraph and read by route. Every version only adds columns to the previous one,
+// so the version of a file is the highest one whose columns are all in the header.
+pub const SCHEMA_VERSION: u32 = 15;
+
+pub const SCHEMA_COLUMNS: [&[&str]; 15] = [
+	// 1: undirected graph
+	&["node1", "node2", "WKT", "category", "lanes"],
+	// 2: direction of traffic
+	&["oneway", "maxspeed"],
+	// 3: direction of traffic for other modes (`oneway` is for cars)
+	&["oneway_bike", "oneway_foot", "oneway_bus"],
+	// 4: edge id, referenced by the restrictions table
+	&["id"],
+	// 5: which modes may use the edge
+	&["car", "bike", "foot", "bus"],
+	// 6: speed in km/h and tags kept by the profile
+	&["speed", "tags"],
+	// 7: values by direction, relative to the order of points in geometry
+	&["maxspeed_forward", "maxspeed_backward", "lanes_forward", "lanes_backward", "maxspeed_conditional_forward", "maxspeed_conditional_backward"],
+	// 8: elevation gain and loss in metres and max grade in %, empty without DEM
+	&["ascent", "descent", "max_grade"],
+	// 9: edges across pedestrian areas
+	&["area"],
+	// 10: road, rail, ferry or transfer edges, and railway attributes
+	&["network", "service", "usage", "electrified", "gauge"],
+	// 11: OSM ways with node ranges, and node ids of the edge
+	&["ways", "nodes"],
+	// 12: conditional restrictions, `value @ (condition)`
+	&["oneway_conditional", "car_conditional", "bike_conditional", "foot_conditional", "bus_conditional"],
+	// 13: indoor levels of the way and of its ends
+	&["level", "level1", "level2"],
+	// 14: lanes:conditional, also by direction
+	&["lanes_conditional", "lanes_conditional_forward", "lanes_conditional_backward"],
+	// 15: layer of the way, from layer, bridge and tunnel
+	&["layer"],
+];
+
+// CSV file next to the graph, e.g. graph.gpkg -> graph_restrictions.csv
+pub fn sidecar_path(output: &str, name: &str) -> String {
+	match output.rsplit_once('.') {
+		Some((stem, ext)) if !ext.contains('/') => format!("{}_{}.csv", stem, name),
+		_ => format!("{}_{}.csv", output, name),
+	}
+}
+
+pub fn schema_version(headers: &StringRecord) -> Option<u32> {
+	let mut version = None;
+	for (i, cols) in SCHEMA_COLUMNS.iter().enumerate() {
+		if !cols.iter().all(|c| headers.iter().any(|h| h == *c)) {
+			break
+		}
+		version = Some(i as u32 + 1);
+	}
+	version
+}
+
+// columns that are in no schema version, the profile's attributes (see profile::Attribute)
+pub fn attribute_columns(headers: &StringRecord) -> Vec<(usize, String)> {
+	headers.iter().enumerate()
+		.filter(|(_, h)| *h != "WKT_Z" && !SCHEMA_COLUMNS.iter().any(|cols| cols.contains(h)))
+		.map(|(i, h)| (i, h.to_string()))
+		.collect()
+}
@@ -1,10 +1,11 @@
 use indicatif::{ProgressBar, ProgressState, ProgressStyle, ProgressFinish};
 use rand::{thread_rng, seq::SliceRandom};
+use osmtags::conditions::Moment;
 use osm_route::{
 	errors::{RoutingError, ok_or_pe},
 	isochrone::Isochrone,
 	objects::{VertexId, Cost, VertexScore, TimeCheck, VisitedMap},
-	graph::{Graph, OnewayPath},
+	graph::{Graph, OnewayPath, LoadOptions},
 	traits::{GraphPath, Router},
 };
 use geo::{Point, EuclideanDistance, CoordFloat};
@@ -27,11 +28,18 @@ impl Bearing<f64> for Point {
 
 const LANDMARKS:usize = 16;
 
+// distances from a landmark to a vertex and from the vertex to the landmark, None if there's no path
+#[derive(Clone, Copy, Debug)]
+pub struct LandmarkDist {
+	pub from: Option<Cost>,
+	pub to: Option<Cost>,
+}
+
 pub struct AltRouter<'a> {
 	pub graph: &'a Graph,
 	pub center: Point,
 	//pub iso: Vec<Isochrone>,
-	pub landmark_dist: HashMap<VertexId, Vec<Cost>>
+	pub landmark_dist: HashMap<VertexId, Vec<LandmarkDist>>
 }
 
 
@@ -55,12 +63,17 @@ impl<'a> AltRouter<'a> {
 			}
 		}
 
-		// generate isochrones for each node in farthest
-		let iso: Vec<Isochrone> = farthest.par_iter().map(|(_, _, n)| Isochrone::try_new(&graph, &n, None).ok()).flatten().collect();
+		// generate isochrones for each node in farthest, in both directions, because edges may be oneway
+		let iso: Vec<(Isochrone, Isochrone)> = farthest.par_iter().map(|(_, _, n)| {
+			Some((Isochrone::try_new(&graph, &n, None).ok()?, Isochrone::try_new_reverse(&graph, &n, None).ok()?))
+		}).flatten().collect();
 
 		let mut landmark_dist = HashMap::new();
-		for (k, _) in iso[0].distances.iter() {
-			let r = iso.iter().map(|i| *i.distances.get(k).unwrap()).collect();
+		for k in vertice.keys() {
+			let r = iso.iter().map(|(fw, bw)| LandmarkDist {
+				from: fw.distances.get(k).cloned(),
+				to: bw.distances.get(k).cloned()
+			}).collect();
 			landmark_dist.insert(*k, r);
 		}
 
@@ -73,16 +86,17 @@ impl<'a> AltRouter<'a> {
 	}
 
 	pub fn estimate(&self, v: &VertexId, target: &VertexId) -> Cost {
-		let l0 = vec![Cost(0); LANDMARKS];
+		let l0 = vec![LandmarkDist { from: None, to: None }; LANDMARKS];
 		let x1 = self.landmark_dist.get(v).unwrap_or(&l0);
 		let x2 = self.landmark_dist.get(target).unwrap_or(&l0);
-		// we need both d(v) - d(w) and d(w) - d(v), to get the max. Since they're opposite, just take one positive of the two
-		let delta: Vec<i64> = x1.iter().zip(x2).map(|(c1, c2)| (c2.0 - c1.0).abs()).collect();
-		let cst = delta.iter().max().unwrap_or(&0);
-		if (v.0 == 2496884827 || v.0 == 1388196118 || v.0 == 2496884832 || v.0 == 2261287594 || v.0 == 2261287447 || v.0 == 2261287508) && target.0 == 7599909457 {
-			println!("estimate v {:?} {:?} target {:?} {:?} delta {:?} max {:?}", v.0, x1, target.0, x2, delta, cst);
-		}
-		Cost(*cst)
+		// triangle inequality gives d(v, t) >= d(L, t) - d(L, v) and d(v, t) >= d(v, L) - d(t, L).
+		// in undirected graph they're opposite and the max of them is just abs()
+		let cst = x1.iter().zip(x2).map(|(d1, d2)| {
+			let a = match (d1.from, d2.from) { (Some(lv), Some(lt)) => lt.0 - lv.0, _ => 0 };
+			let b = match (d1.to, d2.to) { (Some(vl), Some(tl)) => vl.0 - tl.0, _ => 0 };
+			a.max(b)
+		}).max().unwrap_or(0);
+		Cost(cst)
 
 		/*let v1 = self.graph.vertice.get(v).unwrap();
 		let v2 = self.graph.vertice.get(target).unwrap();
@@ -137,9 +151,9 @@ impl<'a> Router for AltRouter<'a> {
 	}
 }
 
-fn run(path: &str) -> Result<(), Box<dyn Error>> {
+fn run(path: &str, opts: &LoadOptions) -> Result<(), Box<dyn Error>> {
 	let mut tc = TimeCheck::new();
-	let graph = Graph::from_path(path, true)?;
+	let graph = Graph::from_path_with(path, true, opts)?;
 	let alt = AltRouter::new(&graph);
 	println!("reading graph: {} s", tc.delta()?);
 
@@ -160,6 +174,8 @@ fn run(path: &str) -> Result<(), Box<dyn Error>> {
 		cr.serialize(("vid", "WKT", "kind", "dists", "max_dist", "mean_dist", "min_dist"))?;
 		for (vid, dists) in alt.landmark_dist.iter() {
 			let pt = alt.graph.vertice.get(vid).unwrap();
+			let dists: Vec<Cost> = dists.iter().filter_map(|d| d.from).collect();
+			if dists.is_empty() { continue; }
 			cr.serialize((
 				vid, pt.geom.to_wkt().to_string(), "v",
 				dists.iter().map(|i| (i.0 / 100).to_string()).collect::<Vec<String>>().join(";"),
@@ -203,12 +219,25 @@ fn run(path: &str) -> Result<(), Box<dyn Error>> {
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
-	let args: Vec<_> = std::env::args_os().collect();
+	let mut args: Vec<String> = std::env::args().collect();
+	// --departure YYYY-MM-DDTHH:MM: conditional restrictions and maxspeeds at that local time
+	let departure = match args.iter().position(|a| a == "--departure") {
+		Some(i) => {
+			let at = args.get(i + 1).ok_or("--departure needs a time")?;
+			let m = Moment::parse(at).ok_or_else(|| format!("can't parse departure time {}, expected YYYY-MM-DDTHH:MM", at))?;
+			args.drain(i..i + 2);
+			Some(m)
+		},
+		None => None,
+	};
+	// --by-time: edges weigh their travel time instead of length
+	let by_time = args.iter().any(|a| a == "--by-time");
+	args.retain(|a| a != "--by-time");
 	match args.len() {
 		2 => {
-			run(&args[1].to_str().unwrap())?;
+			run(&args[1], &LoadOptions { by_time, departure })?;
 		}
-		_ => println!("usage: alt_route GRAPH.CSV",),
+		_ => println!("usage: alt_route [--by-time] [--departure YYYY-MM-DDTHH:MM] GRAPH.CSV",),
 	};
 	Ok(())
 }
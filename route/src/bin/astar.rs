@@ -1,10 +1,11 @@
 use rayon::prelude::*;
 use indicatif::{ProgressBar, ProgressState, ProgressStyle, ProgressFinish};
 use rand::{thread_rng, seq::SliceRandom};
+use osmtags::conditions::Moment;
 use osm_route::{
 	errors::RoutingError,
 	objects::{VertexId, Cost, VertexScore, VisitedMap, TimeCheck},
-	graph::{Graph, BidirPath},
+	graph::{Graph, BidirPath, LoadOptions},
 	traits::{GraphPath, Router},
 };
 use geo::EuclideanDistance;
@@ -79,8 +80,9 @@ impl<'a> Router for AstarRouter<'a> {
 
 			let vtx1 = vertice.get(&vs.vid).expect("node id not existing");
 
-			for e in vtx1.edges.iter() {
-				let vtx2 = vertice.get(&e.v2).expect("other node not existing in vertice hashmap");
+			// backward search goes against the edges' direction
+			for e in vtx1.rev_edges.iter() {
+				let vtx2 = vertice.get(&e.v1).expect("other node not existing in vertice hashmap");
 
 				if !backward_visited.contains_key(&vtx2.id) {
 					let vs2 = VertexScore::new(
@@ -98,9 +100,9 @@ impl<'a> Router for AstarRouter<'a> {
 }
 
 
-fn run(path: &str) -> Result<(), Box<dyn Error>> {
+fn run(path: &str, opts: &LoadOptions) -> Result<(), Box<dyn Error>> {
 	let mut tc = TimeCheck::new();
-	let graph = Graph::from_path(path, true)?;
+	let graph = Graph::from_path_with(path, true, opts)?;
 	let astar = AstarRouter { graph: &graph };
 	println!("reading graph: {} s", tc.delta()?);
 
@@ -149,12 +151,25 @@ fn run(path: &str) -> Result<(), Box<dyn Error>> {
 
 
 fn main() -> Result<(), Box<dyn Error>> {
-	let args: Vec<_> = std::env::args_os().collect();
+	let mut args: Vec<String> = std::env::args().collect();
+	// --departure YYYY-MM-DDTHH:MM: conditional restrictions and maxspeeds at that local time
+	let departure = match args.iter().position(|a| a == "--departure") {
+		Some(i) => {
+			let at = args.get(i + 1).ok_or("--departure needs a time")?;
+			let m = Moment::parse(at).ok_or_else(|| format!("can't parse departure time {}, expected YYYY-MM-DDTHH:MM", at))?;
+			args.drain(i..i + 2);
+			Some(m)
+		},
+		None => None,
+	};
+	// --by-time: edges weigh their travel time instead of length
+	let by_time = args.iter().any(|a| a == "--by-time");
+	args.retain(|a| a != "--by-time");
 	match args.len() {
 		2 => {
-			run(&args[1].to_str().unwrap())?;
+			run(&args[1], &LoadOptions { by_time, departure })?;
 		}
-		_ => println!("usage: osm_route GRAPH.CSV",),
+		_ => println!("usage: osm_route [--by-time] [--departure YYYY-MM-DDTHH:MM] GRAPH.CSV",),
 	};
 	Ok(())
 }
@@ -188,4 +203,20 @@ pub mod astar_tests {
 			assert_eq!(route.vertice_nums().unwrap(), expected_nums);
 		}
 	}
+
+	#[test]
+	fn test_oneway_routing() {
+		// 5-7 is oneway 7 -> 5, so 2 -> 10 has to go around via 6 and 9
+		let mut g = graph_with_cul_de_sac();
+		g.get_mut(&VertexId(5)).unwrap().edges.retain(|e| e.v2 != VertexId(7));
+		g.get_mut(&VertexId(7)).unwrap().rev_edges.retain(|e| e.v1 != VertexId(5));
+		let astar = AstarRouter { graph: &g };
+		for (v1, v2, expected_nums) in [
+			(2, 10, vec![2, 3, 5, 6, 9, 7, 10]),
+			(10, 2, vec![10, 7, 5, 3, 2]),
+		] {
+			let route = astar.shortest_path(&VertexId(v1), &VertexId(v2)).unwrap();
+			assert_eq!(route.vertice_nums().unwrap(), expected_nums);
+		}
+	}
 }
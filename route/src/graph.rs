@@ -4,11 +4,13 @@ use crate::{
 	errors::{ok_or_pe, RoutingError},
 };
 use csv::Reader;
+use osmtags::{OneWay, conditions::Moment, schema::{schema_version, attribute_columns, sidecar_path}};
 use geo::{LineString, EuclideanLength, CoordNum, Point};
 use num_traits::float::Float;
 use proj::Proj;
+use serde::Deserialize;
 use std::{ collections::HashMap, error::Error,
-	io::Read,
+	io::Read, path::Path,
 };
 
 
@@ -28,6 +30,32 @@ where T: CoordNum + Float {
 	Ok(LineString::<T>::from(res))
 }
 
+// row of the vertex table written by osmgraph, only the columns needed here
+#[derive(Deserialize)]
+struct VertexRow {
+	node: i64,
+	car: bool,
+}
+
+// speeds above it weigh as much as it, so that straight distance stays a lower bound of the cost for A* and ALT
+pub const TOP_SPEED: u16 = 150;
+
+// travel time as metres at TOP_SPEED; edges without speed (older files) weigh their length
+pub fn edge_weight(length: f64, speed: u16) -> Weight {
+	match speed {
+		0 => Weight(length.round() as u64),
+		s => Weight((length * TOP_SPEED as f64 / s.min(TOP_SPEED) as f64).round() as u64),
+	}
+}
+
+// edges weigh their length, or their travel time if `by_time` is set;
+// conditional restrictions (e.g. `no @ (Mo-Fr 07:00-19:00)`) and maxspeeds are applied if `departure` is given
+#[derive(Clone, Debug, Default)]
+pub struct LoadOptions {
+	pub by_time: bool,
+	pub departure: Option<Moment>,
+}
+
 #[derive(Debug)]
 pub struct Graph {
 	pub vertice: HashMap<VertexId, Vertex>,
@@ -37,18 +65,22 @@ impl Graph {
 	pub fn new() -> Self { Self { vertice: HashMap::new() } }
 
 	pub fn add_edge(&mut self, edge: Edge, bidirectional: bool) {
-		let geom = edge.geom.clone().into_points()[0];
+		let points = edge.geom.clone().into_points();
+		let (geom1, geom2) = (points[0], points[points.len() - 1]);
 
 		let vid = edge.v1;
 		let v = self.vertice.entry(vid).or_insert_with(|| Vertex {
-				id: vid, edges: vec![], geom });
+				id: vid, edges: vec![], rev_edges: vec![], geom: geom1 });
 			v.edges.push(edge.clone());
 
+		// the other end must be in the graph too, even if no edges go out of it
+		let vid = edge.v2;
+		let v = self.vertice.entry(vid).or_insert_with(|| Vertex {
+				id: vid, edges: vec![], rev_edges: vec![], geom: geom2 });
+			v.rev_edges.push(edge.clone());
+
 		if bidirectional {
-			let mut ee = edge;
-			(ee.v1, ee.v2) = (ee.v2, ee.v1);
-			ee.geom.0.reverse();
-			self.add_edge(ee, false);
+			self.add_edge(edge.reversed(), false);
 		}
 	}
 
@@ -66,24 +98,86 @@ impl Graph {
 		}
 	}
 
+	// also reads the vertex table next to the graph, if there is one, see close_barriers
 	pub fn from_path(path: &str, project: bool) -> Result<Self, Box<dyn Error>> {
-		Self::parse_csv(Reader::from_path(path)?, project)
+		Self::from_path_with(path, project, &LoadOptions::default())
+	}
+
+	pub fn from_path_with(path: &str, project: bool, opts: &LoadOptions) -> Result<Self, Box<dyn Error>> {
+		let mut g = Self::parse_csv(Reader::from_path(path)?, project, opts)?;
+		g.close_barriers(path)?;
+		Ok(g)
+	}
+
+	// barriers closed for cars (bollards, private gates) from `<graph>_vertices.csv`
+	pub fn close_barriers(&mut self, path: &str) -> Result<(), Box<dyn Error>> {
+		let vertices = sidecar_path(path, "vertices");
+		if !Path::new(&vertices).exists() {
+			return Ok(())
+		}
+		for row in Reader::from_path(&vertices)?.deserialize() {
+			let row: VertexRow = row?;
+			if !row.car {
+				self.close_vertex(&VertexId(row.node));
+			}
+		}
+		Ok(())
+	}
+
+	// the vertex can be reached, but not passed: edges going out of it are removed
+	pub fn close_vertex(&mut self, vid: &VertexId) {
+		let edges = match self.vertice.get_mut(vid) {
+			Some(v) => std::mem::take(&mut v.edges),
+			None => return,
+		};
+		for e in edges {
+			if let Some(v2) = self.vertice.get_mut(&e.v2) {
+				v2.rev_edges.retain(|r| r.v1 != *vid);
+			}
+		}
 	}
 
 	pub fn from_reader<R: Read>(buf: R, project: bool) -> Result<Self, Box<dyn Error>> {
-		Self::parse_csv(Reader::from_reader(buf), project)
+		Self::parse_csv(Reader::from_reader(buf), project, &LoadOptions::default())
 	}
 
-	pub fn parse_csv<R: Read>(mut csvr: Reader<R>, project: bool) -> Result<Self, Box<dyn Error>> {
+	// adds the edge or its reverse (which may have another speed) in the directions where traffic is allowed
+	pub fn add_oneway_edge(&mut self, edge: Edge, reverse: Edge, oneway: OneWay) {
+		match oneway {
+			OneWay::No => {
+				self.add_edge(edge, false);
+				self.add_edge(reverse, false);
+			},
+			OneWay::Forward => self.add_edge(edge, false),
+			OneWay::Backward => self.add_edge(reverse, false),
+			// direction depends on time, can't be used in a static graph
+			OneWay::Reversible => {}
+		}
+	}
+
+	// the graph for cars, see LoadOptions
+	pub fn parse_csv<R: Read>(mut csvr: Reader<R>, project: bool, opts: &LoadOptions) -> Result<Self, Box<dyn Error>> {
+		let departure = opts.departure.as_ref();
 		let mut g = Self::new();
 		let converter = project.then(|| Proj::new_known_crs("EPSG:4326", "EPSG:3857", None).unwrap());
-
-		for e in csvr.deserialize() {
-			let e: RawEdge = e?;
-			let geom = if let Some(ref c) = converter { convert(&e.WKT, &c)? } else { e.WKT };
-			let weight = Weight(geom.euclidean_length().round() as u64);
-
-			g.add_edge(Edge { v1: e.node1, v2: e.node2, geom: geom.clone(), weight }, true); // TODO: decide on bidirectional
+		// v1 files have no oneway column, all their edges are loaded as bidirectional
+		let headers = csvr.headers()?.clone();
+		schema_version(&headers).ok_or("graph CSV header does not match any schema version")?;
+		let attributes = attribute_columns(&headers);
+
+		for rec in csvr.records() {
+			let e = RawEdge::from_record(&rec?, &headers, &attributes)?;
+			let (car, oneway) = e.car_at(departure);
+			if !car { continue }
+			let geom = if let Some(ref c) = converter { convert(&e.WKT, c)? } else { e.WKT.clone() };
+			let length = geom.euclidean_length();
+			let weight = |speed| if opts.by_time { edge_weight(length, speed) } else { Weight(length.round() as u64) };
+
+			let speed = e.speed_at(true, departure);
+			let edge = Edge { v1: e.node1, v2: e.node2, geom, weight: weight(speed), speed };
+			let speed = e.speed_at(false, departure);
+			let reverse = Edge { weight: weight(speed), speed, ..edge.reversed() };
+			g.add_oneway_edge(edge, reverse, oneway);
 		};
 		Ok(g)
 	}
@@ -221,7 +315,7 @@ pub fn make_graph() -> Graph {
 
 	for (vid1, vid2) in edges.iter() {
 		let (v1, v2) = (VertexId(*vid1), VertexId(*vid2));
-		g.add_edge(Edge { v1, v2, weight: Weight(1), geom: geom.clone() }, true)
+		g.add_edge(Edge { v1, v2, weight: Weight(1), geom: geom.clone(), speed: 0 }, true)
 	}
 	g
 }
@@ -253,7 +347,7 @@ pub fn graph_with_cul_de_sac() -> Graph {
 		let c2 = vrt.get(&n2).unwrap().clone();
 		let geom = LineString::from(vec![c1, c2]);
 
-		let e = Edge { v1, v2, geom: geom.clone(), weight: Weight(geom.euclidean_length() as u64) };
+		let e = Edge { v1, v2, geom: geom.clone(), weight: Weight(geom.euclidean_length() as u64), speed: 0 };
 		g.add_edge(e, true);
 	});
 	g
@@ -295,7 +389,7 @@ pub mod graph_tests {
 	fn from_edge_coords_correct() {
 		// initially, coordinates were confused in add_edge.
 		let mut g = Graph::new();
-		g.add_edge(Edge { v1: VertexId(5), v2: VertexId(10), geom: LineString::from(vec![(12., 14.0), (39., 45.0), (48., 55.0)]), weight: Weight(100) }, true);
+		g.add_edge(Edge { v1: VertexId(5), v2: VertexId(10), geom: LineString::from(vec![(12., 14.0), (39., 45.0), (48., 55.0)]), weight: Weight(100), speed: 0 }, true);
 		let v1 = g.get(&VertexId(5)).unwrap();
 		let v2 = g.get(&VertexId(10)).unwrap();
 		assert_eq!((v1.geom.x(), v1.geom.y()), (12.0, 14.0));
@@ -304,6 +398,93 @@ pub mod graph_tests {
 		assert_eq!(v2.edges.len(), 1);
 	}
 
+	#[test]
+	fn test_oneway_csv() {
+		let csv = "node1,node2,WKT,category,lanes,oneway,maxspeed\n\
+			1,2,\"LINESTRING(0 0,1 0)\",Primary,1,No,\n\
+			2,3,\"LINESTRING(1 0,2 0)\",Primary,1,Forward,60\n\
+			3,4,\"LINESTRING(2 0,3 0)\",Primary,1,Backward,\n";
+		let g = Graph::from_reader(csv.as_bytes(), false).unwrap();
+		for (a, b, exists) in [(1, 2, true), (2, 1, true), (2, 3, true), (3, 2, false), (3, 4, false), (4, 3, true)] {
+			assert_eq!(g.get_edge(&VertexId(a), &VertexId(b)).is_some(), exists);
+		}
+		let v3 = g.get(&VertexId(3)).unwrap();
+		assert_eq!(v3.edges.len(), 0);
+		assert_eq!(v3.rev_edges.len(), 2);
+		let e43 = g.get_edge(&VertexId(4), &VertexId(3)).unwrap();
+		assert_eq!(e43.geom.0[0].x, 3.0);
+
+		// schema v1 has no oneway column, edges are bidirectional
+		let csv = "node1,node2,WKT,category,lanes\n1,2,\"LINESTRING(0 0,1 0)\",primary,2\n";
+		let g = Graph::from_reader(csv.as_bytes(), false).unwrap();
+		assert!(g.get_edge(&VertexId(2), &VertexId(1)).is_some());
+
+		// edges closed for cars are skipped
+		let csv = "node1,node2,WKT,category,lanes,oneway,maxspeed,oneway_bike,oneway_foot,oneway_bus,id,car,bike,foot,bus\n\
+			1,2,\"LINESTRING(0 0,1 0)\",Primary,1,No,,No,No,No,1,true,true,true,true\n\
+			2,3,\"LINESTRING(1 0,2 0)\",Footway,1,No,,No,No,No,2,false,false,true,false\n";
+		let g = Graph::from_reader(csv.as_bytes(), false).unwrap();
+		assert!(g.get_edge(&VertexId(1), &VertexId(2)).is_some());
+		assert!(g.get(&VertexId(3)).is_none());
+
+		assert!(Graph::from_reader("a,b\n1,2\n".as_bytes(), false).is_err());
+
+		// a bollard at 2: it can be reached from both sides, but not passed
+		let csv = "node1,node2,WKT,category,lanes,oneway,maxspeed\n\
+			1,2,\"LINESTRING(0 0,1 0)\",Primary,1,No,\n\
+			2,3,\"LINESTRING(1 0,2 0)\",Primary,1,No,\n";
+		let mut g = Graph::from_reader(csv.as_bytes(), false).unwrap();
+		g.close_vertex(&VertexId(2));
+		assert!(g.get_edge(&VertexId(1), &VertexId(2)).is_some() && g.get_edge(&VertexId(3), &VertexId(2)).is_some());
+		assert!(g.get_edge(&VertexId(2), &VertexId(1)).is_none() && g.get_edge(&VertexId(2), &VertexId(3)).is_none());
+		assert!(g.get(&VertexId(1)).unwrap().rev_edges.is_empty());
+	}
+
+	#[test]
+	fn test_conditional_csv() {
+		let csv = "node1,node2,WKT,category,lanes,oneway,maxspeed,car,speed,maxspeed_conditional_forward,maxspeed_conditional_backward,oneway_conditional,car_conditional,name\n\
+			1,2,\"LINESTRING(0 0,1 0)\",Primary,1,No,,true,60,30 @ (22:00-06:00),,Forward @ (Mo-Fr 07:00-10:00),,Main\n\
+			2,3,\"LINESTRING(1 0,2 0)\",Primary,1,No,,true,40,,,,no @ (Mo-Fr 07:00-19:00); no @ (Sa 10:00-12:00 AND wet),\n";
+		let load = |at: &str, by_time: bool| {
+			let opts = LoadOptions { by_time, departure: Moment::parse(at) };
+			Graph::parse_csv(Reader::from_reader(csv.as_bytes()), false, &opts).unwrap()
+		};
+		let build = |at: &str| load(at, true);
+		// Monday morning: 1-2 is oneway, 2-3 is closed
+		let g = build("2024-05-06T08:30");
+		assert!(g.get_edge(&VertexId(2), &VertexId(1)).is_none() && g.get(&VertexId(3)).is_none());
+		assert_eq!(g.get_edge(&VertexId(1), &VertexId(2)).unwrap().speed, 60);
+		// night: both open, 1->2 is slower
+		let g = build("2024-05-06T23:00");
+		let (e12, e21) = (g.get_edge(&VertexId(1), &VertexId(2)).unwrap(), g.get_edge(&VertexId(2), &VertexId(1)).unwrap());
+		assert_eq!((e12.speed, e21.speed), (30, 60));
+		// and weighs twice as much: 1 unit at 30 and 60 km/h is 5 and 2.5 units at TOP_SPEED
+		assert_eq!((e12.weight.0, e21.weight.0), (5, 3));
+		// by length, the speed doesn't change the weight
+		let g = load("2024-05-06T23:00", false);
+		let (e12, e21) = (g.get_edge(&VertexId(1), &VertexId(2)).unwrap(), g.get_edge(&VertexId(2), &VertexId(1)).unwrap());
+		assert_eq!((e12.speed, e21.speed, e12.weight.0, e21.weight.0), (30, 60, 1, 1));
+		assert!(g.get_edge(&VertexId(3), &VertexId(2)).is_some());
+		// Saturday: the time alone is not enough, the road is open
+		assert!(build("2024-05-11T11:00").get_edge(&VertexId(2), &VertexId(3)).is_some());
+		// without departure time, conditions are ignored
+		let g = Graph::from_reader(csv.as_bytes(), false).unwrap();
+		assert!(g.get_edge(&VertexId(2), &VertexId(1)).is_some() && g.get(&VertexId(3)).is_some());
+
+		let rec = csv::StringRecord::from(vec!["1", "2", "LINESTRING(0 0,1 0)", "Primary", "1", "No", "", "true", "60", "", "", "", "", "Main"]);
+		let headers = Reader::from_reader(csv.as_bytes()).headers().unwrap().clone();
+		let e = RawEdge::from_record(&rec, &headers, &attribute_columns(&headers)).unwrap();
+		assert_eq!(e.attrs.get("name").map(|s| s.as_str()), Some("Main"));
+
+		// a higher conditional maxspeed replaces the speed while it applies, any access value opens the road
+		let rec = csv::StringRecord::from(vec!["1", "2", "LINESTRING(0 0,1 0)", "Primary", "1", "No", "", "false", "40", "60 @ (22:00-06:00)", "", "",
+			"destination @ (Mo-Fr 07:00-19:00)", ""]);
+		let e = RawEdge::from_record(&rec, &headers, &attribute_columns(&headers)).unwrap();
+		let (night, monday) = (Moment::parse("2024-05-06T23:00").unwrap(), Moment::parse("2024-05-06T08:30").unwrap());
+		assert_eq!((e.speed_at(true, Some(&night)), e.speed_at(true, Some(&monday))), (60, 40));
+		assert_eq!((e.car_at(Some(&monday)).0, e.car_at(Some(&night)).0), (true, false));
+	}
+
 	fn to_hm(m: &[(i64, i64, i64)]) -> VisitedMap {
 		m.iter()
 		.map(|(f, v, c)| (VertexId(*f), VertexId(*v), Cost(*c)))
@@ -14,7 +14,17 @@ pub struct Isochrone {
 }
 
 impl Isochrone {
+	// distances from source to other vertice
 	pub fn try_new(graph: &Graph, source: &VertexId, max_dist: Option<&Cost>) -> Result<Self, Box<dyn Error>> {
+		Self::_build(graph, source, max_dist, false)
+	}
+
+	// distances from other vertice to source (follows incoming edges)
+	pub fn try_new_reverse(graph: &Graph, source: &VertexId, max_dist: Option<&Cost>) -> Result<Self, Box<dyn Error>> {
+		Self::_build(graph, source, max_dist, true)
+	}
+
+	fn _build(graph: &Graph, source: &VertexId, max_dist: Option<&Cost>, reverse: bool) -> Result<Self, Box<dyn Error>> {
 		let vertice = &graph.vertice;
 		let max_dist = max_dist.unwrap_or(&Cost(0));
 		let source_geom = vertice.get(source).ok_or(format!("source nid {:?} not in the graph", source.0))?.geom;
@@ -33,8 +43,9 @@ impl Isochrone {
 
 			let vtx1 = vertice.get(&vs.vid).expect("node id not existing");
 
-			for e in vtx1.edges.iter() {
-				let other_id = e.v2;
+			let edges = if reverse { &vtx1.rev_edges } else { &vtx1.edges };
+			for e in edges.iter() {
+				let other_id = if reverse { e.v1 } else { e.v2 };
 
 				if !iso.distances.contains_key(&other_id) {
 					let other_score = VertexScore::new(
@@ -87,7 +98,7 @@ mod isochrone_tests {
 
 		for (vid1, vid2) in edges.iter() {
 			let (v1, v2) = (VertexId(*vid1), VertexId(*vid2));
-			g.add_edge(Edge { v1, v2, weight: Weight(2), geom: dummy_geom.clone() }, true);
+			g.add_edge(Edge { v1, v2, weight: Weight(2), geom: dummy_geom.clone(), speed: 0 }, true);
 		}
 
 		// UNLIMITED ISOCHRONE must have all vertice
@@ -1,6 +1,8 @@
 use serde::{Serialize, Deserialize};
 use geo::{LineString, Point};
 use crate::serialize_wkt;
+use csv::StringRecord;
+use osmtags::{OneWay, MaxSpeed, access::parse_access, conditions::{Moment, value_at}, normalize::{parse_maxspeed, UNLIMITED}};
 use std::{
 	cmp::Ordering, ops::{Add, Sub}, collections::HashMap,
 	time::SystemTime, error::Error,
@@ -38,6 +40,8 @@ pub struct Vertex {
 	pub id: VertexId,
 	pub geom: Point,
 	pub edges: Vec<Edge>,
+	// incoming edges, for backward search and distances to a vertex
+	pub rev_edges: Vec<Edge>,
 }
 
 #[derive(Clone, Debug)]
@@ -46,6 +50,17 @@ pub struct Edge {
 	pub v2: VertexId,
 	pub weight: Weight,
 	pub geom: LineString,
+	// km/h in this direction, 0 if unknown
+	pub speed: u16,
+}
+
+impl Edge {
+	// the same edge in the opposite direction
+	pub fn reversed(&self) -> Self {
+		let mut geom = self.geom.clone();
+		geom.0.reverse();
+		Self { v1: self.v2, v2: self.v1, weight: self.weight, geom, speed: self.speed }
+	}
 }
 
 #[derive(Clone, Debug)]
@@ -90,7 +105,78 @@ pub struct RawEdge {
 	#[serde(with = "serialize_wkt")]
 	pub WKT: LineString,
 	pub category: String,
-	pub lanes: u8
+	pub lanes: u8,
+	// the columns below are absent in schema v1 files
+	#[serde(default)]
+	pub oneway: OneWay,
+	#[serde(default)]
+	pub maxspeed: MaxSpeed,
+	#[serde(default)]
+	pub oneway_bike: OneWay,
+	#[serde(default)]
+	pub oneway_foot: OneWay,
+	#[serde(default)]
+	pub oneway_bus: OneWay,
+	// before schema v5 all modes were allowed everywhere
+	#[serde(default = "allowed")]
+	pub car: bool,
+	#[serde(default = "allowed")]
+	pub bike: bool,
+	#[serde(default = "allowed")]
+	pub foot: bool,
+	#[serde(default = "allowed")]
+	pub bus: bool,
+	// 0 before schema v6
+	#[serde(default)]
+	pub speed: u16,
+	// schema v7 and v12, `value @ (condition)`
+	#[serde(default)]
+	pub maxspeed_conditional_forward: String,
+	#[serde(default)]
+	pub maxspeed_conditional_backward: String,
+	#[serde(default)]
+	pub oneway_conditional: String,
+	#[serde(default)]
+	pub car_conditional: String,
+	// schema v13, levels of node1 and node2 in indoor graphs
+	#[serde(default)]
+	pub level1: Option<f64>,
+	#[serde(default)]
+	pub level2: Option<f64>,
+	// profile's attribute columns with non-empty values, see from_record
+	#[serde(skip)]
+	pub attrs: HashMap<String, String>,
+}
+
+fn allowed() -> bool { true }
+
+impl RawEdge {
+	// `attributes` are from osmtags::schema::attribute_columns
+	pub fn from_record(rec: &StringRecord, headers: &StringRecord, attributes: &[(usize, String)]) -> Result<Self, csv::Error> {
+		let mut e: RawEdge = rec.deserialize(Some(headers))?;
+		e.attrs = attributes.iter()
+			.filter_map(|(i, name)| rec.get(*i).filter(|v| !v.is_empty()).map(|v| (name.clone(), v.to_string())))
+			.collect();
+		Ok(e)
+	}
+
+	// car access and direction at the departure time, with conditional restrictions that apply then
+	pub fn car_at(&self, at: Option<&Moment>) -> (bool, OneWay) {
+		let at = match at { Some(at) => at, None => return (self.car, self.oneway) };
+		(
+			value_at(&self.car_conditional, at).and_then(|v| parse_access(&v)).unwrap_or(self.car),
+			value_at(&self.oneway_conditional, at).and_then(|v| OneWay::from_name(&v)).unwrap_or(self.oneway),
+		)
+	}
+
+	// speed in the direction of the geometry (or against it), or a conditional maxspeed while it applies
+	pub fn speed_at(&self, forward: bool, at: Option<&Moment>) -> u16 {
+		let cond = if forward { &self.maxspeed_conditional_forward } else { &self.maxspeed_conditional_backward };
+		match at.and_then(|at| value_at(cond, at)).and_then(|v| parse_maxspeed(&v, "")) {
+			Some(UNLIMITED) | None => self.speed,
+			Some(v) => v,
+		}
+	}
 }
 
 